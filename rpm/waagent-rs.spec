@@ -30,17 +30,19 @@ source $HOME/.cargo/env
 cargo build --release
 
 %install
-install -Dm0755 target/release/waagent-rs-poc %{buildroot}/usr/bin/waagent-rs-poc
-install -Dm0440 waagent-poc/sudoers.d/waagent-rs %{buildroot}/etc/sudoers.d/waagent-rs
+install -Dm0755 target/release/waagent %{buildroot}/usr/bin/waagent
 install -Dm0755 init/systemd/waagent-rs.service %{buildroot}/usr/lib/systemd/system/waagent-rs.service
+install -Dm0644 init/systemd/waagent-rs-helper.socket %{buildroot}/usr/lib/systemd/system/waagent-rs-helper.socket
+install -Dm0644 init/systemd/waagent-rs-helper.service %{buildroot}/usr/lib/systemd/system/waagent-rs-helper.service
 
 %pre
 getent passwd waagent-rs >/dev/null || useradd -r -d /nonexistent -s /usr/sbin/nologin waagent-rs
 
 %files
-/usr/bin/waagent-rs-poc
-/etc/sudoers.d/waagent-rs
+/usr/bin/waagent
 /usr/lib/systemd/system/waagent-rs.service
+/usr/lib/systemd/system/waagent-rs-helper.socket
+/usr/lib/systemd/system/waagent-rs-helper.service
 %license LICENSE
 %doc README.md
 
@@ -0,0 +1,45 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum CertificateError {
+    /// Reading or writing certificate files failed, or openssl could not be started.
+    Io(io::Error),
+    /// An openssl command exited with a non-zero code.
+    Openssl { command: String, stderr: String },
+    /// The host or an extension handed us data we could not make sense of.
+    InvalidData(String),
+    /// No certificate with this thumbprint has been installed.
+    NotFound(String),
+}
+
+impl fmt::Display for CertificateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CertificateError::Io(e) => write!(f, "I/O error: {}", e),
+            CertificateError::Openssl { command, stderr } => {
+                write!(f, "openssl {} failed: {}", command, stderr)
+            }
+            CertificateError::InvalidData(reason) => write!(f, "invalid data: {}", reason),
+            CertificateError::NotFound(thumbprint) => {
+                write!(f, "no certificate installed for thumbprint {}", thumbprint)
+            }
+        }
+    }
+}
+
+impl Error for CertificateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CertificateError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CertificateError {
+    fn from(e: io::Error) -> Self {
+        CertificateError::Io(e)
+    }
+}
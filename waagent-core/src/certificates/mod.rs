@@ -0,0 +1,7 @@
+mod error;
+mod openssl;
+mod store;
+
+pub use error::CertificateError;
+pub use openssl::Openssl;
+pub use store::CertificateStore;
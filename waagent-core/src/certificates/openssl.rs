@@ -0,0 +1,46 @@
+use super::CertificateError;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+use tracing::debug;
+
+/// Thin wrapper around the openssl command line tool (`OS.OpensslPath`).
+#[derive(Debug, Clone)]
+pub struct Openssl {
+    path: PathBuf,
+}
+
+impl Openssl {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Runs openssl with `args`, feeding `input` on stdin, and returns stdout.
+    pub fn run(&self, args: &[&str], input: Option<&[u8]>) -> Result<Vec<u8>, CertificateError> {
+        debug!("Running {} {}", self.path.display(), args.join(" "));
+        let mut child = Command::new(&self.path)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Write stdin from another thread so a large output cannot deadlock us.
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let input = input.unwrap_or_default().to_vec();
+        let writer = thread::spawn(move || stdin.write_all(&input));
+
+        let output = child.wait_with_output()?;
+        // A command that does not read stdin closes the pipe early; only its exit code matters.
+        let _ = writer.join();
+
+        if !output.status.success() {
+            return Err(CertificateError::Openssl {
+                command: args.first().copied().unwrap_or_default().to_string(),
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+        Ok(output.stdout)
+    }
+}
@@ -0,0 +1,412 @@
+//! The configuration as typed structs, one per section, generated from the
+//! table of keys below. That table is also the schema `ConfigSchema` is built
+//! from, so a key only ever has to be spelled once.
+
+use super::defaults::NONE_STR;
+use super::{Config, ConfigValue, ExpectedType, KeyMetadata};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// How a field of [`AgentConfig`] is read from a [`ConfigValue`].
+trait ConfigType: Sized {
+    const EXPECTED_TYPE: ExpectedType;
+
+    fn from_config_value(value: &ConfigValue) -> Option<Self>;
+}
+
+impl ConfigType for bool {
+    const EXPECTED_TYPE: ExpectedType = ExpectedType::Bool;
+
+    fn from_config_value(value: &ConfigValue) -> Option<Self> {
+        match value {
+            ConfigValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl ConfigType for u32 {
+    const EXPECTED_TYPE: ExpectedType = ExpectedType::Integer;
+
+    fn from_config_value(value: &ConfigValue) -> Option<Self> {
+        match value {
+            ConfigValue::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+/// Periods, intervals and timeouts are given in seconds.
+impl ConfigType for Duration {
+    const EXPECTED_TYPE: ExpectedType = ExpectedType::Integer;
+
+    fn from_config_value(value: &ConfigValue) -> Option<Self> {
+        u32::from_config_value(value).map(|secs| Duration::from_secs(secs.into()))
+    }
+}
+
+impl ConfigType for String {
+    const EXPECTED_TYPE: ExpectedType = ExpectedType::String;
+
+    fn from_config_value(value: &ConfigValue) -> Option<Self> {
+        match value {
+            ConfigValue::String(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl ConfigType for PathBuf {
+    const EXPECTED_TYPE: ExpectedType = ExpectedType::String;
+
+    fn from_config_value(value: &ConfigValue) -> Option<Self> {
+        String::from_config_value(value).map(PathBuf::from)
+    }
+}
+
+/// Optional strings are written as `None` (or `""`) when unset.
+impl ConfigType for Option<String> {
+    const EXPECTED_TYPE: ExpectedType = ExpectedType::String;
+
+    fn from_config_value(value: &ConfigValue) -> Option<Self> {
+        String::from_config_value(value)
+            .map(|value| Some(value).filter(|v| !v.is_empty() && v != NONE_STR))
+    }
+}
+
+impl ConfigType for Option<u16> {
+    const EXPECTED_TYPE: ExpectedType = ExpectedType::Port;
+
+    fn from_config_value(value: &ConfigValue) -> Option<Self> {
+        match value {
+            ConfigValue::Port(port) => Some(*port),
+            _ => None,
+        }
+    }
+}
+
+/// The value of `key` in `config`, or its default when `config` does not
+/// hold a value of the right type.
+fn typed_value<T: ConfigType + Default>(config: &Config, defaults: &Config, key: &str) -> T {
+    config
+        .get_value(key)
+        .and_then(T::from_config_value)
+        .or_else(|| defaults.get_value(key).and_then(T::from_config_value))
+        .unwrap_or_default()
+}
+
+macro_rules! agent_config {
+    ($(
+        $(#[$section_doc:meta])*
+        $section:ident: $section_type:ident {
+            $(
+                $(#[doc = $doc:literal])+
+                $key:literal => $field:ident: $field_type:ty
+                $({ $($meta:ident: $meta_value:expr),* $(,)? })?
+            ),* $(,)?
+        }
+    ),* $(,)?) => {
+        /// Every setting of `waagent.conf`, typed. Convert a [`Config`] into
+        /// it with `AgentConfig::from(&config)`.
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct AgentConfig {
+            $($(#[$section_doc])* pub $section: $section_type,)*
+        }
+
+        $(
+            $(#[$section_doc])*
+            #[derive(Debug, Clone, PartialEq)]
+            pub struct $section_type {
+                $(
+                    $(#[doc = $doc])+
+                    #[doc = ""]
+                    #[doc = concat!("`", $key, "`")]
+                    pub $field: $field_type,
+                )*
+            }
+        )*
+
+        impl AgentConfig {
+            fn from_config(config: &Config, defaults: &Config) -> Self {
+                Self {
+                    $($section: $section_type {
+                        $($field: typed_value(config, defaults, $key),)*
+                    },)*
+                }
+            }
+        }
+
+        /// Every key with its type, description and constraints, in the order
+        /// of the table.
+        pub(super) const SCHEMA_KEYS: &[KeyMetadata] = &[
+            $($(KeyMetadata {
+                $($($meta: $meta_value,)*)?
+                ..KeyMetadata::new(
+                    $key,
+                    <$field_type as ConfigType>::EXPECTED_TYPE,
+                    concat!($($doc),+).trim_ascii(),
+                )
+            },)*)*
+        ];
+    };
+}
+
+#[rustfmt::skip]
+agent_config! {
+    /// Keys outside any section.
+    general: GeneralConfig {
+        /// Detect a System Center Virtual Machine Manager environment.
+        "DetectScvmmEnv" => detect_scvmm_env: bool,
+        /// Hold extension handling until inVMArtifactsProfile.OnHold is false.
+        "EnableOverProvisioning" => enable_over_provisioning: bool,
+    },
+    lib: LibConfig {
+        /// Directory the agent keeps its state in.
+        "Lib.Dir" => dir: PathBuf,
+    },
+    dvd: DvdConfig {
+        /// Where the provisioning DVD is mounted.
+        "DVD.MountPoint" => mount_point: PathBuf,
+    },
+    pid: PidConfig {
+        /// File the agent writes its process id to.
+        "Pid.File" => file: PathBuf,
+    },
+    os: OsConfig {
+        /// Allow fallback to HTTP if HTTPS is unavailable.
+        "OS.AllowHTTP" => allow_http: bool,
+        /// Add firewall rules to protect access to Azure host node services.
+        "OS.EnableFirewall" => enable_firewall: bool,
+        /// How often, in seconds, to check the firewall rules.
+        "OS.EnableFirewallPeriod" => enable_firewall_period: Duration,
+        /// Whether FIPS is enabled.
+        "OS.EnableFIPS" => enable_fips: bool,
+        /// Enable RDMA management and set up; only for HPC images.
+        "OS.EnableRDMA" => enable_rdma: bool,
+        /// Update the RDMA driver.
+        "OS.UpdateRdmaDriver" => update_rdma_driver: bool,
+        /// Check the RDMA driver version.
+        "OS.CheckRdmaDriver" => check_rdma_driver: bool,
+        /// Path of the openssl binary.
+        "OS.OpensslPath" => openssl_path: PathBuf,
+        /// Directory of SSH keys and configuration files.
+        "OS.SshDir" => ssh_dir: PathBuf,
+        /// Parent directory of user home directories.
+        "OS.HomeDir" => home_dir: PathBuf,
+        /// File holding password hashes.
+        "OS.PasswordPath" => password_path: PathBuf,
+        /// Directory sudoers entries are written to.
+        "OS.SudoersDir" => sudoers_dir: PathBuf,
+        /// Root device timeout in seconds, or None to leave it alone.
+        "OS.RootDeviceScsiTimeout" => root_device_scsi_timeout: Option<String>,
+        /// How often, in seconds, to set the root device timeout.
+        "OS.RootDeviceScsiTimeoutPeriod" => root_device_scsi_timeout_period: Duration,
+        /// How often, in seconds, to remove persistent network rules.
+        "OS.RemovePersistentNetRulesPeriod" => remove_persistent_net_rules_period: Duration,
+        /// How often, in seconds, to check for a DHCP client restart.
+        "OS.MonitorDhcpClientRestartPeriod" => monitor_dhcp_client_restart_period: Duration,
+        /// SSH ClientAliveInterval, in seconds.
+        "OS.SshClientAliveInterval" => ssh_client_alive_interval: Duration,
+    },
+    logs: LogsConfig {
+        /// Enable verbose logging.
+        "Logs.Verbose" => verbose: bool,
+        /// Log to the console.
+        "Logs.Console" => console: bool,
+        /// Enable periodic log collection.
+        "Logs.Collect" => collect: bool,
+        /// How often, in seconds, to collect logs.
+        "Logs.CollectPeriod" => collect_period: Duration,
+    },
+    /// `Extensions.*`, and `Extension.LogDir`.
+    extensions: ExtensionsConfig {
+        /// Enable extension handling.
+        "Extensions.Enabled" => enabled: bool,
+        /// How often, in seconds, to fetch the goal state.
+        "Extensions.GoalStatePeriod" => goal_state_period: Duration,
+        /// How often, in seconds, to fetch the goal state until the first one is handled.
+        "Extensions.InitialGoalStatePeriod" => initial_goal_state_period: Duration,
+        /// Wait for cloud-init to finish before handling extensions.
+        "Extensions.WaitForCloudInit" => wait_for_cloud_init: bool,
+        /// How long, in seconds, to wait for cloud-init.
+        "Extensions.WaitForCloudInitTimeout" => wait_for_cloud_init_timeout: Duration,
+        /// Directory extension logs are written to.
+        "Extension.LogDir" => log_dir: PathBuf,
+    },
+    provisioning: ProvisioningConfig {
+        /// Provisioning agent: auto, waagent, cloud-init or disabled.
+        "Provisioning.Agent" => agent: String,
+        /// Allow resetting the password of a system user.
+        "Provisioning.AllowResetSysUser" => allow_reset_sys_user: bool,
+        /// Generate a fresh host key pair.
+        "Provisioning.RegenerateSshHostKeyPair" => regenerate_ssh_host_key_pair: bool,
+        /// Type of the host key pair to generate; auto needs OpenSSH 5.9 or later.
+        "Provisioning.SshHostKeyPairType" => ssh_host_key_pair_type: String {
+            allowed_values: &["rsa", "dsa", "ecdsa", "ed25519", "auto"],
+        },
+        /// Make password authentication for the root account unavailable.
+        "Provisioning.DeleteRootPassword" => delete_root_password: bool,
+        /// Decode CustomData from Base64.
+        "Provisioning.DecodeCustomData" => decode_custom_data: bool,
+        /// Execute CustomData after provisioning.
+        "Provisioning.ExecuteCustomData" => execute_custom_data: bool,
+        /// Publish host name changes through DHCP requests.
+        "Provisioning.MonitorHostName" => monitor_host_name: bool,
+        /// How often, in seconds, to check the host name.
+        "Provisioning.MonitorHostNamePeriod" => monitor_host_name_period: Duration,
+        /// Algorithm crypt uses to hash passwords.
+        "Provisioning.PasswordCryptId" => password_crypt_id: String,
+        /// Length of the random salt used to hash passwords.
+        "Provisioning.PasswordCryptSaltLength" => password_crypt_salt_length: u32,
+    },
+    http_proxy: HttpProxyConfig {
+        /// Proxy server used to access the internet, or None.
+        "HttpProxy.Host" => host: Option<String>,
+        /// Port of the proxy server, or None.
+        "HttpProxy.Port" => port: Option<u16>,
+    },
+    resource_disk: ResourceDiskConfig {
+        /// Format the resource disk if unformatted; if not, it is not mounted.
+        "ResourceDisk.Format" => format: bool,
+        /// File system of the resource disk; FreeBSD images use ufs2.
+        "ResourceDisk.Filesystem" => filesystem: String {
+            allowed_values: &["ext3", "ext4", "xfs", "btrfs", "ufs2"],
+        },
+        /// Mount point of the resource disk.
+        "ResourceDisk.MountPoint" => mount_point: PathBuf,
+        /// Comma-separated mount options, or None.
+        "ResourceDisk.MountOptions" => mount_options: Option<String>,
+        /// Create and use a swap file on the resource disk.
+        "ResourceDisk.EnableSwap" => enable_swap: bool,
+        /// Encrypt the swap file.
+        "ResourceDisk.EnableSwapEncryption" => enable_swap_encryption: bool,
+        /// Size of the swap file, in MB.
+        "ResourceDisk.SwapSizeMB" => swap_size_mb: u32,
+    },
+    /// `AutoUpdate.*`, and `Autoupdate.Frequency`.
+    auto_update: AutoUpdateConfig {
+        /// Enable goal state processing auto-update; replaced by AutoUpdate.UpdateToLatestVersion.
+        "AutoUpdate.Enabled" => enabled: bool { deprecated: true },
+        /// Update the agent to the latest version.
+        "AutoUpdate.UpdateToLatestVersion" => update_to_latest_version: bool,
+        /// Update family of the agent; should not be changed.
+        "AutoUpdate.GAFamily" => ga_family: String {
+            allowed_values: &["Prod", "Test"],
+        },
+        /// How often, in seconds, to check for agent updates.
+        "Autoupdate.Frequency" => frequency: Duration,
+    },
+    policy: PolicyConfig {
+        /// File holding the extension policy.
+        "Policy.PolicyFilePath" => policy_file_path: PathBuf,
+    },
+    protocol: ProtocolConfig {
+        /// How the WireServer endpoint is discovered.
+        "Protocol.EndpointDiscovery" => endpoint_discovery: String {
+            allowed_values: &["dhcp", "static"],
+        },
+    },
+    /// "Debug" options are experimental and may be removed in later versions
+    /// of the Agent.
+    debug: DebugConfig {
+        /// Log cgroup metrics.
+        "Debug.CgroupLogMetrics" => cgroup_log_metrics: bool,
+        /// Disable cgroups when the process check fails.
+        "Debug.CgroupDisableOnProcessCheckFailure" => cgroup_disable_on_process_check_failure: bool,
+        /// Disable cgroups when the quota check fails.
+        "Debug.CgroupDisableOnQuotaCheckFailure" => cgroup_disable_on_quota_check_failure: bool,
+        /// How often, in seconds, to check the cgroups.
+        "Debug.CgroupCheckPeriod" => cgroup_check_period: Duration,
+        /// CPU quota of the agent, as a percentage of one CPU.
+        "Debug.AgentCpuQuota" => agent_cpu_quota: u32 { min: Some(1), max: Some(100) },
+        /// Throttled CPU time, in seconds, above which the agent is restarted.
+        "Debug.AgentCpuThrottledTimeThreshold" => agent_cpu_throttled_time_threshold: Duration,
+        /// Memory quota of the agent, in bytes.
+        "Debug.AgentMemoryQuota" => agent_memory_quota: u32,
+        /// Check the memory usage of the agent.
+        "Debug.EnableAgentMemoryUsageCheck" => enable_agent_memory_usage_check: bool,
+        /// Enable Fast Track goal states.
+        "Debug.EnableFastTrack" => enable_fast_track: bool,
+        /// Enable agent versioning.
+        "Debug.EnableGAVersioning" => enable_ga_versioning: bool,
+        /// Enable resource limiting with cgroup v2.
+        "Debug.EnableCgroupV2ResourceLimiting" => enable_cgroup_v2_resource_limiting: bool,
+        /// Enforce the extension policy.
+        "Debug.EnableExtensionPolicy" => enable_extension_policy: bool,
+        /// How often, in seconds, to collect extension telemetry events.
+        "Debug.EtpCollectionPeriod" => etp_collection_period: Duration,
+        /// How often, in seconds, to check for hotfix updates.
+        "Debug.AutoUpdateHotfixFrequency" => auto_update_hotfix_frequency: Duration,
+        /// How often, in seconds, to check for regular updates.
+        "Debug.AutoUpdateNormalFrequency" => auto_update_normal_frequency: Duration,
+        /// How often, in seconds, to log the firewall rules.
+        "Debug.FirewallRulesLogPeriod" => firewall_rules_log_period: Duration,
+        /// Delay, in seconds, before the first log collection.
+        "Debug.LogCollectorInitialDelay" => log_collector_initial_delay: Duration,
+    },
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        let defaults = Config::default();
+        Self::from_config(&defaults, &defaults)
+    }
+}
+
+/// Values missing from `config`, or of the wrong type, take their defaults.
+impl From<&Config> for AgentConfig {
+    fn from(config: &Config) -> Self {
+        Self::from_config(config, &Config::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HashMap;
+
+    #[test]
+    fn test_defaults() {
+        let config = AgentConfig::default();
+
+        assert!(config.extensions.enabled);
+        assert_eq!(config.extensions.goal_state_period, Duration::from_secs(6));
+        assert_eq!(config.lib.dir, PathBuf::from("/var/lib/waagent"));
+        assert_eq!(config.resource_disk.filesystem, "ext3");
+        assert_eq!(config.resource_disk.mount_options, None);
+        assert_eq!(config.http_proxy.port, None);
+        assert_eq!(config.debug.agent_memory_quota, 30 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_from_config() {
+        let mut values = HashMap::new();
+        values.insert("OS.EnableFirewall".to_string(), ConfigValue::Bool(true));
+        values.insert(
+            "OS.EnableFirewallPeriod".to_string(),
+            ConfigValue::Integer(60),
+        );
+        values.insert(
+            "ResourceDisk.MountOptions".to_string(),
+            ConfigValue::String("nofail".to_string()),
+        );
+        values.insert("HttpProxy.Port".to_string(), ConfigValue::Port(Some(3128)));
+        // The wrong type falls back to the default rather than to false.
+        values.insert(
+            "Extensions.Enabled".to_string(),
+            ConfigValue::String("y".to_string()),
+        );
+
+        let config = AgentConfig::from(&Config::from_map(values));
+
+        assert!(config.os.enable_firewall);
+        assert_eq!(config.os.enable_firewall_period, Duration::from_secs(60));
+        assert_eq!(
+            config.resource_disk.mount_options.as_deref(),
+            Some("nofail")
+        );
+        assert_eq!(config.http_proxy.port, Some(3128));
+        assert!(config.extensions.enabled);
+        assert_eq!(config.provisioning.agent, "auto");
+    }
+}
@@ -1,4 +1,4 @@
-use super::{Config, ConfigValue, HashMap};
+use super::{Config, ConfigSource, ConfigValue, HashMap};
 
 macro_rules! load_defaults_hashmap {
     ($hashmap:ident, {
@@ -18,9 +18,13 @@ pub const NONE_STR: &str = "None";
 
 impl Default for Config {
     fn default() -> Self {
-        Self {
-            config: get_config_defaults(),
-        }
+        let config = get_config_defaults();
+        let origins = config
+            .keys()
+            .map(|key| (key.clone(), ConfigSource::Default))
+            .collect();
+
+        Self { config, origins }
     }
 }
 
@@ -0,0 +1,48 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The line was ignored or did not say anything; the agent runs as if it
+    /// were not there.
+    Warning,
+    /// The value is wrong: the line is ignored, or in a single file its
+    /// default is used instead.
+    Error,
+}
+
+/// Something wrong with one line of a configuration file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigDiagnostic {
+    pub severity: Severity,
+    /// 1-based; 0 for an environment variable, which has no lines.
+    pub line: usize,
+    pub key: String,
+    /// The value as written, without surrounding whitespace or comment.
+    pub value: String,
+    pub reason: String,
+}
+
+impl ConfigDiagnostic {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl fmt::Display for ConfigDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.severity)?;
+        if self.line > 0 {
+            write!(f, "line {}: ", self.line)?;
+        }
+        write!(f, "{}={}: {}", self.key, self.value, self.reason)
+    }
+}
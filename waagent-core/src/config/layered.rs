@@ -0,0 +1,204 @@
+//! Loading the configuration in layers: the defaults, `waagent.conf`, the
+//! `*.conf` fragments in `waagent.conf.d` in lexical order, and last
+//! `WAAGENT_<SECTION>_<KEY>` environment variables. Each layer overrides the
+//! ones before it, and the [`Config`] remembers which layer each value came
+//! from.
+
+use super::parser::parse_config_value;
+use super::{Config, ConfigDiagnostic, ConfigSchema, Severity};
+use crate::utils::fileutils::read_file;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+pub const ENV_PREFIX: &str = "WAAGENT_";
+
+/// Where the value of a key came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    Default,
+    File(PathBuf),
+    /// The name of the environment variable.
+    Environment(String),
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::File(path) => write!(f, "{}", path.display()),
+            ConfigSource::Environment(name) => write!(f, "${}", name),
+        }
+    }
+}
+
+impl Config {
+    /// Loads `path`, the fragments in `<path>.d` and the environment of the
+    /// process. `path` must exist; the fragment directory need not.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::load_with_env(path, std::env::vars())
+    }
+
+    /// Like [`Config::load`], with `vars` in place of the environment of the
+    /// process.
+    pub fn load_with_env(
+        path: &Path,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> io::Result<Self> {
+        let (config, diagnostics) = Self::load_with_diagnostics(path, vars)?;
+        for (source, diagnostic) in diagnostics {
+            warn!("{}: {}", source, diagnostic);
+        }
+        Ok(config)
+    }
+
+    /// Like [`Config::load_with_env`], and also returns what is wrong with
+    /// each layer, along with the file or variable it is wrong in.
+    pub fn load_with_diagnostics(
+        path: &Path,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> io::Result<(Self, Vec<(ConfigSource, ConfigDiagnostic)>)> {
+        let mut config = Self::default();
+        let mut diagnostics = Vec::new();
+
+        diagnostics.extend(config.apply_file(path)?);
+        for fragment in drop_in_files(&drop_in_dir(path))? {
+            diagnostics.extend(config.apply_file(&fragment)?);
+        }
+        diagnostics.extend(config.apply_env(vars));
+
+        Ok((config, diagnostics))
+    }
+
+    fn apply_file(&mut self, path: &Path) -> io::Result<Vec<(ConfigSource, ConfigDiagnostic)>> {
+        debug!("Reading configuration from {}", path.display());
+        let data = read_file(path)?;
+        let (values, diagnostics) = Self::parse_layer(&data);
+
+        let source = ConfigSource::File(path.to_path_buf());
+        for (key, value) in values {
+            self.set(key, value, source.clone());
+        }
+        Ok(diagnostics
+            .into_iter()
+            .map(|diagnostic| (source.clone(), diagnostic))
+            .collect())
+    }
+
+    fn apply_env(
+        &mut self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Vec<(ConfigSource, ConfigDiagnostic)> {
+        let schema = ConfigSchema::new();
+        let mut diagnostics = Vec::new();
+
+        for (name, value) in vars {
+            if !name.starts_with(ENV_PREFIX) {
+                continue;
+            }
+            // Variables have no lines
+            let diagnostic = |severity, key: &str, reason: String| ConfigDiagnostic {
+                severity,
+                line: 0,
+                key: key.to_string(),
+                value: value.clone(),
+                reason,
+            };
+            let Some(metadata) = schema.keys().find(|m| env_var_name(m.key) == name) else {
+                let diagnostic = diagnostic(
+                    Severity::Warning,
+                    &name,
+                    "unknown configuration key; the variable is ignored".to_string(),
+                );
+                diagnostics.push((ConfigSource::Environment(name), diagnostic));
+                continue;
+            };
+
+            match parse_config_value(value.trim(), metadata) {
+                Ok(config_value) => {
+                    self.set(
+                        metadata.key.to_string(),
+                        config_value,
+                        ConfigSource::Environment(name),
+                    );
+                }
+                Err(expected) => {
+                    let diagnostic = diagnostic(
+                        Severity::Error,
+                        metadata.key,
+                        format!("expected {}; the variable is ignored", expected),
+                    );
+                    diagnostics.push((ConfigSource::Environment(name), diagnostic));
+                }
+            }
+        }
+        diagnostics
+    }
+}
+
+/// The environment variable that overrides `key`: `WAAGENT_OS_ENABLEFIREWALL`
+/// for `OS.EnableFirewall`, `WAAGENT_DETECTSCVMMENV` for `DetectScvmmEnv`.
+pub fn env_var_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.replace('.', "_").to_uppercase())
+}
+
+/// `/etc/waagent.conf.d` for `/etc/waagent.conf`.
+pub fn drop_in_dir(path: &Path) -> PathBuf {
+    let mut dir = OsString::from(path.as_os_str());
+    dir.push(".d");
+    PathBuf::from(dir)
+}
+
+/// The `*.conf` files in `dir`, in lexical order; none if `dir` does not exist.
+fn drop_in_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "conf") && path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_var_name() {
+        assert_eq!(
+            env_var_name("OS.EnableFirewall"),
+            "WAAGENT_OS_ENABLEFIREWALL"
+        );
+        assert_eq!(env_var_name("DetectScvmmEnv"), "WAAGENT_DETECTSCVMMENV");
+    }
+
+    #[test]
+    fn test_drop_in_dir() {
+        assert_eq!(
+            drop_in_dir(Path::new("/etc/waagent.conf")),
+            Path::new("/etc/waagent.conf.d")
+        );
+    }
+
+    #[test]
+    fn test_schema_keys_have_distinct_env_var_names() {
+        let schema = ConfigSchema::new();
+        let mut names: Vec<_> = schema.keys().map(|m| env_var_name(m.key)).collect();
+        names.sort();
+        names.dedup();
+
+        assert_eq!(names.len(), schema.keys().count());
+    }
+}
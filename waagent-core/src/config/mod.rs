@@ -1,9 +1,15 @@
+pub mod agent;
 mod defaults;
+mod diagnostics;
+mod layered;
 mod parser;
 mod schema;
 mod show;
 mod types;
 
-pub use schema::ConfigSchema;
+pub use agent::AgentConfig;
+pub use diagnostics::{ConfigDiagnostic, Severity};
+pub use layered::{drop_in_dir, env_var_name, ConfigSource, ENV_PREFIX};
+pub use schema::{ConfigSchema, KeyMetadata};
 pub use std::collections::HashMap;
 pub use types::{Config, ConfigValue, ExpectedType};
@@ -1,50 +1,145 @@
 use super::defaults::NONE_STR;
-use super::{Config, ConfigSchema, ConfigValue, ExpectedType, HashMap};
+use super::{
+    Config, ConfigDiagnostic, ConfigSchema, ConfigSource, ConfigValue, ExpectedType, HashMap,
+    KeyMetadata, Severity,
+};
 use crate::utils::fileutils::read_file;
 use std::path::Path;
 
 impl Config {
     pub fn from_file(path: &Path) -> std::io::Result<Self> {
+        Self::from_file_with_diagnostics(path).map(|(config, _)| config)
+    }
+
+    /// Like [`Config::from_file`], and also returns what is wrong with the
+    /// file: unknown keys, missing and invalid values, keys set twice.
+    pub fn from_file_with_diagnostics(
+        path: &Path,
+    ) -> std::io::Result<(Self, Vec<ConfigDiagnostic>)> {
         // This will return Err if read_file returns Err, which would be due to open()
         // https://doc.rust-lang.org/stable/std/fs/struct.OpenOptions.html#method.open
         // will also fail if the data in this stream is not valid UTF-8 then an error is returned and buf is unchanged
         // caller will need to handle errors.
         let data: String = read_file(path)?;
-        let parsed_data = Self::parse(&data);
+        let (parsed_data, diagnostics) = Self::parse_with_diagnostics(&data);
 
-        Ok(Self {
-            config: Self::merge_with_defaults(parsed_data),
-        })
+        let mut config = Self::default();
+        for (key, value) in parsed_data {
+            config.set(key, value, ConfigSource::File(path.to_path_buf()));
+        }
+        Ok((config, diagnostics))
     }
 
+    #[cfg(test)]
     fn parse(data: &str) -> HashMap<String, ConfigValue> {
+        Self::parse_with_diagnostics(data).0
+    }
+
+    /// Missing and invalid values are replaced by their defaults.
+    pub(super) fn parse_with_diagnostics(
+        data: &str,
+    ) -> (HashMap<String, ConfigValue>, Vec<ConfigDiagnostic>) {
+        Self::parse_lines(data, Some(&Config::default()))
+    }
+
+    /// For a file layered over others: lines with missing and invalid values
+    /// are left out, so whatever the key was set to before stays in effect.
+    pub(super) fn parse_layer(data: &str) -> (HashMap<String, ConfigValue>, Vec<ConfigDiagnostic>) {
+        Self::parse_lines(data, None)
+    }
+
+    fn parse_lines(
+        data: &str,
+        defaults: Option<&Config>,
+    ) -> (HashMap<String, ConfigValue>, Vec<ConfigDiagnostic>) {
         let mut values = HashMap::new();
+        let mut diagnostics = Vec::new();
+        let mut first_lines: HashMap<String, usize> = HashMap::new();
         let schema = ConfigSchema::new();
-        let defaults = Config::default();
-
-        for line in data.lines() {
+        let fallback = if defaults.is_some() {
+            "the default is used"
+        } else {
+            "the line is ignored"
+        };
+
+        for (index, line) in data.lines().enumerate() {
+            let line_number = index + 1;
             if line.is_empty() || line.starts_with('#') {
                 continue;
             }
 
             let (key, value) = match split_key_value(line) {
                 Some(pair) => pair,
-                None => continue,
+                None => {
+                    // A line that is only a comment or whitespace says nothing
+                    let content = line.split('#').next().unwrap_or("").trim();
+                    if let Some(value) = content.strip_prefix('=') {
+                        diagnostics.push(ConfigDiagnostic {
+                            severity: Severity::Warning,
+                            line: line_number,
+                            key: String::new(),
+                            value: value.trim().to_string(),
+                            reason: "no key; the line is ignored".to_string(),
+                        });
+                    }
+                    continue;
+                }
+            };
+            let mut diagnostic = |severity, reason: String| {
+                diagnostics.push(ConfigDiagnostic {
+                    severity,
+                    line: line_number,
+                    key: key.clone(),
+                    value: value.clone(),
+                    reason,
+                })
             };
 
-            if !schema.is_valid_key(&key) {
+            let Some(metadata) = schema.get_metadata(&key) else {
+                diagnostic(
+                    Severity::Warning,
+                    "unknown key; the line is ignored".to_string(),
+                );
                 continue;
+            };
+
+            if let Some(first_line) = first_lines.get(&key) {
+                diagnostic(
+                    Severity::Warning,
+                    format!("also set on line {}; this line wins", first_line),
+                );
+            } else {
+                first_lines.insert(key.clone(), line_number);
             }
 
-            let expected_type = schema.get_expected_type(&key);
-            let config_value = parse_config_value(&key, &value, expected_type, &defaults);
+            if metadata.deprecated {
+                diagnostic(
+                    Severity::Warning,
+                    "deprecated key; still read for now".to_string(),
+                );
+            }
+
+            let config_value = match parse_config_value(&value, metadata) {
+                Ok(config_value) => Some(config_value),
+                Err(expected) => {
+                    if value.is_empty() {
+                        diagnostic(Severity::Warning, format!("no value; {}", fallback));
+                    } else {
+                        diagnostic(
+                            Severity::Error,
+                            format!("expected {}; {}", expected, fallback),
+                        );
+                    }
+                    defaults.and_then(|defaults| fallback_to_default(&key, defaults))
+                }
+            };
 
             if let Some(config_value) = config_value {
                 values.insert(key.to_string(), config_value);
             }
         }
 
-        values
+        (values, diagnostics)
     }
 }
 
@@ -62,27 +157,21 @@ fn split_key_value(line: &str) -> Option<(String, String)> {
     Some((key.to_string(), value.to_string()))
 }
 
-fn parse_config_value(
-    key: &str,
+/// `Err` describes what a valid value of the key looks like.
+pub(super) fn parse_config_value(
     value: &str,
-    expected_type: Option<&ExpectedType>,
-    defaults: &Config,
-) -> Option<ConfigValue> {
-    match key {
-        "HttpProxy.Port" => {
-            parse_port_value(&value).or_else(|| fallback_to_default(&key, &defaults))
-        }
-        _ if expected_type == Some(&ExpectedType::Bool) => {
-            parse_bool_value(&value).or_else(|| fallback_to_default(&key, &defaults))
-        }
-        _ if expected_type == Some(&ExpectedType::String) => {
-            parse_string_value(&value).or_else(|| fallback_to_default(&key, &defaults))
-        }
-        _ if expected_type == Some(&ExpectedType::Integer) => {
-            parse_integer_value(&value).or_else(|| fallback_to_default(&key, &defaults))
-        }
-        _ => None,
+    metadata: &KeyMetadata,
+) -> Result<ConfigValue, String> {
+    let config_value = match metadata.expected_type {
+        ExpectedType::Bool => parse_bool_value(value),
+        ExpectedType::String => parse_string_value(value),
+        ExpectedType::Integer => parse_integer_value(value),
+        ExpectedType::Port => parse_port_value(value),
     }
+    .ok_or_else(|| metadata.expected_type.describe().to_string())?;
+
+    metadata.check(&config_value)?;
+    Ok(config_value)
 }
 
 fn parse_bool_value(value: &str) -> Option<ConfigValue> {
@@ -123,6 +212,8 @@ fn fallback_to_default(key: &str, defaults: &Config) -> Option<ConfigValue> {
 #[cfg(test)]
 mod tests {
     use crate::config::types::{Config, ConfigValue};
+    use crate::config::Severity;
+
     #[test]
     fn test_parse_bool() {
         let input = "Extensions.Enabled=y";
@@ -306,4 +397,83 @@ mod tests {
             Some(&ConfigValue::String("μcloud-init".to_string()))
         );
     }
+
+    #[test]
+    fn test_diagnostics_report_line_numbers() {
+        let input = "# comment\n\nExtensions.Enabled=maybe\nFake.Key=1\nOS.EnableFirewall=y";
+        let (_, diagnostics) = Config::parse_with_diagnostics(input);
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].line, 3);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].key, "Extensions.Enabled");
+        assert_eq!(diagnostics[0].value, "maybe");
+        assert_eq!(diagnostics[1].line, 4);
+        assert_eq!(diagnostics[1].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn test_diagnostics_missing_value_and_key() {
+        let input = "ResourceDisk.SwapSizeMB=\n=y\n   # indented comment";
+        let (_, diagnostics) = Config::parse_with_diagnostics(input);
+
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics.iter().all(|d| d.severity == Severity::Warning));
+        assert_eq!(diagnostics[1].key, "");
+        assert_eq!(diagnostics[1].value, "y");
+    }
+
+    #[test]
+    fn test_diagnostics_duplicate_key() {
+        let input = "Logs.Verbose=n\nLogs.Verbose=y";
+        let (_, diagnostics) = Config::parse_with_diagnostics(input);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, 2);
+        assert_eq!(diagnostics[0].reason, "also set on line 1; this line wins");
+    }
+
+    #[test]
+    fn test_valid_file_has_no_diagnostics() {
+        let input = "Lib.Dir=/var/lib/waagent\nHttpProxy.Port=None\nOS.EnableFirewall=y # on";
+        let (_, diagnostics) = Config::parse_with_diagnostics(input);
+
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_parse_value_outside_allowed_values() {
+        let input = "Provisioning.SshHostKeyPairType=dsa2\nDebug.AgentCpuQuota=150";
+        let (parsed, diagnostics) = Config::parse_with_diagnostics(input);
+
+        assert_eq!(
+            parsed.get("Provisioning.SshHostKeyPairType"),
+            Some(&ConfigValue::String("rsa".to_string()))
+        );
+        assert_eq!(
+            parsed.get("Debug.AgentCpuQuota"),
+            Some(&ConfigValue::Integer(50))
+        );
+        assert_eq!(
+            diagnostics[0].reason,
+            "expected one of rsa, dsa, ecdsa, ed25519, auto; the default is used"
+        );
+        assert_eq!(
+            diagnostics[1].reason,
+            "expected a whole number from 1 to 100; the default is used"
+        );
+    }
+
+    #[test]
+    fn test_parse_deprecated_key() {
+        let input = "AutoUpdate.Enabled=n";
+        let (parsed, diagnostics) = Config::parse_with_diagnostics(input);
+
+        assert_eq!(
+            parsed.get("AutoUpdate.Enabled"),
+            Some(&ConfigValue::Bool(false))
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+    }
 }
@@ -1,27 +1,116 @@
-use super::{ExpectedType, HashMap};
-
-macro_rules! load_schema_hashmap {
-    ($hashmap:ident, {
-        BOOL_TYPES: {$($bool_key:literal),* $(,)?},
-        STRING_TYPES: {$($str_key:literal),* $(,)?},
-        INTEGER_TYPES: {$($int_key:literal),* $(,)?},
-        PORT_TYPES: {$($port_key:literal),* $(,)?}
-    }) => {
-        $($hashmap.insert($bool_key.to_string(), ExpectedType::Bool);)*
-        $($hashmap.insert($str_key.to_string(), ExpectedType::String);)*
-        $($hashmap.insert($int_key.to_string(), ExpectedType::Integer);)*
-        $($hashmap.insert($port_key.to_string(), ExpectedType::Port);)*
-    };
+use super::agent::SCHEMA_KEYS;
+use super::{Config, ConfigValue, ExpectedType, HashMap};
+use serde_json::{json, Map, Value};
+
+/// Everything known about one key of `waagent.conf`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyMetadata {
+    pub key: &'static str,
+    pub expected_type: ExpectedType,
+    pub description: &'static str,
+    /// The only values a string key accepts; empty when it accepts any.
+    pub allowed_values: &'static [&'static str],
+    pub min: Option<u32>,
+    pub max: Option<u32>,
+    /// Still read, but superseded by another key.
+    pub deprecated: bool,
+}
+
+impl KeyMetadata {
+    pub(super) const fn new(
+        key: &'static str,
+        expected_type: ExpectedType,
+        description: &'static str,
+    ) -> Self {
+        Self {
+            key,
+            expected_type,
+            description,
+            allowed_values: &[],
+            min: None,
+            max: None,
+            deprecated: false,
+        }
+    }
+
+    /// Checks a value of the right type against the allowed values and the
+    /// range. The error describes what a valid value looks like.
+    pub fn check(&self, value: &ConfigValue) -> Result<(), String> {
+        match value {
+            ConfigValue::String(value)
+                if !self.allowed_values.is_empty()
+                    && !self.allowed_values.contains(&value.as_str()) =>
+            {
+                Err(format!("one of {}", self.allowed_values.join(", ")))
+            }
+            ConfigValue::Integer(value) => {
+                let min = self.min.unwrap_or(u32::MIN);
+                let max = self.max.unwrap_or(u32::MAX);
+                if (min..=max).contains(value) {
+                    Ok(())
+                } else {
+                    Err(format!("a whole number from {} to {}", min, max))
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The JSON Schema of the key's value, with `default` as its default.
+    fn json_schema(&self, default: Option<&ConfigValue>) -> Value {
+        let mut schema = match self.expected_type {
+            ExpectedType::Bool => json!({ "type": "boolean" }),
+            ExpectedType::String => json!({ "type": "string" }),
+            ExpectedType::Integer => json!({
+                "type": "integer",
+                "minimum": self.min.unwrap_or(u32::MIN),
+                "maximum": self.max.unwrap_or(u32::MAX),
+            }),
+            ExpectedType::Port => json!({
+                "type": ["integer", "null"],
+                "minimum": u16::MIN,
+                "maximum": u16::MAX,
+            }),
+        };
+
+        schema["description"] = json!(self.description);
+        if !self.allowed_values.is_empty() {
+            schema["enum"] = json!(self.allowed_values);
+        }
+        if self.deprecated {
+            schema["deprecated"] = json!(true);
+        }
+        if let Some(default) = default {
+            schema["default"] = match default {
+                ConfigValue::Bool(value) => json!(value),
+                ConfigValue::String(value) => json!(value),
+                ConfigValue::Integer(value) => json!(value),
+                ConfigValue::Port(port) => json!(port),
+            };
+        }
+        schema
+    }
 }
 
 pub struct ConfigSchema {
     schema: HashMap<String, ExpectedType>,
+    metadata: HashMap<String, &'static KeyMetadata>,
+}
+
+impl Default for ConfigSchema {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ConfigSchema {
     pub fn new() -> Self {
         Self {
             schema: get_config_schema(),
+            metadata: SCHEMA_KEYS
+                .iter()
+                .map(|metadata| (metadata.key.to_string(), metadata))
+                .collect(),
         }
     }
 
@@ -33,6 +122,15 @@ impl ConfigSchema {
         self.schema.get(key)
     }
 
+    pub fn get_metadata(&self, key: &str) -> Option<&KeyMetadata> {
+        self.metadata.get(key).copied()
+    }
+
+    /// Every key, grouped by section.
+    pub fn keys(&self) -> impl Iterator<Item = &KeyMetadata> {
+        SCHEMA_KEYS.iter()
+    }
+
     pub fn is_valid_key(&self, key: &str) -> bool {
         self.schema.contains_key(key)
     }
@@ -40,105 +138,34 @@ impl ConfigSchema {
     pub fn expected_types(&self) -> std::collections::hash_map::Values<'_, String, ExpectedType> {
         self.schema.values()
     }
-}
 
-#[rustfmt::skip]
-fn get_config_schema() -> HashMap<String, ExpectedType> {
-    let mut schema = HashMap::new();
-    load_schema_hashmap! (schema, {
-        BOOL_TYPES: {
-            "OS.AllowHTTP",
-            "OS.EnableFirewall",
-            "OS.EnableFIPS",
-            "OS.EnableRDMA",
-            "OS.UpdateRdmaDriver",
-            "OS.CheckRdmaDriver",
-            "Logs.Verbose",
-            "Logs.Console",
-            "Logs.Collect",
-            "Extensions.Enabled",
-            "Extensions.WaitForCloudInit",
-            "Provisioning.AllowResetSysUser",
-            "Provisioning.RegenerateSshHostKeyPair",
-            "Provisioning.DeleteRootPassword",
-            "Provisioning.DecodeCustomData",
-            "Provisioning.ExecuteCustomData",
-            "Provisioning.MonitorHostName",
-            "DetectScvmmEnv",
-            "ResourceDisk.Format",
-            "ResourceDisk.EnableSwap",
-            "ResourceDisk.EnableSwapEncryption",
-            "AutoUpdate.Enabled",
-            "AutoUpdate.UpdateToLatestVersion",
-            "EnableOverProvisioning",
-            //
-            // "Debug" options are experimental and may be removed in later
-            // versions of the Agent.
-            //
-            "Debug.CgroupLogMetrics",
-            "Debug.CgroupDisableOnProcessCheckFailure",
-            "Debug.CgroupDisableOnQuotaCheckFailure",
-            "Debug.EnableAgentMemoryUsageCheck",
-            "Debug.EnableFastTrack",
-            "Debug.EnableGAVersioning",
-            "Debug.EnableCgroupV2ResourceLimiting",
-            "Debug.EnableExtensionPolicy",
-        },
-        STRING_TYPES: {
-            "Lib.Dir",
-            "DVD.MountPoint",
-            "Pid.File",
-            "Extension.LogDir",
-            "OS.OpensslPath",
-            "OS.SshDir",
-            "OS.HomeDir",
-            "OS.PasswordPath",
-            "OS.SudoersDir",
-            "OS.RootDeviceScsiTimeout",
-            "Provisioning.Agent",
-            "Provisioning.SshHostKeyPairType",
-            "Provisioning.PasswordCryptId",
-            "HttpProxy.Host",
-            "ResourceDisk.MountPoint",
-            "ResourceDisk.MountOptions",
-            "ResourceDisk.Filesystem",
-            "AutoUpdate.GAFamily",
-            "Policy.PolicyFilePath",
-            "Protocol.EndpointDiscovery",
-        },
-        INTEGER_TYPES: {
-            "Extensions.GoalStatePeriod",
-            "Extensions.InitialGoalStatePeriod",
-            "Extensions.WaitForCloudInitTimeout",
-            "OS.EnableFirewallPeriod",
-            "OS.RemovePersistentNetRulesPeriod",
-            "OS.RootDeviceScsiTimeoutPeriod",
-            "OS.MonitorDhcpClientRestartPeriod",
-            "OS.SshClientAliveInterval",
-            "Provisioning.MonitorHostNamePeriod",
-            "Provisioning.PasswordCryptSaltLength",
-            "ResourceDisk.SwapSizeMB",
-            "Autoupdate.Frequency",
-            "Logs.CollectPeriod",
-            //
-            //  "Debug" options are experimental and may be removed in later
-            //  versions of the Agent.
-            //
-            "Debug.CgroupCheckPeriod",
-            "Debug.AgentCpuQuota",
-            "Debug.AgentCpuThrottledTimeThreshold",
-            "Debug.AgentMemoryQuota",
-            "Debug.EtpCollectionPeriod",
-            "Debug.AutoUpdateHotfixFrequency",
-            "Debug.AutoUpdateNormalFrequency",
-            "Debug.FirewallRulesLogPeriod",
-            "Debug.LogCollectorInitialDelay",
-        },
-        PORT_TYPES: {
-            "HttpProxy.Port",
+    /// The configuration as a JSON Schema object, one property per key, for
+    /// tools that generate or check `waagent.conf`. Booleans are `true` and
+    /// `false` rather than `y` and `n`; an unset port is `null`.
+    pub fn to_json_schema(&self) -> Value {
+        let defaults = Config::default();
+        let properties: Map<String, Value> = self
+            .keys()
+            .map(|metadata| {
+                let schema = metadata.json_schema(defaults.get_value(metadata.key));
+                (metadata.key.to_string(), schema)
+            })
+            .collect();
 
-        }
-        });
+        json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "waagent.conf",
+            "type": "object",
+            "properties": properties,
+            "additionalProperties": false,
+        })
+    }
+}
 
-    schema
+/// Built from the key table behind `AgentConfig`.
+fn get_config_schema() -> HashMap<String, ExpectedType> {
+    SCHEMA_KEYS
+        .iter()
+        .map(|metadata| (metadata.key.to_string(), metadata.expected_type))
+        .collect()
 }
@@ -1,8 +1,18 @@
-use super::{Config, ConfigValue};
+use super::{Config, ConfigSource, ConfigValue};
 use std::collections::BTreeMap;
 
 impl Config {
     pub fn show(self) -> String {
+        self.render(false)
+    }
+
+    /// Like [`Config::show`], with a comment after each value saying where
+    /// it came from: the defaults, a file or an environment variable.
+    pub fn show_origins(self) -> String {
+        self.render(true)
+    }
+
+    fn render(self, with_origins: bool) -> String {
         let merged = Self::merge_with_defaults(self.config().clone());
         let sorted: BTreeMap<_, _> = merged.into_iter().collect();
         let mut output = String::new();
@@ -15,7 +25,19 @@ impl Config {
                 ConfigValue::Port(Some(port)) => port.to_string(),
                 ConfigValue::Port(None) => "None".to_string(),
             };
-            output.push_str(&format!("{} = {}\n", key, value));
+            // Values of a `Config` built from a map have no known origin
+            let origin = match self.origin(&key) {
+                Some(origin) => Some(origin),
+                None if self.config().contains_key(&key) => None,
+                None => Some(&ConfigSource::Default),
+            };
+
+            match origin {
+                Some(origin) if with_origins => {
+                    output.push_str(&format!("{} = {}  # {}\n", key, value, origin))
+                }
+                _ => output.push_str(&format!("{} = {}\n", key, value)),
+            }
         }
 
         output
@@ -52,4 +74,15 @@ mod tests {
         sorted.sort();
         assert_eq!(lines, sorted);
     }
+
+    #[test]
+    fn test_show_origins() {
+        let mut user_config = HashMap::new();
+        user_config.insert("OS.AllowHTTP".to_string(), ConfigValue::Bool(true));
+
+        let output = Config::from_map(user_config).show_origins();
+
+        assert!(output.contains("OS.AllowHTTP = true\n"));
+        assert!(output.contains("OS.EnableFirewall = false  # default\n"));
+    }
 }
@@ -1,4 +1,4 @@
-use super::HashMap;
+use super::{ConfigSource, HashMap};
 use crate::config::defaults::get_config_defaults;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -9,7 +9,7 @@ pub enum ConfigValue {
     Port(Option<u16>), //u16 because ports 2^16 = 0-65535
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ExpectedType {
     Bool,
     Integer,
@@ -17,8 +17,22 @@ pub enum ExpectedType {
     Port,
 }
 
+impl ExpectedType {
+    /// What a valid value looks like, for messages about invalid ones.
+    pub fn describe(&self) -> &'static str {
+        match self {
+            ExpectedType::Bool => "y or n",
+            ExpectedType::Integer => "a non-negative whole number",
+            ExpectedType::String => "a non-empty string",
+            ExpectedType::Port => "a port number or None",
+        }
+    }
+}
+
 pub struct Config {
     pub config: HashMap<String, ConfigValue>,
+    /// Where each value came from; empty for a `Config` built from a map.
+    pub(super) origins: HashMap<String, ConfigSource>,
 }
 
 impl Config {
@@ -34,8 +48,41 @@ impl Config {
         self.config.get(key)
     }
 
+    pub fn origin(&self, key: &str) -> Option<&ConfigSource> {
+        self.origins.get(key)
+    }
+
+    pub(super) fn set(&mut self, key: String, value: ConfigValue, source: ConfigSource) {
+        self.origins.insert(key.clone(), source);
+        self.config.insert(key, value);
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.config.get(key) {
+            Some(ConfigValue::Bool(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_integer(&self, key: &str) -> Option<u32> {
+        match self.config.get(key) {
+            Some(ConfigValue::Integer(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_string(&self, key: &str) -> Option<&str> {
+        match self.config.get(key) {
+            Some(ConfigValue::String(value)) => Some(value),
+            _ => None,
+        }
+    }
+
     pub fn from_map(hashmap: HashMap<String, ConfigValue>) -> Self {
-        Self { config: hashmap }
+        Self {
+            config: hashmap,
+            origins: HashMap::new(),
+        }
     }
 
     #[rustfmt::skip]
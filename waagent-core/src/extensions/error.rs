@@ -0,0 +1,85 @@
+use crate::protocol::ProtocolError;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum ExtensionError {
+    /// Reading or writing handler files failed.
+    Io(io::Error),
+    /// The manifest or the handler package could not be downloaded.
+    Download(ProtocolError),
+    /// The handler package is not a valid zip archive.
+    Package(zip::result::ZipError),
+    /// A manifest or settings document is missing or invalid.
+    Manifest(String),
+    /// An extension wrote a status file that does not match the expected schema.
+    InvalidStatus { path: PathBuf, reason: String },
+    /// The goal state requested a state the agent does not know about.
+    UnknownState(String),
+    /// A handler command exited with a non-zero code.
+    CommandFailed {
+        command: String,
+        exit_code: Option<i32>,
+        output: String,
+    },
+    /// A handler command did not finish in time and was killed.
+    Timeout { command: String },
+}
+
+impl fmt::Display for ExtensionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtensionError::Io(e) => write!(f, "I/O error: {}", e),
+            ExtensionError::Download(e) => write!(f, "download failed: {}", e),
+            ExtensionError::Package(e) => write!(f, "invalid handler package: {}", e),
+            ExtensionError::Manifest(reason) => write!(f, "invalid manifest: {}", reason),
+            ExtensionError::InvalidStatus { path, reason } => {
+                write!(f, "invalid status file {}: {}", path.display(), reason)
+            }
+            ExtensionError::UnknownState(state) => {
+                write!(f, "unknown requested handler state '{}'", state)
+            }
+            ExtensionError::CommandFailed {
+                command,
+                exit_code,
+                output,
+            } => write!(
+                f,
+                "'{}' failed with exit code {:?}: {}",
+                command, exit_code, output
+            ),
+            ExtensionError::Timeout { command } => write!(f, "'{}' timed out", command),
+        }
+    }
+}
+
+impl Error for ExtensionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ExtensionError::Io(e) => Some(e),
+            ExtensionError::Download(e) => Some(e),
+            ExtensionError::Package(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ExtensionError {
+    fn from(e: io::Error) -> Self {
+        ExtensionError::Io(e)
+    }
+}
+
+impl From<ProtocolError> for ExtensionError {
+    fn from(e: ProtocolError) -> Self {
+        ExtensionError::Download(e)
+    }
+}
+
+impl From<zip::result::ZipError> for ExtensionError {
+    fn from(e: zip::result::ZipError) -> Self {
+        ExtensionError::Package(e)
+    }
+}
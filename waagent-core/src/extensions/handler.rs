@@ -0,0 +1,339 @@
+use super::{ExtensionError, HandlerManifest};
+use serde_json::json;
+use std::fmt;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::{debug, info};
+
+const HANDLER_MANIFEST_FILE: &str = "HandlerManifest.json";
+const HANDLER_ENVIRONMENT_FILE: &str = "HandlerEnvironment.json";
+const HANDLER_STATE_FILE: &str = "HandlerState";
+const HEARTBEAT_FILE: &str = "heartbeat.log";
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(300);
+/// Only the tail of a command's output is kept for logs and status.
+const MAX_OUTPUT_LEN: usize = 4096;
+
+/// Lifecycle state of an installed handler, persisted in `config/HandlerState`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandlerState {
+    NotInstalled,
+    Installed,
+    Enabled,
+    Disabled,
+}
+
+impl HandlerState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            HandlerState::NotInstalled => "NotInstalled",
+            HandlerState::Installed => "Installed",
+            HandlerState::Enabled => "Enabled",
+            HandlerState::Disabled => "Disabled",
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        match value.trim() {
+            "Installed" => HandlerState::Installed,
+            "Enabled" => HandlerState::Enabled,
+            "Disabled" => HandlerState::Disabled,
+            _ => HandlerState::NotInstalled,
+        }
+    }
+}
+
+impl fmt::Display for HandlerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The commands a handler declares in its manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandlerCommand {
+    Install,
+    Enable,
+    Disable,
+    Uninstall,
+    Update,
+}
+
+impl HandlerCommand {
+    fn command_line(self, manifest: &HandlerManifest) -> &str {
+        match self {
+            HandlerCommand::Install => &manifest.install_command,
+            HandlerCommand::Enable => &manifest.enable_command,
+            HandlerCommand::Disable => &manifest.disable_command,
+            HandlerCommand::Uninstall => &manifest.uninstall_command,
+            HandlerCommand::Update => &manifest.update_command,
+        }
+    }
+}
+
+impl fmt::Display for HandlerCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            HandlerCommand::Install => "install",
+            HandlerCommand::Enable => "enable",
+            HandlerCommand::Disable => "disable",
+            HandlerCommand::Uninstall => "uninstall",
+            HandlerCommand::Update => "update",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CommandResult {
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// One version of an extension handler on disk. Packages are unpacked into
+/// `<lib_dir>/<name>-<version>`, and the handler logs to `<log_dir>/<name>`.
+#[derive(Debug, Clone)]
+pub struct ExtensionHandler {
+    name: String,
+    version: String,
+    dir: PathBuf,
+    log_dir: PathBuf,
+}
+
+impl ExtensionHandler {
+    pub fn new(lib_dir: &Path, log_dir: &Path, name: &str, version: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            version: version.to_string(),
+            dir: lib_dir.join(format!("{}-{}", name, version)),
+            log_dir: log_dir.join(name),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn config_dir(&self) -> PathBuf {
+        self.dir.join("config")
+    }
+
+    pub fn status_dir(&self) -> PathBuf {
+        self.dir.join("status")
+    }
+
+    pub fn settings_file(&self, seq_no: u32) -> PathBuf {
+        self.config_dir().join(format!("{}.settings", seq_no))
+    }
+
+    pub fn status_file(&self, seq_no: u32) -> PathBuf {
+        self.status_dir().join(format!("{}.status", seq_no))
+    }
+
+    pub fn is_installed(&self) -> bool {
+        self.dir.join(HANDLER_MANIFEST_FILE).is_file()
+    }
+
+    pub fn manifest(&self) -> Result<HandlerManifest, ExtensionError> {
+        HandlerManifest::from_file(&self.dir.join(HANDLER_MANIFEST_FILE))
+    }
+
+    /// Extracts a handler package (zip) into the handler directory and makes
+    /// its files executable by the owner, as handlers ship their scripts
+    /// without relying on the archive's permission bits.
+    pub fn unpack(&self, package: &[u8]) -> Result<(), ExtensionError> {
+        fs::create_dir_all(&self.dir)?;
+        zip::ZipArchive::new(Cursor::new(package))?.extract(&self.dir)?;
+        if !self.is_installed() {
+            return Err(ExtensionError::Manifest(format!(
+                "package for {} {} has no {}",
+                self.name, self.version, HANDLER_MANIFEST_FILE
+            )));
+        }
+
+        #[cfg(unix)]
+        add_owner_execute(&self.dir)?;
+
+        fs::create_dir_all(self.config_dir())?;
+        fs::create_dir_all(self.status_dir())?;
+        debug!(
+            "Unpacked {} {} into {:?}",
+            self.name, self.version, self.dir
+        );
+        Ok(())
+    }
+
+    /// Writes `HandlerEnvironment.json`, which tells the handler where its
+    /// log, config and status folders are.
+    pub fn write_handler_environment(&self) -> Result<(), ExtensionError> {
+        fs::create_dir_all(&self.log_dir)?;
+        let environment = json!([{
+            "name": self.name,
+            "version": 1.0,
+            "handlerEnvironment": {
+                "logFolder": self.log_dir,
+                "configFolder": self.config_dir(),
+                "statusFolder": self.status_dir(),
+                "heartbeatFile": self.dir.join(HEARTBEAT_FILE),
+            }
+        }]);
+        let contents = serde_json::to_string(&environment)
+            .map_err(|e| ExtensionError::Manifest(e.to_string()))?;
+        fs::write(self.dir.join(HANDLER_ENVIRONMENT_FILE), contents)?;
+        Ok(())
+    }
+
+    /// Writes the runtime settings the host sent for `seq_no` to `config/<seq_no>.settings`.
+    pub fn write_settings(&self, seq_no: u32, settings: &str) -> Result<(), ExtensionError> {
+        fs::create_dir_all(self.config_dir())?;
+        fs::write(self.settings_file(seq_no), settings)?;
+        Ok(())
+    }
+
+    pub fn state(&self) -> HandlerState {
+        fs::read_to_string(self.config_dir().join(HANDLER_STATE_FILE))
+            .map(|s| HandlerState::parse(&s))
+            .unwrap_or(HandlerState::NotInstalled)
+    }
+
+    pub fn set_state(&self, state: HandlerState) -> Result<(), ExtensionError> {
+        fs::create_dir_all(self.config_dir())?;
+        fs::write(self.config_dir().join(HANDLER_STATE_FILE), state.as_str())?;
+        Ok(())
+    }
+
+    /// Deletes the handler directory. Logs are left in place.
+    pub fn remove(&self) -> Result<(), ExtensionError> {
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(())
+    }
+
+    /// Runs one of the manifest commands from the handler directory with the
+    /// environment variables handlers expect. `extra_env` is added on top,
+    /// e.g. the previous version during an update. The command runs with the
+    /// agent's privileges, which handlers expect to be root's.
+    pub async fn run(
+        &self,
+        command: HandlerCommand,
+        seq_no: u32,
+        wire_endpoint: &str,
+        extra_env: &[(&str, &str)],
+    ) -> Result<CommandResult, ExtensionError> {
+        let manifest = self.manifest()?;
+        let command_line = self
+            .dir
+            .join(command.command_line(&manifest))
+            .to_string_lossy()
+            .to_string();
+        let label = format!("{} {} {}", self.name, self.version, command);
+        info!("Running {}: {}", label, command_line);
+
+        let mut process = shell(&command_line);
+        process
+            .current_dir(&self.dir)
+            .env("ConfigSequenceNumber", seq_no.to_string())
+            .env("AZURE_GUEST_AGENT_EXTENSION_PATH", &self.dir)
+            .env("AZURE_GUEST_AGENT_EXTENSION_VERSION", &self.version)
+            .env("AZURE_GUEST_AGENT_WIRE_PROTOCOL_ADDRESS", wire_endpoint)
+            .envs(extra_env.iter().copied())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        let output = match tokio::time::timeout(COMMAND_TIMEOUT, process.output()).await {
+            Ok(output) => output?,
+            Err(_) => return Err(ExtensionError::Timeout { command: label }),
+        };
+        let result = CommandResult {
+            exit_code: output.status.code(),
+            stdout: tail(&output.stdout),
+            stderr: tail(&output.stderr),
+        };
+        debug!("{} exited with {:?}", label, result.exit_code);
+
+        if !output.status.success() {
+            return Err(ExtensionError::CommandFailed {
+                command: label,
+                exit_code: result.exit_code,
+                output: format!("stdout: {} stderr: {}", result.stdout, result.stderr),
+            });
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(unix)]
+fn shell(command_line: &str) -> Command {
+    let mut command = Command::new("/bin/sh");
+    command.arg("-c").arg(command_line);
+    command
+}
+
+#[cfg(not(unix))]
+fn shell(command_line: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(command_line);
+    command
+}
+
+#[cfg(unix)]
+fn add_owner_execute(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            add_owner_execute(&path)?;
+        } else {
+            let mut permissions = fs::metadata(&path)?.permissions();
+            permissions.set_mode(permissions.mode() | 0o100);
+            fs::set_permissions(&path, permissions)?;
+        }
+    }
+    Ok(())
+}
+
+fn tail(output: &[u8]) -> String {
+    let start = output.len().saturating_sub(MAX_OUTPUT_LEN);
+    String::from_utf8_lossy(&output[start..]).trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handler_state_round_trip() {
+        for state in [
+            HandlerState::NotInstalled,
+            HandlerState::Installed,
+            HandlerState::Enabled,
+            HandlerState::Disabled,
+        ] {
+            assert_eq!(HandlerState::parse(state.as_str()), state);
+        }
+        assert_eq!(HandlerState::parse("garbage"), HandlerState::NotInstalled);
+    }
+
+    #[test]
+    fn test_tail_keeps_end_of_output() {
+        let output = vec![b'a'; MAX_OUTPUT_LEN + 10];
+        assert_eq!(tail(&output).len(), MAX_OUTPUT_LEN);
+        assert_eq!(tail(b"  done\n"), "done");
+    }
+}
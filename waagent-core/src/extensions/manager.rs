@@ -0,0 +1,333 @@
+use super::{
+    handler_status, ExtensionError, ExtensionHandler, HandlerCommand, HandlerState,
+    PluginVersionManifest,
+};
+use crate::config::{AgentConfig, Config};
+use crate::protocol::{ExtensionsConfig, HandlerStatus, Plugin, WireServerClient};
+use quick_xml::de::from_str;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+/// What happened to one plugin of the ExtensionsConfig.
+#[derive(Debug)]
+pub struct HandlerOutcome {
+    pub name: String,
+    pub version: String,
+    /// Sequence number of the settings the handler was run with, if any.
+    pub seq_no: Option<u32>,
+    /// State of the handler after processing.
+    pub state: HandlerState,
+    pub result: Result<(), ExtensionError>,
+}
+
+/// Brings the handlers on disk in line with the plugins listed in the goal
+/// state: downloads and installs new handlers, updates to new versions and
+/// runs enable, disable or uninstall as requested.
+pub struct ExtensionsManager {
+    client: WireServerClient,
+    lib_dir: PathBuf,
+    log_dir: PathBuf,
+}
+
+impl ExtensionsManager {
+    pub fn new(client: WireServerClient, lib_dir: &Path, log_dir: &Path) -> Self {
+        Self {
+            client,
+            lib_dir: lib_dir.to_path_buf(),
+            log_dir: log_dir.to_path_buf(),
+        }
+    }
+
+    /// Uses `Lib.Dir` for handler packages and `Extension.LogDir` for their logs.
+    pub fn from_config(config: &Config, client: WireServerClient) -> Self {
+        let config = AgentConfig::from(config);
+        Self::new(client, &config.lib.dir, &config.extensions.log_dir)
+    }
+
+    pub fn handler(&self, name: &str, version: &str) -> ExtensionHandler {
+        ExtensionHandler::new(&self.lib_dir, &self.log_dir, name, version)
+    }
+
+    /// Processes every plugin in order. A failing plugin does not stop the others.
+    pub async fn process(&self, extensions_config: &ExtensionsConfig) -> Vec<HandlerOutcome> {
+        let mut outcomes = Vec::new();
+        for plugin in extensions_config.plugins() {
+            let runtime_settings = extensions_config
+                .settings_for(&plugin.name, &plugin.version)
+                .and_then(|s| s.runtime_settings.as_ref());
+            let seq_no = runtime_settings.map(|s| s.seq_no);
+            let settings = runtime_settings.map(|s| s.settings.as_str());
+            let handler = self.handler(&plugin.name, &plugin.version);
+
+            let result = self
+                .process_plugin(&handler, plugin, seq_no, settings)
+                .await;
+            if let Err(e) = &result {
+                warn!(
+                    "Failed to process {} {}: {}",
+                    plugin.name, plugin.version, e
+                );
+            }
+            outcomes.push(HandlerOutcome {
+                name: plugin.name.clone(),
+                version: plugin.version.clone(),
+                seq_no,
+                state: handler.state(),
+                result,
+            });
+        }
+        outcomes
+    }
+
+    /// Collects the handler status to report for the outcomes of the last
+    /// `process` call, reading each extension's current status file.
+    pub fn collect_status(&self, outcomes: &[HandlerOutcome]) -> Vec<HandlerStatus> {
+        outcomes
+            .iter()
+            .filter_map(|outcome| {
+                handler_status(&self.handler(&outcome.name, &outcome.version), outcome)
+            })
+            .collect()
+    }
+
+    async fn process_plugin(
+        &self,
+        handler: &ExtensionHandler,
+        plugin: &Plugin,
+        seq_no: Option<u32>,
+        settings: Option<&str>,
+    ) -> Result<(), ExtensionError> {
+        let requested = plugin.state.as_deref().unwrap_or("enabled");
+        match requested {
+            "enabled" => self.enable(handler, plugin, seq_no, settings).await,
+            "disabled" => self.disable(handler, seq_no, settings).await,
+            "uninstall" => self.uninstall(handler, seq_no.unwrap_or(0)).await,
+            other => Err(ExtensionError::UnknownState(other.to_string())),
+        }
+    }
+
+    async fn enable(
+        &self,
+        handler: &ExtensionHandler,
+        plugin: &Plugin,
+        seq_no: Option<u32>,
+        settings: Option<&str>,
+    ) -> Result<(), ExtensionError> {
+        if !handler.is_installed() {
+            self.download(handler, plugin).await?;
+        }
+        handler.write_handler_environment()?;
+
+        let seq_no = seq_no.unwrap_or(0);
+        if handler.state() == HandlerState::Enabled && handler.settings_file(seq_no).exists() {
+            debug!(
+                "{} {} already enabled with sequence number {}",
+                handler.name(),
+                handler.version(),
+                seq_no
+            );
+            return Ok(());
+        }
+        handler.write_settings(seq_no, settings.unwrap_or_default())?;
+
+        if handler.state() == HandlerState::NotInstalled {
+            let previous = self
+                .installed_versions(handler.name())?
+                .into_iter()
+                .find(|h| h.version() != handler.version());
+            match previous {
+                Some(previous) => self.update(&previous, handler, seq_no).await?,
+                None => {
+                    self.run(handler, HandlerCommand::Install, seq_no, &[])
+                        .await?;
+                }
+            }
+            handler.set_state(HandlerState::Installed)?;
+        }
+
+        self.run(handler, HandlerCommand::Enable, seq_no, &[])
+            .await?;
+        handler.set_state(HandlerState::Enabled)
+    }
+
+    async fn disable(
+        &self,
+        handler: &ExtensionHandler,
+        seq_no: Option<u32>,
+        settings: Option<&str>,
+    ) -> Result<(), ExtensionError> {
+        if !handler.is_installed() {
+            debug!(
+                "{} {} is not installed, nothing to disable",
+                handler.name(),
+                handler.version()
+            );
+            return Ok(());
+        }
+
+        let seq_no = seq_no.unwrap_or(0);
+        handler.write_settings(seq_no, settings.unwrap_or_default())?;
+        if handler.state() == HandlerState::Enabled {
+            self.run(handler, HandlerCommand::Disable, seq_no, &[])
+                .await?;
+            handler.set_state(HandlerState::Disabled)?;
+        }
+        Ok(())
+    }
+
+    async fn uninstall(
+        &self,
+        handler: &ExtensionHandler,
+        seq_no: u32,
+    ) -> Result<(), ExtensionError> {
+        if !handler.is_installed() {
+            return Ok(());
+        }
+
+        if handler.state() == HandlerState::Enabled {
+            self.run(handler, HandlerCommand::Disable, seq_no, &[])
+                .await?;
+            handler.set_state(HandlerState::Disabled)?;
+        }
+        self.run(handler, HandlerCommand::Uninstall, seq_no, &[])
+            .await?;
+        handler.remove()
+    }
+
+    /// Replaces `previous` with `handler`: the old version is disabled, the new
+    /// one runs its update command, then the old version is uninstalled and removed.
+    async fn update(
+        &self,
+        previous: &ExtensionHandler,
+        handler: &ExtensionHandler,
+        seq_no: u32,
+    ) -> Result<(), ExtensionError> {
+        info!(
+            "Updating {} from {} to {}",
+            handler.name(),
+            previous.version(),
+            handler.version()
+        );
+
+        if previous.state() == HandlerState::Enabled {
+            self.run(previous, HandlerCommand::Disable, seq_no, &[])
+                .await?;
+            previous.set_state(HandlerState::Disabled)?;
+        }
+
+        let env = [("VERSION", previous.version())];
+        self.run(handler, HandlerCommand::Update, seq_no, &env)
+            .await?;
+
+        if let Err(e) = self
+            .run(previous, HandlerCommand::Uninstall, seq_no, &env)
+            .await
+        {
+            warn!(
+                "Uninstall of {} {} failed during update: {}",
+                previous.name(),
+                previous.version(),
+                e
+            );
+        }
+        previous.remove()?;
+
+        if handler.manifest()?.update_with_install() {
+            self.run(handler, HandlerCommand::Install, seq_no, &[])
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn run(
+        &self,
+        handler: &ExtensionHandler,
+        command: HandlerCommand,
+        seq_no: u32,
+        extra_env: &[(&str, &str)],
+    ) -> Result<(), ExtensionError> {
+        handler
+            .run(command, seq_no, self.client.endpoint(), extra_env)
+            .await
+            .map(|_| ())
+    }
+
+    /// Downloads the plugin manifest from the plugin location (or its failover
+    /// location) and unpacks the first package that can be downloaded.
+    async fn download(
+        &self,
+        handler: &ExtensionHandler,
+        plugin: &Plugin,
+    ) -> Result<(), ExtensionError> {
+        let locations = [
+            plugin.location.as_deref(),
+            plugin.failover_location.as_deref(),
+        ];
+        let mut last_error = None;
+
+        for location in locations.into_iter().flatten() {
+            let manifest = match self.fetch_manifest(location).await {
+                Ok(manifest) => manifest,
+                Err(e) => {
+                    warn!("Failed to fetch manifest from {}: {}", location, e);
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+
+            for uri in manifest.package_uris(&plugin.version) {
+                info!(
+                    "Downloading {} {} from {}",
+                    plugin.name, plugin.version, uri
+                );
+                match self.client.download(uri).await {
+                    Ok(package) => return handler.unpack(&package),
+                    Err(e) => {
+                        warn!("Failed to download {}: {}", uri, e);
+                        last_error = Some(e.into());
+                    }
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            ExtensionError::Manifest(format!(
+                "no package published for {} {}",
+                plugin.name, plugin.version
+            ))
+        }))
+    }
+
+    async fn fetch_manifest(
+        &self,
+        location: &str,
+    ) -> Result<PluginVersionManifest, ExtensionError> {
+        let bytes = self.client.download(location).await?;
+        from_str(&String::from_utf8_lossy(&bytes))
+            .map_err(|e| ExtensionError::Manifest(format!("{}: {}", location, e)))
+    }
+
+    /// Installed versions of the handler called `name`.
+    fn installed_versions(&self, name: &str) -> Result<Vec<ExtensionHandler>, ExtensionError> {
+        if !self.lib_dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let prefix = format!("{}-", name);
+        let mut handlers = Vec::new();
+        for entry in fs::read_dir(&self.lib_dir)? {
+            let file_name = entry?.file_name().to_string_lossy().to_string();
+            let version = match file_name.strip_prefix(&prefix) {
+                // Versions start with a digit, which keeps "Foo" from matching "Foo-Bar-1.0".
+                Some(version) if version.starts_with(|c: char| c.is_ascii_digit()) => version,
+                _ => continue,
+            };
+            let handler = self.handler(name, version);
+            if handler.is_installed() {
+                handlers.push(handler);
+            }
+        }
+        Ok(handlers)
+    }
+}
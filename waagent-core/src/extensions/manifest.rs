@@ -0,0 +1,163 @@
+use super::ExtensionError;
+use crate::protocol::Uris;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// The plugin manifest referenced by a plugin's `location`: the package URIs
+/// for every published version of the handler.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct PluginVersionManifest {
+    #[serde(rename = "Plugins", default)]
+    pub plugins: PluginVersions,
+    #[serde(rename = "InternalPlugins", default)]
+    pub internal_plugins: PluginVersions,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct PluginVersions {
+    #[serde(rename = "Plugin", default)]
+    pub plugins: Vec<PluginVersion>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PluginVersion {
+    #[serde(rename = "Version")]
+    pub version: String,
+    #[serde(rename = "Uris", default)]
+    pub uris: Uris,
+}
+
+impl PluginVersionManifest {
+    /// Package URIs published for exactly `version`, in the order they should be tried.
+    pub fn package_uris(&self, version: &str) -> Vec<&str> {
+        self.plugins
+            .plugins
+            .iter()
+            .chain(&self.internal_plugins.plugins)
+            .filter(|p| p.version == version)
+            .flat_map(|p| p.uris.uris.iter().map(String::as_str))
+            .collect()
+    }
+}
+
+/// The `handlerManifest` section of a handler's `HandlerManifest.json`. Commands
+/// are relative to the handler directory.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HandlerManifest {
+    pub install_command: String,
+    pub uninstall_command: String,
+    pub update_command: String,
+    pub enable_command: String,
+    pub disable_command: String,
+    #[serde(default)]
+    pub reboot_after_install: bool,
+    #[serde(default)]
+    pub report_heartbeat: bool,
+    #[serde(default)]
+    pub update_mode: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HandlerManifestEntry {
+    handler_manifest: HandlerManifest,
+}
+
+impl HandlerManifest {
+    pub fn from_json(json: &str) -> Result<Self, ExtensionError> {
+        let entries: Vec<HandlerManifestEntry> = serde_json::from_str(json)
+            .map_err(|e| ExtensionError::Manifest(format!("HandlerManifest.json: {}", e)))?;
+        entries
+            .into_iter()
+            .next()
+            .map(|entry| entry.handler_manifest)
+            .ok_or_else(|| ExtensionError::Manifest("HandlerManifest.json is empty".to_string()))
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ExtensionError> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// Whether the install command has to run again after an update.
+    pub fn update_with_install(&self) -> bool {
+        self.update_mode.as_deref() == Some("UpdateWithInstall")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quick_xml::de::from_str;
+
+    #[test]
+    fn test_deserialize_plugin_version_manifest() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<PluginVersionManifest xmlns:i="http://www.w3.org/2001/XMLSchema-instance">
+  <Plugins>
+    <Plugin>
+      <Version>2.1.9</Version>
+      <Uris><Uri>https://host/CustomScript__2.1.9</Uri></Uris>
+    </Plugin>
+    <Plugin>
+      <Version>2.1.10</Version>
+      <Uris>
+        <Uri>https://host1/CustomScript__2.1.10</Uri>
+        <Uri>https://host2/CustomScript__2.1.10</Uri>
+      </Uris>
+    </Plugin>
+  </Plugins>
+  <InternalPlugins>
+    <Plugin>
+      <Version>2.1.10</Version>
+      <Uris><Uri>https://internal/CustomScript__2.1.10</Uri></Uris>
+    </Plugin>
+  </InternalPlugins>
+</PluginVersionManifest>"#;
+
+        let manifest: PluginVersionManifest = from_str(xml).unwrap();
+
+        assert_eq!(
+            manifest.package_uris("2.1.10"),
+            vec![
+                "https://host1/CustomScript__2.1.10",
+                "https://host2/CustomScript__2.1.10",
+                "https://internal/CustomScript__2.1.10",
+            ]
+        );
+        assert!(manifest.package_uris("3.0").is_empty());
+    }
+
+    #[test]
+    fn test_parse_handler_manifest() {
+        let json = r#"[{
+  "name": "CustomScript",
+  "version": 1.0,
+  "handlerManifest": {
+    "installCommand": "bin/custom-script-shim install",
+    "uninstallCommand": "bin/custom-script-shim uninstall",
+    "updateCommand": "bin/custom-script-shim update",
+    "enableCommand": "bin/custom-script-shim enable",
+    "disableCommand": "bin/custom-script-shim disable",
+    "rebootAfterInstall": false,
+    "reportHeartbeat": false,
+    "updateMode": "UpdateWithInstall"
+  }
+}]"#;
+
+        let manifest = HandlerManifest::from_json(json).unwrap();
+
+        assert_eq!(manifest.enable_command, "bin/custom-script-shim enable");
+        assert!(!manifest.reboot_after_install);
+        assert!(manifest.update_with_install());
+    }
+
+    #[test]
+    fn test_parse_handler_manifest_rejects_empty_list() {
+        assert!(matches!(
+            HandlerManifest::from_json("[]"),
+            Err(ExtensionError::Manifest(_))
+        ));
+    }
+}
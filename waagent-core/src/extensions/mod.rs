@@ -0,0 +1,11 @@
+mod error;
+mod handler;
+mod manager;
+mod manifest;
+mod status;
+
+pub use error::ExtensionError;
+pub use handler::{CommandResult, ExtensionHandler, HandlerCommand, HandlerState};
+pub use manager::{ExtensionsManager, HandlerOutcome};
+pub use manifest::{HandlerManifest, PluginVersion, PluginVersionManifest, PluginVersions};
+pub use status::{handler_status, read_status_file, INVALID_STATUS_CODE, PROCESSING_ERROR_CODE};
@@ -0,0 +1,202 @@
+use super::{ExtensionError, ExtensionHandler, HandlerOutcome, HandlerState};
+use crate::protocol::{
+    ExtensionStatus, FormattedMessage, HandlerStatus, RuntimeSettingsStatus, SettingsStatus,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use tracing::warn;
+
+/// Values an extension may use for `status` and `substatus[].status`.
+const VALID_STATUSES: &[&str] = &["transitioning", "error", "success", "warning"];
+
+/// Code reported for a handler whose download or commands failed.
+pub const PROCESSING_ERROR_CODE: i32 = 1000;
+/// Code reported when an extension wrote a status file that does not match the schema.
+pub const INVALID_STATUS_CODE: i32 = 1009;
+
+#[derive(Debug, Deserialize)]
+struct StatusFileEntry {
+    #[serde(rename = "timestampUTC", default)]
+    timestamp_utc: Option<String>,
+    status: ExtensionStatus,
+}
+
+/// Reads and validates a `status/<seq>.status` file. Returns `None` when the
+/// extension has not written it yet.
+pub fn read_status_file(path: &Path) -> Result<Option<SettingsStatus>, ExtensionError> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let invalid = |reason: String| ExtensionError::InvalidStatus {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let contents = fs::read_to_string(path)?;
+    let entries: Vec<StatusFileEntry> =
+        serde_json::from_str(&contents).map_err(|e| invalid(e.to_string()))?;
+    let entry = entries
+        .into_iter()
+        .next()
+        .ok_or_else(|| invalid("status file is an empty list".to_string()))?;
+    validate(&entry.status).map_err(invalid)?;
+
+    Ok(Some(SettingsStatus {
+        status: entry.status,
+        timestamp_utc: entry.timestamp_utc.unwrap_or_else(timestamp),
+    }))
+}
+
+fn validate(status: &ExtensionStatus) -> Result<(), String> {
+    if !VALID_STATUSES.contains(&status.status.as_str()) {
+        return Err(format!("invalid status '{}'", status.status));
+    }
+    for substatus in &status.substatus {
+        if !VALID_STATUSES.contains(&substatus.status.as_str()) {
+            return Err(format!(
+                "invalid substatus '{}' for {:?}",
+                substatus.status, substatus.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Builds the status reported for one processed plugin. Handlers that were
+/// uninstalled are not reported.
+pub fn handler_status(
+    handler: &ExtensionHandler,
+    outcome: &HandlerOutcome,
+) -> Option<HandlerStatus> {
+    let (status, code, message) = match (&outcome.result, outcome.state) {
+        (Err(e), _) => ("NotReady", PROCESSING_ERROR_CODE, e.to_string()),
+        (Ok(()), HandlerState::NotInstalled) => return None,
+        (Ok(()), HandlerState::Enabled) => ("Ready", 0, "Plugin enabled".to_string()),
+        (Ok(()), HandlerState::Installed) => (
+            "NotReady",
+            0,
+            "Plugin installed but not enabled".to_string(),
+        ),
+        (Ok(()), HandlerState::Disabled) => ("NotReady", 0, "Plugin disabled".to_string()),
+    };
+
+    let runtime_settings_status = outcome.seq_no.and_then(|seq_no| {
+        let settings_status = match read_status_file(&handler.status_file(seq_no)) {
+            Ok(settings_status) => settings_status?,
+            Err(e) => {
+                warn!("{}", e);
+                invalid_status(&e)
+            }
+        };
+        Some(RuntimeSettingsStatus {
+            settings_status,
+            sequence_number: seq_no,
+        })
+    });
+
+    Some(HandlerStatus {
+        handler_name: outcome.name.clone(),
+        handler_version: outcome.version.clone(),
+        status: status.to_string(),
+        code,
+        formatted_message: Some(FormattedMessage::new(message)),
+        runtime_settings_status,
+    })
+}
+
+fn invalid_status(error: &ExtensionError) -> SettingsStatus {
+    SettingsStatus {
+        status: ExtensionStatus {
+            name: None,
+            operation: None,
+            configuration_applied_time: None,
+            status: "error".to_string(),
+            code: INVALID_STATUS_CODE,
+            formatted_message: Some(FormattedMessage::new(error.to_string())),
+            substatus: Vec::new(),
+        },
+        timestamp_utc: timestamp(),
+    }
+}
+
+fn timestamp() -> String {
+    Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write_status(dir: &TempDir, contents: &str) -> std::path::PathBuf {
+        let path = dir.path().join("0.status");
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_read_valid_status_file() {
+        let dir = TempDir::new().unwrap();
+        let path = write_status(
+            &dir,
+            r#"[{
+  "version": 1.0,
+  "timestampUTC": "2024-05-01T10:00:00Z",
+  "status": {
+    "name": "Microsoft.Azure.Extensions.CustomScript",
+    "operation": "Enable",
+    "status": "success",
+    "code": 0,
+    "formattedMessage": {"lang": "en-US", "message": "Enable succeeded"},
+    "substatus": [
+      {"name": "StdOut", "status": "success", "code": 0, "formattedMessage": {"lang": "en-US", "message": "hi"}}
+    ]
+  }
+}]"#,
+        );
+
+        let settings_status = read_status_file(&path).unwrap().unwrap();
+
+        assert_eq!(settings_status.timestamp_utc, "2024-05-01T10:00:00Z");
+        assert_eq!(settings_status.status.status, "success");
+        assert_eq!(settings_status.status.operation.as_deref(), Some("Enable"));
+        assert_eq!(settings_status.status.substatus.len(), 1);
+        assert_eq!(
+            settings_status.status.substatus[0].formatted_message,
+            Some(FormattedMessage::new("hi"))
+        );
+    }
+
+    #[test]
+    fn test_missing_status_file_is_not_an_error() {
+        let dir = TempDir::new().unwrap();
+        assert!(read_status_file(&dir.path().join("0.status"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_malformed_status_files_are_rejected() {
+        let dir = TempDir::new().unwrap();
+        for contents in [
+            "not json",
+            "[]",
+            r#"{"status": {"status": "success"}}"#,
+            r#"[{"status": {"code": 0}}]"#,
+            r#"[{"status": {"status": "done", "code": 0}}]"#,
+            r#"[{"status": {"status": "success", "substatus": [{"status": "ok"}]}}]"#,
+        ] {
+            let path = write_status(&dir, contents);
+            assert!(
+                matches!(
+                    read_status_file(&path),
+                    Err(ExtensionError::InvalidStatus { .. })
+                ),
+                "accepted {}",
+                contents
+            );
+        }
+    }
+}
@@ -1,4 +1,11 @@
+pub mod certificates;
 pub mod config;
+pub mod extensions;
 pub mod network;
+#[cfg(unix)]
+pub mod privileged;
+pub mod protocol;
+pub mod provisioning;
+pub mod resource_disk;
 pub mod system;
 pub mod utils;
@@ -0,0 +1,179 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// An IPv4 or IPv6 network such as `168.63.129.16/32` or `fd00::/8`.
+/// Host bits are cleared, so equal networks compare equal however they
+/// were written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpCidr {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CidrError {
+    InvalidAddress(String),
+    InvalidPrefixLength(String),
+}
+
+impl IpCidr {
+    pub fn new(address: IpAddr, prefix_len: u8) -> Result<Self, CidrError> {
+        let address = match address {
+            IpAddr::V4(v4) if prefix_len <= 32 => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(prefix_len))
+                    .unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(v4) & mask))
+            }
+            IpAddr::V6(v6) if prefix_len <= 128 => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(prefix_len))
+                    .unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(v6) & mask))
+            }
+            _ => {
+                return Err(CidrError::InvalidPrefixLength(format!(
+                    "{}/{}",
+                    address, prefix_len
+                )))
+            }
+        };
+        Ok(Self {
+            address,
+            prefix_len,
+        })
+    }
+
+    /// The network holding only `address`.
+    pub fn host(address: IpAddr) -> Self {
+        let prefix_len = if address.is_ipv4() { 32 } else { 128 };
+        Self {
+            address,
+            prefix_len,
+        }
+    }
+
+    pub fn address(&self) -> IpAddr {
+        self.address
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn is_ipv6(&self) -> bool {
+        self.address.is_ipv6()
+    }
+}
+
+impl From<IpAddr> for IpCidr {
+    fn from(address: IpAddr) -> Self {
+        Self::host(address)
+    }
+}
+
+impl From<Ipv4Addr> for IpCidr {
+    fn from(address: Ipv4Addr) -> Self {
+        Self::host(address.into())
+    }
+}
+
+impl From<Ipv6Addr> for IpCidr {
+    fn from(address: Ipv6Addr) -> Self {
+        Self::host(address.into())
+    }
+}
+
+/// Accepts `address/prefix` or a bare address, which is taken as a host.
+impl FromStr for IpCidr {
+    type Err = CidrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = match s.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (s, None),
+        };
+        let address: IpAddr = address
+            .parse()
+            .map_err(|_| CidrError::InvalidAddress(s.to_string()))?;
+        match prefix_len {
+            Some(prefix_len) => {
+                let prefix_len = prefix_len
+                    .parse()
+                    .map_err(|_| CidrError::InvalidPrefixLength(s.to_string()))?;
+                Self::new(address, prefix_len)
+            }
+            None => Ok(Self::host(address)),
+        }
+    }
+}
+
+impl fmt::Display for IpCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+/// Serialized as its `address/prefix` string.
+impl Serialize for IpCidr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for IpCidr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Display for CidrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CidrError::InvalidAddress(s) => write!(f, "invalid IP address in {}", s),
+            CidrError::InvalidPrefixLength(s) => write!(f, "invalid prefix length in {}", s),
+        }
+    }
+}
+
+impl Error for CidrError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let wireserver: IpCidr = "168.63.129.16".parse().unwrap();
+        assert_eq!(wireserver, IpCidr::from(Ipv4Addr::new(168, 63, 129, 16)));
+        assert_eq!(wireserver.to_string(), "168.63.129.16/32");
+
+        let network: IpCidr = "10.1.2.3/8".parse().unwrap();
+        assert_eq!(network.to_string(), "10.0.0.0/8");
+
+        let v6: IpCidr = "fd00::1/8".parse().unwrap();
+        assert!(v6.is_ipv6());
+        assert_eq!(v6.to_string(), "fd00::/8");
+        assert_eq!("::/0".parse::<IpCidr>().unwrap().prefix_len(), 0);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!(
+            "168.63.129.16/33".parse::<IpCidr>(),
+            Err(CidrError::InvalidPrefixLength(
+                "168.63.129.16/33".to_string()
+            ))
+        );
+        assert_eq!(
+            "wireserver".parse::<IpCidr>(),
+            Err(CidrError::InvalidAddress("wireserver".to_string()))
+        );
+        assert!("fd00::/129".parse::<IpCidr>().is_err());
+        assert!("10.0.0.0/x".parse::<IpCidr>().is_err());
+    }
+}
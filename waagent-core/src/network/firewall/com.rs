@@ -0,0 +1,242 @@
+// src/firewall/com.rs
+use super::*;
+use std::net::{IpAddr, Ipv4Addr};
+
+#[cfg(windows)]
+pub use backend::ComBackend;
+
+/// Remote addresses the way `INetFwRule::SetRemoteAddresses` takes them; `*`
+/// is any address.
+pub fn format_addresses(destinations: &[IpCidr]) -> String {
+    if destinations.is_empty() {
+        return "*".to_string();
+    }
+    let destinations: Vec<_> = destinations.iter().map(|d| d.to_string()).collect();
+    destinations.join(",")
+}
+
+/// Parses `INetFwRule::RemoteAddresses`. Windows Firewall writes IPv4
+/// networks with a dotted mask, e.g. `10.0.0.0/255.0.0.0`. Ranges and
+/// keywords such as `LocalSubnet` are not networks and give `None`.
+pub fn parse_addresses(addresses: &str) -> Option<Vec<IpCidr>> {
+    if addresses.is_empty() || addresses == "*" {
+        return Some(Vec::new());
+    }
+    addresses.split(',').map(parse_address).collect()
+}
+
+fn parse_address(address: &str) -> Option<IpCidr> {
+    let Some((address, mask)) = address.split_once('/') else {
+        return address.parse().ok();
+    };
+    let address: IpAddr = address.parse().ok()?;
+    let prefix_len = match mask.parse::<Ipv4Addr>() {
+        Ok(mask) => {
+            let bits = u32::from(mask);
+            if bits.leading_ones() + bits.trailing_zeros() != 32 {
+                return None;
+            }
+            bits.leading_ones() as u8
+        }
+        Err(_) => mask.parse().ok()?,
+    };
+    IpCidr::new(address, prefix_len).ok()
+}
+
+#[cfg(windows)]
+mod backend {
+    use super::*;
+    use windows::core::{Interface, BSTR};
+    use windows::Win32::Foundation::{RPC_E_CHANGED_MODE, VARIANT_TRUE};
+    use windows::Win32::NetworkManagement::WindowsFirewall::{
+        INetFwPolicy2, INetFwRule, NetFwPolicy2, NetFwRule, NET_FW_ACTION_ALLOW,
+        NET_FW_ACTION_BLOCK, NET_FW_IP_PROTOCOL_ANY, NET_FW_IP_PROTOCOL_TCP,
+        NET_FW_IP_PROTOCOL_UDP, NET_FW_PROFILE2_ALL, NET_FW_RULE_DIR_IN, NET_FW_RULE_DIR_OUT,
+    };
+    use windows::Win32::System::Com::{
+        CoCreateInstance, CoInitializeEx, CoUninitialize, IDispatch, CLSCTX_INPROC_SERVER,
+        COINIT_MULTITHREADED,
+    };
+    use windows::Win32::System::Ole::IEnumVARIANT;
+    use windows::Win32::System::Variant::VARIANT;
+
+    /// Drives Windows Firewall through its COM API, `INetFwPolicy2` and
+    /// `INetFwRule`, without starting a process per call.
+    #[derive(Debug, Default)]
+    pub struct ComBackend;
+
+    impl ComBackend {
+        pub fn new() -> Self {
+            Self
+        }
+
+        /// Every rule Windows Firewall has. The caller holds the apartment.
+        fn native_rules(&self) -> Result<Vec<INetFwRule>, Box<dyn Error>> {
+            let mut rules = Vec::new();
+            unsafe {
+                let enumerator = policy()?.Rules()?._NewEnum()?.cast::<IEnumVARIANT>()?;
+                loop {
+                    let mut item = [VARIANT::default()];
+                    let mut fetched = 0;
+                    enumerator.Next(&mut item, &mut fetched).ok()?;
+                    if fetched == 0 {
+                        break;
+                    }
+                    rules.push(IDispatch::try_from(&item[0])?.cast::<INetFwRule>()?);
+                }
+            }
+            Ok(rules)
+        }
+
+        /// The native rules called `name`. The caller holds the apartment.
+        fn named_rules(&self, name: &str) -> Result<Vec<INetFwRule>, Box<dyn Error>> {
+            let mut named = Vec::new();
+            for rule in self.native_rules()? {
+                if unsafe { rule.Name()? }.to_string() == name {
+                    named.push(rule);
+                }
+            }
+            Ok(named)
+        }
+
+        fn native_rule(rule: &FirewallRule) -> Result<INetFwRule, Box<dyn Error>> {
+            let direction = match rule.direction {
+                Direction::Inbound => NET_FW_RULE_DIR_IN,
+                Direction::Outbound => NET_FW_RULE_DIR_OUT,
+            };
+            let action = match rule.action {
+                Action::Allow => NET_FW_ACTION_ALLOW,
+                Action::Block => NET_FW_ACTION_BLOCK,
+            };
+            let protocol = match rule.protocol {
+                Protocol::Tcp => NET_FW_IP_PROTOCOL_TCP,
+                Protocol::Udp => NET_FW_IP_PROTOCOL_UDP,
+                Protocol::Any => NET_FW_IP_PROTOCOL_ANY,
+            };
+            unsafe {
+                let native: INetFwRule = CoCreateInstance(&NetFwRule, None, CLSCTX_INPROC_SERVER)?;
+                native.SetName(&BSTR::from(rule.name.as_str()))?;
+                native.SetDirection(direction)?;
+                native.SetAction(action)?;
+                // Ports can only be set once the protocol is TCP or UDP
+                native.SetProtocol(protocol.0)?;
+                native.SetRemoteAddresses(&BSTR::from(format_addresses(&rule.destinations)))?;
+                if let Some(port) = rule.port {
+                    native.SetRemotePorts(&BSTR::from(port.to_string()))?;
+                }
+                if let Some(path) = &rule.program_path {
+                    native.SetApplicationName(&BSTR::from(path.as_str()))?;
+                }
+                native.SetProfiles(NET_FW_PROFILE2_ALL.0)?;
+                native.SetEnabled(VARIANT_TRUE)?;
+                Ok(native)
+            }
+        }
+
+        /// The rule `native` stands for, or `None` when it uses something the
+        /// rule model has no room for, such as address ranges.
+        fn rule_of(native: &INetFwRule) -> Result<Option<FirewallRule>, Box<dyn Error>> {
+            unsafe {
+                let direction = match native.Direction()? {
+                    NET_FW_RULE_DIR_IN => Direction::Inbound,
+                    NET_FW_RULE_DIR_OUT => Direction::Outbound,
+                    _ => return Ok(None),
+                };
+                let action = match native.Action()? {
+                    NET_FW_ACTION_ALLOW => Action::Allow,
+                    NET_FW_ACTION_BLOCK => Action::Block,
+                    _ => return Ok(None),
+                };
+                let protocol = match native.Protocol()? {
+                    p if p == NET_FW_IP_PROTOCOL_TCP.0 => Protocol::Tcp,
+                    p if p == NET_FW_IP_PROTOCOL_UDP.0 => Protocol::Udp,
+                    _ => Protocol::Any,
+                };
+                let Some(destinations) = parse_addresses(&native.RemoteAddresses()?.to_string())
+                else {
+                    return Ok(None);
+                };
+                let program_path = native.ApplicationName()?.to_string();
+                Ok(Some(FirewallRule {
+                    name: native.Name()?.to_string(),
+                    direction,
+                    action,
+                    protocol,
+                    destinations,
+                    port: native.RemotePorts()?.to_string().parse().ok(),
+                    uid_owner: None,
+                    program_path: Some(program_path).filter(|path| !path.is_empty()),
+                    new_connections_only: false,
+                }))
+            }
+        }
+    }
+
+    impl FirewallBackend for ComBackend {
+        fn add_rule(&self, rule: &FirewallRule) -> Result<(), Box<dyn Error>> {
+            let _apartment = Apartment::enter()?;
+            let native = Self::native_rule(rule)?;
+            unsafe { policy()?.Rules()?.Add(&native)? };
+            Ok(())
+        }
+
+        fn delete_rules(&self, name: &str) -> Result<(), Box<dyn Error>> {
+            let _apartment = Apartment::enter()?;
+            let rules = unsafe { policy()?.Rules()? };
+            // Remove takes out one rule of that name at a time
+            let native_name = BSTR::from(name);
+            for _ in self.named_rules(name)? {
+                unsafe { rules.Remove(&native_name)? };
+            }
+            Ok(())
+        }
+
+        fn find_rules(&self, name: &str) -> Result<Vec<FirewallRule>, Box<dyn Error>> {
+            let _apartment = Apartment::enter()?;
+            let mut rules = Vec::new();
+            for native in self.named_rules(name)? {
+                rules.extend(Self::rule_of(&native)?);
+            }
+            Ok(rules)
+        }
+
+        fn list_rules(&self) -> Result<Vec<FirewallRule>, Box<dyn Error>> {
+            let _apartment = Apartment::enter()?;
+            let mut rules = Vec::new();
+            for native in self.native_rules()? {
+                rules.extend(Self::rule_of(&native)?);
+            }
+            Ok(rules)
+        }
+    }
+
+    fn policy() -> windows::core::Result<INetFwPolicy2> {
+        unsafe { CoCreateInstance(&NetFwPolicy2, None, CLSCTX_INPROC_SERVER) }
+    }
+
+    /// COM initialised on the calling thread for as long as it lives. Callers
+    /// take it before any COM object so it is dropped after all of them.
+    struct Apartment {
+        initialized: bool,
+    }
+
+    impl Apartment {
+        fn enter() -> Result<Self, Box<dyn Error>> {
+            let result = unsafe { CoInitializeEx(None, COINIT_MULTITHREADED) };
+            // A thread already in a single-threaded apartment can use that one
+            if result == RPC_E_CHANGED_MODE {
+                return Ok(Self { initialized: false });
+            }
+            result.ok()?;
+            Ok(Self { initialized: true })
+        }
+    }
+
+    impl Drop for Apartment {
+        fn drop(&mut self) {
+            if self.initialized {
+                unsafe { CoUninitialize() };
+            }
+        }
+    }
+}
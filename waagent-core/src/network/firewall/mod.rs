@@ -1,50 +1,309 @@
 // src/firewall.rs
+use serde::{Deserialize, Serialize};
 use std::error::Error;
+use std::fmt;
 
 // Platform-specific modules
 pub mod windows;
+pub mod netsh;
+pub mod com;
 pub mod unix;
+pub mod nftables;
+mod cidr;
+mod policy;
 
 // Platform-specific exports
-pub use windows::WindowsFirewallManager;
+pub use windows::{FirewallBackend, WindowsFirewallManager};
+pub use netsh::NetshBackend;
+#[cfg(windows)]
+pub use com::ComBackend;
 pub use unix::UnixFirewallManager;
+pub use nftables::NftablesFirewallManager;
+pub use cidr::{CidrError, IpCidr};
+pub use policy::{discover_endpoint, FirewallCheck, WireServerFirewallPolicy, DEFAULT_WIRESERVER_IP};
+pub(crate) use policy::current_uid;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FirewallRule {
     pub name: String, // Unique identifier for the rule
     pub direction: Direction,
     pub action: Action,
     pub protocol: Protocol,
-    pub destination: String,
+    pub destinations: Vec<IpCidr>, // Remote addresses, any when empty
     pub port: Option<u16>,
     pub uid_owner: Option<String>, // Unix-specific
     pub program_path: Option<String>, // Windows-specific
+    pub new_connections_only: bool, // Match only NEW and INVALID connections (Unix-specific)
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Direction {
     Inbound,
     Outbound,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Action {
     Allow,
     Block,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Protocol {
     Tcp,
     Udp,
     Any,
 }
 
-pub trait FirewallManager {
+/// Packet and byte counts of an installed rule.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleCounters {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+/// A rule read back from the firewall, with its counters when the backend keeps them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListedRule {
+    pub rule: FirewallRule,
+    pub counters: Option<RuleCounters>,
+}
+
+impl fmt::Display for FirewallRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.action {
+            Action::Allow => "allow",
+            Action::Block => "block",
+        };
+        let (direction, preposition) = match self.direction {
+            Direction::Inbound => ("inbound", "from"),
+            Direction::Outbound => ("outbound", "to"),
+        };
+        let protocol = match self.protocol {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+            Protocol::Any => "any",
+        };
+        let destinations = if self.destinations.is_empty() {
+            "any".to_string()
+        } else {
+            let destinations: Vec<_> = self.destinations.iter().map(|d| d.to_string()).collect();
+            destinations.join(",")
+        };
+        write!(
+            f,
+            "{}: {} {} {} {} {}",
+            self.name, action, direction, protocol, preposition, destinations
+        )?;
+        if let Some(port) = self.port {
+            write!(f, " port {}", port)?;
+        }
+        if let Some(uid) = &self.uid_owner {
+            write!(f, " uid {}", uid)?;
+        }
+        if let Some(program) = &self.program_path {
+            write!(f, " program {}", program)?;
+        }
+        if self.new_connections_only {
+            write!(f, " (new connections)")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ListedRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.rule)?;
+        if let Some(counters) = self.counters {
+            write!(
+                f,
+                " [{} packets, {} bytes]",
+                counters.packets, counters.bytes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub trait FirewallManager: Send + Sync {
     fn add_rule(&self, rule: &FirewallRule) -> Result<(), Box<dyn Error>>;
     fn remove_rule(&self, rule: &FirewallRule) -> Result<(), Box<dyn Error>>;
     fn rule_exists(&self, rule: &FirewallRule) -> Result<bool, Box<dyn Error>>;
-    fn list_rules(&self) -> Result<Vec<String>, Box<dyn Error>>;
+    fn list_rules(&self) -> Result<Vec<ListedRule>, Box<dyn Error>>;
+    /// Deletes every rule the agent created, and nothing else. Returns how
+    /// many rules were removed.
+    fn remove_all_managed_rules(&self) -> Result<usize, Box<dyn Error>>;
+    
+    /// Installs `rules` in order, all or nothing: when one fails, the
+    /// firewall is put back the way it was before the call. Rules already
+    /// present are replaced along with the rest, so afterwards the set is
+    /// installed exactly once and in this order.
+    ///
+    /// Backends that can snapshot or batch their changes override this to do
+    /// it in one step. The default removes the rules that are present, adds
+    /// the set one rule at a time, and undoes both on failure.
+    fn apply(&self, rules: &[FirewallRule]) -> Result<(), Box<dyn Error>> {
+        let mut removed: Vec<&FirewallRule> = Vec::new();
+        let mut added: Vec<&FirewallRule> = Vec::new();
+        let mut replace = || -> Result<(), String> {
+            for rule in rules {
+                let exists = self.rule_exists(rule).map_err(|e| format!("{}: {}", rule.name, e))?;
+                if exists {
+                    self.remove_rule(rule).map_err(|e| format!("{}: {}", rule.name, e))?;
+                    removed.push(rule);
+                }
+            }
+            for rule in rules {
+                self.add_rule(rule).map_err(|e| format!("{}: {}", rule.name, e))?;
+                added.push(rule);
+            }
+            Ok(())
+        };
+        
+        let Err(e) = replace() else {
+            return Ok(());
+        };
+        for rule in added.iter().rev() {
+            if let Err(undo) = self.remove_rule(rule) {
+                tracing::warn!("Failed to roll back rule {}: {}", rule.name, undo);
+            }
+        }
+        for rule in removed {
+            if let Err(undo) = self.add_rule(rule) {
+                tracing::warn!("Failed to restore rule {}: {}", rule.name, undo);
+            }
+        }
+        Err(format!("Failed to apply rule {}, rolled back", e).into())
+    }
+}
+
+impl FirewallRule {
+    /// The IPv4 and the IPv6 destinations.
+    pub fn destinations_by_family(&self) -> (Vec<IpCidr>, Vec<IpCidr>) {
+        self.destinations.iter().partition(|d| !d.is_ipv6())
+    }
+
+    /// Checks the fields that end up verbatim in nft and iptables-restore
+    /// scripts: the name is a word of `[A-Za-z0-9_-]` and the owner a numeric
+    /// uid. The privileged helper refuses rules that fail this.
+    pub fn validate(&self) -> Result<(), String> {
+        let valid_name = !self.name.is_empty()
+            && self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid_name {
+            return Err(format!(
+                "rule name {:?} may only contain letters, digits, '_' and '-'",
+                self.name
+            ));
+        }
+        if let Some(uid) = &self.uid_owner {
+            if uid.parse::<u32>().is_err() {
+                return Err(format!("rule {} has owner {:?}, not a uid", self.name, uid));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Backends hold one rule per address family, or per address for iptables,
+/// where `FirewallRule` has a single rule with several destinations. Merges
+/// listed rules that share a name and differ only in destinations back into
+/// one, adding up their counters. Unnamed rules are left as they are.
+fn merge_destinations(listed: Vec<ListedRule>) -> Vec<ListedRule> {
+    let mut merged: Vec<ListedRule> = Vec::new();
+    for entry in listed {
+        let same_rule = |other: &ListedRule| {
+            let (a, b) = (&other.rule, &entry.rule);
+            !a.name.is_empty()
+                && !a.destinations.is_empty()
+                && !b.destinations.is_empty()
+                && a.name == b.name
+                && a.direction == b.direction
+                && a.action == b.action
+                && a.protocol == b.protocol
+                && a.port == b.port
+                && a.uid_owner == b.uid_owner
+                && a.program_path == b.program_path
+                && a.new_connections_only == b.new_connections_only
+        };
+        match merged.iter_mut().find(|other| same_rule(other)) {
+            Some(other) => {
+                other.rule.destinations.extend(entry.rule.destinations);
+                other.counters = match (other.counters, entry.counters) {
+                    (Some(a), Some(b)) => Some(RuleCounters {
+                        packets: a.packets + b.packets,
+                        bytes: a.bytes + b.bytes,
+                    }),
+                    (a, b) => a.or(b),
+                };
+            }
+            None => merged.push(entry),
+        }
+    }
+    merged
+}
+
+/// Splits a rule listing line into words, keeping double-quoted words
+/// (rule names and comments) together and without their quotes. Inside
+/// quotes a backslash escapes the next character, as iptables writes them.
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut in_word = false;
+    for c in line.chars() {
+        if escaped {
+            word.push(c);
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if quoted => escaped = true,
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+/// Writes `word` the way `split_words` and iptables-restore read it back as
+/// a single word: double-quoted, with `"` and `\` escaped, when it is empty
+/// or holds whitespace, quotes or backslashes.
+fn quote_word(word: &str) -> String {
+    let plain = !word.is_empty()
+        && !word
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '\\');
+    if plain {
+        return word.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in word.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
 }
 
 // Factory function
@@ -53,5 +312,43 @@ pub fn create_firewall_manager() -> Box<dyn FirewallManager> {
     return Box::new(WindowsFirewallManager::new());
     
     #[cfg(unix)]
-    return Box::new(unix::UnixFirewallManager::new());
-}
\ No newline at end of file
+    return create_unix_firewall_manager(&crate::utils::command::SystemCommandRunner);
+}
+
+/// Prefers nftables when nft is installed and iptables is either missing or
+/// only the nf_tables shim. Hosts still on iptables-legacy keep using it, so
+/// the agent's rules stay where the rest of the host's rules are.
+///
+/// Root runs the tools directly. Anyone else goes through the privileged
+/// helper; without it every operation fails rather than reaching for sudo.
+#[cfg(unix)]
+fn create_unix_firewall_manager(
+    runner: &dyn crate::utils::command::CommandRunner,
+) -> Box<dyn FirewallManager> {
+    if !crate::privileged::is_root() {
+        let helper = crate::privileged::HelperClient::default();
+        if !helper.is_available() {
+            tracing::warn!(
+                "The privileged helper socket {} is missing; firewall rules cannot be managed",
+                helper.socket_path().display()
+            );
+        }
+        return Box::new(crate::privileged::HelperFirewallManager::new(helper));
+    }
+    
+    let iptables = runner
+        .run("iptables", &["--version"], None)
+        .ok()
+        .filter(|output| output.success());
+    let iptables_is_legacy = iptables
+        .as_ref()
+        .is_some_and(|output| !output.stdout.contains("nf_tables"));
+
+    if NftablesFirewallManager::is_available(runner) && !iptables_is_legacy {
+        tracing::debug!("Using the nftables firewall backend");
+        Box::new(NftablesFirewallManager::new_no_sudo())
+    } else {
+        tracing::debug!("Using the iptables firewall backend");
+        Box::new(UnixFirewallManager::new_no_sudo())
+    }
+}
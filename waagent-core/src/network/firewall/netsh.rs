@@ -0,0 +1,193 @@
+// src/firewall/netsh.rs
+use super::*;
+use crate::utils::command::{CommandOutput, CommandRunner, SystemCommandRunner};
+use std::sync::Arc;
+use tracing::debug;
+
+/// Drives Windows Firewall through `netsh advfirewall firewall`. Commands go
+/// through a `CommandRunner`, so the arguments and the parsing of the output
+/// can be checked against recorded netsh output on any platform.
+pub struct NetshBackend {
+    runner: Arc<dyn CommandRunner>,
+}
+
+impl Default for NetshBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetshBackend {
+    pub fn new() -> Self {
+        Self::with_runner(Arc::new(SystemCommandRunner))
+    }
+
+    pub fn with_runner(runner: Arc<dyn CommandRunner>) -> Self {
+        Self { runner }
+    }
+
+    /// Arguments of the `netsh` command that adds `rule` under its name as
+    /// given. Owner uids and connection state have no netsh equivalent and
+    /// are left out.
+    pub fn add_args(rule: &FirewallRule) -> Vec<String> {
+        let mut args = vec![
+            "advfirewall".to_string(),
+            "firewall".to_string(),
+            "add".to_string(),
+            "rule".to_string(),
+            format!("name={}", rule.name),
+        ];
+
+        let dir = match rule.direction {
+            Direction::Inbound => "in",
+            Direction::Outbound => "out",
+        };
+        args.push(format!("dir={}", dir));
+
+        let action = match rule.action {
+            Action::Allow => "allow",
+            Action::Block => "block",
+        };
+        args.push(format!("action={}", action));
+
+        let protocol = match rule.protocol {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+            Protocol::Any => "any",
+        };
+        args.push(format!("protocol={}", protocol));
+
+        // Remote IPs, IPv4 and IPv6 alike
+        if !rule.destinations.is_empty() {
+            let destinations: Vec<_> = rule.destinations.iter().map(|d| d.to_string()).collect();
+            args.push(format!("remoteip={}", destinations.join(",")));
+        }
+
+        if let Some(port) = rule.port {
+            args.push(format!("remoteport={}", port));
+        }
+
+        if let Some(path) = &rule.program_path {
+            args.push(format!("program={}", path));
+        }
+
+        args
+    }
+
+    /// Parses `netsh advfirewall firewall show rule ... verbose` output, a
+    /// block of `Key: Value` lines per rule. Names are returned as netsh
+    /// prints them.
+    pub fn parse_rules(output: &str) -> Vec<FirewallRule> {
+        let mut rules = Vec::new();
+        let mut fields: Vec<(&str, &str)> = Vec::new();
+        for line in output.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+            if key == "Rule Name" && !fields.is_empty() {
+                rules.extend(Self::parse_rule(&fields));
+                fields.clear();
+            }
+            fields.push((key, value));
+        }
+        rules.extend(Self::parse_rule(&fields));
+        rules
+    }
+
+    fn parse_rule(fields: &[(&str, &str)]) -> Option<FirewallRule> {
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| *value)
+        };
+        let direction = match field("Direction")? {
+            "In" => Direction::Inbound,
+            "Out" => Direction::Outbound,
+            _ => return None,
+        };
+        let action = match field("Action")? {
+            "Allow" => Action::Allow,
+            "Block" => Action::Block,
+            _ => return None,
+        };
+        let protocol = match field("Protocol").unwrap_or("Any") {
+            "TCP" => Protocol::Tcp,
+            "UDP" => Protocol::Udp,
+            _ => Protocol::Any,
+        };
+        let destinations = match field("RemoteIP").unwrap_or("Any") {
+            "Any" => Vec::new(),
+            remote => remote
+                .split(',')
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .ok()?,
+        };
+        Some(FirewallRule {
+            name: field("Rule Name")?.to_string(),
+            direction,
+            action,
+            protocol,
+            destinations,
+            port: field("RemotePort").and_then(|port| port.parse().ok()),
+            uid_owner: None,
+            program_path: field("Program")
+                .filter(|program| *program != "Any")
+                .map(str::to_string),
+            new_connections_only: false,
+        })
+    }
+
+    fn show_rules(&self, filter: &str) -> Result<CommandOutput, Box<dyn Error>> {
+        let args = ["advfirewall", "firewall", "show", "rule", filter, "verbose"];
+        debug!("Command: netsh {}", args.join(" "));
+        Ok(self.runner.run("netsh", &args, None)?)
+    }
+
+    fn run(&self, args: &[&str]) -> Result<(), Box<dyn Error>> {
+        debug!("Command: netsh {}", args.join(" "));
+        let output = self.runner.run("netsh", args, None)?;
+
+        if !output.success() {
+            // netsh reports most errors on stdout
+            let mut message = output.stderr.trim();
+            if message.is_empty() {
+                message = output.stdout.trim();
+            }
+            return Err(format!("netsh command failed: {}", message).into());
+        }
+
+        Ok(())
+    }
+}
+
+impl FirewallBackend for NetshBackend {
+    fn add_rule(&self, rule: &FirewallRule) -> Result<(), Box<dyn Error>> {
+        let args = Self::add_args(rule);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        self.run(&args)
+    }
+
+    fn delete_rules(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        let filter = format!("name={}", name);
+        self.run(&["advfirewall", "firewall", "delete", "rule", &filter])
+    }
+
+    fn find_rules(&self, name: &str) -> Result<Vec<FirewallRule>, Box<dyn Error>> {
+        // netsh exits non-zero when no rule matches, so the exit code cannot
+        // tell a missing rule from a failure; the rule blocks can.
+        let output = self.show_rules(&format!("name={}", name))?;
+        let rules = Self::parse_rules(&output.stdout);
+        Ok(rules.into_iter().filter(|rule| rule.name == name).collect())
+    }
+
+    fn list_rules(&self) -> Result<Vec<FirewallRule>, Box<dyn Error>> {
+        let output = self.show_rules("name=all")?;
+        if !output.success() {
+            return Err(format!("Failed to list rules: {}", output.stdout.trim()).into());
+        }
+        Ok(Self::parse_rules(&output.stdout))
+    }
+}
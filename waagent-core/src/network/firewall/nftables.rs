@@ -0,0 +1,694 @@
+// src/firewall/nftables.rs
+use super::*;
+use crate::utils::command::{CommandRunner, SystemCommandRunner};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+/// Manages rules in a table of its own, so the agent never touches rules
+/// owned by the distro or the administrator. Every rule carries its name
+/// as the nft comment, which is how it is found again.
+pub struct NftablesFirewallManager {
+    runner: Arc<dyn CommandRunner>,
+    use_sudo: bool,
+}
+
+impl Default for NftablesFirewallManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NftablesFirewallManager {
+    pub const TABLE: &'static str = "waagent";
+    const FAMILY: &'static str = "inet";
+    /// Same hook priority as the iptables security table.
+    const PRIORITY: i32 = 50;
+
+    pub fn new() -> Self {
+        Self::with_runner(Arc::new(SystemCommandRunner), true)
+    }
+
+    pub fn new_no_sudo() -> Self {
+        Self::with_runner(Arc::new(SystemCommandRunner), false)
+    }
+
+    pub fn with_runner(runner: Arc<dyn CommandRunner>, use_sudo: bool) -> Self {
+        Self { runner, use_sudo }
+    }
+
+    /// Whether the `nft` tool is installed.
+    pub fn is_available(runner: &dyn CommandRunner) -> bool {
+        matches!(runner.run("nft", &["--version"], None), Ok(output) if output.success())
+    }
+}
+
+impl FirewallManager for NftablesFirewallManager {
+    fn add_rule(&self, rule: &FirewallRule) -> Result<(), Box<dyn Error>> {
+        // Check if rule already exists before adding
+        if self.rule_exists(rule)? {
+            warn!("Rule already exists, skipping: {:?}", rule);
+            return Ok(());
+        }
+
+        let mut ruleset = Self::table_definition();
+        ruleset.push_str(&self.delete_commands(rule)?);
+        ruleset.push_str(&Self::add_commands(rule));
+        self.load_ruleset(&ruleset)
+    }
+
+    fn remove_rule(&self, rule: &FirewallRule) -> Result<(), Box<dyn Error>> {
+        let listing = self.list_chain(Self::chain(rule))?;
+        let handles = Self::find_handles(&listing, &rule.name);
+        if handles.is_empty() {
+            return Err(format!("Rule {} not found", rule.name).into());
+        }
+        self.load_ruleset(&Self::delete_handles(rule, &handles))
+    }
+
+    /// Whether a rule called `rule.name` is installed and still does exactly
+    /// what `rule` says; one edited by hand keeps its comment but not its body.
+    fn rule_exists(&self, rule: &FirewallRule) -> Result<bool, Box<dyn Error>> {
+        // A missing table simply means no rule was added yet.
+        match self.list_chain(Self::chain(rule)) {
+            Ok(listing) => Ok(Self::is_listed(&listing, rule)),
+            Err(_) => Ok(false),
+        }
+    }
+
+    fn list_rules(&self) -> Result<Vec<ListedRule>, Box<dyn Error>> {
+        let output = self.run(&["-a", "list", "table", Self::FAMILY, Self::TABLE], None)?;
+        Ok(Self::parse_rules(&output))
+    }
+
+    fn remove_all_managed_rules(&self) -> Result<usize, Box<dyn Error>> {
+        // Everything in the table belongs to the agent, so dropping the table
+        // removes its rules and chains in one go.
+        let rules = match self.list_rules() {
+            Ok(rules) => rules,
+            Err(e) => {
+                debug!("No {} table to remove: {}", Self::TABLE, e);
+                return Ok(0);
+            }
+        };
+        self.load_ruleset(&format!("delete table {} {}\n", Self::FAMILY, Self::TABLE))?;
+        Ok(rules.len())
+    }
+
+    fn apply(&self, rules: &[FirewallRule]) -> Result<(), Box<dyn Error>> {
+        // The kernel applies an nft script as one transaction, so when a rule
+        // is rejected none of the others take effect and nothing needs undoing.
+        // The old rules go and the new ones come in that same transaction.
+        let mut ruleset = Self::table_definition();
+        for rule in rules {
+            ruleset.push_str(&self.delete_commands(rule)?);
+        }
+        for rule in rules {
+            ruleset.push_str(&Self::add_commands(rule));
+        }
+        self.load_ruleset(&ruleset)
+    }
+}
+
+impl NftablesFirewallManager {
+    /// Declares the table and both chains. Declaring them again is a no-op,
+    /// so this is prepended to every ruleset that adds rules.
+    fn table_definition() -> String {
+        format!(
+            "table {family} {table} {{\n\
+             \tchain input {{\n\
+             \t\ttype filter hook input priority {priority}; policy accept;\n\
+             \t}}\n\
+             \tchain output {{\n\
+             \t\ttype filter hook output priority {priority}; policy accept;\n\
+             \t}}\n\
+             }}\n",
+            family = Self::FAMILY,
+            table = Self::TABLE,
+            priority = Self::PRIORITY
+        )
+    }
+
+    /// The `add rule` commands for `rule`, one per address family.
+    fn add_commands(rule: &FirewallRule) -> String {
+        Self::rule_expressions(rule)
+            .into_iter()
+            .map(|expression| {
+                format!(
+                    "add rule {} {} {} {}\n",
+                    Self::FAMILY,
+                    Self::TABLE,
+                    Self::chain(rule),
+                    expression
+                )
+            })
+            .collect()
+    }
+
+    fn chain(rule: &FirewallRule) -> &'static str {
+        match rule.direction {
+            Direction::Inbound => "input",
+            Direction::Outbound => "output",
+        }
+    }
+
+    /// A rule can only match addresses of one family, so a rule with both
+    /// IPv4 and IPv6 destinations becomes two nft rules sharing the comment.
+    fn rule_expressions(rule: &FirewallRule) -> Vec<String> {
+        let (v4, v6) = rule.destinations_by_family();
+        let address = match rule.direction {
+            Direction::Inbound => "saddr",
+            Direction::Outbound => "daddr",
+        };
+
+        let mut matches = Vec::new();
+        for (family, destinations) in [("ip", v4), ("ip6", v6)] {
+            let destinations: Vec<_> = destinations.iter().map(|d| d.to_string()).collect();
+            match destinations.len() {
+                0 => {}
+                1 => matches.push(format!("{} {} {}", family, address, destinations[0])),
+                _ => matches.push(format!(
+                    "{} {} {{ {} }}",
+                    family,
+                    address,
+                    destinations.join(", ")
+                )),
+            }
+        }
+        if matches.is_empty() {
+            return vec![Self::rule_expression(rule, None)];
+        }
+        matches
+            .into_iter()
+            .map(|address_match| Self::rule_expression(rule, Some(address_match)))
+            .collect()
+    }
+
+    fn rule_expression(rule: &FirewallRule, address_match: Option<String>) -> String {
+        let mut expression = Vec::new();
+
+        // Match on the remote address
+        expression.extend(address_match);
+
+        // Protocol and port
+        match (&rule.protocol, rule.port) {
+            (Protocol::Tcp, Some(port)) => expression.push(format!("tcp dport {}", port)),
+            (Protocol::Udp, Some(port)) => expression.push(format!("udp dport {}", port)),
+            (Protocol::Tcp, None) => expression.push("meta l4proto tcp".to_string()),
+            (Protocol::Udp, None) => expression.push("meta l4proto udp".to_string()),
+            (Protocol::Any, Some(port)) => expression.push(format!("th dport {}", port)),
+            (Protocol::Any, None) => {}
+        }
+
+        if let Some(uid) = &rule.uid_owner {
+            expression.push(format!("meta skuid {}", uid));
+        }
+
+        if rule.new_connections_only {
+            expression.push("ct state invalid,new".to_string());
+        }
+
+        // Keep packet and byte counts for list_rules
+        expression.push("counter".to_string());
+
+        expression.push(
+            match rule.action {
+                Action::Allow => "accept",
+                Action::Block => "drop",
+            }
+            .to_string(),
+        );
+        expression.push(format!("comment \"{}\"", rule.name));
+        expression.join(" ")
+    }
+
+    /// Parses `nft list table` output. Only rules in the `input` and
+    /// `output` chains are returned, and only those ending in a verdict.
+    /// The IPv4 and IPv6 halves of a rule are merged back into one.
+    pub fn parse_rules(output: &str) -> Vec<ListedRule> {
+        let mut direction = None;
+        let mut rules = Vec::new();
+        for line in output.lines() {
+            let line = line.trim();
+            if let Some(chain) = line.strip_prefix("chain ") {
+                direction = match chain.split_whitespace().next() {
+                    Some("input") => Some(Direction::Inbound),
+                    Some("output") => Some(Direction::Outbound),
+                    _ => None,
+                };
+            } else if let Some(direction) = direction {
+                rules.extend(Self::parse_rule(line, direction, false));
+            }
+        }
+        merge_destinations(rules)
+    }
+
+    /// Whether `listing`, from `nft -a list chain`, holds `rule` as the agent
+    /// adds it. Lines are parsed strictly, so a rule that gained a match the
+    /// agent does not write, or lost one, is not taken for `rule`.
+    fn is_listed(listing: &str, rule: &FirewallRule) -> bool {
+        let listed: Vec<_> = listing
+            .lines()
+            .filter_map(|line| Self::parse_rule(line.trim(), rule.direction, true))
+            .filter(|listed| listed.rule.name == rule.name)
+            .collect();
+        // nft cannot match on the program
+        let expected = FirewallRule {
+            program_path: None,
+            ..rule.clone()
+        };
+        // Duplicates and the order of set elements do not matter
+        let same = |a: &[IpCidr], b: &[IpCidr]| a.iter().all(|d| b.contains(d));
+        merge_destinations(listed).into_iter().any(|listed| {
+            same(&listed.rule.destinations, &expected.destinations)
+                && same(&expected.destinations, &listed.rule.destinations)
+                && FirewallRule {
+                    destinations: expected.destinations.clone(),
+                    ..listed.rule
+                } == expected
+        })
+    }
+
+    /// A single value, or the elements of an anonymous set `{ a, b }`.
+    fn next_values<'a>(words: &mut impl Iterator<Item = &'a str>) -> Option<Vec<String>> {
+        let first = words.next()?;
+        if first != "{" {
+            return Some(first.split(',').map(str::to_string).collect());
+        }
+        let mut values = Vec::new();
+        for word in words.by_ref() {
+            if word == "}" {
+                return Some(values);
+            }
+            values.extend(
+                word.split(',')
+                    .filter(|value| !value.is_empty())
+                    .map(str::to_string),
+            );
+        }
+        None
+    }
+
+    /// `strict` rejects rules with anything the agent does not write itself,
+    /// instead of skipping over it.
+    fn parse_rule(line: &str, direction: Direction, strict: bool) -> Option<ListedRule> {
+        // Drop the `# handle <n>` annotation added by `nft -a`.
+        let line = line.split_once(" # handle ").map_or(line, |(rule, _)| rule);
+        let words = split_words(line);
+        let mut words = words.iter().map(String::as_str);
+
+        let mut rule = FirewallRule {
+            name: String::new(),
+            direction,
+            action: Action::Allow,
+            protocol: Protocol::Any,
+            destinations: Vec::new(),
+            port: None,
+            uid_owner: None,
+            program_path: None,
+            new_connections_only: false,
+        };
+        let remote = match direction {
+            Direction::Inbound => "saddr",
+            Direction::Outbound => "daddr",
+        };
+        let mut counters = None;
+        let mut action = None;
+        while let Some(word) = words.next() {
+            match word {
+                "!=" => return None,
+                "ip" | "ip6" if words.next()? == remote => {
+                    for address in Self::next_values(&mut words)? {
+                        rule.destinations.push(address.parse().ok()?);
+                    }
+                }
+                "tcp" | "udp" | "th" => {
+                    rule.protocol = match word {
+                        "tcp" => Protocol::Tcp,
+                        "udp" => Protocol::Udp,
+                        _ => Protocol::Any,
+                    };
+                    if words.next()? == "dport" {
+                        rule.port = words.next()?.parse().ok();
+                    } else if strict {
+                        return None;
+                    }
+                }
+                "l4proto" => {
+                    rule.protocol = match words.next()? {
+                        "tcp" => Protocol::Tcp,
+                        "udp" => Protocol::Udp,
+                        _ => Protocol::Any,
+                    }
+                }
+                "skuid" => rule.uid_owner = Some(words.next()?.to_string()),
+                "state" => {
+                    rule.new_connections_only = Self::next_values(&mut words)?
+                        .iter()
+                        .any(|state| state == "new")
+                }
+                "packets" => {
+                    let packets = words.next()?.parse().ok()?;
+                    if words.next()? != "bytes" {
+                        return None;
+                    }
+                    let bytes = words.next()?.parse().ok()?;
+                    counters = Some(RuleCounters { packets, bytes });
+                }
+                "accept" => action = Some(Action::Allow),
+                "drop" => action = Some(Action::Block),
+                "reject" if !strict => action = Some(Action::Block),
+                "comment" => rule.name = words.next()?.to_string(),
+                "meta" | "ct" | "counter" => {}
+                _ if strict => return None,
+                _ => {}
+            }
+        }
+        rule.action = action?;
+        Some(ListedRule { rule, counters })
+    }
+
+    /// Handles of the rules commented with `name` in `nft -a` output, where
+    /// each rule line ends in `comment "<name>" # handle <n>`.
+    fn find_handles(listing: &str, name: &str) -> Vec<u64> {
+        let comment = format!("comment \"{}\"", name);
+        listing
+            .lines()
+            .filter(|line| line.contains(&comment))
+            .filter_map(|line| line.rsplit_once("# handle "))
+            .filter_map(|(_, handle)| handle.trim().parse().ok())
+            .collect()
+    }
+
+    /// Commands deleting every rule called `rule.name`, so that adding `rule`
+    /// in the same ruleset replaces a rule that drifted. None if the table or
+    /// the rule is not there yet.
+    fn delete_commands(&self, rule: &FirewallRule) -> Result<String, Box<dyn Error>> {
+        match self.list_chain(Self::chain(rule)) {
+            Ok(listing) => Ok(Self::delete_handles(
+                rule,
+                &Self::find_handles(&listing, &rule.name),
+            )),
+            Err(_) => Ok(String::new()),
+        }
+    }
+
+    fn delete_handles(rule: &FirewallRule, handles: &[u64]) -> String {
+        handles
+            .iter()
+            .map(|handle| {
+                format!(
+                    "delete rule {} {} {} handle {}\n",
+                    Self::FAMILY,
+                    Self::TABLE,
+                    Self::chain(rule),
+                    handle
+                )
+            })
+            .collect()
+    }
+
+    fn list_chain(&self, chain: &str) -> Result<String, Box<dyn Error>> {
+        self.run(
+            &["-a", "list", "chain", Self::FAMILY, Self::TABLE, chain],
+            None,
+        )
+    }
+
+    /// Applies `ruleset` in a single transaction: either all of it takes
+    /// effect or none of it does.
+    fn load_ruleset(&self, ruleset: &str) -> Result<(), Box<dyn Error>> {
+        debug!("Applying nftables ruleset:\n{}", ruleset);
+        self.run(&["-f", "-"], Some(ruleset.as_bytes()))?;
+        Ok(())
+    }
+
+    fn run(&self, args: &[&str], input: Option<&[u8]>) -> Result<String, Box<dyn Error>> {
+        debug!("Command: nft {}", args.join(" "));
+
+        let output = if self.use_sudo {
+            let mut sudo_args = vec!["nft"];
+            sudo_args.extend_from_slice(args);
+            self.runner.run_checked("sudo", &sudo_args, input)?
+        } else {
+            self.runner.run_checked("nft", args, input)?
+        };
+        Ok(output.stdout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::command::CommandOutput;
+    use std::io;
+    use std::sync::Mutex;
+
+    const LISTING: &str = "table inet waagent {
+\tchain output { # handle 2
+\t\ttype filter hook output priority 50; policy accept;
+\t\tip daddr 168.63.129.16 tcp dport 53 accept comment \"AllowWireServerDns\" # handle 4
+\t\tip daddr 168.63.129.16 meta l4proto tcp ct state invalid,new drop comment \"BlockWireServer\" # handle 6
+\t}
+}
+";
+
+    /// Answers `list` with `listing` and records what `nft -f -` was fed.
+    struct FakeNft {
+        listing: Option<&'static str>,
+        applied: Mutex<Vec<String>>,
+    }
+
+    impl CommandRunner for FakeNft {
+        fn run(
+            &self,
+            _program: &str,
+            args: &[&str],
+            input: Option<&[u8]>,
+        ) -> io::Result<CommandOutput> {
+            if args.contains(&"list") {
+                return Ok(match self.listing {
+                    Some(listing) => CommandOutput {
+                        stdout: listing.to_string(),
+                        ..Default::default()
+                    },
+                    None => CommandOutput {
+                        exit_code: 1,
+                        stderr: "No such file or directory".to_string(),
+                        ..Default::default()
+                    },
+                });
+            }
+            let input = String::from_utf8_lossy(input.unwrap_or_default()).into_owned();
+            self.applied.lock().unwrap().push(input);
+            Ok(CommandOutput::default())
+        }
+    }
+
+    fn manager(listing: Option<&'static str>) -> (Arc<FakeNft>, NftablesFirewallManager) {
+        let nft = Arc::new(FakeNft {
+            listing,
+            applied: Mutex::new(Vec::new()),
+        });
+        let manager = NftablesFirewallManager::with_runner(nft.clone(), false);
+        (nft, manager)
+    }
+
+    fn rule(name: &str) -> FirewallRule {
+        FirewallRule {
+            name: name.to_string(),
+            direction: Direction::Outbound,
+            action: Action::Allow,
+            protocol: Protocol::Tcp,
+            destinations: vec![DEFAULT_WIRESERVER_IP.into()],
+            port: None,
+            uid_owner: Some("0".to_string()),
+            program_path: None,
+            new_connections_only: false,
+        }
+    }
+
+    /// The rule of the WireServer policy called `name`.
+    fn policy_rule(name: &str) -> FirewallRule {
+        WireServerFirewallPolicy::new(DEFAULT_WIRESERVER_IP, 0)
+            .rules()
+            .into_iter()
+            .find(|rule| rule.name == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_add_rule_creates_the_table() {
+        let (nft, manager) = manager(None);
+
+        manager.add_rule(&rule("AllowWireServerAgent")).unwrap();
+
+        let applied = nft.applied.lock().unwrap();
+        assert_eq!(applied.len(), 1);
+        assert!(applied[0].starts_with("table inet waagent {\n"));
+        assert!(applied[0].ends_with(
+            "add rule inet waagent output ip daddr 168.63.129.16/32 meta l4proto tcp \
+             meta skuid 0 counter accept comment \"AllowWireServerAgent\"\n"
+        ));
+    }
+
+    #[test]
+    fn test_apply_replaces_rules_in_one_transaction() {
+        let (nft, manager) = manager(Some(LISTING));
+
+        manager
+            .apply(&[
+                policy_rule("AllowWireServerDns"),
+                rule("AllowWireServerAgent"),
+                rule("AllowMetadata"),
+            ])
+            .unwrap();
+
+        let applied = nft.applied.lock().unwrap();
+        assert_eq!(applied.len(), 1);
+        let commands: Vec<_> = applied[0]
+            .lines()
+            .filter(|line| line.starts_with("add rule") || line.starts_with("delete rule"))
+            .collect();
+        // The DNS rule is there already; it is added again so the set ends
+        // up in order
+        assert_eq!(commands.len(), 4);
+        assert_eq!(commands[0], "delete rule inet waagent output handle 4");
+        assert!(commands[1].ends_with("comment \"AllowWireServerDns\""));
+        assert!(commands[2].ends_with("comment \"AllowWireServerAgent\""));
+        assert!(commands[3].ends_with("comment \"AllowMetadata\""));
+    }
+
+    #[test]
+    fn test_rule_expression() {
+        let mut block = rule("BlockWireServer");
+        block.action = Action::Block;
+        block.uid_owner = None;
+        block.port = Some(80);
+        block.new_connections_only = true;
+
+        assert_eq!(
+            NftablesFirewallManager::rule_expressions(&block),
+            vec![
+                "ip daddr 168.63.129.16/32 tcp dport 80 ct state invalid,new counter drop \
+                 comment \"BlockWireServer\""
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rule_expressions_per_family() {
+        let mut dual_stack = rule("AllowMetadata");
+        dual_stack.uid_owner = None;
+        dual_stack.destinations = vec![
+            "168.63.129.16".parse().unwrap(),
+            "169.254.169.254".parse().unwrap(),
+            "fd00:ec2::254".parse().unwrap(),
+        ];
+
+        assert_eq!(
+            NftablesFirewallManager::rule_expressions(&dual_stack),
+            vec![
+                "ip daddr { 168.63.129.16/32, 169.254.169.254/32 } meta l4proto tcp counter \
+                 accept comment \"AllowMetadata\"",
+                "ip6 daddr fd00:ec2::254/128 meta l4proto tcp counter accept \
+                 comment \"AllowMetadata\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rule_exists_and_remove_rule_by_name() {
+        let (nft, manager) = manager(Some(LISTING));
+        let block = policy_rule("BlockWireServer");
+
+        assert!(manager.rule_exists(&block).unwrap());
+        assert!(!manager.rule_exists(&rule("AllowWireServerAgent")).unwrap());
+
+        manager.remove_rule(&block).unwrap();
+        assert_eq!(
+            nft.applied.lock().unwrap().as_slice(),
+            ["delete rule inet waagent output handle 6\n"]
+        );
+        assert!(manager.remove_rule(&rule("AllowWireServerAgent")).is_err());
+    }
+
+    #[test]
+    fn test_rule_exists_compares_the_rule_body() {
+        let block = policy_rule("BlockWireServer");
+        let listed = |line: &str| {
+            let listing = format!(
+                "table inet waagent {{\n\tchain output {{\n\t\t{}\n\t}}\n}}\n",
+                line
+            );
+            NftablesFirewallManager::is_listed(&listing, &block)
+        };
+
+        assert!(listed(
+            "ip daddr 168.63.129.16 meta l4proto tcp ct state invalid,new counter packets 3 \
+             bytes 180 drop comment \"BlockWireServer\" # handle 6"
+        ));
+        // Same comment, but no longer limited to new connections
+        assert!(!listed(
+            "ip daddr 168.63.129.16 meta l4proto tcp drop comment \"BlockWireServer\" # handle 6"
+        ));
+        // A match the agent never writes
+        assert!(!listed(
+            "ip daddr 168.63.129.16 oifname \"eth1\" meta l4proto tcp ct state invalid,new drop \
+             comment \"BlockWireServer\" # handle 6"
+        ));
+        assert!(!listed(
+            "ip daddr 168.63.129.16 meta l4proto tcp ct state invalid,new reject \
+             comment \"BlockWireServer\" # handle 6"
+        ));
+        assert!(!listed(
+            "ip daddr 10.0.0.1 meta l4proto tcp ct state invalid,new drop \
+             comment \"BlockWireServer\" # handle 6"
+        ));
+    }
+
+    #[test]
+    fn test_add_rule_replaces_a_drifted_rule_in_the_same_transaction() {
+        const DRIFTED: &str = "table inet waagent {
+\tchain output { # handle 2
+\t\ttype filter hook output priority 50; policy accept;
+\t\tip daddr 168.63.129.16 meta l4proto tcp drop comment \"BlockWireServer\" # handle 6
+\t}
+}
+";
+        let (nft, manager) = manager(Some(DRIFTED));
+
+        manager.add_rule(&policy_rule("BlockWireServer")).unwrap();
+
+        let applied = nft.applied.lock().unwrap();
+        assert_eq!(applied.len(), 1);
+        let commands: Vec<_> = applied[0]
+            .lines()
+            .filter(|line| line.starts_with("add rule") || line.starts_with("delete rule"))
+            .collect();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0], "delete rule inet waagent output handle 6");
+        assert!(
+            commands[1].ends_with("ct state invalid,new counter drop comment \"BlockWireServer\"")
+        );
+    }
+
+    #[test]
+    fn test_missing_table_means_no_rules() {
+        let (nft, manager) = manager(None);
+
+        assert!(!manager.rule_exists(&rule("BlockWireServer")).unwrap());
+        assert_eq!(manager.remove_all_managed_rules().unwrap(), 0);
+        assert!(nft.applied.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_remove_all_managed_rules_deletes_the_table() {
+        let (nft, manager) = manager(Some(LISTING));
+
+        assert_eq!(manager.remove_all_managed_rules().unwrap(), 2);
+        assert_eq!(
+            nft.applied.lock().unwrap().as_slice(),
+            ["delete table inet waagent\n"]
+        );
+    }
+}
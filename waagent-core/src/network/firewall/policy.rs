@@ -0,0 +1,307 @@
+use super::*;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use tracing::{debug, info, warn};
+
+/// The well-known WireServer address, used when no other endpoint was recorded.
+pub const DEFAULT_WIRESERVER_IP: Ipv4Addr = Ipv4Addr::new(168, 63, 129, 16);
+/// File in `Lib.Dir` holding the WireServer address found through DHCP.
+const ENDPOINT_FILE: &str = "WireServerEndpoint";
+
+/// Restricts access to the WireServer to the agent. Other processes may
+/// still resolve DNS through it, and connections they already hold are left
+/// alone, but any new connection from them is dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WireServerFirewallPolicy {
+    endpoint: Ipv4Addr,
+    uid: u32,
+}
+
+impl WireServerFirewallPolicy {
+    pub fn new(endpoint: Ipv4Addr, uid: u32) -> Self {
+        Self { endpoint, uid }
+    }
+
+    /// A policy for the endpoint recorded in `lib_dir` and the uid this
+    /// process runs as.
+    pub fn discover(lib_dir: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(discover_endpoint(lib_dir), current_uid()?))
+    }
+
+    pub fn endpoint(&self) -> Ipv4Addr {
+        self.endpoint
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    /// The rules in the order they have to appear in the chain: both
+    /// ACCEPT rules must come before the DROP rule.
+    pub fn rules(&self) -> Vec<FirewallRule> {
+        let rule = |name: &str, action, port, uid_owner, new_connections_only| FirewallRule {
+            name: name.to_string(),
+            direction: Direction::Outbound,
+            action,
+            protocol: Protocol::Tcp,
+            destinations: vec![self.endpoint.into()],
+            port,
+            uid_owner,
+            program_path: None,
+            new_connections_only,
+        };
+        vec![
+            rule("AllowWireServerDns", Action::Allow, Some(53), None, false),
+            rule(
+                "AllowWireServerAgent",
+                Action::Allow,
+                None,
+                Some(self.uid.to_string()),
+                false,
+            ),
+            rule("BlockWireServer", Action::Block, None, None, true),
+        ]
+    }
+
+    /// Compares the installed rules with this policy.
+    pub fn check(&self, manager: &dyn FirewallManager) -> Result<FirewallCheck, Box<dyn Error>> {
+        let mut missing = Vec::new();
+        for rule in self.rules() {
+            if !manager.rule_exists(&rule)? {
+                missing.push(rule);
+            }
+        }
+
+        // Only meaningful when every rule is there; otherwise they are all re-added anyway.
+        let out_of_order = missing.is_empty() && !self.in_order(&manager.list_rules()?);
+        Ok(FirewallCheck {
+            missing,
+            out_of_order,
+        })
+    }
+
+    /// Whether the rules appear in the listing in policy order.
+    fn in_order(&self, listing: &[ListedRule]) -> bool {
+        let positions: Vec<_> = self
+            .rules()
+            .iter()
+            .map(|rule| {
+                listing
+                    .iter()
+                    .position(|listed| listed.rule.name == rule.name)
+            })
+            .collect();
+        positions.windows(2).all(|pair| pair[0] < pair[1])
+    }
+
+    /// Installs the rules, or repairs them when some are missing or out of
+    /// order, and returns what was found. A repair hands the whole set to
+    /// [`FirewallManager::apply`], which replaces the rules that are present
+    /// in the same step, so a failure never leaves the block rule in place
+    /// without the rules that let DNS and the agent through.
+    pub fn install(&self, manager: &dyn FirewallManager) -> Result<FirewallCheck, Box<dyn Error>> {
+        let check = self.check(manager)?;
+        if check.is_compliant() {
+            debug!("WireServer firewall rules are in place");
+            return Ok(check);
+        }
+
+        manager.apply(&self.rules())?;
+        info!(
+            "Installed WireServer firewall rules for {} (agent uid {})",
+            self.endpoint, self.uid
+        );
+        Ok(check)
+    }
+}
+
+/// How the installed rules differed from the policy.
+#[derive(Debug, Clone, Default)]
+pub struct FirewallCheck {
+    /// Rules of the policy that were not installed.
+    pub missing: Vec<FirewallRule>,
+    /// All rules were installed, but not in policy order.
+    pub out_of_order: bool,
+}
+
+impl FirewallCheck {
+    pub fn is_compliant(&self) -> bool {
+        self.missing.is_empty() && !self.out_of_order
+    }
+}
+
+impl std::fmt::Display for FirewallCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.missing.is_empty() {
+            let names: Vec<_> = self.missing.iter().map(|rule| rule.name.as_str()).collect();
+            write!(f, "missing firewall rules: {}", names.join(", "))
+        } else if self.out_of_order {
+            write!(f, "firewall rules are out of order")
+        } else {
+            write!(f, "firewall rules are in place")
+        }
+    }
+}
+
+/// The WireServer address saved in `lib_dir`, or the default one.
+pub fn discover_endpoint(lib_dir: &Path) -> Ipv4Addr {
+    let path = lib_dir.join(ENDPOINT_FILE);
+    match fs::read_to_string(&path) {
+        Ok(contents) => match contents.trim().parse() {
+            Ok(endpoint) => return endpoint,
+            Err(_) => warn!(
+                "Ignoring invalid WireServer endpoint {:?} in {}",
+                contents.trim(),
+                path.display()
+            ),
+        },
+        Err(e) => debug!("No WireServer endpoint in {}: {}", path.display(), e),
+    }
+    DEFAULT_WIRESERVER_IP
+}
+
+#[cfg(unix)]
+pub(crate) fn current_uid() -> Result<u32, Box<dyn Error>> {
+    use std::os::unix::fs::MetadataExt;
+    // /proc/self belongs to the effective uid of the process reading it.
+    Ok(fs::metadata("/proc/self")?.uid())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn current_uid() -> Result<u32, Box<dyn Error>> {
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// A chain that keeps rules in the order they were added.
+    #[derive(Default)]
+    struct FakeChain {
+        rules: Mutex<Vec<FirewallRule>>,
+    }
+
+    impl FirewallManager for FakeChain {
+        fn add_rule(&self, rule: &FirewallRule) -> Result<(), Box<dyn Error>> {
+            self.rules.lock().unwrap().push(rule.clone());
+            Ok(())
+        }
+
+        fn remove_rule(&self, rule: &FirewallRule) -> Result<(), Box<dyn Error>> {
+            self.rules.lock().unwrap().retain(|r| r.name != rule.name);
+            Ok(())
+        }
+
+        fn rule_exists(&self, rule: &FirewallRule) -> Result<bool, Box<dyn Error>> {
+            Ok(self.rules.lock().unwrap().contains(rule))
+        }
+
+        fn list_rules(&self) -> Result<Vec<ListedRule>, Box<dyn Error>> {
+            let rules = self.rules.lock().unwrap();
+            Ok(rules
+                .iter()
+                .map(|rule| ListedRule {
+                    rule: rule.clone(),
+                    counters: None,
+                })
+                .collect())
+        }
+
+        fn remove_all_managed_rules(&self) -> Result<usize, Box<dyn Error>> {
+            Ok(std::mem::take(&mut *self.rules.lock().unwrap()).len())
+        }
+    }
+
+    fn names(rules: &[FirewallRule]) -> Vec<&str> {
+        rules.iter().map(|rule| rule.name.as_str()).collect()
+    }
+
+    fn installed(chain: &FakeChain) -> Vec<String> {
+        let rules = chain.list_rules().unwrap();
+        rules.into_iter().map(|listed| listed.rule.name).collect()
+    }
+
+    #[test]
+    fn test_rules() {
+        let policy = WireServerFirewallPolicy::new(DEFAULT_WIRESERVER_IP, 0);
+        let rules = policy.rules();
+
+        assert_eq!(
+            names(&rules),
+            vec![
+                "AllowWireServerDns",
+                "AllowWireServerAgent",
+                "BlockWireServer"
+            ]
+        );
+        assert!(rules
+            .iter()
+            .all(|rule| rule.destinations == vec!["168.63.129.16/32".parse().unwrap()]));
+        assert_eq!(rules[0].port, Some(53));
+        assert_eq!(rules[1].uid_owner.as_deref(), Some("0"));
+        assert!(matches!(rules[2].action, Action::Block));
+        assert!(rules[2].new_connections_only);
+    }
+
+    #[test]
+    fn test_install_restores_the_rule_order() {
+        let policy = WireServerFirewallPolicy::new(DEFAULT_WIRESERVER_IP, 0);
+        let chain = FakeChain::default();
+        chain.add_rule(&policy.rules()[2]).unwrap();
+
+        let check = policy.install(&chain).unwrap();
+
+        assert!(!check.out_of_order);
+        assert_eq!(
+            names(&check.missing),
+            vec!["AllowWireServerDns", "AllowWireServerAgent"]
+        );
+        assert_eq!(
+            installed(&chain),
+            vec![
+                "AllowWireServerDns",
+                "AllowWireServerAgent",
+                "BlockWireServer"
+            ]
+        );
+        assert!(policy.install(&chain).unwrap().is_compliant());
+    }
+
+    #[test]
+    fn test_install_reorders_rules() {
+        let policy = WireServerFirewallPolicy::new(DEFAULT_WIRESERVER_IP, 0);
+        let chain = FakeChain::default();
+        for rule in policy.rules().iter().rev() {
+            chain.add_rule(rule).unwrap();
+        }
+
+        let check = policy.install(&chain).unwrap();
+
+        assert!(check.missing.is_empty());
+        assert!(check.out_of_order);
+        assert_eq!(check.to_string(), "firewall rules are out of order");
+        assert_eq!(
+            installed(&chain),
+            vec![
+                "AllowWireServerDns",
+                "AllowWireServerAgent",
+                "BlockWireServer"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_discover_endpoint() {
+        let dir = tempfile::TempDir::new().unwrap();
+        assert_eq!(discover_endpoint(dir.path()), DEFAULT_WIRESERVER_IP);
+
+        fs::write(dir.path().join(ENDPOINT_FILE), "10.0.0.4\n").unwrap();
+        assert_eq!(discover_endpoint(dir.path()), Ipv4Addr::new(10, 0, 0, 4));
+
+        fs::write(dir.path().join(ENDPOINT_FILE), "not an address").unwrap();
+        assert_eq!(discover_endpoint(dir.path()), DEFAULT_WIRESERVER_IP);
+    }
+}
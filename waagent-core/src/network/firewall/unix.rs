@@ -1,11 +1,18 @@
 use super::*;
-use std::process::Command;
+use std::io::Write;
+use std::process::{Command, Stdio};
 use tracing::{debug, warn};
 
 pub struct UnixFirewallManager {
     use_sudo: bool,
 }
 
+impl Default for UnixFirewallManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl UnixFirewallManager {
     pub fn new() -> Self {
         Self { use_sudo: true }
@@ -24,34 +31,218 @@ impl FirewallManager for UnixFirewallManager {
             return Ok(());
         }
         
-        let args = self.build_iptables_args(rule, "UPSERT")?;
-        self.execute_command(args)
+        for args in self.build_iptables_args(rule, "UPSERT")? {
+            self.execute_command(args)?;
+        }
+        Ok(())
     }
     
     fn remove_rule(&self, rule: &FirewallRule) -> Result<(), Box<dyn Error>> {
-        let args = self.build_iptables_args(rule, "DELETE")?;
-        self.execute_command(args)
+        for args in self.build_iptables_args(rule, "DELETE")? {
+            self.execute_command(args)?;
+        }
+        Ok(())
     }
     
     fn rule_exists(&self, rule: &FirewallRule) -> Result<bool, Box<dyn Error>> {
-        let args = self.build_iptables_args(rule, "CHECK")?;
-        match self.execute_command(args) {
-            Ok(_) => Ok(true),
-            Err(_) => Ok(false),
+        for args in self.build_iptables_args(rule, "CHECK")? {
+            if self.execute_command(args).is_err() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+    
+    fn list_rules(&self) -> Result<Vec<ListedRule>, Box<dyn Error>> {
+        let mut rules = Vec::new();
+        for program in Self::PROGRAMS {
+            // -S prints rules as the arguments that created them, -v adds counters
+            match self.list_chain(program, &["-v"]) {
+                Ok(stdout) => rules.extend(Self::parse_rules(&stdout)),
+                Err(e) if program == "ip6tables" => debug!("Skipping IPv6 rules: {}", e),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(merge_destinations(rules))
+    }
+    
+    fn remove_all_managed_rules(&self) -> Result<usize, Box<dyn Error>> {
+        let mut removed = 0;
+        for program in Self::PROGRAMS {
+            let stdout = match self.list_chain(program, &[]) {
+                Ok(stdout) => stdout,
+                Err(e) if program == "ip6tables" => {
+                    debug!("Skipping IPv6 rules: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            for line in stdout.lines() {
+                let words = split_words(line);
+                if words.first().map(String::as_str) != Some("-A") || !Self::is_managed(&words) {
+                    continue;
+                }
+                
+                // Delete by the exact spec iptables printed for the rule
+                let mut args = vec![
+                    program.to_string(),
+                    "-t".to_string(), "security".to_string(),
+                    "-D".to_string(),
+                ];
+                args.extend(words.into_iter().skip(1));
+                self.execute_command(args)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+    
+    fn apply(&self, rules: &[FirewallRule]) -> Result<(), Box<dyn Error>> {
+        // iptables-restore commits a table in one step, so the old rules are
+        // deleted and the set appended again at once. That is one commit per
+        // family; the snapshots undo the first family if the second fails.
+        let needs_ipv6 = rules.iter().any(|rule| {
+            rule.destinations.is_empty() || rule.destinations.iter().any(|d| d.is_ipv6())
+        });
+        let mut restored: Vec<(&str, String)> = Vec::new();
+        for program in Self::PROGRAMS {
+            let result = self.save_table(program).and_then(|snapshot| {
+                let listing = self.list_chain(program, &[])?;
+                let script = self.restore_script(program, &listing, rules)?;
+                debug!("Applying {} rules:\n{}", program, script);
+                self.restore(program, &script, &["--noflush"])?;
+                Ok(snapshot)
+            });
+            match result {
+                Ok(snapshot) => restored.push((program, snapshot)),
+                Err(e) if program == "ip6tables" && !needs_ipv6 => debug!("Skipping IPv6 rules: {}", e),
+                Err(e) => {
+                    for (program, snapshot) in &restored {
+                        if let Err(undo) = self.restore_table(program, snapshot) {
+                            warn!("Failed to restore {} rules: {}", program, undo);
+                        }
+                    }
+                    return Err(format!("Failed to apply firewall rules, rolled back: {}", e).into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl UnixFirewallManager {
+    /// Prefix of the comment match on every rule the agent adds; the rest of
+    /// the comment is the rule name.
+    pub const COMMENT_PREFIX: &'static str = "waagent:";
+    /// IPv4 rules live in iptables, IPv6 rules in ip6tables.
+    const PROGRAMS: [&'static str; 2] = ["iptables", "ip6tables"];
+    
+    fn is_managed(words: &[String]) -> bool {
+        words
+            .windows(2)
+            .any(|pair| pair[0] == "--comment" && pair[1].starts_with(Self::COMMENT_PREFIX))
+    }
+    
+    fn command(&self, program: &str) -> Command {
+        if self.use_sudo {
+            let mut c = Command::new("sudo");
+            c.arg(program);
+            c
+        } else {
+            Command::new(program)
+        }
+    }
+    
+    /// The security table as `iptables-save` prints it.
+    fn save_table(&self, program: &str) -> Result<String, Box<dyn Error>> {
+        let output = self
+            .command(&format!("{}-save", program))
+            .args(["-t", "security"])
+            .output()?;
+            
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("Failed to save rules: {}", stderr).into());
+        }
+        
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+    
+    /// Replaces the security table with a snapshot from `save_table`. Other
+    /// tables are not touched.
+    fn restore_table(&self, program: &str, snapshot: &str) -> Result<(), Box<dyn Error>> {
+        self.restore(program, snapshot, &[])
+    }
+    
+    /// The `iptables-restore --noflush` input that replaces `rules` in the
+    /// OUTPUT chain as `iptables -S OUTPUT` listed it: every rule carrying
+    /// one of their names is deleted by the spec iptables printed for it,
+    /// then `rules` are appended in order.
+    fn restore_script(&self, program: &str, listing: &str, rules: &[FirewallRule]) -> Result<String, Box<dyn Error>> {
+        let comments: Vec<String> = rules
+            .iter()
+            .map(|rule| format!("{}{}", Self::COMMENT_PREFIX, rule.name))
+            .collect();
+        let mut script = String::from("*security\n");
+        
+        for line in listing.lines() {
+            let Some(spec) = line.trim().strip_prefix("-A ") else {
+                continue;
+            };
+            let words = split_words(spec);
+            if words.windows(2).any(|pair| pair[0] == "--comment" && comments.contains(&pair[1])) {
+                script.push_str(&format!("-D {}\n", spec));
+            }
+        }
+        for rule in rules {
+            for args in self.build_iptables_args(rule, "UPSERT")? {
+                if args[0] == program {
+                    // Drop the program and `-t security`
+                    let words: Vec<String> = args[3..].iter().map(|arg| quote_word(arg)).collect();
+                    script.push_str(&format!("{}\n", words.join(" ")));
+                }
+            }
+        }
+        
+        script.push_str("COMMIT\n");
+        Ok(script)
+    }
+    
+    /// Feeds `input` to `<program>-restore`, limited to the security table.
+    fn restore(&self, program: &str, input: &str, extra_args: &[&str]) -> Result<(), Box<dyn Error>> {
+        let mut child = self
+            .command(&format!("{}-restore", program))
+            .args(["-T", "security"])
+            .args(extra_args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(input.as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("Failed to restore rules: {}", stderr).into());
         }
+        
+        Ok(())
     }
     
-    fn list_rules(&self) -> Result<Vec<String>, Box<dyn Error>> {
+    fn list_chain(&self, program: &str, extra_args: &[&str]) -> Result<String, Box<dyn Error>> {
         let mut cmd = if self.use_sudo {
             let mut c = Command::new("sudo");
-            c.arg("iptables");
+            c.arg(program);
             c
         } else {
-            Command::new("iptables")
+            Command::new(program)
         };
         
         let output = cmd
-            .args(&["-t", "security", "-L", "OUTPUT", "-n", "--line-numbers"])
+            .args(["-t", "security", "-S", "OUTPUT"])
+            .args(extra_args)
             .output()?;
             
         if !output.status.success() {
@@ -59,15 +250,117 @@ impl FirewallManager for UnixFirewallManager {
             return Err(format!("Failed to list rules: {}", stderr).into());
         }
         
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        Ok(stdout.lines().map(|s| s.to_string()).collect())
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+    
+    /// Parses `iptables -S -v` or `ip6tables -S -v` output. Lines that are
+    /// not rules (`-P`, `-N`), rules in other chains and rules jumping
+    /// anywhere but ACCEPT, DROP or REJECT are skipped, as are negated
+    /// matches, which `FirewallRule` cannot express. iptables stores a rule
+    /// per destination; those are merged back into one rule.
+    pub fn parse_rules(output: &str) -> Vec<ListedRule> {
+        merge_destinations(output.lines().filter_map(Self::parse_rule).collect())
+    }
+
+    fn parse_rule(line: &str) -> Option<ListedRule> {
+        let words = split_words(line);
+        let mut words = words.iter().map(String::as_str);
+        if words.next()? != "-A" {
+            return None;
+        }
+        let direction = match words.next()? {
+            "OUTPUT" => Direction::Outbound,
+            "INPUT" => Direction::Inbound,
+            _ => return None,
+        };
+        let remote_flag = match direction {
+            Direction::Outbound => "-d",
+            Direction::Inbound => "-s",
+        };
+
+        let mut rule = FirewallRule {
+            name: String::new(),
+            direction,
+            action: Action::Allow,
+            protocol: Protocol::Any,
+            destinations: Vec::new(),
+            port: None,
+            uid_owner: None,
+            program_path: None,
+            new_connections_only: false,
+        };
+        let mut counters = None;
+        let mut action = None;
+        while let Some(word) = words.next() {
+            match word {
+                "!" => return None,
+                flag if flag == remote_flag => {
+                    for destination in words.next()?.split(',') {
+                        rule.destinations.push(destination.parse().ok()?);
+                    }
+                }
+                "-p" => {
+                    rule.protocol = match words.next()? {
+                        "tcp" => Protocol::Tcp,
+                        "udp" => Protocol::Udp,
+                        _ => Protocol::Any,
+                    }
+                }
+                "--dport" => rule.port = words.next()?.parse().ok(),
+                "--uid-owner" => rule.uid_owner = Some(words.next()?.to_string()),
+                "--ctstate" => rule.new_connections_only = words.next()?.contains("NEW"),
+                "--comment" => {
+                    let comment = words.next()?;
+                    rule.name = comment
+                        .strip_prefix(Self::COMMENT_PREFIX)
+                        .unwrap_or(comment)
+                        .to_string();
+                }
+                "-c" => {
+                    counters = Some(RuleCounters {
+                        packets: words.next()?.parse().ok()?,
+                        bytes: words.next()?.parse().ok()?,
+                    })
+                }
+                "-j" => {
+                    action = match words.next()? {
+                        "ACCEPT" => Some(Action::Allow),
+                        "DROP" | "REJECT" => Some(Action::Block),
+                        _ => return None,
+                    }
+                }
+                _ => {}
+            }
+        }
+        rule.action = action?;
+        Some(ListedRule { rule, counters })
     }
 }
 
 impl UnixFirewallManager {
-    fn build_iptables_args(&self, rule: &FirewallRule, operation: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    /// One command per address family the rule covers: iptables for IPv4
+    /// destinations, ip6tables for IPv6 ones, and both when the rule has no
+    /// destinations and so applies to any address.
+    fn build_iptables_args(&self, rule: &FirewallRule, operation: &str) -> Result<Vec<Vec<String>>, Box<dyn Error>> {
+        let (v4, v6) = rule.destinations_by_family();
+        let commands: Vec<(&str, Vec<IpCidr>)> = if rule.destinations.is_empty() {
+            vec![("iptables", v4), ("ip6tables", v6)]
+        } else {
+            [("iptables", v4), ("ip6tables", v6)]
+                .into_iter()
+                .filter(|(_, destinations)| !destinations.is_empty())
+                .collect()
+        };
+        
+        commands
+            .into_iter()
+            .map(|(program, destinations)| self.build_family_args(rule, operation, program, &destinations))
+            .collect()
+    }
+    
+    fn build_family_args(&self, rule: &FirewallRule, operation: &str, program: &str, destinations: &[IpCidr]) -> Result<Vec<String>, Box<dyn Error>> {
         let mut args = vec![
-            "iptables".to_string(),
+            program.to_string(),
             "-t".to_string(), "security".to_string(),
         ];
         
@@ -79,8 +372,11 @@ impl UnixFirewallManager {
             _ => return Err("Invalid operation".into()),
         }
         
-        // Add destination
-        args.extend(["-d".to_string(), rule.destination.clone()]);
+        // Add destinations; iptables expands a comma-separated list into a rule per address
+        if !destinations.is_empty() {
+            let destinations: Vec<_> = destinations.iter().map(|d| d.to_string()).collect();
+            args.extend(["-d".to_string(), destinations.join(",")]);
+        }
         
         // Add protocol
         match rule.protocol {
@@ -102,6 +398,20 @@ impl UnixFirewallManager {
             ]);
         }
         
+        // Name the rule so it can be found in listings
+        args.extend([
+            "-m".to_string(), "comment".to_string(),
+            "--comment".to_string(), format!("{}{}", Self::COMMENT_PREFIX, rule.name),
+        ]);
+        
+        // Leave established connections alone
+        if rule.new_connections_only {
+            args.extend([
+                "-m".to_string(), "conntrack".to_string(),
+                "--ctstate".to_string(), "INVALID,NEW".to_string(),
+            ]);
+        }
+        
         // Add action
         match rule.action {
             Action::Allow => args.extend(["-j".to_string(), "ACCEPT".to_string()]),
@@ -134,4 +444,75 @@ impl UnixFirewallManager {
         
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LISTING: &str = "-P OUTPUT ACCEPT
+-A OUTPUT -d 168.63.129.16/32 -p tcp -m tcp --dport 53 -m comment --comment \"waagent:AllowWireServerDns\" -j ACCEPT
+-A OUTPUT -d 168.63.129.16/32 -p tcp -m comment --comment \"waagent:BlockWireServer\" -j DROP
+-A OUTPUT -d 10.0.0.1/32 -m comment --comment \"not the agent's\" -j DROP
+";
+
+    #[test]
+    fn test_restore_script_replaces_rules_in_one_commit() {
+        let manager = UnixFirewallManager::new_no_sudo();
+        let rules = WireServerFirewallPolicy::new(DEFAULT_WIRESERVER_IP, 1000).rules();
+
+        let script = manager.restore_script("iptables", LISTING, &rules).unwrap();
+
+        assert_eq!(
+            script,
+            "*security\n\
+             -D OUTPUT -d 168.63.129.16/32 -p tcp -m tcp --dport 53 -m comment \
+             --comment \"waagent:AllowWireServerDns\" -j ACCEPT\n\
+             -D OUTPUT -d 168.63.129.16/32 -p tcp -m comment \
+             --comment \"waagent:BlockWireServer\" -j DROP\n\
+             -A OUTPUT -d 168.63.129.16/32 -p tcp --dport 53 -m comment \
+             --comment waagent:AllowWireServerDns -j ACCEPT\n\
+             -A OUTPUT -d 168.63.129.16/32 -p tcp -m owner --uid-owner 1000 -m comment \
+             --comment waagent:AllowWireServerAgent -j ACCEPT\n\
+             -A OUTPUT -d 168.63.129.16/32 -p tcp -m comment \
+             --comment waagent:BlockWireServer -m conntrack --ctstate INVALID,NEW -j DROP\n\
+             COMMIT\n"
+        );
+    }
+
+    #[test]
+    fn test_restore_script_leaves_other_families_alone() {
+        let manager = UnixFirewallManager::new_no_sudo();
+        let rules = WireServerFirewallPolicy::new(DEFAULT_WIRESERVER_IP, 1000).rules();
+
+        let script = manager.restore_script("ip6tables", "-P OUTPUT ACCEPT\n", &rules).unwrap();
+
+        assert_eq!(script, "*security\nCOMMIT\n");
+    }
+
+    #[test]
+    fn test_restore_script_quotes_names_with_spaces() {
+        let manager = UnixFirewallManager::new_no_sudo();
+        let policy = WireServerFirewallPolicy::new(DEFAULT_WIRESERVER_IP, 1000);
+        let mut rule = policy.rules()[0].clone();
+        rule.name = "Allow \"Wire\\Server\" DNS".to_string();
+        let listing = "-A OUTPUT -d 168.63.129.16/32 -p tcp -m tcp --dport 53 -m comment \
+                       --comment \"waagent:Allow \\\"Wire\\\\Server\\\" DNS\" -j ACCEPT\n";
+
+        let script = manager.restore_script("iptables", listing, &[rule]).unwrap();
+
+        assert_eq!(
+            script,
+            "*security\n\
+             -D OUTPUT -d 168.63.129.16/32 -p tcp -m tcp --dport 53 -m comment \
+             --comment \"waagent:Allow \\\"Wire\\\\Server\\\" DNS\" -j ACCEPT\n\
+             -A OUTPUT -d 168.63.129.16/32 -p tcp --dport 53 -m comment \
+             --comment \"waagent:Allow \\\"Wire\\\\Server\\\" DNS\" -j ACCEPT\n\
+             COMMIT\n"
+        );
+        assert_eq!(
+            split_words(script.lines().nth(2).unwrap())[11],
+            "waagent:Allow \"Wire\\Server\" DNS"
+        );
+    }
+}
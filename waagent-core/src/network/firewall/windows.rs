@@ -1,146 +1,125 @@
 // src/firewall/windows.rs
 use super::*;
-use std::process::Command;
 
-pub struct WindowsFirewallManager;
+/// One way of driving Windows Firewall: `ComBackend` on Windows, or
+/// `NetshBackend` anywhere a `CommandRunner` can stand in for netsh. A
+/// backend only translates between `FirewallRule` and the native rule; naming
+/// is up to the manager, so the names a backend is given and returns are full
+/// names, prefix included.
+pub trait FirewallBackend: Send + Sync {
+    fn add_rule(&self, rule: &FirewallRule) -> Result<(), Box<dyn Error>>;
+    /// Deletes every rule called `name`.
+    fn delete_rules(&self, name: &str) -> Result<(), Box<dyn Error>>;
+    /// The rules called `name`; Windows Firewall does not require names to
+    /// be unique.
+    fn find_rules(&self, name: &str) -> Result<Vec<FirewallRule>, Box<dyn Error>>;
+    fn list_rules(&self) -> Result<Vec<FirewallRule>, Box<dyn Error>>;
+}
+
+pub struct WindowsFirewallManager {
+    backend: Box<dyn FirewallBackend>,
+}
+
+impl Default for WindowsFirewallManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl WindowsFirewallManager {
     const RULE_PREFIX: &'static str = "MicrosoftAzure_";
     
     pub fn new() -> Self {
-        Self
+        #[cfg(windows)]
+        let backend = Box::new(ComBackend::new());
+        #[cfg(not(windows))]
+        let backend = Box::new(NetshBackend::new());
+        Self::with_backend(backend)
+    }
+    
+    pub fn with_backend(backend: Box<dyn FirewallBackend>) -> Self {
+        Self { backend }
+    }
+    
+    /// The name Windows Firewall knows the rule by.
+    fn native_name(name: &str) -> String {
+        format!("{}{}", Self::RULE_PREFIX, name)
     }
 }
 
 impl FirewallManager for WindowsFirewallManager {
+    /// Fails for rules Windows Firewall cannot express. Dropping the owner or
+    /// the connection state instead would turn an exception into a rule that
+    /// applies to everyone.
     fn add_rule(&self, rule: &FirewallRule) -> Result<(), Box<dyn Error>> {
-        let args = self.build_netsh_args(rule, "add")?;
-        self.execute_netsh_command(args)
+        if rule.uid_owner.is_some() {
+            return Err(format!(
+                "Windows Firewall cannot restrict rule {} to a user",
+                rule.name
+            )
+            .into());
+        }
+        if rule.new_connections_only {
+            return Err(format!(
+                "Windows Firewall cannot restrict rule {} to new connections",
+                rule.name
+            )
+            .into());
+        }
+        
+        let mut native = rule.clone();
+        native.name = Self::native_name(&rule.name);
+        self.backend.add_rule(&native)
     }
     
     fn remove_rule(&self, rule: &FirewallRule) -> Result<(), Box<dyn Error>> {
-        let filter = self.build_rule_filter(rule)?;
-        let args = vec![
-            "advfirewall".to_string(),
-            "firewall".to_string(),
-            "delete".to_string(),
-            "rule".to_string(),
-            filter,
-        ];
-        self.execute_netsh_command(args)
+        self.backend.delete_rules(&Self::native_name(&rule.name))
     }
     
     fn rule_exists(&self, rule: &FirewallRule) -> Result<bool, Box<dyn Error>> {
-        let filter = self.build_rule_filter(rule)?;
-        let args = vec![
-            "advfirewall".to_string(),
-            "firewall".to_string(),
-            "show".to_string(),
-            "rule".to_string(),
-            filter,
-        ];
-        
-        match self.execute_netsh_command(args) {
-            Ok(_) => Ok(true),
-            Err(_) => Ok(false),
-        }
+        let found = self.backend.find_rules(&Self::native_name(&rule.name))?;
+        Ok(!found.is_empty())
+    }
+    
+    /// Every rule, with the agent's name prefix stripped. Windows Firewall
+    /// keeps no counters.
+    fn list_rules(&self) -> Result<Vec<ListedRule>, Box<dyn Error>> {
+        let rules = self.backend.list_rules()?;
+        Ok(rules
+            .into_iter()
+            .map(|mut rule| {
+                if let Some(name) = rule.name.strip_prefix(Self::RULE_PREFIX) {
+                    rule.name = name.to_string();
+                }
+                ListedRule { rule, counters: None }
+            })
+            .collect())
     }
     
-    fn list_rules(&self) -> Result<Vec<String>, Box<dyn Error>> {
-        let output = Command::new("netsh")
-            .args(&["advfirewall", "firewall", "show", "rule", "name=all"])
-            .output()?;
-            
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(format!("Failed to list rules: {}", stderr).into());
+    fn remove_all_managed_rules(&self) -> Result<usize, Box<dyn Error>> {
+        // Rules are deleted by their full name, which takes care of duplicates
+        let names = Self::managed_rule_names(&self.backend.list_rules()?);
+        for name in &names {
+            self.backend.delete_rules(name)?;
         }
-        
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        Ok(stdout.lines().map(|s| s.to_string()).collect())
+        Ok(names.len())
     }
 }
 
 impl WindowsFirewallManager {
-    fn build_netsh_args(&self, rule: &FirewallRule, operation: &str) -> Result<Vec<String>, Box<dyn Error>> {
-        let mut args = vec![
-            "advfirewall".to_string(),
-            "firewall".to_string(),
-            operation.to_string(),
-            "rule".to_string(),
-            format!("name={}{}", Self::RULE_PREFIX, rule.name),
-        ];
-        
-        // Direction
-        let dir = match rule.direction {
-            Direction::Inbound => "in",
-            Direction::Outbound => "out",
-        };
-        args.push(format!("dir={}", dir));
-        
-        // Action
-        let action = match rule.action {
-            Action::Allow => "allow",
-            Action::Block => "block",
-        };
-        args.push(format!("action={}", action));
-        
-        // Protocol
-        let protocol = match rule.protocol {
-            Protocol::Tcp => "tcp",
-            Protocol::Udp => "udp", 
-            Protocol::Any => "any",
-        };
-        args.push(format!("protocol={}", protocol));
-        
-        // Remote IP
-        args.push(format!("remoteip={}", rule.destination));
-        
-        // Port if specified
-        if let Some(port) = rule.port {
-            args.push(format!("remoteport={}", port));
-        }
-        
-        // Program path if specified (Windows-specific)
-        if let Some(path) = &rule.program_path {
-            args.push(format!("program={}", path));
-        }
-        
-        Ok(args)
-    }
-    
-    fn build_rule_filter(&self, rule: &FirewallRule) -> Result<String, Box<dyn Error>> {
-        Ok(format!("name={}{}", Self::RULE_PREFIX, rule.name))
-    }
-    
-    fn execute_netsh_command(&self, args: Vec<String>) -> Result<(), Box<dyn Error>> {
-        let output = Command::new("netsh")
-            .args(&args)
-            .output()?;
-            
-        if !output.status.success() {
-            let mut stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
-
-            // if stderr is empty, then output stdout
-            if stderr.is_empty() {
-                stderr = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    /// Full names of the rules carrying the agent's prefix, without duplicates.
+    fn managed_rule_names(rules: &[FirewallRule]) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for rule in rules {
+            if rule.name.starts_with(Self::RULE_PREFIX) && !names.contains(&rule.name) {
+                names.push(rule.name.clone());
             }
-
-            return Err(format!("netsh command failed: {}", stderr).into());
         }
-        
-        Ok(())
+        names
     }
     
     // Bonus: Add a method to clean up all auto-created rules
     pub fn cleanup_auto_rules(&self) -> Result<(), Box<dyn Error>> {
-        let args = vec![
-            "advfirewall".to_string(),
-            "firewall".to_string(),
-            "delete".to_string(),
-            "rule".to_string(),
-            format!("name={}*", Self::RULE_PREFIX),
-        ];
-        self.execute_netsh_command(args)
+        self.remove_all_managed_rules().map(|_| ())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,143 @@
+use super::{HelperError, HelperRequest, HelperResponse, DEFAULT_SOCKET_PATH};
+use crate::network::firewall::{FirewallManager, FirewallRule, ListedRule};
+use crate::resource_disk::ResourceDiskStatus;
+use std::error::Error;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Formatting and mounting the resource disk can take a while; nothing else should.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Sends requests to the privileged helper, one connection per request.
+#[derive(Debug, Clone)]
+pub struct HelperClient {
+    socket_path: PathBuf,
+}
+
+impl Default for HelperClient {
+    fn default() -> Self {
+        Self::new(DEFAULT_SOCKET_PATH)
+    }
+}
+
+impl HelperClient {
+    pub fn new(socket_path: impl Into<PathBuf>) -> Self {
+        Self {
+            socket_path: socket_path.into(),
+        }
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    /// Whether a helper socket is present to connect to.
+    pub fn is_available(&self) -> bool {
+        self.socket_path.exists()
+    }
+
+    /// Sends `request` and waits for the answer. Denied and failed requests
+    /// come back as errors.
+    pub fn call(&self, request: &HelperRequest) -> Result<HelperResponse, HelperError> {
+        let mut stream = UnixStream::connect(&self.socket_path)?;
+        stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+
+        let mut message = serde_json::to_vec(request)?;
+        message.push(b'\n');
+        // The helper may answer and close before reading everything, e.g. when
+        // it rejects the caller; its answer says more than the failed write.
+        let written = stream.write_all(&message);
+
+        let mut line = String::new();
+        match BufReader::new(stream).read_line(&mut line) {
+            Ok(n) if n > 0 => {}
+            read => {
+                written?;
+                read?;
+            }
+        }
+        match serde_json::from_str(&line)? {
+            HelperResponse::Denied { reason } => Err(HelperError::Denied(reason)),
+            HelperResponse::Failed { error } => Err(HelperError::Failed(error)),
+            response => Ok(response),
+        }
+    }
+
+    /// Has the helper set up the resource disk as its configuration says.
+    /// `None` when `ResourceDisk.Format` is off.
+    pub fn setup_resource_disk(&self) -> Result<Option<ResourceDiskStatus>, HelperError> {
+        match self.call(&HelperRequest::SetupResourceDisk)? {
+            HelperResponse::ResourceDisk { status } => Ok(status),
+            response => Err(unexpected(response)),
+        }
+    }
+}
+
+fn unexpected(response: HelperResponse) -> HelperError {
+    HelperError::Protocol(format!("unexpected response {:?}", response))
+}
+
+/// Manages firewall rules through the privileged helper, for an agent that
+/// runs without root.
+#[derive(Debug, Clone, Default)]
+pub struct HelperFirewallManager {
+    client: HelperClient,
+}
+
+impl HelperFirewallManager {
+    pub fn new(client: HelperClient) -> Self {
+        Self { client }
+    }
+}
+
+impl FirewallManager for HelperFirewallManager {
+    fn add_rule(&self, rule: &FirewallRule) -> Result<(), Box<dyn Error>> {
+        let request = HelperRequest::AddRule { rule: rule.clone() };
+        match self.client.call(&request)? {
+            HelperResponse::Done => Ok(()),
+            response => Err(unexpected(response).into()),
+        }
+    }
+
+    fn remove_rule(&self, rule: &FirewallRule) -> Result<(), Box<dyn Error>> {
+        let request = HelperRequest::RemoveRule { rule: rule.clone() };
+        match self.client.call(&request)? {
+            HelperResponse::Done => Ok(()),
+            response => Err(unexpected(response).into()),
+        }
+    }
+
+    fn rule_exists(&self, rule: &FirewallRule) -> Result<bool, Box<dyn Error>> {
+        let request = HelperRequest::RuleExists { rule: rule.clone() };
+        match self.client.call(&request)? {
+            HelperResponse::Exists { exists } => Ok(exists),
+            response => Err(unexpected(response).into()),
+        }
+    }
+
+    fn list_rules(&self) -> Result<Vec<ListedRule>, Box<dyn Error>> {
+        match self.client.call(&HelperRequest::ListRules)? {
+            HelperResponse::Rules { rules } => Ok(rules),
+            response => Err(unexpected(response).into()),
+        }
+    }
+
+    fn remove_all_managed_rules(&self) -> Result<usize, Box<dyn Error>> {
+        match self.client.call(&HelperRequest::RemoveAllManagedRules)? {
+            HelperResponse::Removed { count } => Ok(count),
+            response => Err(unexpected(response).into()),
+        }
+    }
+
+    fn apply(&self, rules: &[FirewallRule]) -> Result<(), Box<dyn Error>> {
+        let request = HelperRequest::Apply {
+            rules: rules.to_vec(),
+        };
+        match self.client.call(&request)? {
+            HelperResponse::Done => Ok(()),
+            response => Err(unexpected(response).into()),
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum HelperError {
+    /// Connecting to the helper socket or exchanging messages failed.
+    Io(io::Error),
+    /// A message could not be encoded or decoded, or the helper answered
+    /// with a response that does not fit the request.
+    Protocol(String),
+    /// The helper refused the caller.
+    Denied(String),
+    /// The helper accepted the request, but carrying it out failed.
+    Failed(String),
+}
+
+impl fmt::Display for HelperError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HelperError::Io(e) => write!(f, "I/O error: {}", e),
+            HelperError::Protocol(reason) => write!(f, "helper protocol error: {}", reason),
+            HelperError::Denied(reason) => write!(f, "helper denied the request: {}", reason),
+            HelperError::Failed(reason) => write!(f, "helper request failed: {}", reason),
+        }
+    }
+}
+
+impl Error for HelperError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HelperError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HelperError {
+    fn from(e: io::Error) -> Self {
+        HelperError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for HelperError {
+    fn from(e: serde_json::Error) -> Self {
+        HelperError::Protocol(e.to_string())
+    }
+}
@@ -0,0 +1,22 @@
+//! A small root-owned helper that performs the few privileged operations the
+//! agent needs, so the agent itself can run unprivileged without a sudoers
+//! grant. The agent sends one typed request per connection over a Unix
+//! socket; the helper checks the caller's peer credentials before acting.
+
+mod client;
+mod error;
+mod protocol;
+mod server;
+
+pub use client::{HelperClient, HelperFirewallManager};
+pub use error::HelperError;
+pub use protocol::{HelperRequest, HelperResponse};
+pub use server::{bind_socket, gid_of_group, systemd_socket, uid_of_user, HelperServer};
+
+/// Where the helper listens unless told otherwise; matches the socket unit.
+pub const DEFAULT_SOCKET_PATH: &str = "/run/waagent-rs/helper.sock";
+
+/// Whether this process runs as root and can act without the helper.
+pub fn is_root() -> bool {
+    crate::network::firewall::current_uid().is_ok_and(|uid| uid == 0)
+}
@@ -0,0 +1,72 @@
+use crate::network::firewall::{FirewallRule, ListedRule};
+use crate::resource_disk::ResourceDiskStatus;
+use serde::{Deserialize, Serialize};
+
+/// Everything the helper does on the agent's behalf. Each message is a
+/// single line of JSON; anything that does not decode into one of these is
+/// rejected.
+///
+/// `SetupResourceDisk` has no parameters on purpose: the helper formats and
+/// mounts the resource disk and enables swap as its own `waagent.conf` says,
+/// so the agent chooses neither the device, the mount point nor the options.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum HelperRequest {
+    AddRule { rule: FirewallRule },
+    RemoveRule { rule: FirewallRule },
+    RuleExists { rule: FirewallRule },
+    Apply { rules: Vec<FirewallRule> },
+    ListRules,
+    RemoveAllManagedRules,
+    SetupResourceDisk,
+}
+
+impl HelperRequest {
+    /// The firewall rules the request carries.
+    pub fn rules(&self) -> &[FirewallRule] {
+        match self {
+            HelperRequest::AddRule { rule }
+            | HelperRequest::RemoveRule { rule }
+            | HelperRequest::RuleExists { rule } => std::slice::from_ref(rule),
+            HelperRequest::Apply { rules } => rules,
+            HelperRequest::ListRules
+            | HelperRequest::RemoveAllManagedRules
+            | HelperRequest::SetupResourceDisk => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum HelperResponse {
+    Done,
+    Exists { exists: bool },
+    Rules { rules: Vec<ListedRule> },
+    Removed { count: usize },
+    ResourceDisk { status: Option<ResourceDiskStatus> },
+    Denied { reason: String },
+    Failed { error: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_encoding() {
+        assert_eq!(
+            serde_json::to_string(&HelperRequest::ListRules).unwrap(),
+            r#"{"request":"list_rules"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<HelperRequest>(r#"{"request":"setup_resource_disk"}"#).unwrap(),
+            HelperRequest::SetupResourceDisk
+        );
+        assert!(
+            serde_json::from_str::<HelperRequest>(r#"{"request":"run","command":"sh"}"#).is_err()
+        );
+        // The agent cannot name what to mount, or where
+        let mount = r#"{"request":"mount","device":"/tmp/image","mount_point":"/etc","filesystem":"ext4","options":"loop,suid,exec"}"#;
+        assert!(serde_json::from_str::<HelperRequest>(mount).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+use super::{HelperRequest, HelperResponse};
+use crate::network::firewall::{FirewallManager, FirewallRule};
+use crate::resource_disk::{ResourceDisk, ResourceDiskError, ResourceDiskSettings};
+use crate::utils::command::CommandRunner;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::Semaphore;
+use tracing::{debug, info, warn};
+
+/// Requests are a few hundred bytes; anything much larger is not ours.
+const MAX_REQUEST_BYTES: u64 = 64 * 1024;
+/// How long a caller has to send its request once connected.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Connections served at once; further callers wait in the listen backlog.
+const MAX_CONNECTIONS: usize = 16;
+/// systemd passes socket-activated sockets starting at this descriptor.
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Serves helper requests from callers whose uid is in `allowed_uids`.
+#[derive(Clone)]
+pub struct HelperServer {
+    firewall: Arc<dyn FirewallManager>,
+    runner: Arc<dyn CommandRunner>,
+    allowed_uids: Vec<u32>,
+    resource_disk: Option<ResourceDisk>,
+    request_timeout: Duration,
+}
+
+impl HelperServer {
+    pub fn new(
+        firewall: Arc<dyn FirewallManager>,
+        runner: Arc<dyn CommandRunner>,
+        allowed_uids: Vec<u32>,
+    ) -> Self {
+        Self {
+            firewall,
+            runner,
+            allowed_uids,
+            resource_disk: None,
+            request_timeout: REQUEST_TIMEOUT,
+        }
+    }
+
+    /// Lets callers set up the resource disk described by `settings`, which
+    /// the helper reads from its own configuration. Without it the request is
+    /// denied.
+    pub fn with_resource_disk(mut self, settings: ResourceDiskSettings) -> Self {
+        self.resource_disk = Some(ResourceDisk::new(settings, self.runner.clone()));
+        self
+    }
+
+    /// Replaces the 10 seconds a caller has to send its request.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Accepts connections until the listener fails, one task per connection
+    /// and at most `MAX_CONNECTIONS` at a time, so idle callers cannot tie the
+    /// helper up.
+    pub async fn serve(self, listener: UnixListener) -> io::Result<()> {
+        info!("Privileged helper serving uids {:?}", self.allowed_uids);
+        let connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+        loop {
+            let permit = connections
+                .clone()
+                .acquire_owned()
+                .await
+                .map_err(io::Error::other)?;
+            let (stream, _) = listener.accept().await?;
+            let server = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.handle(stream).await {
+                    warn!("Helper connection failed: {}", e);
+                }
+                drop(permit);
+            });
+        }
+    }
+
+    async fn handle(&self, stream: UnixStream) -> io::Result<()> {
+        let uid = stream.peer_cred()?.uid();
+        let (reader, mut writer) = stream.into_split();
+
+        // Read the request even from callers we reject: closing the socket
+        // with unread data resets the connection and the caller would see
+        // EPIPE or ECONNRESET instead of the reason.
+        let mut line = String::new();
+        let mut reader = BufReader::new(reader.take(MAX_REQUEST_BYTES));
+        tokio::time::timeout(self.request_timeout, reader.read_line(&mut line))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no request received"))??;
+
+        let response = if !self.allowed_uids.contains(&uid) {
+            warn!("Rejecting helper request from uid {}", uid);
+            HelperResponse::Denied {
+                reason: format!("uid {} is not allowed", uid),
+            }
+        } else {
+            match serde_json::from_str::<HelperRequest>(&line) {
+                Ok(request) => {
+                    debug!("Helper request from uid {}: {:?}", uid, request);
+                    let server = self.clone();
+                    tokio::task::spawn_blocking(move || server.dispatch(request))
+                        .await
+                        .unwrap_or_else(|e| HelperResponse::Failed {
+                            error: e.to_string(),
+                        })
+                }
+                // Anything outside the protocol is refused, not attempted
+                Err(e) => HelperResponse::Denied {
+                    reason: format!("invalid request: {}", e),
+                },
+            }
+        };
+
+        let mut message = serde_json::to_vec(&response).map_err(io::Error::other)?;
+        message.push(b'\n');
+        writer.write_all(&message).await?;
+        writer.shutdown().await
+    }
+
+    /// Carries out one request. Blocking: firewall tools and mount run to completion.
+    pub fn dispatch(&self, request: HelperRequest) -> HelperResponse {
+        // Names and owners are written into root's firewall scripts as they are
+        if let Err(reason) = request.rules().iter().try_for_each(FirewallRule::validate) {
+            return HelperResponse::Denied {
+                reason: format!("invalid rule: {}", reason),
+            };
+        }
+
+        let failed = |e: Box<dyn std::error::Error>| HelperResponse::Failed {
+            error: e.to_string(),
+        };
+        match request {
+            HelperRequest::AddRule { rule } => self
+                .firewall
+                .add_rule(&rule)
+                .map_or_else(failed, |_| HelperResponse::Done),
+            HelperRequest::RemoveRule { rule } => self
+                .firewall
+                .remove_rule(&rule)
+                .map_or_else(failed, |_| HelperResponse::Done),
+            HelperRequest::RuleExists { rule } => self
+                .firewall
+                .rule_exists(&rule)
+                .map_or_else(failed, |exists| HelperResponse::Exists { exists }),
+            HelperRequest::Apply { rules } => self
+                .firewall
+                .apply(&rules)
+                .map_or_else(failed, |_| HelperResponse::Done),
+            HelperRequest::ListRules => self
+                .firewall
+                .list_rules()
+                .map_or_else(failed, |rules| HelperResponse::Rules { rules }),
+            HelperRequest::RemoveAllManagedRules => self
+                .firewall
+                .remove_all_managed_rules()
+                .map_or_else(failed, |count| HelperResponse::Removed { count }),
+            HelperRequest::SetupResourceDisk => self.setup_resource_disk(),
+        }
+    }
+
+    fn setup_resource_disk(&self) -> HelperResponse {
+        let Some(resource_disk) = &self.resource_disk else {
+            return HelperResponse::Denied {
+                reason: "resource disk handling is not configured".to_string(),
+            };
+        };
+
+        match resource_disk.setup() {
+            Ok(status) => HelperResponse::ResourceDisk { status },
+            Err(ResourceDiskError::InvalidMountOptions(reason)) => {
+                HelperResponse::Denied { reason }
+            }
+            Err(e) => HelperResponse::Failed {
+                error: e.to_string(),
+            },
+        }
+    }
+}
+
+/// Binds the helper socket at `path`, replacing a stale one. Like the socket
+/// unit, only the owner and `group` may connect; callers are also checked per
+/// connection through peer credentials.
+pub fn bind_socket(path: &Path, group: Option<u32>) -> io::Result<UnixListener> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    if let Some(gid) = group {
+        std::os::unix::fs::chown(path, None, Some(gid))?;
+    }
+    fs::set_permissions(path, fs::Permissions::from_mode(0o660))?;
+    Ok(listener)
+}
+
+/// The listening socket systemd passed through socket activation, if any.
+pub fn systemd_socket() -> io::Result<Option<UnixListener>> {
+    use std::os::unix::io::FromRawFd;
+
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        == Some(std::process::id());
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|fds| fds.parse::<i32>().ok())
+        .unwrap_or(0);
+    if !for_us || count < 1 {
+        return Ok(None);
+    }
+
+    // SAFETY: systemd hands this process ownership of descriptors starting
+    // at SD_LISTEN_FDS_START, and nothing else in the helper has used them.
+    let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    listener.set_nonblocking(true)?;
+    UnixListener::from_std(listener).map(Some)
+}
+
+/// Looks `group` up in a group file such as `/etc/group`.
+pub fn gid_of_group(group: &str, groups: &Path) -> io::Result<Option<u32>> {
+    let contents = fs::read_to_string(groups)?;
+    Ok(contents.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next()? != group {
+            return None;
+        }
+        fields.nth(1)?.parse().ok()
+    }))
+}
+
+/// Looks `user` up in a passwd file such as `/etc/passwd`.
+pub fn uid_of_user(user: &str, passwd: &Path) -> io::Result<Option<u32>> {
+    let contents = fs::read_to_string(passwd)?;
+    Ok(contents.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next()? != user {
+            return None;
+        }
+        fields.nth(1)?.parse().ok()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uid_of_user() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let passwd = dir.path().join("passwd");
+        fs::write(
+            &passwd,
+            "root:x:0:0:root:/root:/bin/bash\n\
+             waagent-rs:x:998:998::/nonexistent:/usr/sbin/nologin\n",
+        )
+        .unwrap();
+
+        assert_eq!(uid_of_user("waagent-rs", &passwd).unwrap(), Some(998));
+        assert_eq!(uid_of_user("root", &passwd).unwrap(), Some(0));
+        assert_eq!(uid_of_user("nobody", &passwd).unwrap(), None);
+    }
+
+    #[test]
+    fn test_gid_of_group() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let groups = dir.path().join("group");
+        fs::write(&groups, "root:x:0:\nwaagent-rs:x:997:\n").unwrap();
+
+        assert_eq!(gid_of_group("waagent-rs", &groups).unwrap(), Some(997));
+        assert_eq!(gid_of_group("nobody", &groups).unwrap(), None);
+    }
+}
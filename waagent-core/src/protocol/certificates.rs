@@ -0,0 +1,44 @@
+use serde::Deserialize;
+
+/// The Certificates goal state document. `data` is a base64 encoded PKCS#7
+/// blob encrypted with the transport certificate the agent sent with the request.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Certificates {
+    #[serde(rename = "Version", default)]
+    pub version: Option<String>,
+    #[serde(rename = "Incarnation", default)]
+    pub incarnation: Option<u32>,
+    #[serde(rename = "Format", default)]
+    pub format: Option<String>,
+    #[serde(rename = "Data", default)]
+    pub data: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quick_xml::de::from_str;
+
+    #[test]
+    fn test_deserialize_certificates() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<CertificateFile><Version>2012-11-30</Version><Incarnation>2</Incarnation><Format>Pkcs7BlobWithPfxContents</Format><Data>MIIOgwYJKoZIhvcNAQcDoIIOdDCCDnACAQIxggEwMIIBLAIBAoAU</Data></CertificateFile>"#;
+
+        let certificates: Certificates = from_str(xml).unwrap();
+
+        assert_eq!(certificates.incarnation, Some(2));
+        assert_eq!(
+            certificates.format.as_deref(),
+            Some("Pkcs7BlobWithPfxContents")
+        );
+        assert!(certificates.data.unwrap().starts_with("MIIOgw"));
+    }
+
+    #[test]
+    fn test_deserialize_certificates_without_data() {
+        let certificates: Certificates =
+            from_str("<CertificateFile><Version>2012-11-30</Version></CertificateFile>").unwrap();
+
+        assert!(certificates.data.is_none());
+    }
+}
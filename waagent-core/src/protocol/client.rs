@@ -0,0 +1,341 @@
+use super::{
+    get_user_agent, Certificates, Configuration, ExtensionsConfig, FullGoalState, GoalState,
+    HandlerStatus, Health, HealthDetails, HealthStatus, HostingEnvironmentConfig, ProtocolError,
+    SharedConfig, StatusUploadRequest, TelemetryData, VmStatus, AGENT_NAME, STATUS_API_VERSION,
+    STATUS_SERVICE_PORT, WIRESERVER_API_VERSION, WIRESERVER_ENDPOINT,
+};
+use base64::prelude::*;
+use quick_xml::de::from_str;
+use quick_xml::se::to_string;
+use reqwest::{Client, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+use tracing::{debug, info};
+
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+const DOWNLOAD_TIMEOUT_SECS: u64 = 300;
+const CERTIFICATES_CIPHER: &str = "DES_EDE3_CBC";
+
+/// Client for the WireServer (host) endpoints used by the agent: goal state,
+/// health, telemetry and the status service.
+#[derive(Debug, Clone)]
+pub struct WireServerClient {
+    client: Client,
+    endpoint: String,
+    status_endpoint: String,
+    transport_certificate: Option<String>,
+}
+
+impl Default for WireServerClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WireServerClient {
+    pub fn new() -> Self {
+        Self::with_endpoint(WIRESERVER_ENDPOINT)
+    }
+
+    /// Creates a client for a WireServer at `endpoint` (e.g. `http://168.63.129.16`).
+    /// The status service is expected on the same host, on its well known port.
+    pub fn with_endpoint(endpoint: &str) -> Self {
+        let endpoint = endpoint.trim_end_matches('/').to_string();
+        let status_endpoint = format!("{}:{}", endpoint, STATUS_SERVICE_PORT);
+        Self::with_endpoints(&endpoint, &status_endpoint)
+    }
+
+    /// Creates a client with an explicit status service endpoint. Mostly useful
+    /// to point both endpoints at a local test server.
+    pub fn with_endpoints(endpoint: &str, status_endpoint: &str) -> Self {
+        Self {
+            client: Client::new(),
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            status_endpoint: status_endpoint.trim_end_matches('/').to_string(),
+            transport_certificate: None,
+        }
+    }
+
+    /// Sets the transport certificate (base64 DER, no PEM armor) sent when
+    /// requesting the Certificates document. Without it the document is skipped.
+    pub fn with_transport_certificate(mut self, certificate: String) -> Self {
+        self.transport_certificate = Some(certificate);
+        self
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    pub async fn fetch_goal_state(&self) -> Result<GoalState, ProtocolError> {
+        let url = format!("{}/machine?comp=goalstate", self.endpoint);
+        let request = self
+            .client
+            .get(&url)
+            .header("x-ms-version", WIRESERVER_API_VERSION);
+
+        let xml = self.send(request, &url).await?.text().await?;
+        let goal_state = from_str::<GoalState>(&xml)?;
+        debug!("Received GoalState: {:?}", goal_state);
+
+        Ok(goal_state)
+    }
+
+    /// Fetches the goal state and follows its URIs, returning the goal state
+    /// with all of its sub-documents resolved.
+    pub async fn fetch_full_goal_state(&self) -> Result<FullGoalState, ProtocolError> {
+        let goal_state = self.fetch_goal_state().await?;
+        self.resolve_goal_state(goal_state, None).await
+    }
+
+    /// Downloads the sub-documents referenced by `goal_state`. Documents whose
+    /// URI did not change since `previous` are reused instead of downloaded again.
+    pub async fn resolve_goal_state(
+        &self,
+        goal_state: GoalState,
+        previous: Option<&FullGoalState>,
+    ) -> Result<FullGoalState, ProtocolError> {
+        let configuration = goal_state.configuration().clone();
+        let previous_configuration = previous.map(|p| p.goal_state.configuration());
+        let unchanged = |uri: &str, previous_uri: fn(&Configuration) -> Option<&str>| {
+            previous_configuration.and_then(previous_uri) == Some(uri)
+        };
+
+        let hosting_environment = match configuration.hosting_environment_config_uri() {
+            Some(uri) if unchanged(uri, Configuration::hosting_environment_config_uri) => {
+                previous.and_then(|p| p.hosting_environment.clone())
+            }
+            Some(uri) => Some(self.fetch_hosting_environment_config(uri).await?),
+            None => None,
+        };
+        let shared_config = match configuration.shared_config_uri() {
+            Some(uri) if unchanged(uri, Configuration::shared_config_uri) => {
+                previous.and_then(|p| p.shared_config.clone())
+            }
+            Some(uri) => Some(self.fetch_shared_config(uri).await?),
+            None => None,
+        };
+        let extensions_config = match configuration.extensions_config_uri() {
+            Some(uri) if unchanged(uri, Configuration::extensions_config_uri) => {
+                previous.and_then(|p| p.extensions_config.clone())
+            }
+            Some(uri) => Some(self.fetch_extensions_config(uri).await?),
+            None => None,
+        };
+        let certificates = match configuration.certificates_uri() {
+            Some(uri)
+                if unchanged(uri, Configuration::certificates_uri)
+                    && previous.is_some_and(|p| p.certificates.is_some()) =>
+            {
+                previous.and_then(|p| p.certificates.clone())
+            }
+            Some(uri) => self.fetch_certificates(uri).await?,
+            None => None,
+        };
+        let full_config = match configuration.full_config_uri() {
+            Some(uri) if unchanged(uri, Configuration::full_config_uri) => {
+                previous.and_then(|p| p.full_config.clone())
+            }
+            Some(uri) => Some(self.fetch_document(uri).await?),
+            None => None,
+        };
+
+        Ok(FullGoalState {
+            goal_state,
+            hosting_environment,
+            shared_config,
+            extensions_config,
+            certificates,
+            full_config,
+        })
+    }
+
+    pub async fn fetch_hosting_environment_config(
+        &self,
+        uri: &str,
+    ) -> Result<HostingEnvironmentConfig, ProtocolError> {
+        self.fetch_xml(uri).await
+    }
+
+    pub async fn fetch_shared_config(&self, uri: &str) -> Result<SharedConfig, ProtocolError> {
+        self.fetch_xml(uri).await
+    }
+
+    pub async fn fetch_extensions_config(
+        &self,
+        uri: &str,
+    ) -> Result<ExtensionsConfig, ProtocolError> {
+        self.fetch_xml(uri).await
+    }
+
+    /// Fetches the Certificates document. Returns `None` when no transport
+    /// certificate has been configured, since the host refuses the request then.
+    pub async fn fetch_certificates(
+        &self,
+        uri: &str,
+    ) -> Result<Option<Certificates>, ProtocolError> {
+        let certificate = match &self.transport_certificate {
+            Some(certificate) => certificate,
+            None => {
+                debug!("No transport certificate configured, skipping Certificates document");
+                return Ok(None);
+            }
+        };
+
+        let request = self
+            .client
+            .get(uri)
+            .header("x-ms-version", WIRESERVER_API_VERSION)
+            .header("x-ms-agent-name", AGENT_NAME)
+            .header("x-ms-cipher-name", CERTIFICATES_CIPHER)
+            .header("x-ms-guest-agent-public-x509-cert", certificate.as_str());
+
+        let xml = self.send(request, uri).await?.text().await?;
+        Ok(Some(from_str::<Certificates>(&xml)?))
+    }
+
+    /// Fetches a goal state sub-document as raw text.
+    pub async fn fetch_document(&self, uri: &str) -> Result<String, ProtocolError> {
+        let request = self
+            .client
+            .get(uri)
+            .header("x-ms-version", WIRESERVER_API_VERSION)
+            .header("x-ms-agent-name", AGENT_NAME);
+
+        Ok(self.send(request, uri).await?.text().await?)
+    }
+
+    /// Downloads an artifact outside of the WireServer API, such as an
+    /// extension manifest or handler package.
+    pub async fn download(&self, uri: &str) -> Result<Vec<u8>, ProtocolError> {
+        let request = self.client.get(uri);
+        let response = self
+            .send_with_timeout(request, uri, Duration::from_secs(DOWNLOAD_TIMEOUT_SECS))
+            .await?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    async fn fetch_xml<T: DeserializeOwned>(&self, uri: &str) -> Result<T, ProtocolError> {
+        let xml = self.fetch_document(uri).await?;
+        Ok(from_str::<T>(&xml)?)
+    }
+
+    pub async fn send_health_report(
+        &self,
+        goal_state: &GoalState,
+        status: HealthStatus,
+    ) -> Result<(), ProtocolError> {
+        self.send_health_report_with_details(goal_state, status, None)
+            .await
+    }
+
+    /// Health report with a substatus, used to report provisioning progress and failures.
+    pub async fn send_health_report_with_details(
+        &self,
+        goal_state: &GoalState,
+        status: HealthStatus,
+        details: Option<HealthDetails>,
+    ) -> Result<(), ProtocolError> {
+        let health_xml = to_string(&Health::with_details(goal_state, status, details))?;
+        debug!("Generated health report XML: {}", health_xml);
+
+        let url = format!("{}/machine?comp=health", self.endpoint);
+        let request = self.wireserver_post(&url).body(health_xml);
+        let response = self.send(request, &url).await?;
+        info!("Health report status: {}", response.status());
+
+        Ok(())
+    }
+
+    pub async fn send_telemetry_event(
+        &self,
+        telemetry: &TelemetryData,
+    ) -> Result<(), ProtocolError> {
+        let telemetry_xml = to_string(telemetry)?;
+        debug!(
+            "Sending {} event: {}",
+            telemetry.event_name(),
+            telemetry_xml
+        );
+
+        let url = format!("{}/machine?comp=telemetrydata", self.endpoint);
+        let request = self.wireserver_post(&url).body(telemetry_xml);
+        let response = self.send(request, &url).await?;
+        info!(
+            "{} event status: {}",
+            telemetry.event_name(),
+            response.status()
+        );
+
+        Ok(())
+    }
+
+    /// Uploads the VM status blob, including the status of every extension handler.
+    pub async fn send_status_report(
+        &self,
+        goal_state: &GoalState,
+        handlers: &[HandlerStatus],
+    ) -> Result<(), ProtocolError> {
+        let status = VmStatus::new(goal_state, handlers.to_vec());
+        let status_content_str = serde_json::to_string(&status)?;
+        let payload = StatusUploadRequest::new(
+            goal_state,
+            BASE64_STANDARD.encode(status_content_str.as_bytes()),
+        );
+
+        let url = format!("{}/status", self.status_endpoint);
+        let role_instance = goal_state.role_instance_id();
+        let request = self
+            .client
+            .put(&url)
+            .header("x-ms-version", STATUS_API_VERSION)
+            .header("x-ms-agent-name", AGENT_NAME)
+            .header("User-Agent", get_user_agent())
+            .header("x-ms-containerid", goal_state.container_id())
+            .header(
+                "x-ms-host-config-name",
+                format!("{}.0.{}.0._gpg.1.xml", role_instance, role_instance),
+            )
+            .json(&payload);
+
+        let response = self.send(request, &url).await?;
+        info!("Status service response: {}", response.status());
+
+        Ok(())
+    }
+
+    fn wireserver_post(&self, url: &str) -> RequestBuilder {
+        self.client
+            .post(url)
+            .header("x-ms-version", WIRESERVER_API_VERSION)
+            .header("x-ms-agent-name", AGENT_NAME)
+            .header("User-Agent", get_user_agent())
+            .header("Content-Type", "text/xml;charset=utf-8")
+    }
+
+    async fn send(&self, request: RequestBuilder, url: &str) -> Result<Response, ProtocolError> {
+        self.send_with_timeout(request, url, Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .await
+    }
+
+    async fn send_with_timeout(
+        &self,
+        request: RequestBuilder,
+        url: &str,
+        timeout: Duration,
+    ) -> Result<Response, ProtocolError> {
+        let response = request.timeout(timeout).send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let body = response.text().await.unwrap_or_default();
+            return Err(ProtocolError::UnexpectedStatus {
+                url: url.to_string(),
+                status,
+                body,
+            });
+        }
+
+        Ok(response)
+    }
+}
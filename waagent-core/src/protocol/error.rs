@@ -0,0 +1,72 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// The request could not be sent or the response body could not be read.
+    Http(reqwest::Error),
+    /// The WireServer answered with a non-success status code.
+    UnexpectedStatus {
+        url: String,
+        status: u16,
+        body: String,
+    },
+    /// An XML document could not be (de)serialized.
+    Xml(quick_xml::DeError),
+    /// A JSON document could not be (de)serialized.
+    Json(serde_json::Error),
+}
+
+impl ProtocolError {
+    /// Returns true when the WireServer could not be reached at all, e.g. because
+    /// of a timeout or a refused connection. Callers use this to decide whether
+    /// the firewall is getting in the way.
+    pub fn is_connectivity(&self) -> bool {
+        match self {
+            ProtocolError::Http(e) => e.is_timeout() || e.is_connect(),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Http(e) => write!(f, "HTTP request failed: {}", e),
+            ProtocolError::UnexpectedStatus { url, status, body } => {
+                write!(f, "{} returned status {}: {}", url, status, body)
+            }
+            ProtocolError::Xml(e) => write!(f, "invalid XML document: {}", e),
+            ProtocolError::Json(e) => write!(f, "invalid JSON document: {}", e),
+        }
+    }
+}
+
+impl Error for ProtocolError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ProtocolError::Http(e) => Some(e),
+            ProtocolError::UnexpectedStatus { .. } => None,
+            ProtocolError::Xml(e) => Some(e),
+            ProtocolError::Json(e) => Some(e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for ProtocolError {
+    fn from(e: reqwest::Error) -> Self {
+        ProtocolError::Http(e)
+    }
+}
+
+impl From<quick_xml::DeError> for ProtocolError {
+    fn from(e: quick_xml::DeError) -> Self {
+        ProtocolError::Xml(e)
+    }
+}
+
+impl From<serde_json::Error> for ProtocolError {
+    fn from(e: serde_json::Error) -> Self {
+        ProtocolError::Json(e)
+    }
+}
@@ -0,0 +1,195 @@
+use serde::Deserialize;
+
+/// The ExtensionsConfig goal state document: which VM extensions (plugins)
+/// should be present and the settings for each of them.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ExtensionsConfig {
+    #[serde(rename = "GuestAgentExtension", default)]
+    pub guest_agent_extension: Option<GuestAgentExtension>,
+    #[serde(rename = "StatusUploadBlob", default)]
+    pub status_upload_blob: Option<StatusUploadBlob>,
+    #[serde(rename = "InVMGoalStateMetaData", default)]
+    pub in_vm_metadata: Option<InVmGoalStateMetaData>,
+    #[serde(rename = "Plugins", default)]
+    pub plugins: Plugins,
+    #[serde(rename = "PluginSettings", default)]
+    pub plugin_settings: PluginSettings,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct GuestAgentExtension {
+    #[serde(rename = "GAFamilies", default)]
+    pub ga_families: GaFamilies,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct GaFamilies {
+    #[serde(rename = "GAFamily", default)]
+    pub families: Vec<GaFamily>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct GaFamily {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Version", default)]
+    pub version: Option<String>,
+    #[serde(rename = "Uris", default)]
+    pub uris: Uris,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Uris {
+    #[serde(rename = "Uri", default)]
+    pub uris: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct StatusUploadBlob {
+    #[serde(rename = "@statusBlobType", default)]
+    pub blob_type: Option<String>,
+    #[serde(rename = "$text")]
+    pub uri: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct InVmGoalStateMetaData {
+    #[serde(rename = "@inSvdSeqNo", default)]
+    pub in_svd_seq_no: Option<String>,
+    #[serde(rename = "@activityId", default)]
+    pub activity_id: Option<String>,
+    #[serde(rename = "@correlationId", default)]
+    pub correlation_id: Option<String>,
+    #[serde(rename = "@createdOnTicks", default)]
+    pub created_on_ticks: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Plugins {
+    #[serde(rename = "Plugin", default)]
+    pub plugins: Vec<Plugin>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Plugin {
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(rename = "@version")]
+    pub version: String,
+    #[serde(rename = "@location", default)]
+    pub location: Option<String>,
+    #[serde(rename = "@failoverlocation", default)]
+    pub failover_location: Option<String>,
+    #[serde(rename = "@state", default)]
+    pub state: Option<String>,
+    #[serde(rename = "@autoUpgrade", default)]
+    pub auto_upgrade: Option<String>,
+    #[serde(rename = "@isJson", default)]
+    pub is_json: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct PluginSettings {
+    #[serde(rename = "Plugin", default)]
+    pub plugins: Vec<PluginSetting>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PluginSetting {
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(rename = "@version")]
+    pub version: String,
+    #[serde(rename = "RuntimeSettings", default)]
+    pub runtime_settings: Option<RuntimeSettings>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RuntimeSettings {
+    #[serde(rename = "@seqNo")]
+    pub seq_no: u32,
+    /// JSON document with the handler settings, passed to the extension as-is.
+    #[serde(rename = "$text", default)]
+    pub settings: String,
+}
+
+impl ExtensionsConfig {
+    pub fn plugins(&self) -> &[Plugin] {
+        &self.plugins.plugins
+    }
+
+    /// Settings for the plugin with the given name and version, if the host sent any.
+    pub fn settings_for(&self, name: &str, version: &str) -> Option<&PluginSetting> {
+        self.plugin_settings
+            .plugins
+            .iter()
+            .find(|p| p.name == name && p.version == version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quick_xml::de::from_str;
+
+    #[test]
+    fn test_deserialize_extensions_config() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<Extensions version="1.0.0.0" goalStateIncarnation="2">
+  <GuestAgentExtension xmlns:i="http://www.w3.org/2001/XMLSchema-instance">
+    <GAFamilies>
+      <GAFamily>
+        <Name>Prod</Name>
+        <Uris>
+          <Uri>https://zrdfepirv2.blob.core.windows.net/Prod_manifest.xml</Uri>
+          <Uri>https://rdfepirv2.blob.core.windows.net/Prod_manifest.xml</Uri>
+        </Uris>
+      </GAFamily>
+    </GAFamilies>
+  </GuestAgentExtension>
+  <StatusUploadBlob statusBlobType="BlockBlob">https://storage.blob.core.windows.net/vhds/vm.status?sv=2014-02-14</StatusUploadBlob>
+  <InVMGoalStateMetaData inSvdSeqNo="2" activityId="a" correlationId="c" />
+  <Plugins>
+    <Plugin name="Microsoft.Azure.Extensions.CustomScript" version="2.1.10" location="https://host/manifest.xml" state="enabled" autoUpgrade="true" isJson="true" />
+  </Plugins>
+  <PluginSettings>
+    <Plugin name="Microsoft.Azure.Extensions.CustomScript" version="2.1.10">
+      <RuntimeSettings seqNo="4">{"runtimeSettings":[{"handlerSettings":{"publicSettings":{"commandToExecute":"echo hi"}}}]}</RuntimeSettings>
+    </Plugin>
+  </PluginSettings>
+</Extensions>"#;
+
+        let config: ExtensionsConfig = from_str(xml).unwrap();
+
+        let families = &config.guest_agent_extension.as_ref().unwrap().ga_families;
+        assert_eq!(families.families[0].name, "Prod");
+        assert_eq!(families.families[0].uris.uris.len(), 2);
+
+        let blob = config.status_upload_blob.as_ref().unwrap();
+        assert_eq!(blob.blob_type.as_deref(), Some("BlockBlob"));
+        assert!(blob.uri.ends_with("vm.status?sv=2014-02-14"));
+
+        assert_eq!(config.plugins().len(), 1);
+        let plugin = &config.plugins()[0];
+        assert_eq!(plugin.name, "Microsoft.Azure.Extensions.CustomScript");
+        assert_eq!(plugin.state.as_deref(), Some("enabled"));
+
+        let settings = config
+            .settings_for(&plugin.name, &plugin.version)
+            .and_then(|s| s.runtime_settings.as_ref())
+            .unwrap();
+        assert_eq!(settings.seq_no, 4);
+        assert!(settings.settings.contains("commandToExecute"));
+    }
+
+    #[test]
+    fn test_deserialize_empty_extensions_config() {
+        let config: ExtensionsConfig =
+            from_str(r#"<Extensions version="1.0.0.0" goalStateIncarnation="1"></Extensions>"#)
+                .unwrap();
+
+        assert!(config.plugins().is_empty());
+        assert!(config.status_upload_blob.is_none());
+        assert!(config.settings_for("any", "1.0").is_none());
+    }
+}
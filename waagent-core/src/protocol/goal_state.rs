@@ -0,0 +1,237 @@
+use super::{Certificates, ExtensionsConfig, HostingEnvironmentConfig, SharedConfig};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct GoalState {
+    #[serde(rename = "Version")]
+    pub version: String,
+    #[serde(rename = "Incarnation")]
+    pub incarnation: u32,
+    #[serde(rename = "Machine", default)]
+    pub machine: Machine,
+    #[serde(rename = "Container")]
+    pub container: Container,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Machine {
+    #[serde(rename = "ExpectedState", default)]
+    pub expected_state: Option<String>,
+    #[serde(rename = "StopRolesDeadlineHint", default)]
+    pub stop_roles_deadline_hint: Option<u32>,
+    #[serde(rename = "LBProbePorts", default)]
+    pub lb_probe_ports: Option<LBProbePorts>,
+    #[serde(rename = "ExpectHealthReport", default)]
+    pub expect_health_report: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LBProbePorts {
+    #[serde(rename = "Port", default)]
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Container {
+    #[serde(rename = "ContainerId")]
+    pub container_id: String,
+    #[serde(rename = "RoleInstanceList")]
+    pub role_instance_list: RoleInstanceList,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RoleInstanceList {
+    #[serde(rename = "RoleInstance")]
+    pub role_instance: RoleInstance,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RoleInstance {
+    #[serde(rename = "InstanceId")]
+    pub instance_id: String,
+    #[serde(rename = "State", default)]
+    pub state: Option<String>,
+    #[serde(rename = "Configuration", default)]
+    pub configuration: Configuration,
+}
+
+/// URIs of the goal state sub-documents. Older fabric versions omit some of
+/// them, so every entry is optional.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Configuration {
+    #[serde(rename = "HostingEnvironmentConfig", default)]
+    pub hosting_environment_config: Option<String>,
+    #[serde(rename = "SharedConfig", default)]
+    pub shared_config: Option<String>,
+    #[serde(rename = "ExtensionsConfig", default)]
+    pub extensions_config: Option<String>,
+    #[serde(rename = "FullConfig", default)]
+    pub full_config: Option<String>,
+    #[serde(rename = "Certificates", default)]
+    pub certificates: Option<String>,
+    #[serde(rename = "ConfigName", default)]
+    pub config_name: Option<String>,
+}
+
+/// A goal state together with every sub-document it references, downloaded
+/// and parsed. Documents the goal state does not reference are `None`.
+#[derive(Debug, Clone)]
+pub struct FullGoalState {
+    pub goal_state: GoalState,
+    pub hosting_environment: Option<HostingEnvironmentConfig>,
+    pub shared_config: Option<SharedConfig>,
+    pub extensions_config: Option<ExtensionsConfig>,
+    pub certificates: Option<Certificates>,
+    /// The FullConfig document is only kept as raw XML.
+    pub full_config: Option<String>,
+}
+
+impl FullGoalState {
+    pub fn incarnation(&self) -> u32 {
+        self.goal_state.incarnation
+    }
+}
+
+impl GoalState {
+    pub fn container_id(&self) -> &str {
+        &self.container.container_id
+    }
+
+    pub fn role_instance_id(&self) -> &str {
+        &self.container.role_instance_list.role_instance.instance_id
+    }
+
+    pub fn configuration(&self) -> &Configuration {
+        &self
+            .container
+            .role_instance_list
+            .role_instance
+            .configuration
+    }
+}
+
+impl Configuration {
+    pub fn hosting_environment_config_uri(&self) -> Option<&str> {
+        non_empty(&self.hosting_environment_config)
+    }
+
+    pub fn shared_config_uri(&self) -> Option<&str> {
+        non_empty(&self.shared_config)
+    }
+
+    pub fn extensions_config_uri(&self) -> Option<&str> {
+        non_empty(&self.extensions_config)
+    }
+
+    pub fn full_config_uri(&self) -> Option<&str> {
+        non_empty(&self.full_config)
+    }
+
+    pub fn certificates_uri(&self) -> Option<&str> {
+        non_empty(&self.certificates)
+    }
+}
+
+// Some fabric versions send the element with no content instead of leaving it out.
+fn non_empty(uri: &Option<String>) -> Option<&str> {
+    uri.as_deref().map(str::trim).filter(|u| !u.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quick_xml::de::from_str;
+
+    const GOAL_STATE_XML: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<GoalState xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:noNamespaceSchemaLocation="goalstate10.xsd">
+  <Version>2012-11-30</Version>
+  <Incarnation>3</Incarnation>
+  <Machine>
+    <ExpectedState>Started</ExpectedState>
+    <StopRolesDeadlineHint>300000</StopRolesDeadlineHint>
+    <LBProbePorts>
+      <Port>16001</Port>
+    </LBProbePorts>
+    <ExpectHealthReport>FALSE</ExpectHealthReport>
+  </Machine>
+  <Container>
+    <ContainerId>c6d5526c-5ac2-4200-b6e2-56f2b70c5ab2</ContainerId>
+    <RoleInstanceList>
+      <RoleInstance>
+        <InstanceId>b61f93d0.MachineRole_IN_0</InstanceId>
+        <State>Started</State>
+        <Configuration>
+          <HostingEnvironmentConfig>http://168.63.129.16:80/machine/c6d5526c/b61f93d0?comp=config&amp;type=hostingEnvironmentConfig&amp;incarnation=3</HostingEnvironmentConfig>
+          <SharedConfig>http://168.63.129.16:80/machine/c6d5526c/b61f93d0?comp=config&amp;type=sharedConfig&amp;incarnation=3</SharedConfig>
+          <ExtensionsConfig>http://168.63.129.16:80/machine/c6d5526c/b61f93d0?comp=config&amp;type=extensionsConfig&amp;incarnation=3</ExtensionsConfig>
+          <FullConfig>http://168.63.129.16:80/machine/c6d5526c/b61f93d0?comp=config&amp;type=fullConfig&amp;incarnation=3</FullConfig>
+          <Certificates>http://168.63.129.16:80/machine/c6d5526c/b61f93d0?comp=certificates&amp;incarnation=3</Certificates>
+          <ConfigName>b61f93d0.0.b61f93d0.0._canary.1.xml</ConfigName>
+        </Configuration>
+      </RoleInstance>
+    </RoleInstanceList>
+  </Container>
+</GoalState>"#;
+
+    #[test]
+    fn test_deserialize_goal_state() {
+        let goal_state: GoalState = from_str(GOAL_STATE_XML).unwrap();
+
+        assert_eq!(goal_state.version, "2012-11-30");
+        assert_eq!(goal_state.incarnation, 3);
+        assert_eq!(
+            goal_state.machine.lb_probe_ports.as_ref().unwrap().port,
+            Some(16001)
+        );
+        assert_eq!(
+            goal_state.container_id(),
+            "c6d5526c-5ac2-4200-b6e2-56f2b70c5ab2"
+        );
+        assert_eq!(goal_state.role_instance_id(), "b61f93d0.MachineRole_IN_0");
+        assert!(goal_state
+            .configuration()
+            .extensions_config
+            .as_ref()
+            .unwrap()
+            .ends_with("type=extensionsConfig&incarnation=3"));
+    }
+
+    #[test]
+    fn test_deserialize_goal_state_without_optional_elements() {
+        let xml = r#"<GoalState>
+  <Version>2010-12-15</Version>
+  <Incarnation>1</Incarnation>
+  <Machine>
+    <ExpectedState>Started</ExpectedState>
+  </Machine>
+  <Container>
+    <ContainerId>container</ContainerId>
+    <RoleInstanceList>
+      <RoleInstance>
+        <InstanceId>instance</InstanceId>
+        <Configuration>
+          <SharedConfig>http://168.63.129.16/shared</SharedConfig>
+          <Certificates></Certificates>
+        </Configuration>
+      </RoleInstance>
+    </RoleInstanceList>
+  </Container>
+</GoalState>"#;
+        let goal_state: GoalState = from_str(xml).unwrap();
+
+        assert!(goal_state.machine.lb_probe_ports.is_none());
+        assert!(goal_state.machine.expect_health_report.is_none());
+        let configuration = goal_state.configuration();
+        assert_eq!(
+            configuration.shared_config.as_deref(),
+            Some("http://168.63.129.16/shared")
+        );
+        assert!(configuration.extensions_config_uri().is_none());
+        assert!(configuration.certificates_uri().is_none());
+    }
+
+    #[test]
+    fn test_deserialize_goal_state_rejects_garbage() {
+        assert!(from_str::<GoalState>("<GoalState><Version>1</Version></GoalState>").is_err());
+    }
+}
@@ -0,0 +1,164 @@
+use super::GoalState;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthStatus {
+    Ready,
+    NotReady,
+}
+
+impl fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthStatus::Ready => write!(f, "Ready"),
+            HealthStatus::NotReady => write!(f, "NotReady"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Health {
+    #[serde(rename = "GoalStateIncarnation")]
+    pub goal_state_incarnation: u32,
+    #[serde(rename = "Container")]
+    pub container: HealthContainer,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthContainer {
+    #[serde(rename = "ContainerId")]
+    pub container_id: String,
+    #[serde(rename = "RoleInstanceList")]
+    pub role_instance_list: HealthRoleInstanceList,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthRoleInstanceList {
+    #[serde(rename = "Role")]
+    pub role: HealthRole,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthRole {
+    #[serde(rename = "InstanceId")]
+    pub instance_id: String,
+    #[serde(rename = "Health")]
+    pub health: HealthState,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthState {
+    #[serde(rename = "State")]
+    pub state: String,
+    #[serde(rename = "Details", skip_serializing_if = "Option::is_none")]
+    pub details: Option<HealthDetails>,
+}
+
+/// Why the VM is not ready yet, e.g. while provisioning is still running.
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthDetails {
+    #[serde(rename = "SubStatus")]
+    pub sub_status: String,
+    #[serde(rename = "Description")]
+    pub description: String,
+}
+
+impl HealthDetails {
+    pub fn new(sub_status: impl Into<String>, description: impl Into<String>) -> Self {
+        Self {
+            sub_status: sub_status.into(),
+            description: description.into(),
+        }
+    }
+
+    pub fn provisioning(description: impl Into<String>) -> Self {
+        Self::new("Provisioning", description)
+    }
+
+    pub fn provisioning_failed(description: impl Into<String>) -> Self {
+        Self::new("ProvisioningFailed", description)
+    }
+}
+
+impl Health {
+    pub fn new(goal_state: &GoalState, status: HealthStatus) -> Self {
+        Self::with_details(goal_state, status, None)
+    }
+
+    pub fn with_details(
+        goal_state: &GoalState,
+        status: HealthStatus,
+        details: Option<HealthDetails>,
+    ) -> Self {
+        Health {
+            goal_state_incarnation: goal_state.incarnation,
+            container: HealthContainer {
+                container_id: goal_state.container_id().to_string(),
+                role_instance_list: HealthRoleInstanceList {
+                    role: HealthRole {
+                        instance_id: goal_state.role_instance_id().to_string(),
+                        health: HealthState {
+                            state: status.to_string(),
+                            details,
+                        },
+                    },
+                },
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_health() {
+        let health = Health {
+            goal_state_incarnation: 2,
+            container: HealthContainer {
+                container_id: "container".to_string(),
+                role_instance_list: HealthRoleInstanceList {
+                    role: HealthRole {
+                        instance_id: "instance".to_string(),
+                        health: HealthState {
+                            state: HealthStatus::Ready.to_string(),
+                            details: None,
+                        },
+                    },
+                },
+            },
+        };
+
+        let xml = quick_xml::se::to_string(&health).unwrap();
+        assert_eq!(
+            xml,
+            "<Health><GoalStateIncarnation>2</GoalStateIncarnation><Container><ContainerId>container</ContainerId><RoleInstanceList><Role><InstanceId>instance</InstanceId><Health><State>Ready</State></Health></Role></RoleInstanceList></Container></Health>"
+        );
+    }
+
+    #[test]
+    fn test_serialize_health_with_details() {
+        let health = Health {
+            goal_state_incarnation: 1,
+            container: HealthContainer {
+                container_id: "container".to_string(),
+                role_instance_list: HealthRoleInstanceList {
+                    role: HealthRole {
+                        instance_id: "instance".to_string(),
+                        health: HealthState {
+                            state: HealthStatus::NotReady.to_string(),
+                            details: Some(HealthDetails::provisioning_failed("useradd failed")),
+                        },
+                    },
+                },
+            },
+        };
+
+        let xml = quick_xml::se::to_string(&health).unwrap();
+        assert!(xml.contains(
+            "<Health><State>NotReady</State><Details><SubStatus>ProvisioningFailed</SubStatus><Description>useradd failed</Description></Details></Health>"
+        ));
+    }
+}
@@ -0,0 +1,57 @@
+use super::shared_config::{Deployment, IncarnationInfo};
+use serde::Deserialize;
+
+/// The HostingEnvironmentConfig goal state document. The agent only needs the
+/// deployment and role identity out of it.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct HostingEnvironmentConfig {
+    #[serde(rename = "Deployment", default)]
+    pub deployment: Option<Deployment>,
+    #[serde(rename = "Incarnation", default)]
+    pub incarnation: Option<IncarnationInfo>,
+    #[serde(rename = "Role", default)]
+    pub role: Option<HostingRole>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct HostingRole {
+    #[serde(rename = "@name", default)]
+    pub name: Option<String>,
+    #[serde(rename = "@guid", default)]
+    pub guid: Option<String>,
+    #[serde(rename = "@hostingEnvironmentVersion", default)]
+    pub hosting_environment_version: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quick_xml::de::from_str;
+
+    #[test]
+    fn test_deserialize_hosting_environment_config() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<HostingEnvironmentConfig version="1.0.0.0" goalStateIncarnation="1">
+  <StoredCertificates>
+    <StoredCertificate name="Stored0Microsoft.WindowsAzure.Plugins.RemoteAccess.PasswordEncryption" certificateId="sha1:C093FA5CD3AAE057CB7C4E04532B2E16E07C26CA" storeName="My" configurationLevel="System" />
+  </StoredCertificates>
+  <Deployment name="db00a7755a5e4e8a8fe4b19bc3b330c3" guid="{ce5a036f-5c93-40e7-8adf-2613631008ab}" incarnation="2">
+    <Service name="MyVMRoleService" guid="{00000000-0000-0000-0000-000000000000}" />
+  </Deployment>
+  <Incarnation number="1" instance="MachineRole_IN_0" guid="{a0faca35-52e5-4ec7-8fd1-63d2bc107d9b}" />
+  <Role guid="{73d95f1c-6472-e58e-7a1a-523554e11d46}" name="MachineRole" hostingEnvironmentVersion="1" software="" softwareType="ApplicationPackage" entryPoint="" parameters="" settleTimeSeconds="10" />
+  <HostingEnvironmentSettings name="full" Runtime="rd_fabric_stable.110217-1402.RuntimePackage_1.0.0.8.zip">
+    <CAS mode="full" />
+    <PrivilegeLevel mode="max" />
+  </HostingEnvironmentSettings>
+</HostingEnvironmentConfig>"#;
+
+        let config: HostingEnvironmentConfig = from_str(xml).unwrap();
+
+        assert_eq!(config.role.unwrap().name.as_deref(), Some("MachineRole"));
+        assert_eq!(
+            config.incarnation.unwrap().instance.as_deref(),
+            Some("MachineRole_IN_0")
+        );
+    }
+}
@@ -0,0 +1,57 @@
+mod certificates;
+mod client;
+mod error;
+mod extensions_config;
+mod goal_state;
+mod health;
+mod hosting_environment;
+mod poller;
+mod shared_config;
+mod status;
+mod telemetry;
+
+pub use certificates::Certificates;
+pub use client::WireServerClient;
+pub use error::ProtocolError;
+pub use extensions_config::{
+    ExtensionsConfig, GaFamilies, GaFamily, GuestAgentExtension, InVmGoalStateMetaData, Plugin,
+    PluginSetting, PluginSettings, Plugins, RuntimeSettings, StatusUploadBlob, Uris,
+};
+pub use goal_state::{
+    Configuration, Container, FullGoalState, GoalState, LBProbePorts, Machine, RoleInstance,
+    RoleInstanceList,
+};
+pub use health::{
+    Health, HealthContainer, HealthDetails, HealthRole, HealthRoleInstanceList, HealthState, HealthStatus,
+};
+pub use hosting_environment::{HostingEnvironmentConfig, HostingRole};
+pub use poller::{GoalStateEvent, GoalStatePoller};
+pub use shared_config::{Deployment, IncarnationInfo, Instance, Instances, Role, SharedConfig};
+pub use status::{
+    AggregateStatus, ExtensionStatus, FormattedMessage, GoalStateAggregateStatus, GuestAgentStatus,
+    GuestOsInfo, HandlerStatus, RuntimeSettingsStatus, SettingsStatus, StatusHeader,
+    StatusUploadRequest, SubStatus, SupportedFeature, UpdateStatus, VmArtifactsAggregateStatus,
+    VmStatus,
+};
+pub use telemetry::{Event, EventData, Param, Provider, TelemetryData};
+
+use chrono::Utc;
+
+pub const WIRESERVER_ENDPOINT: &str = "http://168.63.129.16";
+pub const STATUS_SERVICE_PORT: u16 = 32526;
+pub const AGENT_VERSION: &str = "waagent-rs/0.0.1";
+pub const AGENT_NAME: &str = "waagent-rs";
+pub const WIRESERVER_API_VERSION: &str = "2012-11-30";
+pub const STATUS_API_VERSION: &str = "2015-09-01";
+
+fn get_timestamp() -> String {
+    Utc::now().format("%Y-%m-%dT%H:%M:%S.%3fZ").to_string()
+}
+
+fn get_rfc3339_timestamp() -> String {
+    Utc::now().to_rfc3339()
+}
+
+fn get_user_agent() -> String {
+    format!("{}/{}", AGENT_NAME, AGENT_VERSION)
+}
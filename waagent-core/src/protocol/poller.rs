@@ -0,0 +1,102 @@
+use super::{FullGoalState, ProtocolError, WireServerClient};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+const EVENT_CHANNEL_CAPACITY: usize = 16;
+
+/// Events published by the [`GoalStatePoller`] to its subscribers.
+#[derive(Debug, Clone)]
+pub enum GoalStateEvent {
+    /// The host published a goal state with a new incarnation.
+    NewGoalState(Arc<FullGoalState>),
+}
+
+/// Polls the WireServer for the goal state and notifies subscribers when the
+/// incarnation changes. Sub-documents are only downloaded again when their
+/// URI changed between incarnations.
+pub struct GoalStatePoller {
+    client: WireServerClient,
+    period: Duration,
+    initial_period: Duration,
+    current: Option<Arc<FullGoalState>>,
+    sender: broadcast::Sender<GoalStateEvent>,
+}
+
+impl GoalStatePoller {
+    /// `initial_period` is used until the first goal state has been retrieved,
+    /// `period` afterwards.
+    pub fn new(client: WireServerClient, period: Duration, initial_period: Duration) -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self {
+            client,
+            period,
+            initial_period,
+            current: None,
+            sender,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<GoalStateEvent> {
+        self.sender.subscribe()
+    }
+
+    /// The most recent goal state, if one has been retrieved yet.
+    pub fn current(&self) -> Option<Arc<FullGoalState>> {
+        self.current.clone()
+    }
+
+    /// Fetches the goal state once. Returns the new goal state (after
+    /// notifying subscribers) when the incarnation changed, `None` otherwise.
+    pub async fn poll_once(&mut self) -> Result<Option<Arc<FullGoalState>>, ProtocolError> {
+        let goal_state = self.client.fetch_goal_state().await?;
+
+        if let Some(current) = &self.current {
+            if current.incarnation() == goal_state.incarnation {
+                debug!(
+                    "Goal state incarnation {} unchanged",
+                    goal_state.incarnation
+                );
+                return Ok(None);
+            }
+        }
+
+        let previous_incarnation = self.current.as_ref().map(|c| c.incarnation());
+        let full_goal_state = Arc::new(
+            self.client
+                .resolve_goal_state(goal_state, self.current.as_deref())
+                .await?,
+        );
+        info!(
+            "New goal state, incarnation {} (previous {:?})",
+            full_goal_state.incarnation(),
+            previous_incarnation
+        );
+
+        self.current = Some(full_goal_state.clone());
+        // Sending only fails when nobody is subscribed, which is fine.
+        let _ = self
+            .sender
+            .send(GoalStateEvent::NewGoalState(full_goal_state.clone()));
+
+        Ok(Some(full_goal_state))
+    }
+
+    /// Polls forever, waiting the initial or regular period between attempts.
+    /// Errors are logged and retried on the next period.
+    pub async fn run(mut self) {
+        loop {
+            let period = if self.current.is_some() {
+                self.period
+            } else {
+                self.initial_period
+            };
+            tokio::time::sleep(period).await;
+
+            if let Err(e) = self.poll_once().await {
+                warn!("Failed to poll goal state: {}", e);
+            }
+        }
+    }
+}
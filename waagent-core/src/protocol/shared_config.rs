@@ -0,0 +1,94 @@
+use serde::Deserialize;
+
+/// The SharedConfig goal state document describing the deployment, the role
+/// and the role instances (with their private addresses).
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct SharedConfig {
+    #[serde(rename = "Deployment", default)]
+    pub deployment: Option<Deployment>,
+    #[serde(rename = "Incarnation", default)]
+    pub incarnation: Option<IncarnationInfo>,
+    #[serde(rename = "Role", default)]
+    pub role: Option<Role>,
+    #[serde(rename = "Instances", default)]
+    pub instances: Instances,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Deployment {
+    #[serde(rename = "@name", default)]
+    pub name: Option<String>,
+    #[serde(rename = "@guid", default)]
+    pub guid: Option<String>,
+    #[serde(rename = "@incarnation", default)]
+    pub incarnation: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct IncarnationInfo {
+    #[serde(rename = "@number", default)]
+    pub number: Option<String>,
+    #[serde(rename = "@instance", default)]
+    pub instance: Option<String>,
+    #[serde(rename = "@guid", default)]
+    pub guid: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Role {
+    #[serde(rename = "@name", default)]
+    pub name: Option<String>,
+    #[serde(rename = "@guid", default)]
+    pub guid: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Instances {
+    #[serde(rename = "Instance", default)]
+    pub instances: Vec<Instance>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Instance {
+    #[serde(rename = "@id")]
+    pub id: String,
+    #[serde(rename = "@address", default)]
+    pub address: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quick_xml::de::from_str;
+
+    #[test]
+    fn test_deserialize_shared_config() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<SharedConfig version="1.0.0.0" goalStateIncarnation="1">
+  <Deployment name="db00a7755a5e4e8a8fe4b19bc3b330c3" guid="{ce5a036f-5c93-40e7-8adf-2613631008ab}" incarnation="2">
+    <Service name="MyVMRoleService" guid="{00000000-0000-0000-0000-000000000000}" />
+    <ServiceInstance name="db00a7755a5e4e8a8fe4b19bc3b330c3.1" guid="{d113f4d7-9ead-4e73-b715-b724b5b7842c}" />
+  </Deployment>
+  <Incarnation number="1" instance="MachineRole_IN_0" guid="{a0faca35-52e5-4ec7-8fd1-63d2bc107d9b}" />
+  <Role guid="{73d95f1c-6472-e58e-7a1a-523554e11d46}" name="MachineRole" settleTimeSeconds="10" />
+  <Instances>
+    <Instance id="MachineRole_IN_0" address="10.115.153.75">
+      <FaultDomains randomId="0" updateId="0" updateCount="0" />
+    </Instance>
+  </Instances>
+</SharedConfig>"#;
+
+        let shared: SharedConfig = from_str(xml).unwrap();
+
+        assert_eq!(
+            shared.deployment.unwrap().name.as_deref(),
+            Some("db00a7755a5e4e8a8fe4b19bc3b330c3")
+        );
+        assert_eq!(shared.role.unwrap().name.as_deref(), Some("MachineRole"));
+        assert_eq!(shared.instances.instances.len(), 1);
+        assert_eq!(
+            shared.instances.instances[0].address.as_deref(),
+            Some("10.115.153.75")
+        );
+    }
+}
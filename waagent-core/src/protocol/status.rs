@@ -0,0 +1,253 @@
+use super::{get_rfc3339_timestamp, get_timestamp, GoalState, AGENT_VERSION};
+use crate::system::SystemInfo;
+use serde::{Deserialize, Serialize};
+
+/// Body of the PUT request sent to the host status service. The status blob
+/// itself travels base64 encoded in `content`.
+#[derive(Debug, Serialize)]
+pub struct StatusUploadRequest {
+    pub content: String,
+    pub headers: Vec<StatusHeader>,
+    #[serde(rename = "requestUri")]
+    pub request_uri: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatusHeader {
+    #[serde(rename = "headerName")]
+    pub header_name: String,
+    #[serde(rename = "headerValue")]
+    pub header_value: String,
+}
+
+impl StatusHeader {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        StatusHeader {
+            header_name: name.into(),
+            header_value: value.into(),
+        }
+    }
+}
+
+impl StatusUploadRequest {
+    pub fn new(goal_state: &GoalState, content_b64: String) -> Self {
+        StatusUploadRequest {
+            content: content_b64,
+            headers: vec![
+                StatusHeader::new("Content-Length", "1024"),
+                StatusHeader::new("x-ms-date", get_timestamp()),
+                StatusHeader::new("x-ms-range", "bytes=0-1023"),
+                StatusHeader::new("x-ms-page-write", "update"),
+                StatusHeader::new("x-ms-version", "2014-02-14"),
+            ],
+            request_uri: format!(
+                "https://md-hdd-placeholder.z27.blob.storage.azure.net/$system/gpg.{}.status",
+                goal_state.container_id()
+            ),
+        }
+    }
+}
+
+const LANG: &str = "en-US";
+
+/// The status blob reported to the host: agent, handler and goal state status.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VmStatus {
+    pub version: String,
+    #[serde(rename = "timestampUTC")]
+    pub timestamp_utc: String,
+    pub aggregate_status: AggregateStatus,
+    #[serde(rename = "guestOSInfo")]
+    pub guest_os_info: GuestOsInfo,
+    pub supported_features: Vec<SupportedFeature>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AggregateStatus {
+    pub guest_agent_status: GuestAgentStatus,
+    pub handler_aggregate_status: Vec<HandlerStatus>,
+    pub vm_artifacts_aggregate_status: VmArtifactsAggregateStatus,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GuestAgentStatus {
+    pub version: String,
+    pub status: String,
+    pub formatted_message: FormattedMessage,
+    pub update_status: UpdateStatus,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateStatus {
+    pub expected_version: String,
+    pub status: String,
+    pub code: i32,
+    pub formatted_message: FormattedMessage,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VmArtifactsAggregateStatus {
+    pub goal_state_aggregate_status: GoalStateAggregateStatus,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GoalStateAggregateStatus {
+    pub formatted_message: FormattedMessage,
+    #[serde(rename = "timestampUTC")]
+    pub timestamp_utc: String,
+    pub in_svd_seq_no: String,
+    pub status: String,
+    pub code: i32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GuestOsInfo {
+    pub computer_name: String,
+    pub os_name: String,
+    pub os_version: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SupportedFeature {
+    #[serde(rename = "Key")]
+    pub key: String,
+    #[serde(rename = "Value")]
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FormattedMessage {
+    #[serde(default)]
+    pub lang: String,
+    #[serde(default)]
+    pub message: String,
+}
+
+impl FormattedMessage {
+    pub fn new(message: impl Into<String>) -> Self {
+        FormattedMessage {
+            lang: LANG.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Status of one extension handler in `handlerAggregateStatus`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HandlerStatus {
+    pub handler_name: String,
+    pub handler_version: String,
+    /// `Ready` or `NotReady`.
+    pub status: String,
+    pub code: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub formatted_message: Option<FormattedMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub runtime_settings_status: Option<RuntimeSettingsStatus>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeSettingsStatus {
+    pub settings_status: SettingsStatus,
+    pub sequence_number: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingsStatus {
+    pub status: ExtensionStatus,
+    #[serde(rename = "timestampUTC")]
+    pub timestamp_utc: String,
+}
+
+/// The status an extension reports for one sequence number, as written by the
+/// extension to `status/<seq>.status`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtensionStatus {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operation: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub configuration_applied_time: Option<String>,
+    /// One of `transitioning`, `error`, `success` or `warning`.
+    pub status: String,
+    #[serde(default)]
+    pub code: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub formatted_message: Option<FormattedMessage>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub substatus: Vec<SubStatus>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubStatus {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub status: String,
+    #[serde(default)]
+    pub code: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub formatted_message: Option<FormattedMessage>,
+}
+
+impl VmStatus {
+    pub fn new(goal_state: &GoalState, handlers: Vec<HandlerStatus>) -> Self {
+        let sys_info = SystemInfo::current();
+        VmStatus {
+            version: "1.1".to_string(),
+            timestamp_utc: get_rfc3339_timestamp(),
+            aggregate_status: AggregateStatus {
+                guest_agent_status: GuestAgentStatus {
+                    version: AGENT_VERSION.to_string(),
+                    status: "Ready".to_string(),
+                    formatted_message: FormattedMessage::new("Guest Agent is running"),
+                    update_status: UpdateStatus {
+                        expected_version: AGENT_VERSION.to_string(),
+                        status: "Success".to_string(),
+                        code: 0,
+                        formatted_message: FormattedMessage::new(""),
+                    },
+                },
+                handler_aggregate_status: handlers,
+                vm_artifacts_aggregate_status: VmArtifactsAggregateStatus {
+                    goal_state_aggregate_status: GoalStateAggregateStatus {
+                        formatted_message: FormattedMessage::new("GoalState executed successfully"),
+                        timestamp_utc: get_rfc3339_timestamp(),
+                        in_svd_seq_no: goal_state.incarnation.to_string(),
+                        status: "Success".to_string(),
+                        code: 0,
+                    },
+                },
+            },
+            guest_os_info: GuestOsInfo {
+                computer_name: sys_info.hostname.clone(),
+                os_name: sys_info.os_name.clone(),
+                os_version: sys_info.os_version.clone(),
+                version: AGENT_VERSION.to_string(),
+            },
+            supported_features: [
+                "MultipleExtensionsPerHandler",
+                "VersioningGovernance",
+                "FastTrack",
+            ]
+            .into_iter()
+            .map(|key| SupportedFeature {
+                key: key.to_string(),
+                value: "1.0".to_string(),
+            })
+            .collect(),
+        }
+    }
+}
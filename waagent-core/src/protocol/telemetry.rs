@@ -0,0 +1,155 @@
+use super::{get_timestamp, GoalState, HealthStatus, AGENT_NAME, AGENT_VERSION};
+use crate::system::SystemStats;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct TelemetryData {
+    #[serde(rename = "@version")]
+    pub version: String,
+    #[serde(rename = "Provider")]
+    pub provider: Provider,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Provider {
+    #[serde(rename = "@id")]
+    pub id: String,
+    #[serde(rename = "Event")]
+    pub event: Event,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Event {
+    #[serde(rename = "@id")]
+    pub id: String,
+    #[serde(rename = "EventData")]
+    pub event_data: EventData,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EventData {
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(rename = "Param")]
+    pub param: Vec<Param>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Param {
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(rename = "@value")]
+    pub value: String,
+}
+
+impl Param {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Param {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+}
+
+impl TelemetryData {
+    pub fn new(event_id: &str, event_name: &str, params: Vec<Param>) -> Self {
+        TelemetryData {
+            version: "1.0".to_string(),
+            provider: Provider {
+                id: AGENT_NAME.to_string(),
+                event: Event {
+                    id: event_id.to_string(),
+                    event_data: EventData {
+                        name: event_name.to_string(),
+                        param: params,
+                    },
+                },
+            },
+        }
+    }
+
+    pub fn event_name(&self) -> &str {
+        &self.provider.event.event_data.name
+    }
+
+    /// Parameters every event carries: agent version, timestamp and the VM identity.
+    pub fn base_params(goal_state: &GoalState) -> Vec<Param> {
+        vec![
+            Param::new("Version", AGENT_VERSION),
+            Param::new("Timestamp", get_timestamp()),
+            Param::new("Container", goal_state.container_id()),
+            Param::new("RoleInstance", goal_state.role_instance_id()),
+        ]
+    }
+
+    pub fn wa_start(goal_state: &GoalState) -> Self {
+        let params = vec![
+            Param::new("Version", AGENT_VERSION),
+            Param::new("GAState", "Ready"),
+            Param::new("Container", goal_state.container_id()),
+            Param::new("RoleInstance", goal_state.role_instance_id()),
+            Param::new("Timestamp", get_timestamp()),
+        ];
+        Self::new("3", "WAStart", params)
+    }
+
+    pub fn heartbeat(goal_state: &GoalState, stats: &SystemStats) -> Self {
+        let mut params = Self::base_params(goal_state);
+        params.extend(vec![
+            Param::new("GAState", "Ready"),
+            Param::new("Role", goal_state.role_instance_id()),
+            Param::new("CPU", stats.cpu_usage_str()),
+            Param::new("Memory", stats.memory_usage_str()),
+            Param::new("ProcessorTime", stats.uptime_seconds_str()),
+        ]);
+        Self::new("1", "HeartBeat", params)
+    }
+
+    /// The Provision event, carrying the state provisioning ended in and a
+    /// message describing the outcome.
+    pub fn provision(goal_state: &GoalState, state: HealthStatus, message: &str) -> Self {
+        let provisioned = state == HealthStatus::Ready;
+        let params = vec![
+            Param::new("Version", AGENT_VERSION),
+            Param::new("IsVMProvisionedForLogs", provisioned.to_string()),
+            Param::new("ProvisioningState", state.to_string()),
+            Param::new("Message", message),
+            Param::new("Container", goal_state.container_id()),
+            Param::new("RoleInstance", goal_state.role_instance_id()),
+            Param::new("Timestamp", get_timestamp()),
+        ];
+        Self::new("4", "Provision", params)
+    }
+
+    /// Reports that the WireServer firewall rules had drifted and were repaired.
+    pub fn firewall(goal_state: &GoalState, message: &str) -> Self {
+        let mut params = Self::base_params(goal_state);
+        params.extend(vec![
+            Param::new("Operation", "Firewall"),
+            Param::new("IsSuccess", "true"),
+            Param::new("Message", message),
+        ]);
+        Self::new("5", "Firewall", params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_telemetry() {
+        let telemetry = TelemetryData::new(
+            "1",
+            "HeartBeat",
+            vec![Param::new("GAState", "Ready"), Param::new("CPU", "1.0%")],
+        );
+
+        let xml = quick_xml::se::to_string(&telemetry).unwrap();
+        assert_eq!(
+            xml,
+            r#"<TelemetryData version="1.0"><Provider id="waagent-rs"><Event id="1"><EventData name="HeartBeat"><Param name="GAState" value="Ready"/><Param name="CPU" value="1.0%"/></EventData></Event></Provider></TelemetryData>"#
+        );
+        assert_eq!(telemetry.event_name(), "HeartBeat");
+    }
+}
@@ -0,0 +1,100 @@
+use super::{CloudInit, ProvisioningError};
+use crate::config::{AgentConfig, Config};
+use std::fmt;
+use std::str::FromStr;
+use tracing::warn;
+
+/// Who provisions the VM, from `Provisioning.Agent`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProvisioningAgent {
+    /// cloud-init when the image runs it, the agent otherwise.
+    Auto,
+    Waagent,
+    CloudInit,
+    Disabled,
+}
+
+impl ProvisioningAgent {
+    pub fn from_config(config: &Config) -> Result<Self, ProvisioningError> {
+        AgentConfig::from(config).provisioning.agent.parse()
+    }
+
+    /// Turns `Auto` into `CloudInit` when cloud-init is enabled. Otherwise it
+    /// is `Waagent` for an agent running as root and `Disabled` for one that
+    /// is not, as only root can create the admin user.
+    pub fn resolve(self, cloud_init: &CloudInit, root: bool) -> Self {
+        match self {
+            ProvisioningAgent::Auto if cloud_init.is_enabled() => ProvisioningAgent::CloudInit,
+            ProvisioningAgent::Auto if root => ProvisioningAgent::Waagent,
+            ProvisioningAgent::Auto => {
+                warn!("cloud-init is not enabled and the agent does not run as root, provisioning is disabled");
+                ProvisioningAgent::Disabled
+            }
+            agent => agent,
+        }
+    }
+}
+
+impl FromStr for ProvisioningAgent {
+    type Err = ProvisioningError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "auto" => Ok(ProvisioningAgent::Auto),
+            "waagent" => Ok(ProvisioningAgent::Waagent),
+            "cloud-init" => Ok(ProvisioningAgent::CloudInit),
+            "disabled" => Ok(ProvisioningAgent::Disabled),
+            other => Err(ProvisioningError::InvalidConfig(format!(
+                "unknown Provisioning.Agent {}",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ProvisioningAgent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProvisioningAgent::Auto => write!(f, "auto"),
+            ProvisioningAgent::Waagent => write!(f, "waagent"),
+            ProvisioningAgent::CloudInit => write!(f, "cloud-init"),
+            ProvisioningAgent::Disabled => write!(f, "disabled"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_provisioning_agent() {
+        for agent in [
+            ProvisioningAgent::Auto,
+            ProvisioningAgent::Waagent,
+            ProvisioningAgent::CloudInit,
+            ProvisioningAgent::Disabled,
+        ] {
+            assert_eq!(
+                agent.to_string().parse::<ProvisioningAgent>().unwrap(),
+                agent
+            );
+        }
+        assert_eq!(
+            "Cloud-Init".parse::<ProvisioningAgent>().unwrap(),
+            ProvisioningAgent::CloudInit
+        );
+        assert!(matches!(
+            "systemd".parse::<ProvisioningAgent>(),
+            Err(ProvisioningError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_config_defaults_to_auto() {
+        assert_eq!(
+            ProvisioningAgent::from_config(&Config::from_map(Default::default())).unwrap(),
+            ProvisioningAgent::Auto
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use super::ProvisioningError;
+use crate::utils::command::CommandRunner;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+use tracing::{debug, info};
+
+/// Written by cloud-init when it has finished all stages of the current boot.
+/// It lives under /run, so it never survives from a previous boot.
+const RESULT_FILE: &str = "/run/cloud-init/result.json";
+const SERVICE: &str = "cloud-init-local.service";
+
+/// Detects cloud-init and waits for it to finish.
+#[derive(Clone)]
+pub struct CloudInit {
+    runner: Arc<dyn CommandRunner>,
+    result_path: PathBuf,
+}
+
+impl CloudInit {
+    pub fn new(runner: Arc<dyn CommandRunner>) -> Self {
+        Self {
+            runner,
+            result_path: PathBuf::from(RESULT_FILE),
+        }
+    }
+
+    /// Reads the completion marker from `path` instead of the cloud-init default.
+    pub fn with_result_path(mut self, path: &Path) -> Self {
+        self.result_path = path.to_path_buf();
+        self
+    }
+
+    /// Whether cloud-init runs on this image, i.e. its local stage is enabled in systemd.
+    pub fn is_enabled(&self) -> bool {
+        match self.runner.run("systemctl", &["is-enabled", SERVICE], None) {
+            Ok(output) => output.stdout.trim().starts_with("enabled"),
+            Err(e) => {
+                debug!("Could not query {}: {}", SERVICE, e);
+                false
+            }
+        }
+    }
+
+    /// `None` while cloud-init is still running, otherwise whether it succeeded.
+    pub fn completion(&self) -> Option<Result<(), ProvisioningError>> {
+        let contents = fs::read_to_string(&self.result_path).ok()?;
+        let result = match serde_json::from_str::<CloudInitResult>(&contents) {
+            Ok(result) if result.v1.errors.is_empty() => Ok(()),
+            Ok(result) => Err(ProvisioningError::CloudInit(result.v1.errors.join("; "))),
+            Err(e) => Err(ProvisioningError::CloudInit(format!(
+                "unreadable {}: {}",
+                self.result_path.display(),
+                e
+            ))),
+        };
+        Some(result)
+    }
+
+    /// Polls for the completion marker until cloud-init has finished or `timeout` has passed.
+    pub async fn wait_for_completion(
+        &self,
+        timeout: Duration,
+        poll_interval: Duration,
+    ) -> Result<(), ProvisioningError> {
+        info!(
+            "Waiting up to {}s for cloud-init to finish",
+            timeout.as_secs()
+        );
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(result) = self.completion() {
+                if result.is_ok() {
+                    info!("cloud-init has finished");
+                }
+                return result;
+            }
+            if Instant::now() >= deadline {
+                return Err(ProvisioningError::CloudInit(format!(
+                    "did not finish within {}s",
+                    timeout.as_secs()
+                )));
+            }
+            sleep(poll_interval).await;
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CloudInitResult {
+    v1: CloudInitResultV1,
+}
+
+#[derive(Debug, Deserialize)]
+struct CloudInitResultV1 {
+    #[serde(default)]
+    errors: Vec<String>,
+}
@@ -0,0 +1,122 @@
+use super::ProvisioningError;
+use crate::utils::command::CommandRunner;
+use crate::utils::fileutils::write_file_with_mode;
+use base64::prelude::*;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::info;
+
+const CUSTOM_DATA_FILE: &str = "CustomData";
+/// Bootstrap scripts install packages, so they get much longer than an
+/// extension command; past this the script is killed and provisioning fails.
+pub(super) const CUSTOM_DATA_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// Only the tail of the script's output ends up in the provisioning status.
+const MAX_OUTPUT_LEN: usize = 4096;
+
+/// Exit code and output of a custom data script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomDataResult {
+    pub exit_code: i32,
+    /// Tail of stdout followed by stderr.
+    pub output: String,
+}
+
+impl CustomDataResult {
+    pub fn success(&self) -> bool {
+        self.exit_code == 0
+    }
+}
+
+impl fmt::Display for CustomDataResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "custom data script exited with {}", self.exit_code)?;
+        if !self.output.is_empty() {
+            write!(f, ": {}", self.output)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes custom data to `Lib.Dir/CustomData`, base64-decoded when `decode` is set.
+pub(super) fn save(lib_dir: &Path, data: &str, decode: bool) -> Result<PathBuf, ProvisioningError> {
+    let contents = if decode {
+        let data: String = data.split_whitespace().collect();
+        BASE64_STANDARD
+            .decode(data)
+            .map_err(|e| ProvisioningError::InvalidOvfEnv(format!("CustomData: {}", e)))?
+    } else {
+        data.as_bytes().to_vec()
+    };
+
+    let path = lib_dir.join(CUSTOM_DATA_FILE);
+    // Created executable by root only, never briefly writable by others
+    write_file_with_mode(&path, &contents, 0o700)?;
+    info!("Saved custom data to {}", path.display());
+    Ok(path)
+}
+
+/// Runs the saved custom data as root, the user the agent runs as, since
+/// bootstrap scripts typically install packages and write system configuration.
+/// A script without a `#!` line is run with `sh`. A script still running after
+/// `timeout` is killed.
+pub(super) fn execute(
+    runner: &dyn CommandRunner,
+    path: &Path,
+    timeout: Duration,
+) -> Result<CustomDataResult, ProvisioningError> {
+    let script = path.to_string_lossy();
+    let has_interpreter = fs::read(path)?.starts_with(b"#!");
+    let output = if has_interpreter {
+        runner.run_with_timeout(&script, &[], None, timeout)?
+    } else {
+        runner.run_with_timeout("sh", &[&script], None, timeout)?
+    };
+    let output = output.ok_or(ProvisioningError::CustomDataTimeout(timeout))?;
+
+    let mut combined = output.stdout;
+    combined.push_str(&output.stderr);
+    let result = CustomDataResult {
+        exit_code: output.exit_code,
+        output: tail(&combined),
+    };
+    info!("{}", result);
+    Ok(result)
+}
+
+fn tail(output: &str) -> String {
+    let output = output.trim();
+    let mut start = output.len().saturating_sub(MAX_OUTPUT_LEN);
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    output[start..].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tail_keeps_end_of_output() {
+        let output = format!("{}é", "a".repeat(MAX_OUTPUT_LEN));
+
+        assert!(tail(&output).ends_with('é'));
+        assert!(tail(&output).len() <= MAX_OUTPUT_LEN);
+        assert_eq!(tail("  done\n"), "done");
+    }
+
+    #[test]
+    fn test_display_custom_data_result() {
+        let result = CustomDataResult {
+            exit_code: 2,
+            output: "apt-get: not found".to_string(),
+        };
+
+        assert_eq!(
+            result.to_string(),
+            "custom data script exited with 2: apt-get: not found"
+        );
+    }
+}
@@ -0,0 +1,68 @@
+use super::CustomDataResult;
+use crate::utils::command::CommandError;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum ProvisioningError {
+    /// Reading ovf-env.xml or writing system configuration failed.
+    Io(io::Error),
+    /// A system tool (useradd, mount, ssh-keygen, ...) failed.
+    Command(CommandError),
+    /// ovf-env.xml is missing required settings or could not be parsed.
+    InvalidOvfEnv(String),
+    /// The agent configuration asks for something we cannot do.
+    InvalidConfig(String),
+    /// cloud-init reported errors or did not finish in time.
+    CloudInit(String),
+    /// The custom data script exited with a non-zero code.
+    CustomData(CustomDataResult),
+    /// The custom data script did not finish in time and was killed.
+    CustomDataTimeout(Duration),
+}
+
+impl fmt::Display for ProvisioningError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProvisioningError::Io(e) => write!(f, "I/O error: {}", e),
+            ProvisioningError::Command(e) => write!(f, "{}", e),
+            ProvisioningError::InvalidOvfEnv(reason) => {
+                write!(f, "invalid ovf-env.xml: {}", reason)
+            }
+            ProvisioningError::InvalidConfig(reason) => {
+                write!(f, "invalid configuration: {}", reason)
+            }
+            ProvisioningError::CloudInit(reason) => write!(f, "cloud-init: {}", reason),
+            ProvisioningError::CustomData(result) => write!(f, "{}", result),
+            ProvisioningError::CustomDataTimeout(timeout) => write!(
+                f,
+                "custom data script did not finish within {} seconds and was killed",
+                timeout.as_secs()
+            ),
+        }
+    }
+}
+
+impl Error for ProvisioningError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ProvisioningError::Io(e) => Some(e),
+            ProvisioningError::Command(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ProvisioningError {
+    fn from(e: io::Error) -> Self {
+        ProvisioningError::Io(e)
+    }
+}
+
+impl From<CommandError> for ProvisioningError {
+    fn from(e: CommandError) -> Self {
+        ProvisioningError::Command(e)
+    }
+}
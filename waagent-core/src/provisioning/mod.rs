@@ -0,0 +1,13 @@
+mod agent;
+mod cloud_init;
+mod custom_data;
+mod error;
+mod ovf;
+mod provisioner;
+
+pub use agent::ProvisioningAgent;
+pub use cloud_init::CloudInit;
+pub use custom_data::CustomDataResult;
+pub use error::ProvisioningError;
+pub use ovf::{mask_password, OvfEnv, SshKey};
+pub use provisioner::{Provisioner, ProvisioningOutcome, ProvisioningSettings};
@@ -0,0 +1,311 @@
+use super::ProvisioningError;
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+
+/// The provisioning settings the host hands to the VM in `ovf-env.xml`.
+#[derive(Clone, Default)]
+pub struct OvfEnv {
+    pub hostname: String,
+    pub username: String,
+    pub user_password: Option<String>,
+    pub disable_ssh_password_authentication: bool,
+    /// Keys to add to the user's authorized_keys.
+    pub public_keys: Vec<SshKey>,
+    /// Private keys to place on the VM, e.g. `~/.ssh/id_rsa`.
+    pub key_pairs: Vec<SshKey>,
+    /// Base64 encoded custom data, as given by the user.
+    pub custom_data: Option<String>,
+    /// Whether the platform expects a guest agent to run on this VM.
+    pub provision_guest_agent: Option<bool>,
+}
+
+/// An SSH key from ovf-env.xml. When `value` is missing the key is the
+/// certificate with `fingerprint` from the goal state.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SshKey {
+    pub fingerprint: Option<String>,
+    pub path: Option<String>,
+    pub value: Option<String>,
+}
+
+// The password must not end up in logs.
+impl fmt::Debug for OvfEnv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OvfEnv")
+            .field("hostname", &self.hostname)
+            .field("username", &self.username)
+            .field("user_password", &self.user_password.as_ref().map(|_| "*"))
+            .field(
+                "disable_ssh_password_authentication",
+                &self.disable_ssh_password_authentication,
+            )
+            .field("public_keys", &self.public_keys)
+            .field("key_pairs", &self.key_pairs)
+            .field("custom_data", &self.custom_data.as_ref().map(|_| "*"))
+            .field("provision_guest_agent", &self.provision_guest_agent)
+            .finish()
+    }
+}
+
+impl OvfEnv {
+    pub fn from_file(path: &Path) -> Result<Self, ProvisioningError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parses ovf-env.xml. Only version 1.x of the provisioning section is understood.
+    pub fn parse(xml: &str) -> Result<Self, ProvisioningError> {
+        let env: Environment = quick_xml::de::from_str(xml)
+            .map_err(|e| ProvisioningError::InvalidOvfEnv(e.to_string()))?;
+
+        let section = env
+            .provisioning_section
+            .ok_or_else(|| missing("ProvisioningSection"))?;
+        match section.version.as_deref() {
+            Some(version) if version.starts_with("1.") => {}
+            other => {
+                return Err(ProvisioningError::InvalidOvfEnv(format!(
+                    "unsupported ProvisioningSection version {:?}",
+                    other
+                )))
+            }
+        }
+        let config = section
+            .linux_provisioning_configuration_set
+            .ok_or_else(|| missing("LinuxProvisioningConfigurationSet"))?;
+
+        let hostname = non_empty(config.host_name).ok_or_else(|| missing("HostName"))?;
+        let username = non_empty(config.user_name).ok_or_else(|| missing("UserName"))?;
+        let ssh = config.ssh.unwrap_or_default();
+
+        Ok(OvfEnv {
+            hostname,
+            username,
+            user_password: non_empty(config.user_password),
+            disable_ssh_password_authentication: parse_bool(
+                config.disable_ssh_password_authentication.as_deref(),
+            ),
+            public_keys: ssh
+                .public_keys
+                .map(|keys| keys.keys)
+                .unwrap_or_default()
+                .into_iter()
+                .map(SshKey::from)
+                .collect(),
+            key_pairs: ssh
+                .key_pairs
+                .map(|keys| keys.keys)
+                .unwrap_or_default()
+                .into_iter()
+                .map(SshKey::from)
+                .collect(),
+            custom_data: non_empty(config.custom_data),
+            provision_guest_agent: env
+                .platform_settings_section
+                .and_then(|s| s.platform_settings)
+                .and_then(|s| s.provision_guest_agent)
+                .map(|value| parse_bool(Some(&value))),
+        })
+    }
+}
+
+/// Replaces the user's password in ovf-env.xml with `*`, so a copy can be kept on disk.
+pub fn mask_password(xml: &str) -> String {
+    let Some(tag) = xml.find("UserPassword>") else {
+        return xml.to_string();
+    };
+    let start = tag + "UserPassword>".len();
+    match xml[start..].find('<') {
+        Some(len) => format!("{}*{}", &xml[..start], &xml[start + len..]),
+        None => xml.to_string(),
+    }
+}
+
+fn missing(element: &str) -> ProvisioningError {
+    ProvisioningError::InvalidOvfEnv(format!("missing {}", element))
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+fn parse_bool(value: Option<&str>) -> bool {
+    value.is_some_and(|v| v.trim().eq_ignore_ascii_case("true"))
+}
+
+impl From<RawSshKey> for SshKey {
+    fn from(key: RawSshKey) -> Self {
+        SshKey {
+            fingerprint: non_empty(key.fingerprint),
+            path: non_empty(key.path),
+            value: non_empty(key.value),
+        }
+    }
+}
+
+// quick-xml drops namespace prefixes (`ns1:`) when matching element names,
+// so the structs below name elements without them.
+
+#[derive(Debug, Deserialize)]
+struct Environment {
+    #[serde(rename = "ProvisioningSection", default)]
+    provisioning_section: Option<ProvisioningSection>,
+    #[serde(rename = "PlatformSettingsSection", default)]
+    platform_settings_section: Option<PlatformSettingsSection>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProvisioningSection {
+    #[serde(rename = "Version", default)]
+    version: Option<String>,
+    #[serde(rename = "LinuxProvisioningConfigurationSet", default)]
+    linux_provisioning_configuration_set: Option<LinuxProvisioningConfigurationSet>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LinuxProvisioningConfigurationSet {
+    #[serde(rename = "HostName", default)]
+    host_name: Option<String>,
+    #[serde(rename = "UserName", default)]
+    user_name: Option<String>,
+    #[serde(rename = "UserPassword", default)]
+    user_password: Option<String>,
+    #[serde(rename = "DisableSshPasswordAuthentication", default)]
+    disable_ssh_password_authentication: Option<String>,
+    #[serde(rename = "SSH", default)]
+    ssh: Option<Ssh>,
+    #[serde(rename = "CustomData", default)]
+    custom_data: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct Ssh {
+    #[serde(rename = "PublicKeys", default)]
+    public_keys: Option<PublicKeys>,
+    #[serde(rename = "KeyPairs", default)]
+    key_pairs: Option<KeyPairs>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PublicKeys {
+    #[serde(rename = "PublicKey", default)]
+    keys: Vec<RawSshKey>,
+}
+
+#[derive(Debug, Deserialize)]
+struct KeyPairs {
+    #[serde(rename = "KeyPair", default)]
+    keys: Vec<RawSshKey>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawSshKey {
+    #[serde(rename = "Fingerprint", default)]
+    fingerprint: Option<String>,
+    #[serde(rename = "Path", default)]
+    path: Option<String>,
+    #[serde(rename = "Value", default)]
+    value: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlatformSettingsSection {
+    #[serde(rename = "PlatformSettings", default)]
+    platform_settings: Option<PlatformSettings>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlatformSettings {
+    #[serde(rename = "ProvisionGuestAgent", default)]
+    provision_guest_agent: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OVF_ENV: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<ns0:Environment xmlns="http://schemas.dmtf.org/ovf/environment/1" xmlns:ns0="http://schemas.dmtf.org/ovf/environment/1" xmlns:ns1="http://schemas.microsoft.com/windowsazure" xmlns:i="http://www.w3.org/2001/XMLSchema-instance">
+  <ns1:ProvisioningSection>
+    <ns1:Version>1.0</ns1:Version>
+    <ns1:LinuxProvisioningConfigurationSet>
+      <ns1:ConfigurationSetType>LinuxProvisioningConfiguration</ns1:ConfigurationSetType>
+      <ns1:HostName>myvm</ns1:HostName>
+      <ns1:UserName>azureuser</ns1:UserName>
+      <ns1:UserPassword>Secret123!</ns1:UserPassword>
+      <ns1:DisableSshPasswordAuthentication>true</ns1:DisableSshPasswordAuthentication>
+      <ns1:SSH>
+        <ns1:PublicKeys>
+          <ns1:PublicKey>
+            <ns1:Fingerprint>EB0C0AB4B2D5FC35F2F0658D19F44C8283E2DD62</ns1:Fingerprint>
+            <ns1:Path>/home/azureuser/.ssh/authorized_keys</ns1:Path>
+            <ns1:Value>ssh-rsa AAAAB3NzaC1yc2E azureuser</ns1:Value>
+          </ns1:PublicKey>
+          <ns1:PublicKey>
+            <ns1:Fingerprint>DF5E6B1B4C7E1E5B8A3D2F7C0E9A4B6D8C1F3E5A</ns1:Fingerprint>
+            <ns1:Path>/home/azureuser/.ssh/authorized_keys</ns1:Path>
+          </ns1:PublicKey>
+        </ns1:PublicKeys>
+        <ns1:KeyPairs>
+          <ns1:KeyPair>
+            <ns1:Fingerprint>EB0C0AB4B2D5FC35F2F0658D19F44C8283E2DD62</ns1:Fingerprint>
+            <ns1:Path>/home/azureuser/.ssh/id_rsa</ns1:Path>
+          </ns1:KeyPair>
+        </ns1:KeyPairs>
+      </ns1:SSH>
+      <ns1:CustomData>ZWNobyBoZWxsbw==</ns1:CustomData>
+    </ns1:LinuxProvisioningConfigurationSet>
+  </ns1:ProvisioningSection>
+  <ns1:PlatformSettingsSection>
+    <ns1:Version>1.0</ns1:Version>
+    <ns1:PlatformSettings>
+      <ns1:KmsServerHostname>kms.core.windows.net</ns1:KmsServerHostname>
+      <ns1:ProvisionGuestAgent>true</ns1:ProvisionGuestAgent>
+    </ns1:PlatformSettings>
+  </ns1:PlatformSettingsSection>
+</ns0:Environment>"#;
+
+    #[test]
+    fn test_parse_ovf_env() {
+        let ovf = OvfEnv::parse(OVF_ENV).unwrap();
+
+        assert_eq!(ovf.hostname, "myvm");
+        assert_eq!(ovf.username, "azureuser");
+        assert_eq!(ovf.user_password.as_deref(), Some("Secret123!"));
+        assert!(ovf.disable_ssh_password_authentication);
+        assert_eq!(ovf.public_keys.len(), 2);
+        assert_eq!(
+            ovf.public_keys[0].value.as_deref(),
+            Some("ssh-rsa AAAAB3NzaC1yc2E azureuser")
+        );
+        assert_eq!(ovf.public_keys[1].value, None);
+        assert_eq!(
+            ovf.key_pairs[0].path.as_deref(),
+            Some("/home/azureuser/.ssh/id_rsa")
+        );
+        assert_eq!(ovf.custom_data.as_deref(), Some("ZWNobyBoZWxsbw=="));
+        assert_eq!(ovf.provision_guest_agent, Some(true));
+        assert!(!format!("{:?}", ovf).contains("Secret123!"));
+    }
+
+    #[test]
+    fn test_parse_rejects_missing_user() {
+        let xml = OVF_ENV.replace("<ns1:UserName>azureuser</ns1:UserName>", "");
+
+        assert!(matches!(
+            OvfEnv::parse(&xml),
+            Err(ProvisioningError::InvalidOvfEnv(reason)) if reason == "missing UserName"
+        ));
+    }
+
+    #[test]
+    fn test_mask_password() {
+        let masked = mask_password(OVF_ENV);
+
+        assert!(masked.contains("<ns1:UserPassword>*</ns1:UserPassword>"));
+        assert!(!masked.contains("Secret123!"));
+        assert_eq!(mask_password("<Environment/>"), "<Environment/>");
+    }
+}
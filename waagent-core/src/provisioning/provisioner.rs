@@ -0,0 +1,549 @@
+use super::ovf::mask_password;
+use super::{custom_data, CustomDataResult, OvfEnv, ProvisioningError, SshKey};
+use crate::certificates::{CertificateStore, Openssl};
+use crate::config::{AgentConfig, Config};
+use crate::utils::command::CommandRunner;
+use crate::utils::fileutils::{replace_file, write_file_with_mode};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+
+const OVF_ENV_FILE: &str = "ovf-env.xml";
+const PROVISIONED_FILE: &str = "provisioned";
+const SUDOERS_FILE: &str = "waagent";
+const SALT_CHARS: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Where provisioning reads from and writes to, and which optional steps it runs.
+#[derive(Debug, Clone)]
+pub struct ProvisioningSettings {
+    /// Keeps the masked copy of ovf-env.xml and the `provisioned` marker.
+    pub lib_dir: PathBuf,
+    pub dvd_device: PathBuf,
+    pub dvd_mount_point: PathBuf,
+    pub hostname_file: PathBuf,
+    pub home_dir: PathBuf,
+    pub ssh_dir: PathBuf,
+    pub sudoers_dir: PathBuf,
+    /// The shadow file, edited when the root password is deleted.
+    pub password_path: PathBuf,
+    pub openssl_path: String,
+    pub password_crypt_id: String,
+    pub password_crypt_salt_length: usize,
+    pub delete_root_password: bool,
+    pub regenerate_ssh_host_key_pair: bool,
+    pub ssh_host_key_pair_type: String,
+    pub decode_custom_data: bool,
+    pub execute_custom_data: bool,
+}
+
+impl ProvisioningSettings {
+    /// Reads `Lib.Dir`, `DVD.MountPoint`, the `OS.*` paths and the `Provisioning.*` options.
+    pub fn from_config(config: &Config) -> Self {
+        let AgentConfig {
+            lib,
+            dvd,
+            os,
+            provisioning,
+            ..
+        } = AgentConfig::from(config);
+
+        Self {
+            lib_dir: lib.dir,
+            dvd_device: PathBuf::from("/dev/sr0"),
+            dvd_mount_point: dvd.mount_point,
+            hostname_file: PathBuf::from("/etc/hostname"),
+            home_dir: os.home_dir,
+            ssh_dir: os.ssh_dir,
+            sudoers_dir: os.sudoers_dir,
+            password_path: os.password_path,
+            openssl_path: os.openssl_path.to_string_lossy().into_owned(),
+            password_crypt_id: provisioning.password_crypt_id,
+            password_crypt_salt_length: provisioning.password_crypt_salt_length as usize,
+            delete_root_password: provisioning.delete_root_password,
+            regenerate_ssh_host_key_pair: provisioning.regenerate_ssh_host_key_pair,
+            ssh_host_key_pair_type: provisioning.ssh_host_key_pair_type,
+            decode_custom_data: provisioning.decode_custom_data,
+            execute_custom_data: provisioning.execute_custom_data,
+        }
+    }
+}
+
+/// What a successful provisioning run did.
+#[derive(Debug)]
+pub struct ProvisioningOutcome {
+    pub ovf: OvfEnv,
+    /// Set when custom data was executed.
+    pub custom_data: Option<CustomDataResult>,
+}
+
+/// Configures a fresh VM from ovf-env.xml: hostname, admin user, password,
+/// sudo, SSH keys and sshd. Runs once; a marker in `Lib.Dir` records success.
+///
+/// Needs root: it creates users and edits `/etc/shadow`, sudoers and sshd's
+/// configuration, none of which the privileged helper does for the agent.
+#[derive(Clone)]
+pub struct Provisioner {
+    settings: ProvisioningSettings,
+    runner: Arc<dyn CommandRunner>,
+    certificates: CertificateStore,
+}
+
+impl Provisioner {
+    pub fn new(settings: ProvisioningSettings, runner: Arc<dyn CommandRunner>) -> Self {
+        let certificates = CertificateStore::new(
+            &settings.lib_dir,
+            Openssl::new(settings.openssl_path.as_str()),
+        );
+        Self {
+            settings,
+            runner,
+            certificates,
+        }
+    }
+
+    pub fn settings(&self) -> &ProvisioningSettings {
+        &self.settings
+    }
+
+    pub fn is_provisioned(&self) -> bool {
+        self.settings.lib_dir.join(PROVISIONED_FILE).exists()
+    }
+
+    /// Provisions from the ovf-env.xml on the provisioning DVD, mounting it if needed.
+    pub fn provision(&self) -> Result<ProvisioningOutcome, ProvisioningError> {
+        let xml = self.read_ovf_env_from_dvd()?;
+        self.provision_from_xml(&xml)
+    }
+
+    /// Provisions from an ovf-env.xml at `path`.
+    pub fn provision_from_file(
+        &self,
+        path: &Path,
+    ) -> Result<ProvisioningOutcome, ProvisioningError> {
+        self.provision_from_xml(&fs::read_to_string(path)?)
+    }
+
+    fn provision_from_xml(&self, xml: &str) -> Result<ProvisioningOutcome, ProvisioningError> {
+        let ovf = OvfEnv::parse(xml)?;
+        info!("Provisioning VM {} for user {}", ovf.hostname, ovf.username);
+
+        fs::create_dir_all(&self.settings.lib_dir)?;
+        write_file(
+            &self.settings.lib_dir.join(OVF_ENV_FILE),
+            &mask_password(xml),
+            0o600,
+        )?;
+
+        self.set_hostname(&ovf.hostname)?;
+        self.create_user(&ovf.username)?;
+        if let Some(password) = &ovf.user_password {
+            self.set_password(&ovf.username, password)?;
+        }
+        self.configure_sudoers(&ovf.username, ovf.user_password.is_some())?;
+        self.deploy_ssh_keys(&ovf)?;
+        if self.settings.delete_root_password {
+            self.delete_root_password()?;
+        }
+        let mut restart_sshd = false;
+        if ovf.disable_ssh_password_authentication {
+            self.disable_ssh_password_authentication()?;
+            restart_sshd = true;
+        }
+        if self.settings.regenerate_ssh_host_key_pair {
+            self.regenerate_ssh_host_keys()?;
+            restart_sshd = true;
+        }
+        if restart_sshd {
+            self.restart_sshd();
+        }
+        let custom_data = self.handle_custom_data(&ovf)?;
+
+        write_file(&self.settings.lib_dir.join(PROVISIONED_FILE), "", 0o644)?;
+        info!("Provisioning complete");
+        Ok(ProvisioningOutcome { ovf, custom_data })
+    }
+
+    /// Saves custom data and, with `Provisioning.ExecuteCustomData`, runs it.
+    /// A failing script fails provisioning.
+    fn handle_custom_data(
+        &self,
+        ovf: &OvfEnv,
+    ) -> Result<Option<CustomDataResult>, ProvisioningError> {
+        let Some(data) = &ovf.custom_data else {
+            return Ok(None);
+        };
+        let path = custom_data::save(
+            &self.settings.lib_dir,
+            data,
+            self.settings.decode_custom_data,
+        )?;
+        if !self.settings.execute_custom_data {
+            return Ok(None);
+        }
+
+        let result = custom_data::execute(
+            self.runner.as_ref(),
+            &path,
+            custom_data::CUSTOM_DATA_TIMEOUT,
+        )?;
+        if !result.success() {
+            return Err(ProvisioningError::CustomData(result));
+        }
+        Ok(Some(result))
+    }
+
+    /// Copies ovf-env.xml off the DVD. The DVD is mounted only if it is not already.
+    fn read_ovf_env_from_dvd(&self) -> Result<String, ProvisioningError> {
+        let mount_point = &self.settings.dvd_mount_point;
+        let path = mount_point.join(OVF_ENV_FILE);
+        if path.is_file() {
+            return Ok(fs::read_to_string(path)?);
+        }
+
+        fs::create_dir_all(mount_point)?;
+        let device = self.settings.dvd_device.to_string_lossy();
+        let mount_point_arg = mount_point.to_string_lossy();
+        self.runner.run_checked(
+            "mount",
+            &["-o", "ro", "-t", "udf,iso9660", &device, &mount_point_arg],
+            None,
+        )?;
+        let xml = fs::read_to_string(&path);
+        if let Err(e) = self.runner.run_checked("umount", &[&mount_point_arg], None) {
+            warn!("Failed to unmount the provisioning DVD: {}", e);
+        }
+        Ok(xml?)
+    }
+
+    fn set_hostname(&self, hostname: &str) -> Result<(), ProvisioningError> {
+        debug!("Setting hostname to {}", hostname);
+        self.runner.run_checked("hostname", &[hostname], None)?;
+        fs::write(&self.settings.hostname_file, format!("{}\n", hostname))?;
+        Ok(())
+    }
+
+    fn create_user(&self, username: &str) -> Result<(), ProvisioningError> {
+        if self.runner.run("id", &[username], None)?.success() {
+            info!("User {} already exists", username);
+            return Ok(());
+        }
+        let home = self.home(username);
+        self.runner.run_checked(
+            "useradd",
+            &["-m", "-d", &home.to_string_lossy(), username],
+            None,
+        )?;
+        info!("Created user {}", username);
+        Ok(())
+    }
+
+    /// Hashes the password with `openssl passwd` using `Provisioning.PasswordCryptId`
+    /// and a random salt, and hands the hash to `chpasswd -e`.
+    fn set_password(&self, username: &str, password: &str) -> Result<(), ProvisioningError> {
+        let crypt_id = self.settings.password_crypt_id.as_str();
+        if !matches!(crypt_id, "1" | "5" | "6") {
+            return Err(ProvisioningError::InvalidConfig(format!(
+                "unsupported Provisioning.PasswordCryptId {}",
+                crypt_id
+            )));
+        }
+        let salt = random_salt(self.settings.password_crypt_salt_length)?;
+        let output = self.runner.run_checked(
+            &self.settings.openssl_path,
+            &[
+                "passwd",
+                &format!("-{}", crypt_id),
+                "-salt",
+                &salt,
+                "-stdin",
+            ],
+            Some(format!("{}\n", password).as_bytes()),
+        )?;
+        let hash = output.stdout.trim();
+        self.runner.run_checked(
+            "chpasswd",
+            &["-e"],
+            Some(format!("{}:{}\n", username, hash).as_bytes()),
+        )?;
+        debug!("Set password for {}", username);
+        Ok(())
+    }
+
+    /// Users provisioned with SSH keys only have no password to type, so they get NOPASSWD.
+    fn configure_sudoers(
+        &self,
+        username: &str,
+        has_password: bool,
+    ) -> Result<(), ProvisioningError> {
+        let rule = if has_password {
+            format!("{} ALL=(ALL) ALL\n", username)
+        } else {
+            format!("{} ALL=(ALL) NOPASSWD: ALL\n", username)
+        };
+        fs::create_dir_all(&self.settings.sudoers_dir)?;
+        write_file(&self.settings.sudoers_dir.join(SUDOERS_FILE), &rule, 0o440)?;
+        Ok(())
+    }
+
+    fn deploy_ssh_keys(&self, ovf: &OvfEnv) -> Result<(), ProvisioningError> {
+        let default_path = self.home(&ovf.username).join(".ssh/authorized_keys");
+
+        for key in &ovf.public_keys {
+            let path = key
+                .path
+                .as_ref()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| default_path.clone());
+            let value = match &key.value {
+                Some(value) => value.clone(),
+                None => self.public_key_from_certificate(key)?,
+            };
+            add_authorized_key(&path, &value)?;
+            self.chown_ssh_dir(&ovf.username, &path)?;
+        }
+
+        for key in &ovf.key_pairs {
+            let path = key.path.as_ref().map(PathBuf::from).ok_or_else(|| {
+                ProvisioningError::InvalidOvfEnv("KeyPair without Path".to_string())
+            })?;
+            let private_key = self.certificate_private_key(key)?;
+            create_ssh_dir(&path)?;
+            write_file(&path, &fs::read_to_string(private_key)?, 0o600)?;
+            let public_key = self.public_key_from_certificate(key)?;
+            write_file(
+                &path.with_extension("pub"),
+                &format!("{}\n", public_key),
+                0o644,
+            )?;
+            self.chown_ssh_dir(&ovf.username, &path)?;
+        }
+        Ok(())
+    }
+
+    /// Keys given only by fingerprint come from the goal state certificates,
+    /// which have to be installed before provisioning runs.
+    fn public_key_from_certificate(&self, key: &SshKey) -> Result<String, ProvisioningError> {
+        let private_key = self.certificate_private_key(key)?;
+        let output = self.runner.run_checked(
+            "ssh-keygen",
+            &["-y", "-f", &private_key.to_string_lossy()],
+            None,
+        )?;
+        Ok(output.stdout.trim().to_string())
+    }
+
+    fn certificate_private_key(&self, key: &SshKey) -> Result<PathBuf, ProvisioningError> {
+        let fingerprint = key.fingerprint.as_deref().ok_or_else(|| {
+            ProvisioningError::InvalidOvfEnv("SSH key without Value or Fingerprint".to_string())
+        })?;
+        let path = self
+            .certificates
+            .private_key_path(&fingerprint.to_uppercase());
+        if !path.is_file() {
+            return Err(ProvisioningError::InvalidOvfEnv(format!(
+                "no certificate installed for SSH key {}",
+                fingerprint
+            )));
+        }
+        Ok(path)
+    }
+
+    fn chown_ssh_dir(&self, username: &str, key_path: &Path) -> Result<(), ProvisioningError> {
+        if let Some(dir) = key_path.parent() {
+            let owner = format!("{0}:{0}", username);
+            self.runner
+                .run_checked("chown", &["-R", &owner, &dir.to_string_lossy()], None)?;
+        }
+        Ok(())
+    }
+
+    fn disable_ssh_password_authentication(&self) -> Result<(), ProvisioningError> {
+        let path = self.settings.ssh_dir.join("sshd_config");
+        let config = fs::read_to_string(&path).unwrap_or_default();
+        let config = set_sshd_option(&config, "PasswordAuthentication", "no");
+        let config = set_sshd_option(&config, "ChallengeResponseAuthentication", "no");
+        fs::write(&path, config)?;
+        info!("Disabled SSH password authentication");
+        Ok(())
+    }
+
+    /// Locks root's password in the shadow file (`OS.PasswordPath`).
+    fn delete_root_password(&self) -> Result<(), ProvisioningError> {
+        let path = &self.settings.password_path;
+        let shadow = fs::read_to_string(path)?;
+        // Written aside and renamed, so a crash never leaves it truncated
+        replace_file(path, lock_root_password(&shadow).as_bytes())?;
+        info!("Deleted root password");
+        Ok(())
+    }
+
+    fn regenerate_ssh_host_keys(&self) -> Result<(), ProvisioningError> {
+        let ssh_dir = &self.settings.ssh_dir;
+        for entry in fs::read_dir(ssh_dir)? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if name.starts_with("ssh_host_") && name.contains("key") {
+                fs::remove_file(&path)?;
+            }
+        }
+
+        let key_type = &self.settings.ssh_host_key_pair_type;
+        let key_path = ssh_dir.join(format!("ssh_host_{}_key", key_type));
+        self.runner.run_checked(
+            "ssh-keygen",
+            &["-N", "", "-t", key_type, "-f", &key_path.to_string_lossy()],
+            None,
+        )?;
+        info!("Regenerated {} SSH host key", key_type);
+        Ok(())
+    }
+
+    /// The unit is `sshd` on most distributions and `ssh` on Debian and Ubuntu;
+    /// try-restart leaves whichever is not running alone.
+    fn restart_sshd(&self) {
+        match self.runner.run(
+            "systemctl",
+            &["try-restart", "sshd.service", "ssh.service"],
+            None,
+        ) {
+            Ok(output) if output.success() => debug!("Restarted sshd"),
+            Ok(output) => warn!("Failed to restart sshd: {}", output.stderr.trim()),
+            Err(e) => warn!("Failed to restart sshd: {}", e),
+        }
+    }
+
+    fn home(&self, username: &str) -> PathBuf {
+        self.settings.home_dir.join(username)
+    }
+}
+
+fn random_salt(length: usize) -> Result<String, ProvisioningError> {
+    let mut bytes = vec![0u8; length];
+    fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes
+        .iter()
+        .map(|b| SALT_CHARS[*b as usize % SALT_CHARS.len()] as char)
+        .collect())
+}
+
+fn create_ssh_dir(key_path: &Path) -> Result<(), ProvisioningError> {
+    if let Some(dir) = key_path.parent() {
+        fs::create_dir_all(dir)?;
+        set_mode(dir, 0o700)?;
+    }
+    Ok(())
+}
+
+/// Appends `key` to an authorized_keys file unless it is already there.
+fn add_authorized_key(path: &Path, key: &str) -> Result<(), ProvisioningError> {
+    create_ssh_dir(path)?;
+    let mut keys = fs::read_to_string(path).unwrap_or_default();
+    if !keys.lines().any(|line| line.trim() == key) {
+        if !keys.is_empty() && !keys.ends_with('\n') {
+            keys.push('\n');
+        }
+        keys.push_str(key);
+        keys.push('\n');
+    }
+    write_file(path, &keys, 0o600)
+}
+
+/// Sets `option` in an sshd_config, replacing any existing (or commented out)
+/// setting, or appending it.
+fn set_sshd_option(config: &str, option: &str, value: &str) -> String {
+    let setting = format!("{} {}", option, value);
+    let mut found = false;
+    let mut lines: Vec<String> = config
+        .lines()
+        .filter_map(|line| {
+            let uncommented = line.trim_start_matches('#').trim_start();
+            if uncommented.split_whitespace().next() != Some(option) {
+                return Some(line.to_string());
+            }
+            // Keep the first occurrence only, so the setting cannot be overridden later on.
+            if found {
+                return None;
+            }
+            found = true;
+            Some(setting.clone())
+        })
+        .collect();
+    if !found {
+        lines.push(setting);
+    }
+    let mut config = lines.join("\n");
+    config.push('\n');
+    config
+}
+
+fn lock_root_password(shadow: &str) -> String {
+    shadow
+        .lines()
+        .map(|line| match line.strip_prefix("root:") {
+            Some(rest) => {
+                let rest = rest.split_once(':').map(|(_, r)| r).unwrap_or_default();
+                format!("root:*LOCK*:{}\n", rest)
+            }
+            None => format!("{}\n", line),
+        })
+        .collect()
+}
+
+/// Creates the file with `mode` rather than fixing it afterwards, so keys and
+/// sudoers rules are never briefly readable or writable by others.
+fn write_file(path: &Path, contents: &str, mode: u32) -> Result<(), ProvisioningError> {
+    write_file_with_mode(path, contents.as_bytes(), mode)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<(), ProvisioningError> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<(), ProvisioningError> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_sshd_option() {
+        let config =
+            "Port 22\n#PasswordAuthentication yes\nPasswordAuthentication yes\nUsePAM yes\n";
+
+        assert_eq!(
+            set_sshd_option(config, "PasswordAuthentication", "no"),
+            "Port 22\nPasswordAuthentication no\nUsePAM yes\n"
+        );
+        assert_eq!(
+            set_sshd_option("Port 22\n", "ChallengeResponseAuthentication", "no"),
+            "Port 22\nChallengeResponseAuthentication no\n"
+        );
+    }
+
+    #[test]
+    fn test_lock_root_password() {
+        let shadow = "root:$6$abc$def:19000:0:99999:7:::\nazureuser:!:19000::::::\n";
+
+        assert_eq!(
+            lock_root_password(shadow),
+            "root:*LOCK*:19000:0:99999:7:::\nazureuser:!:19000::::::\n"
+        );
+    }
+
+    #[test]
+    fn test_random_salt() {
+        let salt = random_salt(16).unwrap();
+
+        assert_eq!(salt.len(), 16);
+        assert!(salt.bytes().all(|b| SALT_CHARS.contains(&b)));
+    }
+}
@@ -0,0 +1,85 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Hyper-V exposes the temporary disk on the second IDE controller, which
+/// shows up on the VMBus with this device id.
+const RESOURCE_DISK_DEVICE_ID: &str = "{00000000-0001-8899-0000-000000000000}";
+/// Symlink created by the Azure udev rules on images that ship them.
+const RESOURCE_DISK_SYMLINK: &str = "disk/azure/resource";
+/// How deep below the VMBus device the block device is looked for
+/// (`host*/target*/*:*:*:*/block/sdX`).
+const MAX_SEARCH_DEPTH: usize = 5;
+
+/// Finds the block device of the temporary disk, e.g. `/dev/sdb`, first
+/// through the udev symlink and then through the VMBus device id in sysfs.
+pub(super) fn find_resource_disk(sys_dir: &Path, dev_dir: &Path) -> Option<PathBuf> {
+    if let Ok(device) = fs::canonicalize(dev_dir.join(RESOURCE_DISK_SYMLINK)) {
+        debug!("Resource disk {} found through udev", device.display());
+        return Some(device);
+    }
+
+    let vmbus_devices = fs::read_dir(sys_dir.join("bus/vmbus/devices")).ok()?;
+    for entry in vmbus_devices.flatten() {
+        let device_id = fs::read_to_string(entry.path().join("device_id")).unwrap_or_default();
+        if !device_id
+            .trim()
+            .eq_ignore_ascii_case(RESOURCE_DISK_DEVICE_ID)
+        {
+            continue;
+        }
+        if let Some(name) = find_block_device(&entry.path(), MAX_SEARCH_DEPTH) {
+            debug!("Resource disk {} found on VMBus", name);
+            return Some(dev_dir.join(name));
+        }
+    }
+    None
+}
+
+/// The name of the first entry in a `block` directory below `dir`.
+fn find_block_device(dir: &Path, depth: usize) -> Option<String> {
+    let block = dir.join("block");
+    if let Some(name) = fs::read_dir(&block)
+        .ok()
+        .and_then(|mut entries| entries.next())
+        .and_then(|entry| entry.ok())
+    {
+        return Some(name.file_name().to_string_lossy().into_owned());
+    }
+    if depth == 0 {
+        return None;
+    }
+    fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .find_map(|entry| find_block_device(&entry.path(), depth - 1))
+}
+
+/// The first partition of `device`: `/dev/sdb1`, or `/dev/nvme0n1p1` for
+/// devices whose name ends in a digit.
+pub(super) fn first_partition(device: &Path) -> PathBuf {
+    let name = device.to_string_lossy();
+    if name.ends_with(|c: char| c.is_ascii_digit()) {
+        PathBuf::from(format!("{}p1", name))
+    } else {
+        PathBuf::from(format!("{}1", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_partition() {
+        assert_eq!(
+            first_partition(Path::new("/dev/sdb")),
+            PathBuf::from("/dev/sdb1")
+        );
+        assert_eq!(
+            first_partition(Path::new("/dev/nvme0n1")),
+            PathBuf::from("/dev/nvme0n1p1")
+        );
+    }
+}
@@ -0,0 +1,51 @@
+use crate::utils::command::CommandError;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum ResourceDiskError {
+    /// Reading sysfs or procfs, or writing the swap file, failed.
+    Io(io::Error),
+    /// A system tool (parted, mkfs, mount, swapon, ...) failed.
+    Command(CommandError),
+    /// No Azure temporary disk is attached to this VM.
+    DeviceNotFound,
+    /// `ResourceDisk.MountOptions` asks for something the agent will not do.
+    InvalidMountOptions(String),
+}
+
+impl fmt::Display for ResourceDiskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResourceDiskError::Io(e) => write!(f, "I/O error: {}", e),
+            ResourceDiskError::Command(e) => write!(f, "{}", e),
+            ResourceDiskError::DeviceNotFound => write!(f, "no resource disk found"),
+            ResourceDiskError::InvalidMountOptions(reason) => {
+                write!(f, "invalid mount options: {}", reason)
+            }
+        }
+    }
+}
+
+impl Error for ResourceDiskError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ResourceDiskError::Io(e) => Some(e),
+            ResourceDiskError::Command(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ResourceDiskError {
+    fn from(e: io::Error) -> Self {
+        ResourceDiskError::Io(e)
+    }
+}
+
+impl From<CommandError> for ResourceDiskError {
+    fn from(e: CommandError) -> Self {
+        ResourceDiskError::Command(e)
+    }
+}
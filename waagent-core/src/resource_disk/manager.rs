@@ -0,0 +1,510 @@
+use super::device::{find_resource_disk, first_partition};
+use super::{ResourceDiskError, ResourceDiskStatus, SwapStatus};
+use crate::config::{AgentConfig, Config};
+use crate::utils::command::{CommandError, CommandRunner};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+
+const SWAP_FILE: &str = "swapfile";
+/// dm-crypt mapping for encrypted swap, `/dev/mapper/<name>`.
+const SWAP_MAPPING: &str = "azure_resource_swap";
+const MB: u64 = 1024 * 1024;
+/// Always added to `ResourceDisk.MountOptions`: nothing on the disk may act
+/// as a setuid binary or a device node.
+const REQUIRED_MOUNT_OPTIONS: &[&str] = &["nosuid", "nodev"];
+/// Options that would let the mount expose or replace something other than
+/// the disk, or undo the required ones.
+const FORBIDDEN_MOUNT_OPTIONS: &[&str] =
+    &["loop", "suid", "dev", "exec", "bind", "rbind", "remount"];
+
+/// How the resource disk is set up, from the `ResourceDisk.*` options.
+#[derive(Debug, Clone)]
+pub struct ResourceDiskSettings {
+    pub format: bool,
+    pub mount_point: PathBuf,
+    pub mount_options: Option<String>,
+    pub filesystem: String,
+    pub enable_swap: bool,
+    pub swap_size_mb: u32,
+    /// Puts swap on dm-crypt with a random key, so swapped pages never reach the disk in plaintext.
+    pub enable_swap_encryption: bool,
+    /// Roots of sysfs, devtmpfs and procfs, where the disk and its mounts are looked up.
+    pub sys_dir: PathBuf,
+    pub dev_dir: PathBuf,
+    pub proc_dir: PathBuf,
+}
+
+impl ResourceDiskSettings {
+    pub fn from_config(config: &Config) -> Self {
+        let config = AgentConfig::from(config).resource_disk;
+
+        Self {
+            format: config.format,
+            mount_point: config.mount_point,
+            mount_options: config.mount_options,
+            filesystem: config.filesystem,
+            enable_swap: config.enable_swap,
+            swap_size_mb: config.swap_size_mb,
+            enable_swap_encryption: config.enable_swap_encryption,
+            sys_dir: PathBuf::from("/sys"),
+            dev_dir: PathBuf::from("/dev"),
+            proc_dir: PathBuf::from("/proc"),
+        }
+    }
+}
+
+/// Prepares the Azure temporary ("resource") disk: partitions and formats it
+/// with `ResourceDisk.Filesystem`, mounts it and puts a swap file on it.
+///
+/// The disk comes NTFS formatted and is wiped whenever the VM moves to
+/// another host, so anything found on it that is not our filesystem is
+/// reformatted.
+#[derive(Clone)]
+pub struct ResourceDisk {
+    settings: ResourceDiskSettings,
+    runner: Arc<dyn CommandRunner>,
+}
+
+impl ResourceDisk {
+    pub fn new(settings: ResourceDiskSettings, runner: Arc<dyn CommandRunner>) -> Self {
+        Self { settings, runner }
+    }
+
+    pub fn settings(&self) -> &ResourceDiskSettings {
+        &self.settings
+    }
+
+    /// Mounts the disk and enables swap as configured. Returns `None` when
+    /// `ResourceDisk.Format` is off.
+    pub fn setup(&self) -> Result<Option<ResourceDiskStatus>, ResourceDiskError> {
+        if !self.settings.format {
+            debug!("Resource disk handling is disabled (ResourceDisk.Format=n)");
+            return Ok(None);
+        }
+
+        let mount_point = self.mount()?;
+        let swap = if self.settings.enable_swap && self.settings.swap_size_mb > 0 {
+            self.enable_swap(&mount_point)?
+        } else {
+            SwapStatus::Disabled
+        };
+        Ok(Some(ResourceDiskStatus { mount_point, swap }))
+    }
+
+    /// Mounts the first partition of the resource disk, formatting the disk
+    /// first if needed. If the partition is already mounted, that mount point
+    /// is returned instead.
+    pub fn mount(&self) -> Result<PathBuf, ResourceDiskError> {
+        // Checked first, so a disk is never formatted only to fail the mount
+        let options = mount_options(self.settings.mount_options.as_deref())?;
+        let device = find_resource_disk(&self.settings.sys_dir, &self.settings.dev_dir)
+            .ok_or(ResourceDiskError::DeviceNotFound)?;
+        let partition = first_partition(&device);
+
+        if let Some(mount_point) = self.mount_point_of(&partition)? {
+            info!(
+                "Resource disk {} is already mounted on {}",
+                partition.display(),
+                mount_point.display()
+            );
+            return Ok(mount_point);
+        }
+
+        let filesystem = &self.settings.filesystem;
+        match self.filesystem_of(&partition)? {
+            Some(existing) if existing == *filesystem => {}
+            existing => {
+                info!(
+                    "Resource disk {} has filesystem {}, formatting it as {}",
+                    partition.display(),
+                    existing.as_deref().unwrap_or("none"),
+                    filesystem
+                );
+                self.format(&device, &partition)?;
+            }
+        }
+
+        let mount_point = &self.settings.mount_point;
+        fs::create_dir_all(mount_point)?;
+        let partition_arg = partition.to_string_lossy();
+        let mount_point_arg = mount_point.to_string_lossy();
+        let args = [
+            "-t",
+            filesystem.as_str(),
+            "-o",
+            options.as_str(),
+            partition_arg.as_ref(),
+            mount_point_arg.as_ref(),
+        ];
+        self.runner.run_checked("mount", &args, None)?;
+
+        info!(
+            "Mounted resource disk {} on {}",
+            partition.display(),
+            mount_point.display()
+        );
+        Ok(mount_point.clone())
+    }
+
+    /// Creates `<mount point>/swapfile` of `ResourceDisk.SwapSizeMB` and turns
+    /// it on, through dm-crypt when `ResourceDisk.EnableSwapEncryption` is set.
+    /// Without cryptsetup, swap falls back to the plain file.
+    pub fn enable_swap(&self, mount_point: &Path) -> Result<SwapStatus, ResourceDiskError> {
+        let swap_file = mount_point.join(SWAP_FILE);
+        if !self.settings.enable_swap_encryption {
+            return self.enable_plain_swap(&swap_file);
+        }
+
+        match self.cryptsetup_version() {
+            Some(version) => {
+                debug!("Using {} for encrypted swap", version);
+                self.enable_encrypted_swap(&swap_file)
+            }
+            None => {
+                let reason = "cryptsetup is not installed".to_string();
+                warn!("Cannot encrypt swap: {}", reason);
+                match self.enable_plain_swap(&swap_file)? {
+                    SwapStatus::Enabled { path, .. } => {
+                        Ok(SwapStatus::EncryptionUnavailable { path, reason })
+                    }
+                    status => Ok(status),
+                }
+            }
+        }
+    }
+
+    fn enable_plain_swap(&self, swap_file: &Path) -> Result<SwapStatus, ResourceDiskError> {
+        let enabled = SwapStatus::Enabled {
+            path: swap_file.to_path_buf(),
+            encrypted: false,
+        };
+        if self.active_swaps()?.iter().any(|swap| swap == swap_file) {
+            info!("Swap file {} is already active", swap_file.display());
+            return Ok(enabled);
+        }
+
+        self.ensure_swap_file(swap_file)?;
+        self.swapon(swap_file)?;
+        Ok(enabled)
+    }
+
+    /// Maps the swap file through dm-crypt in plain mode with a key read from
+    /// /dev/urandom. The key is never stored, so the swap contents are
+    /// unreadable once the mapping is gone, and a new key is used every boot.
+    fn enable_encrypted_swap(&self, swap_file: &Path) -> Result<SwapStatus, ResourceDiskError> {
+        let device = self.settings.dev_dir.join("mapper").join(SWAP_MAPPING);
+        let enabled = SwapStatus::Enabled {
+            path: device.clone(),
+            encrypted: true,
+        };
+        // /proc/swaps lists the mapping by its dm-N node.
+        let active = fs::canonicalize(&device)
+            .map(|node| self.active_swaps().map(|swaps| swaps.contains(&node)))
+            .unwrap_or(Ok(false))?;
+        if active {
+            info!("Encrypted swap {} is already active", device.display());
+            return Ok(enabled);
+        }
+
+        self.ensure_swap_file(swap_file)?;
+        if !device.exists() {
+            self.runner.run_checked(
+                "cryptsetup",
+                &[
+                    "open",
+                    "--type",
+                    "plain",
+                    "--cipher",
+                    "aes-xts-plain64",
+                    "--key-size",
+                    "512",
+                    "--key-file",
+                    "/dev/urandom",
+                    &swap_file.to_string_lossy(),
+                    SWAP_MAPPING,
+                ],
+                None,
+            )?;
+        }
+        self.swapon(&device)?;
+        Ok(enabled)
+    }
+
+    fn cryptsetup_version(&self) -> Option<String> {
+        match self.runner.run("cryptsetup", &["--version"], None) {
+            Ok(output) if output.success() => Some(output.stdout.trim().to_string()),
+            _ => None,
+        }
+    }
+
+    /// Creates the swap file unless one of the configured size exists.
+    fn ensure_swap_file(&self, swap_file: &Path) -> Result<(), ResourceDiskError> {
+        let size = u64::from(self.settings.swap_size_mb) * MB;
+        if fs::metadata(swap_file).map(|m| m.len()).ok() != Some(size) {
+            self.create_swap_file(swap_file, self.settings.swap_size_mb)?;
+        }
+        Ok(())
+    }
+
+    fn swapon(&self, path: &Path) -> Result<(), ResourceDiskError> {
+        let path_arg = path.to_string_lossy();
+        self.runner.run_checked("mkswap", &[&path_arg], None)?;
+        self.runner.run_checked("swapon", &[&path_arg], None)?;
+        info!(
+            "Enabled {} MB swap on {}",
+            self.settings.swap_size_mb,
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// Allocates the swap file, falling back to writing zeros on filesystems
+    /// without fallocate support.
+    fn create_swap_file(&self, path: &Path, size_mb: u32) -> Result<(), ResourceDiskError> {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        // Create it ourselves first, so it is never readable by others.
+        fs::File::create(path)?;
+        set_mode(path, 0o600)?;
+
+        let path_arg = path.to_string_lossy();
+        let size = format!("{}M", size_mb);
+        let fallocate = self
+            .runner
+            .run_checked("fallocate", &["-l", &size, &path_arg], None);
+        if let Err(e) = fallocate {
+            warn!("{}, writing the swap file with dd instead", e);
+            self.runner.run_checked(
+                "dd",
+                &[
+                    "if=/dev/zero",
+                    &format!("of={}", path_arg),
+                    "bs=1M",
+                    &format!("count={}", size_mb),
+                ],
+                None,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn format(&self, device: &Path, partition: &Path) -> Result<(), ResourceDiskError> {
+        let device_arg = device.to_string_lossy();
+        self.runner.run_checked(
+            "parted",
+            &[
+                "-s",
+                "-a",
+                "optimal",
+                &device_arg,
+                "mklabel",
+                "gpt",
+                "mkpart",
+                "primary",
+                "0%",
+                "100%",
+            ],
+            None,
+        )?;
+        // Give udev a chance to create the partition's device node.
+        if let Err(e) = self.runner.run("udevadm", &["settle"], None) {
+            debug!("udevadm settle failed: {}", e);
+        }
+
+        let filesystem = &self.settings.filesystem;
+        let partition_arg = partition.to_string_lossy();
+        let mut args = Vec::new();
+        if let Some(force) = mkfs_force_flag(filesystem) {
+            args.push(force);
+        }
+        args.push(partition_arg.as_ref());
+        self.runner
+            .run_checked(&format!("mkfs.{}", filesystem), &args, None)?;
+        Ok(())
+    }
+
+    /// The filesystem on `partition`, or `None` when blkid finds none. blkid
+    /// exits with 2 when it cannot identify the partition; any other failure
+    /// is an error, so a disk that could not be probed is never formatted.
+    fn filesystem_of(&self, partition: &Path) -> Result<Option<String>, ResourceDiskError> {
+        let output = self
+            .runner
+            .run(
+                "blkid",
+                &["-o", "value", "-s", "TYPE", &partition.to_string_lossy()],
+                None,
+            )
+            .map_err(|source| CommandError::Io {
+                command: "blkid".to_string(),
+                source,
+            })?;
+        let filesystem = output.stdout.trim();
+        match output.exit_code {
+            0 if !filesystem.is_empty() => Ok(Some(filesystem.to_string())),
+            2 if filesystem.is_empty() => Ok(None),
+            exit_code => Err(CommandError::Failed {
+                command: "blkid".to_string(),
+                exit_code,
+                stderr: output.stderr,
+            }
+            .into()),
+        }
+    }
+
+    fn mount_point_of(&self, partition: &Path) -> Result<Option<PathBuf>, ResourceDiskError> {
+        let mounts = fs::read_to_string(self.settings.proc_dir.join("mounts"))?;
+        Ok(mounts.lines().find_map(|line| {
+            let mut fields = line.split_whitespace();
+            (fields.next() == Some(partition.to_str()?))
+                .then(|| fields.next().map(PathBuf::from))
+                .flatten()
+        }))
+    }
+
+    fn active_swaps(&self) -> Result<Vec<PathBuf>, ResourceDiskError> {
+        let swaps = fs::read_to_string(self.settings.proc_dir.join("swaps"))?;
+        Ok(swaps
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_whitespace().next().map(PathBuf::from))
+            .collect())
+    }
+}
+
+/// The `-o` argument for mounting the resource disk: `configured` with
+/// `nosuid,nodev` added. Options that could mount something other than the
+/// disk, or make it setuid, device or executable, are rejected, as is anything
+/// mount could read as another argument.
+pub fn mount_options(configured: Option<&str>) -> Result<String, ResourceDiskError> {
+    let mut options = Vec::new();
+    for option in configured.unwrap_or("").split(',').map(str::trim) {
+        if option.is_empty() {
+            continue;
+        }
+        let valid = !option.starts_with('-')
+            && option
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "=_-".contains(c));
+        if !valid {
+            return Err(ResourceDiskError::InvalidMountOptions(format!(
+                "{:?} is not a mount option",
+                option
+            )));
+        }
+        let name = option.split('=').next().unwrap_or(option);
+        if FORBIDDEN_MOUNT_OPTIONS.contains(&name)
+            || name.starts_with("x-")
+            || name.starts_with("X-")
+        {
+            return Err(ResourceDiskError::InvalidMountOptions(format!(
+                "{} is not allowed",
+                option
+            )));
+        }
+        options.push(option);
+    }
+    for required in REQUIRED_MOUNT_OPTIONS {
+        if !options.contains(required) {
+            options.push(required);
+        }
+    }
+    Ok(options.join(","))
+}
+
+/// mkfs refuses to overwrite an existing filesystem without this flag.
+fn mkfs_force_flag(filesystem: &str) -> Option<&'static str> {
+    match filesystem {
+        "ext2" | "ext3" | "ext4" => Some("-F"),
+        "xfs" | "btrfs" => Some("-f"),
+        _ => None,
+    }
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<(), ResourceDiskError> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<(), ResourceDiskError> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConfigValue, HashMap};
+
+    #[test]
+    fn test_mkfs_force_flag() {
+        assert_eq!(mkfs_force_flag("ext4"), Some("-F"));
+        assert_eq!(mkfs_force_flag("xfs"), Some("-f"));
+        assert_eq!(mkfs_force_flag("vfat"), None);
+    }
+
+    #[test]
+    fn test_mount_options_add_nosuid_and_nodev() {
+        assert_eq!(mount_options(None).unwrap(), "nosuid,nodev");
+        assert_eq!(
+            mount_options(Some("defaults,nofail")).unwrap(),
+            "defaults,nofail,nosuid,nodev"
+        );
+        assert_eq!(mount_options(Some("nodev,nosuid")).unwrap(), "nodev,nosuid");
+    }
+
+    #[test]
+    fn test_mount_options_reject_escalation() {
+        for options in [
+            "loop",
+            "loop=/dev/loop0",
+            "defaults,suid",
+            "dev",
+            "exec",
+            "bind",
+            "rbind",
+            "remount,rw",
+            "x-systemd.automount",
+            "X-mount.mkdir",
+            "-o,bind",
+            "nodev /etc",
+        ] {
+            assert!(
+                matches!(
+                    mount_options(Some(options)),
+                    Err(ResourceDiskError::InvalidMountOptions(_))
+                ),
+                "{}",
+                options
+            );
+        }
+    }
+
+    #[test]
+    fn test_settings_treat_none_as_no_mount_options() {
+        let mut values = HashMap::new();
+        values.insert(
+            "ResourceDisk.MountOptions".to_string(),
+            ConfigValue::String("None".to_string()),
+        );
+        assert_eq!(
+            ResourceDiskSettings::from_config(&Config::from_map(values.clone())).mount_options,
+            None
+        );
+
+        values.insert(
+            "ResourceDisk.MountOptions".to_string(),
+            ConfigValue::String("nodev,nosuid".to_string()),
+        );
+        assert_eq!(
+            ResourceDiskSettings::from_config(&Config::from_map(values))
+                .mount_options
+                .as_deref(),
+            Some("nodev,nosuid")
+        );
+    }
+}
@@ -0,0 +1,8 @@
+mod device;
+mod error;
+mod manager;
+mod status;
+
+pub use error::ResourceDiskError;
+pub use manager::{mount_options, ResourceDisk, ResourceDiskSettings};
+pub use status::{ResourceDiskStatus, SwapStatus};
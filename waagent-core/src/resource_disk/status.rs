@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+
+/// Where the resource disk ended up mounted and how swap was configured.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceDiskStatus {
+    pub mount_point: PathBuf,
+    pub swap: SwapStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SwapStatus {
+    Disabled,
+    /// `path` is the swap file, or the dm-crypt device on top of it when encrypted.
+    Enabled {
+        path: PathBuf,
+        encrypted: bool,
+    },
+    /// Encryption was requested but could not be set up, so swap is unencrypted.
+    EncryptionUnavailable {
+        path: PathBuf,
+        reason: String,
+    },
+}
+
+impl fmt::Display for ResourceDiskStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "resource disk mounted on {}, {}",
+            self.mount_point.display(),
+            self.swap
+        )
+    }
+}
+
+impl fmt::Display for SwapStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SwapStatus::Disabled => write!(f, "swap is disabled"),
+            SwapStatus::Enabled {
+                path,
+                encrypted: true,
+            } => write!(f, "encrypted swap enabled on {}", path.display()),
+            SwapStatus::Enabled {
+                path,
+                encrypted: false,
+            } => write!(f, "swap enabled on {}", path.display()),
+            SwapStatus::EncryptionUnavailable { path, reason } => write!(
+                f,
+                "swap enabled on {} without encryption: {}",
+                path.display(),
+                reason
+            ),
+        }
+    }
+}
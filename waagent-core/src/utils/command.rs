@@ -0,0 +1,245 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Exit code, stdout and stderr of a finished command.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandOutput {
+    /// `-1` when the process was killed by a signal.
+    pub exit_code: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CommandOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == 0
+    }
+}
+
+#[derive(Debug)]
+pub enum CommandError {
+    /// The program could not be started.
+    Io { command: String, source: io::Error },
+    /// The program exited with a non-zero code.
+    Failed {
+        command: String,
+        exit_code: i32,
+        stderr: String,
+    },
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Io { command, source } => {
+                write!(f, "failed to run {}: {}", command, source)
+            }
+            CommandError::Failed {
+                command,
+                exit_code,
+                stderr,
+            } => write!(f, "{} exited with {}: {}", command, exit_code, stderr),
+        }
+    }
+}
+
+impl Error for CommandError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CommandError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Runs external programs. System configuration goes through this trait so
+/// it can be exercised with a fake runner in tests.
+pub trait CommandRunner: Send + Sync {
+    /// Runs `program` with `args`, feeding `input` on stdin, and waits for it to exit.
+    fn run(&self, program: &str, args: &[&str], input: Option<&[u8]>) -> io::Result<CommandOutput>;
+
+    /// Like [`CommandRunner::run`], but treats a non-zero exit code as an error.
+    fn run_checked(
+        &self,
+        program: &str,
+        args: &[&str],
+        input: Option<&[u8]>,
+    ) -> Result<CommandOutput, CommandError> {
+        let output = self
+            .run(program, args, input)
+            .map_err(|source| CommandError::Io {
+                command: program.to_string(),
+                source,
+            })?;
+        if !output.success() {
+            return Err(CommandError::Failed {
+                command: program.to_string(),
+                exit_code: output.exit_code,
+                stderr: output.stderr.trim().to_string(),
+            });
+        }
+        Ok(output)
+    }
+
+    /// Like [`CommandRunner::run`], but kills `program` once it has run for
+    /// `timeout`; `None` means it was killed. Runners that cannot stop a
+    /// program, such as fakes in tests, wait for it as `run` does.
+    fn run_with_timeout(
+        &self,
+        program: &str,
+        args: &[&str],
+        input: Option<&[u8]>,
+        timeout: Duration,
+    ) -> io::Result<Option<CommandOutput>> {
+        let _ = timeout;
+        self.run(program, args, input).map(Some)
+    }
+}
+
+/// How often a command with a timeout is checked on.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Runs commands on the host with [`std::process::Command`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemCommandRunner;
+
+impl SystemCommandRunner {
+    fn spawn(
+        program: &str,
+        args: &[&str],
+        input: Option<&[u8]>,
+    ) -> io::Result<(Child, JoinHandle<io::Result<()>>)> {
+        debug!("Running {} {}", program, args.join(" "));
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Write stdin from another thread so a large output cannot deadlock us.
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let input = input.unwrap_or_default().to_vec();
+        let writer = thread::spawn(move || stdin.write_all(&input));
+        Ok((child, writer))
+    }
+}
+
+impl CommandRunner for SystemCommandRunner {
+    fn run(&self, program: &str, args: &[&str], input: Option<&[u8]>) -> io::Result<CommandOutput> {
+        let (child, writer) = Self::spawn(program, args, input)?;
+        let output = child.wait_with_output()?;
+        // A command that does not read stdin closes the pipe early; only its exit code matters.
+        let _ = writer.join();
+
+        Ok(CommandOutput {
+            exit_code: output.status.code().unwrap_or(-1),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+
+    fn run_with_timeout(
+        &self,
+        program: &str,
+        args: &[&str],
+        input: Option<&[u8]>,
+        timeout: Duration,
+    ) -> io::Result<Option<CommandOutput>> {
+        let (mut child, writer) = Self::spawn(program, args, input)?;
+        let stdout = read_to_end(child.stdout.take().expect("stdout is piped"));
+        let stderr = read_to_end(child.stderr.take().expect("stderr is piped"));
+
+        let deadline = Instant::now() + timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                // The readers are left behind: a process the program started
+                // may still hold its output open.
+                child.kill()?;
+                child.wait()?;
+                return Ok(None);
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+        let _ = writer.join();
+
+        let output = |reader: JoinHandle<Vec<u8>>| {
+            String::from_utf8_lossy(&reader.join().unwrap_or_default()).into_owned()
+        };
+        Ok(Some(CommandOutput {
+            exit_code: status.code().unwrap_or(-1),
+            stdout: output(stdout),
+            stderr: output(stderr),
+        }))
+    }
+}
+
+/// Reads `pipe` until it is closed on a thread of its own.
+fn read_to_end(mut pipe: impl Read + Send + 'static) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        let _ = pipe.read_to_end(&mut buffer);
+        buffer
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_runner_captures_output_and_stdin() {
+        let output = SystemCommandRunner
+            .run("sh", &["-c", "cat; echo err >&2; exit 3"], Some(b"hello"))
+            .unwrap();
+
+        assert_eq!(output.exit_code, 3);
+        assert_eq!(output.stdout, "hello");
+        assert_eq!(output.stderr, "err\n");
+    }
+
+    #[test]
+    fn test_run_checked_reports_failures() {
+        let result = SystemCommandRunner.run_checked("sh", &["-c", "echo nope >&2; exit 1"], None);
+
+        match result {
+            Err(CommandError::Failed {
+                command,
+                exit_code,
+                stderr,
+            }) => {
+                assert_eq!(command, "sh");
+                assert_eq!(exit_code, 1);
+                assert_eq!(stderr, "nope");
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(matches!(
+            SystemCommandRunner.run_checked("/nonexistent/program", &[], None),
+            Err(CommandError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn test_run_with_timeout_kills_a_program_that_runs_too_long() {
+        let finished = SystemCommandRunner
+            .run_with_timeout("sh", &["-c", "echo done"], None, Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(finished.unwrap().stdout, "done\n");
+
+        let started = Instant::now();
+        let killed = SystemCommandRunner
+            .run_with_timeout("sleep", &["10"], None, Duration::from_millis(200))
+            .unwrap();
+        assert!(killed.is_none());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -1,6 +1,7 @@
-use std::fs::File;
+use std::fs::{self, File, OpenOptions};
+use std::io;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 pub fn read_file(path: &Path) -> std::io::Result<String> {
     // Errors should be propagated since this is a lib, caller in waagent-rs should handle errors.
@@ -11,3 +12,87 @@ pub fn read_file(path: &Path) -> std::io::Result<String> {
     file.read_to_string(&mut buffer)?;
     Ok(buffer)
 }
+
+/// Replaces `path` with `contents`. The data goes to a temporary file next to
+/// it that is created with `mode` and then renamed over `path`, so the file is
+/// never readable with wider permissions, nor seen half written. `mode` is
+/// ignored outside Unix.
+pub fn write_file_with_mode(path: &Path, contents: &[u8], mode: u32) -> io::Result<()> {
+    write_through_temp(path, contents, mode, None)
+}
+
+/// Like `write_file_with_mode`, for a file that already exists: the new file
+/// keeps its permissions and, on Unix, its owner and group.
+pub fn replace_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let metadata = fs::metadata(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        write_through_temp(
+            path,
+            contents,
+            metadata.mode() & 0o7777,
+            Some((metadata.uid(), metadata.gid())),
+        )
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        write_through_temp(path, contents, 0, None)
+    }
+}
+
+fn write_through_temp(
+    path: &Path,
+    contents: &[u8],
+    mode: u32,
+    owner: Option<(u32, u32)>,
+) -> io::Result<()> {
+    let temp = temp_path(path)?;
+    // Left behind by an interrupted write
+    match fs::remove_file(&temp) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = (mode, owner);
+
+    let written = options.open(&temp).and_then(|mut file| {
+        #[cfg(unix)]
+        if let Some((uid, gid)) = owner {
+            use std::os::unix::fs::PermissionsExt;
+            std::os::unix::fs::fchown(&file, Some(uid), Some(gid))?;
+            // The umask may have narrowed the mode the file is created with
+            file.set_permissions(fs::Permissions::from_mode(mode))?;
+        }
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+    if let Err(e) = written.and_then(|_| fs::rename(&temp, path)) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// `.<name>.tmp` in the directory of `path`.
+fn temp_path(path: &Path) -> io::Result<PathBuf> {
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a file path", path.display()),
+        )
+    })?;
+    let mut temp = std::ffi::OsString::from(".");
+    temp.push(name);
+    temp.push(".tmp");
+    Ok(path.with_file_name(temp))
+}
@@ -0,0 +1,190 @@
+use base64::prelude::*;
+use serde_json::json;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tempfile::TempDir;
+use waagent_core::certificates::{CertificateError, CertificateStore, Openssl};
+use waagent_core::protocol::Certificates;
+
+fn openssl() -> Openssl {
+    Openssl::new("openssl")
+}
+
+fn mode(path: &Path) -> u32 {
+    fs::metadata(path).unwrap().permissions().mode() & 0o777
+}
+
+/// Generates a self-signed "tenant" certificate, as a customer would upload to a key vault.
+fn tenant_certificate(dir: &Path) -> (String, String) {
+    let cert = dir.join("tenant.crt");
+    let key = dir.join("tenant.key");
+    openssl()
+        .run(
+            &[
+                "req",
+                "-x509",
+                "-nodes",
+                "-subj",
+                "/CN=tenant",
+                "-days",
+                "1",
+                "-newkey",
+                "rsa:2048",
+                "-keyout",
+                key.to_str().unwrap(),
+                "-out",
+                cert.to_str().unwrap(),
+            ],
+            None,
+        )
+        .unwrap();
+    (
+        cert.to_str().unwrap().to_string(),
+        key.to_str().unwrap().to_string(),
+    )
+}
+
+/// Builds the Certificates document the way the host does: the tenant
+/// certificate and key as a PFX, encrypted for the agent's transport certificate.
+fn certificates_document(lib_dir: &Path, cert: &str, key: &str) -> Certificates {
+    let pfx = openssl()
+        .run(
+            &[
+                "pkcs12", "-export", "-in", cert, "-inkey", key, "-passout", "pass:",
+            ],
+            None,
+        )
+        .unwrap();
+    let transport = lib_dir.join("TransportCert.pem");
+    let encrypted = openssl()
+        .run(
+            &[
+                "cms",
+                "-encrypt",
+                "-binary",
+                "-outform",
+                "DER",
+                "-aes256",
+                transport.to_str().unwrap(),
+            ],
+            Some(&pfx),
+        )
+        .unwrap();
+
+    Certificates {
+        version: Some("2012-11-30".to_string()),
+        incarnation: Some(1),
+        format: Some("Pkcs7BlobWithPfxContents".to_string()),
+        data: Some(BASE64_STANDARD.encode(encrypted)),
+    }
+}
+
+#[test]
+fn test_transport_certificate_is_generated_once() {
+    let dir = TempDir::new().unwrap();
+    let store = CertificateStore::new(dir.path(), openssl());
+
+    let first = store.ensure_transport_certificate().unwrap();
+    let second = store.ensure_transport_certificate().unwrap();
+
+    assert_eq!(first, second);
+    let der = BASE64_STANDARD.decode(&first).unwrap();
+    assert_eq!(der[0], 0x30, "expected a DER sequence");
+    assert_eq!(mode(&dir.path().join("TransportPrivate.pem")), 0o600);
+}
+
+#[test]
+fn test_install_certificates_and_decrypt_protected_settings() {
+    let dir = TempDir::new().unwrap();
+    let lib_dir = dir.path().join("lib");
+    let store = CertificateStore::new(&lib_dir, openssl());
+    store.ensure_transport_certificate().unwrap();
+    let (cert, key) = tenant_certificate(dir.path());
+
+    let thumbprints = store
+        .install(&certificates_document(&lib_dir, &cert, &key))
+        .unwrap();
+
+    let expected = String::from_utf8(
+        openssl()
+            .run(
+                &["x509", "-in", &cert, "-fingerprint", "-sha1", "-noout"],
+                None,
+            )
+            .unwrap(),
+    )
+    .unwrap();
+    let expected = expected.trim().split_once('=').unwrap().1.replace(':', "");
+    assert_eq!(thumbprints, vec![expected.clone()]);
+    assert_eq!(mode(&store.certificate_path(&expected)), 0o600);
+    assert_eq!(mode(&store.private_key_path(&expected)), 0o600);
+
+    let settings = json!({"commandToExecute": "echo secret", "storageAccountKey": "key"});
+    let protected_settings = openssl()
+        .run(
+            &[
+                "smime", "-encrypt", "-binary", "-outform", "DER", "-aes256", &cert,
+            ],
+            Some(settings.to_string().as_bytes()),
+        )
+        .unwrap();
+    let protected_settings = BASE64_STANDARD.encode(protected_settings);
+
+    let decrypted = store
+        .decrypt_protected_settings(&expected, &protected_settings)
+        .unwrap();
+    assert_eq!(decrypted, settings);
+}
+
+#[test]
+fn test_install_replaces_a_readable_key() {
+    let dir = TempDir::new().unwrap();
+    let lib_dir = dir.path().join("lib");
+    let store = CertificateStore::new(&lib_dir, openssl());
+    store.ensure_transport_certificate().unwrap();
+    let (cert, key) = tenant_certificate(dir.path());
+    let certificates = certificates_document(&lib_dir, &cert, &key);
+    let thumbprint = store.install(&certificates).unwrap().remove(0);
+    let key_path = store.private_key_path(&thumbprint);
+    fs::set_permissions(&key_path, fs::Permissions::from_mode(0o644)).unwrap();
+    // Left behind by an interrupted install
+    fs::write(lib_dir.join(format!(".{}.prv.tmp", thumbprint)), "stale").unwrap();
+
+    store.install(&certificates).unwrap();
+
+    assert_eq!(mode(&key_path), 0o600);
+    assert!(fs::read_to_string(&key_path)
+        .unwrap()
+        .contains("PRIVATE KEY"));
+    let leftovers: Vec<_> = fs::read_dir(&lib_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .filter(|name| name.to_string_lossy().ends_with(".tmp"))
+        .collect();
+    assert!(leftovers.is_empty(), "{:?}", leftovers);
+}
+
+#[test]
+fn test_decrypt_with_unknown_thumbprint() {
+    let dir = TempDir::new().unwrap();
+    let store = CertificateStore::new(dir.path(), openssl());
+
+    let result = store.decrypt_protected_settings("0123456789ABCDEF", "AAAA");
+
+    assert!(matches!(result, Err(CertificateError::NotFound(_))));
+}
+
+#[test]
+fn test_install_without_data_is_a_no_op() {
+    let dir = TempDir::new().unwrap();
+    let store = CertificateStore::new(dir.path(), openssl());
+    let certificates = Certificates {
+        version: None,
+        incarnation: None,
+        format: None,
+        data: None,
+    };
+
+    assert!(store.install(&certificates).unwrap().is_empty());
+}
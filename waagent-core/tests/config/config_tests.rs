@@ -1,5 +1,8 @@
 use std::path::Path;
-use waagent_core::config::{Config, ConfigSchema, ConfigValue, ExpectedType};
+use waagent_core::config::{
+    drop_in_dir, AgentConfig, Config, ConfigSchema, ConfigSource, ConfigValue, ExpectedType,
+    Severity,
+};
 
 #[test]
 fn test_default_config_has_all_required_keys() {
@@ -98,3 +101,249 @@ fn test_config_file_example() {
     assert_eq!(config.get_value("DetectScvmmEnv"), Some(&ConfigValue::Bool(false)));
     assert_eq!(config.get_value("OS.HomeDir"), Some(&ConfigValue::String("/home".to_string())));
 }
+
+#[test]
+fn test_typed_accessors() {
+    let config = Config::default();
+
+    assert_eq!(config.get_bool("Extensions.Enabled"), Some(true));
+    assert_eq!(config.get_integer("Extensions.GoalStatePeriod"), Some(6));
+    assert_eq!(config.get_string("Lib.Dir"), Some("/var/lib/waagent"));
+
+    // wrong type or unknown key
+    assert_eq!(config.get_bool("Lib.Dir"), None);
+    assert_eq!(config.get_integer("Fake.Key"), None);
+}
+
+#[test]
+fn test_schema_has_a_key_for_every_default() {
+    let default_config = Config::default();
+    let schema_config = ConfigSchema::new();
+    assert!(default_config
+        .config()
+        .keys()
+        .all(|k| schema_config.is_valid_key(k)));
+}
+
+#[test]
+fn test_agent_config_from_file() {
+    let test_config_path: &Path = Path::new("tests/config/data/waagent-test.conf");
+    let config = Config::from_file(test_config_path).unwrap();
+    let agent_config = AgentConfig::from(&config);
+
+    assert!(agent_config.provisioning.delete_root_password);
+    assert_eq!(agent_config.resource_disk.filesystem, "ext4");
+    assert_eq!(agent_config.resource_disk.mount_point, Path::new("/mnt"));
+}
+
+#[test]
+fn test_config_diagnostics_from_file() {
+    let test_config_path: &Path = Path::new("tests/config/data/waagent-diagnostics.conf");
+    let (config, diagnostics) = Config::from_file_with_diagnostics(test_config_path).unwrap();
+
+    let summary: Vec<_> = diagnostics
+        .iter()
+        .map(|d| (d.line, d.severity, d.key.as_str()))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (3, Severity::Error, "Extensions.Enabled"),
+            (6, Severity::Warning, "Logs.Verbose"),
+            (7, Severity::Error, "OS.SshClientAliveInterval"),
+            (8, Severity::Error, "HttpProxy.Port"),
+            (9, Severity::Warning, "Fake.Key"),
+            (10, Severity::Warning, "ResourceDisk.SwapSizeMB"),
+        ]
+    );
+    assert_eq!(
+        diagnostics[0].to_string(),
+        "error: line 3: Extensions.Enabled=yes: expected y or n; the default is used"
+    );
+
+    // Invalid values fall back to their defaults, valid ones still apply
+    assert_eq!(config.get_bool("Extensions.Enabled"), Some(true));
+    assert_eq!(config.get_bool("Logs.Verbose"), Some(true));
+    assert_eq!(config.get_bool("OS.EnableFirewall"), Some(true));
+}
+
+#[test]
+fn test_schema_metadata() {
+    let schema = ConfigSchema::new();
+
+    let key_pair_type = schema
+        .get_metadata("Provisioning.SshHostKeyPairType")
+        .unwrap();
+    assert!(key_pair_type.allowed_values.contains(&"ed25519"));
+    assert!(!key_pair_type.description.is_empty());
+
+    let cpu_quota = schema.get_metadata("Debug.AgentCpuQuota").unwrap();
+    assert_eq!((cpu_quota.min, cpu_quota.max), (Some(1), Some(100)));
+    assert!(cpu_quota.check(&ConfigValue::Integer(101)).is_err());
+
+    assert!(
+        schema
+            .get_metadata("AutoUpdate.Enabled")
+            .unwrap()
+            .deprecated
+    );
+    assert!(schema
+        .keys()
+        .all(|metadata| !metadata.description.is_empty()));
+}
+
+#[test]
+fn test_defaults_satisfy_schema_constraints() {
+    let schema = ConfigSchema::new();
+    let defaults = Config::default();
+
+    for metadata in schema.keys() {
+        let default = defaults.get_value(metadata.key).unwrap();
+        assert_eq!(metadata.check(default), Ok(()), "{}", metadata.key);
+    }
+}
+
+#[test]
+fn test_json_schema() {
+    let json_schema = ConfigSchema::new().to_json_schema();
+    let properties = json_schema["properties"].as_object().unwrap();
+
+    assert_eq!(properties.len(), ConfigSchema::new().schema().len());
+    assert_eq!(
+        properties["Protocol.EndpointDiscovery"]["enum"],
+        serde_json::json!(["dhcp", "static"])
+    );
+    assert_eq!(properties["Protocol.EndpointDiscovery"]["default"], "dhcp");
+    assert_eq!(properties["Debug.AgentCpuQuota"]["maximum"], 100);
+    assert_eq!(properties["OS.EnableFirewall"]["type"], "boolean");
+    assert_eq!(
+        properties["HttpProxy.Port"]["default"],
+        serde_json::Value::Null
+    );
+    assert_eq!(properties["AutoUpdate.Enabled"]["deprecated"], true);
+}
+
+#[test]
+fn test_load_layers_drop_ins_in_lexical_order() {
+    let path = Path::new("tests/config/data/waagent-layered.conf");
+    let config = Config::load_with_env(path, Vec::new()).unwrap();
+
+    assert_eq!(config.get_bool("OS.EnableFirewall"), Some(true));
+    assert_eq!(config.get_integer("ResourceDisk.SwapSizeMB"), Some(4096));
+    assert_eq!(config.get_bool("Logs.Verbose"), Some(false));
+    assert_eq!(config.get_string("ResourceDisk.Filesystem"), Some("ext4"));
+
+    let drop_ins = drop_in_dir(path);
+    assert_eq!(
+        config.origin("OS.EnableFirewall"),
+        Some(&ConfigSource::File(drop_ins.join("10-firewall.conf")))
+    );
+    assert_eq!(
+        config.origin("ResourceDisk.SwapSizeMB"),
+        Some(&ConfigSource::File(drop_ins.join("20-swap.conf")))
+    );
+    assert_eq!(
+        config.origin("Logs.Verbose"),
+        Some(&ConfigSource::File(path.to_path_buf()))
+    );
+    assert_eq!(config.origin("Lib.Dir"), Some(&ConfigSource::Default));
+}
+
+#[test]
+fn test_load_env_overrides_files() {
+    let path = Path::new("tests/config/data/waagent-layered.conf");
+    let vars = vec![
+        (
+            "WAAGENT_RESOURCEDISK_SWAPSIZEMB".to_string(),
+            "512".to_string(),
+        ),
+        ("WAAGENT_DETECTSCVMMENV".to_string(), "y".to_string()),
+        // Invalid and unknown overrides are ignored
+        ("WAAGENT_OS_ENABLEFIREWALL".to_string(), "maybe".to_string()),
+        ("WAAGENT_FAKE_KEY".to_string(), "1".to_string()),
+        ("PATH".to_string(), "/usr/bin".to_string()),
+    ];
+    let config = Config::load_with_env(path, vars).unwrap();
+
+    assert_eq!(config.get_integer("ResourceDisk.SwapSizeMB"), Some(512));
+    assert_eq!(
+        config.origin("ResourceDisk.SwapSizeMB"),
+        Some(&ConfigSource::Environment(
+            "WAAGENT_RESOURCEDISK_SWAPSIZEMB".to_string()
+        ))
+    );
+    assert_eq!(config.get_bool("DetectScvmmEnv"), Some(true));
+    assert_eq!(config.get_bool("OS.EnableFirewall"), Some(true));
+    assert!(config.get_value("Fake.Key").is_none());
+
+    let output = config.show_origins();
+    assert!(output.contains("ResourceDisk.SwapSizeMB = 512  # $WAAGENT_RESOURCEDISK_SWAPSIZEMB\n"));
+    assert!(output.contains("Lib.Dir = /var/lib/waagent  # default\n"));
+}
+
+#[test]
+fn test_load_ignores_invalid_values_in_fragments() {
+    let path = Path::new("tests/config/data/waagent-bad-fragment.conf");
+    let config = Config::load_with_env(path, Vec::new()).unwrap();
+
+    // The base file's values stay, and the fragment is not blamed for them
+    assert_eq!(config.get_bool("Logs.Verbose"), Some(true));
+    assert_eq!(
+        config.origin("Logs.Verbose"),
+        Some(&ConfigSource::File(path.to_path_buf()))
+    );
+    assert_eq!(config.get_string("ResourceDisk.Filesystem"), Some("xfs"));
+    assert_eq!(
+        config.origin("ResourceDisk.Filesystem"),
+        Some(&ConfigSource::File(path.to_path_buf()))
+    );
+    assert_eq!(config.get_integer("ResourceDisk.SwapSizeMB"), Some(2048));
+}
+
+#[test]
+fn test_load_reports_diagnostics_for_every_layer() {
+    let path = Path::new("tests/config/data/waagent-bad-fragment.conf");
+    let vars = vec![
+        ("WAAGENT_OS_ENABLEFIREWALL".to_string(), "maybe".to_string()),
+        ("WAAGENT_FAKE_KEY".to_string(), "1".to_string()),
+    ];
+    let (_, diagnostics) = Config::load_with_diagnostics(path, vars).unwrap();
+
+    let fragment = ConfigSource::File(drop_in_dir(path).join("50-bad.conf"));
+    let summary: Vec<_> = diagnostics
+        .iter()
+        .map(|(source, d)| (source.clone(), d.severity, d.key.as_str()))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (fragment.clone(), Severity::Error, "Logs.Verbose"),
+            (fragment, Severity::Warning, "ResourceDisk.Filesystem"),
+            (
+                ConfigSource::Environment("WAAGENT_OS_ENABLEFIREWALL".to_string()),
+                Severity::Error,
+                "OS.EnableFirewall"
+            ),
+            (
+                ConfigSource::Environment("WAAGENT_FAKE_KEY".to_string()),
+                Severity::Warning,
+                "WAAGENT_FAKE_KEY"
+            ),
+        ]
+    );
+    assert_eq!(
+        diagnostics[0].1.to_string(),
+        "error: line 1: Logs.Verbose=maybe: expected y or n; the line is ignored"
+    );
+    assert_eq!(
+        diagnostics[2].1.to_string(),
+        "error: OS.EnableFirewall=maybe: expected y or n; the variable is ignored"
+    );
+}
+
+#[test]
+fn test_load_requires_the_main_file() {
+    let path = Path::new("tests/config/data/missing.conf");
+
+    assert!(Config::load_with_env(path, Vec::new()).is_err());
+}
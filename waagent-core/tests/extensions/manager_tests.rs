@@ -0,0 +1,336 @@
+use crate::protocol::MockWireServer;
+use quick_xml::de::from_str;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use tempfile::TempDir;
+use waagent_core::extensions::{
+    ExtensionError, ExtensionsManager, HandlerState, INVALID_STATUS_CODE, PROCESSING_ERROR_CODE,
+};
+use waagent_core::protocol::{ExtensionsConfig, WireServerClient};
+use zip::write::SimpleFileOptions;
+
+const NAME: &str = "Test.Extension";
+
+/// Appends "<command> <seq>" to `commands.log` in the lib dir, so the tests can
+/// follow the commands across handler versions. Enable writes a status file.
+const HANDLER_SCRIPT: &str = r#"#!/bin/sh
+echo "$1 $ConfigSequenceNumber" >> "$AZURE_GUEST_AGENT_EXTENSION_PATH/../commands.log"
+if [ "$1" = "$FAIL_ON" ]; then
+  echo "cannot $1" >&2
+  exit 3
+fi
+if [ "$1" = "enable" ]; then
+  echo '$STATUS' > "status/$ConfigSequenceNumber.status"
+fi
+"#;
+
+const STATUS: &str = r#"[{"version": 1.0, "timestampUTC": "2024-05-01T10:00:00Z", "status": {"name": "Test.Extension", "operation": "Enable", "status": "success", "code": 0, "formattedMessage": {"lang": "en-US", "message": "enabled"}, "substatus": [{"name": "StdOut", "status": "success", "code": 0, "formattedMessage": {"lang": "en-US", "message": "hi"}}]}}]"#;
+
+fn package(fail_on: &str) -> Vec<u8> {
+    let manifest = r#"[{"version": 1.0, "handlerManifest": {
+        "installCommand": "scripts/handler.sh install",
+        "uninstallCommand": "scripts/handler.sh uninstall",
+        "updateCommand": "scripts/handler.sh update",
+        "enableCommand": "scripts/handler.sh enable",
+        "disableCommand": "scripts/handler.sh disable"
+    }}]"#;
+    let script = HANDLER_SCRIPT
+        .replace("$FAIL_ON", fail_on)
+        .replace("$STATUS", STATUS);
+
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default();
+    writer.start_file("HandlerManifest.json", options).unwrap();
+    writer.write_all(manifest.as_bytes()).unwrap();
+    writer.start_file("scripts/handler.sh", options).unwrap();
+    writer.write_all(script.as_bytes()).unwrap();
+    writer.finish().unwrap().into_inner()
+}
+
+fn start_server() -> MockWireServer {
+    let manifest = r#"<PluginVersionManifest>
+  <Plugins>
+    <Plugin><Version>1.0.0</Version><Uris><Uri>{endpoint}/package-1.0.0.zip</Uri></Uris></Plugin>
+    <Plugin><Version>1.1.0</Version><Uris><Uri>{endpoint}/package-1.1.0.zip</Uri></Uris></Plugin>
+  </Plugins>
+</PluginVersionManifest>"#;
+    let server = MockWireServer::start(vec![("/manifest.xml", 200, manifest.to_string())]);
+    server.set_binary_route("/package-1.0.0.zip", 200, package("none"));
+    server.set_binary_route("/package-1.1.0.zip", 200, package("none"));
+    server
+}
+
+fn extensions_config(
+    server: &MockWireServer,
+    version: &str,
+    state: &str,
+    seq_no: u32,
+) -> ExtensionsConfig {
+    let xml = format!(
+        r#"<Extensions version="1.0.0.0" goalStateIncarnation="1">
+  <Plugins>
+    <Plugin name="{name}" version="{version}" location="{url}/manifest.xml" state="{state}" />
+  </Plugins>
+  <PluginSettings>
+    <Plugin name="{name}" version="{version}">
+      <RuntimeSettings seqNo="{seq_no}">{{"runtimeSettings":[{{"handlerSettings":{{"publicSettings":{{"seq":{seq_no}}}}}}}]}}</RuntimeSettings>
+    </Plugin>
+  </PluginSettings>
+</Extensions>"#,
+        name = NAME,
+        version = version,
+        url = server.url,
+        state = state,
+        seq_no = seq_no
+    );
+    from_str(&xml).unwrap()
+}
+
+fn manager(server: &MockWireServer, dir: &TempDir) -> ExtensionsManager {
+    ExtensionsManager::new(
+        WireServerClient::with_endpoint(&server.url),
+        &dir.path().join("lib"),
+        &dir.path().join("log"),
+    )
+}
+
+fn commands(dir: &TempDir) -> Vec<String> {
+    fs::read_to_string(dir.path().join("lib/commands.log"))
+        .unwrap_or_default()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+#[tokio::test]
+async fn test_enable_installs_and_enables_handler() {
+    let server = start_server();
+    let dir = TempDir::new().unwrap();
+    let manager = manager(&server, &dir);
+
+    let outcomes = manager
+        .process(&extensions_config(&server, "1.0.0", "enabled", 0))
+        .await;
+
+    assert_eq!(outcomes.len(), 1);
+    assert!(outcomes[0].result.is_ok(), "{:?}", outcomes[0].result);
+    assert_eq!(outcomes[0].state, HandlerState::Enabled);
+    assert_eq!(outcomes[0].seq_no, Some(0));
+    assert_eq!(commands(&dir), vec!["install 0", "enable 0"]);
+
+    let handler = manager.handler(NAME, "1.0.0");
+    let settings = fs::read_to_string(handler.settings_file(0)).unwrap();
+    assert!(settings.contains(r#""publicSettings":{"seq":0}"#));
+
+    let environment: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(handler.dir().join("HandlerEnvironment.json")).unwrap(),
+    )
+    .unwrap();
+    let environment = &environment[0]["handlerEnvironment"];
+    assert_eq!(
+        Path::new(environment["configFolder"].as_str().unwrap()),
+        handler.config_dir()
+    );
+    assert_eq!(
+        Path::new(environment["logFolder"].as_str().unwrap()),
+        dir.path().join("log").join(NAME)
+    );
+}
+
+#[tokio::test]
+async fn test_same_sequence_number_is_not_processed_twice() {
+    let server = start_server();
+    let dir = TempDir::new().unwrap();
+    let manager = manager(&server, &dir);
+
+    manager
+        .process(&extensions_config(&server, "1.0.0", "enabled", 0))
+        .await;
+    server.clear_requests();
+    manager
+        .process(&extensions_config(&server, "1.0.0", "enabled", 0))
+        .await;
+
+    assert_eq!(commands(&dir), vec!["install 0", "enable 0"]);
+    assert!(server.requests().is_empty());
+
+    manager
+        .process(&extensions_config(&server, "1.0.0", "enabled", 1))
+        .await;
+    assert_eq!(commands(&dir), vec!["install 0", "enable 0", "enable 1"]);
+}
+
+#[tokio::test]
+async fn test_new_version_updates_handler() {
+    let server = start_server();
+    let dir = TempDir::new().unwrap();
+    let manager = manager(&server, &dir);
+
+    manager
+        .process(&extensions_config(&server, "1.0.0", "enabled", 0))
+        .await;
+    let outcomes = manager
+        .process(&extensions_config(&server, "1.1.0", "enabled", 1))
+        .await;
+
+    assert!(outcomes[0].result.is_ok(), "{:?}", outcomes[0].result);
+    assert_eq!(
+        commands(&dir),
+        vec![
+            "install 0",
+            "enable 0",
+            "disable 1",
+            "update 1",
+            "uninstall 1",
+            "enable 1"
+        ]
+    );
+    assert!(!manager.handler(NAME, "1.0.0").dir().exists());
+    assert_eq!(
+        manager.handler(NAME, "1.1.0").state(),
+        HandlerState::Enabled
+    );
+}
+
+#[tokio::test]
+async fn test_disable_and_uninstall() {
+    let server = start_server();
+    let dir = TempDir::new().unwrap();
+    let manager = manager(&server, &dir);
+
+    manager
+        .process(&extensions_config(&server, "1.0.0", "enabled", 0))
+        .await;
+    let outcomes = manager
+        .process(&extensions_config(&server, "1.0.0", "disabled", 1))
+        .await;
+    assert_eq!(outcomes[0].state, HandlerState::Disabled);
+
+    let outcomes = manager
+        .process(&extensions_config(&server, "1.0.0", "uninstall", 2))
+        .await;
+    assert!(outcomes[0].result.is_ok(), "{:?}", outcomes[0].result);
+    assert_eq!(outcomes[0].state, HandlerState::NotInstalled);
+    assert_eq!(
+        commands(&dir),
+        vec!["install 0", "enable 0", "disable 1", "uninstall 2"]
+    );
+    assert!(!manager.handler(NAME, "1.0.0").dir().exists());
+}
+
+#[tokio::test]
+async fn test_failed_command_is_reported() {
+    let server = start_server();
+    server.set_binary_route("/package-1.0.0.zip", 200, package("enable"));
+    let dir = TempDir::new().unwrap();
+    let manager = manager(&server, &dir);
+
+    let outcomes = manager
+        .process(&extensions_config(&server, "1.0.0", "enabled", 0))
+        .await;
+
+    match &outcomes[0].result {
+        Err(ExtensionError::CommandFailed {
+            exit_code, output, ..
+        }) => {
+            assert_eq!(*exit_code, Some(3));
+            assert!(output.contains("cannot enable"));
+        }
+        other => panic!("expected CommandFailed, got {:?}", other),
+    }
+    assert_eq!(outcomes[0].state, HandlerState::Installed);
+}
+
+#[tokio::test]
+async fn test_unpublished_version_is_reported() {
+    let server = start_server();
+    let dir = TempDir::new().unwrap();
+    let manager = manager(&server, &dir);
+
+    let outcomes = manager
+        .process(&extensions_config(&server, "9.9.9", "enabled", 0))
+        .await;
+
+    assert!(matches!(
+        outcomes[0].result,
+        Err(ExtensionError::Manifest(_))
+    ));
+    assert!(commands(&dir).is_empty());
+}
+
+#[tokio::test]
+async fn test_collect_status_reports_extension_status() {
+    let server = start_server();
+    let dir = TempDir::new().unwrap();
+    let manager = manager(&server, &dir);
+
+    let outcomes = manager
+        .process(&extensions_config(&server, "1.0.0", "enabled", 0))
+        .await;
+    let handlers = manager.collect_status(&outcomes);
+
+    assert_eq!(handlers.len(), 1);
+    assert_eq!(handlers[0].handler_name, NAME);
+    assert_eq!(handlers[0].status, "Ready");
+    let runtime = handlers[0].runtime_settings_status.as_ref().unwrap();
+    assert_eq!(runtime.sequence_number, 0);
+    assert_eq!(runtime.settings_status.status.status, "success");
+    assert_eq!(
+        runtime.settings_status.status.substatus[0].name.as_deref(),
+        Some("StdOut")
+    );
+}
+
+#[tokio::test]
+async fn test_collect_status_reports_invalid_status_file() {
+    let server = start_server();
+    let dir = TempDir::new().unwrap();
+    let manager = manager(&server, &dir);
+
+    let outcomes = manager
+        .process(&extensions_config(&server, "1.0.0", "enabled", 0))
+        .await;
+    fs::write(
+        manager.handler(NAME, "1.0.0").status_file(0),
+        r#"[{"status": {"status": "done"}}]"#,
+    )
+    .unwrap();
+    let handlers = manager.collect_status(&outcomes);
+
+    let status = &handlers[0]
+        .runtime_settings_status
+        .as_ref()
+        .unwrap()
+        .settings_status
+        .status;
+    assert_eq!(status.status, "error");
+    assert_eq!(status.code, INVALID_STATUS_CODE);
+    assert!(status
+        .formatted_message
+        .as_ref()
+        .unwrap()
+        .message
+        .contains("invalid status 'done'"));
+}
+
+#[tokio::test]
+async fn test_collect_status_reports_failures_and_skips_uninstalled() {
+    let server = start_server();
+    server.set_binary_route("/package-1.0.0.zip", 200, package("enable"));
+    let dir = TempDir::new().unwrap();
+    let manager = manager(&server, &dir);
+
+    let outcomes = manager
+        .process(&extensions_config(&server, "1.0.0", "enabled", 0))
+        .await;
+    let handlers = manager.collect_status(&outcomes);
+    assert_eq!(handlers[0].status, "NotReady");
+    assert_eq!(handlers[0].code, PROCESSING_ERROR_CODE);
+    assert!(handlers[0].runtime_settings_status.is_none());
+
+    let outcomes = manager
+        .process(&extensions_config(&server, "1.0.0", "uninstall", 1))
+        .await;
+    assert!(manager.collect_status(&outcomes).is_empty());
+}
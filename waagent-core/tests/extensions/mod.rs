@@ -0,0 +1 @@
+mod manager_tests;
@@ -0,0 +1,323 @@
+use crate::provisioning::FakeRunner;
+use std::error::Error;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use waagent_core::network::firewall::com::{format_addresses, parse_addresses};
+use waagent_core::network::firewall::{
+    Action, Direction, FirewallManager, FirewallRule, IpCidr, ListedRule, NetshBackend,
+    NftablesFirewallManager, Protocol, RuleCounters, UnixFirewallManager, WindowsFirewallManager,
+    WireServerFirewallPolicy, DEFAULT_WIRESERVER_IP,
+};
+
+fn read_data(name: &str) -> String {
+    fs::read_to_string(format!("tests/network/data/{}", name)).unwrap()
+}
+
+fn rules(listed: &[ListedRule]) -> Vec<FirewallRule> {
+    listed.iter().map(|listed| listed.rule.clone()).collect()
+}
+
+fn counters(listed: &[ListedRule]) -> Vec<Option<RuleCounters>> {
+    listed.iter().map(|listed| listed.counters).collect()
+}
+
+fn cidr(s: &str) -> IpCidr {
+    s.parse().unwrap()
+}
+
+fn counted(packets: u64, bytes: u64) -> Option<RuleCounters> {
+    Some(RuleCounters { packets, bytes })
+}
+
+#[test]
+fn test_parse_iptables_rules() {
+    let listed = UnixFirewallManager::parse_rules(&read_data("iptables-security.txt"));
+
+    // The policy line, the negated match and the LOG rule are skipped.
+    assert_eq!(
+        rules(&listed),
+        WireServerFirewallPolicy::new(DEFAULT_WIRESERVER_IP, 0).rules()
+    );
+    assert_eq!(
+        counters(&listed),
+        vec![counted(12, 720), counted(3521, 1876404), counted(7, 420)]
+    );
+}
+
+#[test]
+fn test_parse_ip6tables_rules_merges_destinations() {
+    let listed = UnixFirewallManager::parse_rules(&read_data("ip6tables-security.txt"));
+
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].rule.name, "AllowMetadata");
+    assert_eq!(
+        listed[0].rule.destinations,
+        vec![cidr("fd00:ec2::254/128"), cidr("fd00::/8")]
+    );
+    assert_eq!(listed[0].counters, counted(3, 180));
+}
+
+#[test]
+fn test_parse_nftables_rules() {
+    let listed = NftablesFirewallManager::parse_rules(&read_data("nft-waagent.txt"));
+
+    assert_eq!(
+        rules(&listed),
+        WireServerFirewallPolicy::new(DEFAULT_WIRESERVER_IP, 0).rules()
+    );
+    assert_eq!(
+        counters(&listed),
+        vec![counted(12, 720), counted(3521, 1876404), counted(7, 420)]
+    );
+}
+
+#[test]
+fn test_parse_nftables_dual_stack_rules() {
+    let listed = NftablesFirewallManager::parse_rules(&read_data("nft-dual-stack.txt"));
+
+    assert_eq!(
+        rules(&listed),
+        vec![FirewallRule {
+            name: "AllowMetadata".to_string(),
+            direction: Direction::Outbound,
+            action: Action::Allow,
+            protocol: Protocol::Tcp,
+            destinations: vec![
+                cidr("168.63.129.16"),
+                cidr("169.254.169.254"),
+                cidr("fd00:ec2::254"),
+            ],
+            port: None,
+            uid_owner: None,
+            program_path: None,
+            new_connections_only: false,
+        }]
+    );
+    assert_eq!(counters(&listed), vec![counted(6, 380)]);
+}
+
+/// A Windows firewall manager whose netsh answers with `stdout`.
+fn netsh_manager(exit_code: i32, stdout: &str) -> (Arc<FakeRunner>, WindowsFirewallManager) {
+    let runner = Arc::new(FakeRunner::default());
+    runner.set_output("netsh", exit_code, stdout);
+    let backend = NetshBackend::with_runner(runner.clone());
+    (
+        runner,
+        WindowsFirewallManager::with_backend(Box::new(backend)),
+    )
+}
+
+/// The netsh commands that were run with `verb` (add, delete, show), one per line.
+fn netsh_commands(runner: &FakeRunner, verb: &str) -> String {
+    runner
+        .calls()
+        .into_iter()
+        .filter(|(_, args, _)| args.get(2).map(String::as_str) == Some(verb))
+        .map(|(program, args, _)| format!("{} {}\n", program, args.join(" ")))
+        .collect()
+}
+
+#[test]
+fn test_parse_netsh_rules() {
+    let (_, manager) = netsh_manager(0, &read_data("netsh-show-rule.txt"));
+    let listed = manager.list_rules().unwrap();
+
+    assert_eq!(
+        rules(&listed),
+        vec![
+            FirewallRule {
+                name: "AllowWireServerDns".to_string(),
+                direction: Direction::Outbound,
+                action: Action::Allow,
+                protocol: Protocol::Tcp,
+                destinations: vec![DEFAULT_WIRESERVER_IP.into()],
+                port: Some(53),
+                uid_owner: None,
+                program_path: None,
+                new_connections_only: false,
+            },
+            FirewallRule {
+                name: "Core Networking - DNS (UDP-Out)".to_string(),
+                direction: Direction::Outbound,
+                action: Action::Allow,
+                protocol: Protocol::Udp,
+                destinations: Vec::new(),
+                port: Some(53),
+                uid_owner: None,
+                program_path: Some(r"C:\Windows\system32\svchost.exe".to_string()),
+                new_connections_only: false,
+            },
+        ]
+    );
+    assert_eq!(counters(&listed), vec![None, None]);
+}
+
+#[test]
+fn test_netsh_rule_exists() {
+    let dns = &WireServerFirewallPolicy::new(DEFAULT_WIRESERVER_IP, 0).rules()[0];
+
+    let (runner, manager) = netsh_manager(0, &read_data("netsh-show-rule.txt"));
+    assert!(manager.rule_exists(dns).unwrap());
+    assert_eq!(
+        netsh_commands(&runner, "show"),
+        "netsh advfirewall firewall show rule name=MicrosoftAzure_AllowWireServerDns verbose\n"
+    );
+
+    let (_, manager) = netsh_manager(1, "No rules match the specified criteria.\n");
+    assert!(!manager.rule_exists(dns).unwrap());
+}
+
+#[test]
+fn test_netsh_add_commands() {
+    let (runner, manager) = netsh_manager(0, "");
+    // The policy's rules without what netsh cannot express
+    let rules: Vec<_> = WireServerFirewallPolicy::new(DEFAULT_WIRESERVER_IP, 0)
+        .rules()
+        .into_iter()
+        .map(|rule| FirewallRule {
+            uid_owner: None,
+            new_connections_only: false,
+            ..rule
+        })
+        .collect();
+
+    manager.apply(&rules).unwrap();
+
+    assert_eq!(
+        netsh_commands(&runner, "add"),
+        read_data("netsh-add-wireserver.txt")
+    );
+}
+
+#[test]
+fn test_netsh_rejects_rules_it_cannot_express() {
+    let (runner, manager) = netsh_manager(0, "");
+    let policy = WireServerFirewallPolicy::new(DEFAULT_WIRESERVER_IP, 1000);
+
+    let error = manager.apply(&policy.rules()).unwrap_err();
+
+    // The block rule would apply to everyone, so nothing past the DNS rule
+    // is added and that one is rolled back
+    assert!(
+        error.to_string().contains("AllowWireServerAgent"),
+        "{}",
+        error
+    );
+    assert!(!netsh_commands(&runner, "add").contains("BlockWireServer"));
+    assert_eq!(
+        netsh_commands(&runner, "delete"),
+        "netsh advfirewall firewall delete rule name=MicrosoftAzure_AllowWireServerDns\n"
+    );
+    for rule in &policy.rules()[1..] {
+        assert!(manager.add_rule(rule).is_err(), "{}", rule.name);
+    }
+}
+
+#[test]
+fn test_remove_all_managed_netsh_rules() {
+    let (runner, manager) = netsh_manager(0, &read_data("netsh-show-rule.txt"));
+
+    assert_eq!(manager.remove_all_managed_rules().unwrap(), 1);
+    assert_eq!(
+        netsh_commands(&runner, "delete"),
+        "netsh advfirewall firewall delete rule name=MicrosoftAzure_AllowWireServerDns\n"
+    );
+}
+
+#[test]
+fn test_com_remote_addresses() {
+    assert_eq!(format_addresses(&[]), "*");
+    assert_eq!(
+        format_addresses(&[cidr("168.63.129.16/32"), cidr("fd00::/8")]),
+        "168.63.129.16/32,fd00::/8"
+    );
+
+    assert_eq!(parse_addresses("*"), Some(vec![]));
+    assert_eq!(
+        parse_addresses("168.63.129.16/255.255.255.255,10.0.0.0/255.0.0.0,fd00::/8"),
+        Some(vec![
+            cidr("168.63.129.16/32"),
+            cidr("10.0.0.0/8"),
+            cidr("fd00::/8")
+        ])
+    );
+    assert_eq!(parse_addresses("10.0.0.1"), Some(vec![cidr("10.0.0.1/32")]));
+    // Not networks the rule model can hold
+    assert_eq!(parse_addresses("10.0.0.0/255.0.255.0"), None);
+    assert_eq!(parse_addresses("10.0.0.1-10.0.0.9"), None);
+    assert_eq!(parse_addresses("LocalSubnet"), None);
+}
+
+#[test]
+fn test_display_listed_rule() {
+    let listed = UnixFirewallManager::parse_rules(&read_data("iptables-security.txt"));
+
+    assert_eq!(
+        listed[2].to_string(),
+        "BlockWireServer: block outbound tcp to 168.63.129.16/32 (new connections) \
+         [7 packets, 420 bytes]"
+    );
+}
+
+/// Keeps rules in memory and refuses to add the rule named `rejected`.
+struct RejectingChain {
+    rules: Mutex<Vec<FirewallRule>>,
+    rejected: &'static str,
+}
+
+impl FirewallManager for RejectingChain {
+    fn add_rule(&self, rule: &FirewallRule) -> Result<(), Box<dyn Error>> {
+        if rule.name == self.rejected {
+            return Err(format!("{} rejected", rule.name).into());
+        }
+        self.rules.lock().unwrap().push(rule.clone());
+        Ok(())
+    }
+
+    fn remove_rule(&self, rule: &FirewallRule) -> Result<(), Box<dyn Error>> {
+        self.rules.lock().unwrap().retain(|r| r != rule);
+        Ok(())
+    }
+
+    fn rule_exists(&self, rule: &FirewallRule) -> Result<bool, Box<dyn Error>> {
+        Ok(self.rules.lock().unwrap().contains(rule))
+    }
+
+    fn list_rules(&self) -> Result<Vec<ListedRule>, Box<dyn Error>> {
+        unimplemented!()
+    }
+
+    fn remove_all_managed_rules(&self) -> Result<usize, Box<dyn Error>> {
+        unimplemented!()
+    }
+}
+
+#[test]
+fn test_apply_rolls_back_on_failure() {
+    let policy = WireServerFirewallPolicy::new(DEFAULT_WIRESERVER_IP, 1000);
+    let rules = policy.rules();
+    // A rule that was there before must survive the rollback.
+    let chain = RejectingChain {
+        rules: Mutex::new(vec![rules[0].clone()]),
+        rejected: "BlockWireServer",
+    };
+
+    let error = chain.apply(&rules).unwrap_err();
+
+    assert!(error.to_string().contains("BlockWireServer"), "{}", error);
+    assert_eq!(*chain.rules.lock().unwrap(), vec![rules[0].clone()]);
+}
+
+#[test]
+fn test_apply_adds_missing_rules_in_order() {
+    let policy = WireServerFirewallPolicy::new(DEFAULT_WIRESERVER_IP, 1000);
+    let rules = policy.rules();
+    let chain = RejectingChain {
+        rules: Mutex::new(vec![rules[0].clone()]),
+        rejected: "",
+    };
+
+    chain.apply(&rules).unwrap();
+
+    assert_eq!(*chain.rules.lock().unwrap(), rules);
+}
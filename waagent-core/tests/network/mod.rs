@@ -0,0 +1 @@
+pub mod firewall_tests;
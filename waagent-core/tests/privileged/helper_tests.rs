@@ -0,0 +1,352 @@
+use crate::provisioning::FakeRunner;
+use crate::resource_disk::Fixture;
+use std::error::Error;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::TempDir;
+use waagent_core::network::firewall::{
+    FirewallManager, FirewallRule, ListedRule, WireServerFirewallPolicy, DEFAULT_WIRESERVER_IP,
+};
+use waagent_core::privileged::{
+    bind_socket, HelperClient, HelperError, HelperFirewallManager, HelperRequest, HelperResponse,
+    HelperServer,
+};
+use waagent_core::resource_disk::{ResourceDiskStatus, SwapStatus};
+
+/// Keeps rules in memory in the order they were added.
+#[derive(Default)]
+struct FakeFirewall {
+    rules: Mutex<Vec<FirewallRule>>,
+}
+
+impl FirewallManager for FakeFirewall {
+    fn add_rule(&self, rule: &FirewallRule) -> Result<(), Box<dyn Error>> {
+        self.rules.lock().unwrap().push(rule.clone());
+        Ok(())
+    }
+
+    fn remove_rule(&self, rule: &FirewallRule) -> Result<(), Box<dyn Error>> {
+        self.rules.lock().unwrap().retain(|r| r != rule);
+        Ok(())
+    }
+
+    fn rule_exists(&self, rule: &FirewallRule) -> Result<bool, Box<dyn Error>> {
+        Ok(self.rules.lock().unwrap().contains(rule))
+    }
+
+    fn list_rules(&self) -> Result<Vec<ListedRule>, Box<dyn Error>> {
+        Ok(self
+            .rules
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|rule| ListedRule {
+                rule: rule.clone(),
+                counters: None,
+            })
+            .collect())
+    }
+
+    fn remove_all_managed_rules(&self) -> Result<usize, Box<dyn Error>> {
+        Ok(std::mem::take(&mut *self.rules.lock().unwrap()).len())
+    }
+}
+
+fn current_uid() -> u32 {
+    fs::metadata("/proc/self").unwrap().uid()
+}
+
+/// Starts `server` on a socket in `dir` and returns a client for it.
+fn start_helper(dir: &TempDir, server: HelperServer) -> HelperClient {
+    let socket = dir.path().join("helper.sock");
+    let listener = bind_socket(&socket, None).unwrap();
+    tokio::spawn(server.serve(listener));
+    HelperClient::new(socket)
+}
+
+fn helper(firewall: Arc<FakeFirewall>, runner: Arc<FakeRunner>, uid: u32) -> HelperServer {
+    HelperServer::new(firewall, runner, vec![uid])
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_helper_firewall_manager() {
+    let dir = TempDir::new().unwrap();
+    let firewall = Arc::new(FakeFirewall::default());
+    let client = start_helper(
+        &dir,
+        helper(
+            firewall.clone(),
+            Arc::new(FakeRunner::default()),
+            current_uid(),
+        ),
+    );
+
+    let policy = WireServerFirewallPolicy::new(DEFAULT_WIRESERVER_IP, 1000);
+    let installed = tokio::task::spawn_blocking(move || {
+        let manager = HelperFirewallManager::new(client);
+        let first = policy.install(&manager).unwrap();
+        let second = policy.check(&manager).unwrap();
+        let listed = manager.list_rules().unwrap();
+        let removed = manager.remove_all_managed_rules().unwrap();
+        (first.is_compliant(), second.is_compliant(), listed, removed)
+    })
+    .await
+    .unwrap();
+
+    let (first, second, listed, removed) = installed;
+    assert!(!first);
+    assert!(second);
+    assert_eq!(
+        listed.into_iter().map(|l| l.rule).collect::<Vec<_>>(),
+        WireServerFirewallPolicy::new(DEFAULT_WIRESERVER_IP, 1000).rules()
+    );
+    assert_eq!(removed, 3);
+    assert!(firewall.rules.lock().unwrap().is_empty());
+}
+
+/// A helper allowed to set up the resource disk in `fixture`.
+fn resource_disk_helper(
+    dir: &TempDir,
+    fixture: &Fixture,
+    runner: &Arc<FakeRunner>,
+) -> HelperClient {
+    let server = helper(
+        Arc::new(FakeFirewall::default()),
+        runner.clone(),
+        current_uid(),
+    )
+    .with_resource_disk(fixture.settings.clone());
+    start_helper(dir, server)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_helper_sets_up_resource_disk() {
+    let dir = TempDir::new().unwrap();
+    let mut fixture = Fixture::new();
+    fixture.settings.enable_swap = false;
+    fixture.settings.mount_options = Some("defaults,nofail".to_string());
+    let runner = Arc::new(FakeRunner::default());
+    runner.set_output("blkid", 0, "ext4\n");
+    let client = resource_disk_helper(&dir, &fixture, &runner);
+
+    let status = tokio::task::spawn_blocking(move || client.setup_resource_disk())
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(
+        status,
+        Some(ResourceDiskStatus {
+            mount_point: fixture.path("mnt/resource"),
+            swap: SwapStatus::Disabled,
+        })
+    );
+    assert_eq!(
+        runner.call("mount").unwrap().0,
+        vec![
+            "-t",
+            "ext4",
+            "-o",
+            "defaults,nofail,nosuid,nodev",
+            &fixture.arg("dev/sdb1"),
+            &fixture.arg("mnt/resource")
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_helper_denies_escalating_mount_options() {
+    for options in [
+        "loop,suid,exec",
+        "dev",
+        "bind",
+        "remount,rw",
+        "X-mount.mkdir",
+    ] {
+        let dir = TempDir::new().unwrap();
+        let mut fixture = Fixture::new();
+        fixture.settings.mount_options = Some(options.to_string());
+        let runner = Arc::new(FakeRunner::default());
+        let client = resource_disk_helper(&dir, &fixture, &runner);
+
+        let result = tokio::task::spawn_blocking(move || client.setup_resource_disk())
+            .await
+            .unwrap();
+
+        assert!(
+            matches!(result, Err(HelperError::Denied(_))),
+            "{}: {:?}",
+            options,
+            result
+        );
+        assert!(runner.calls().is_empty(), "{}", options);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_helper_denies_mount_requests() {
+    let dir = TempDir::new().unwrap();
+    let runner = Arc::new(FakeRunner::default());
+    let client = resource_disk_helper(&dir, &Fixture::new(), &runner);
+
+    // The agent cannot choose what to mount or where
+    let request = r#"{"request":"mount","device":"/var/lib/waagent/image","mount_point":"/etc","filesystem":"ext4","options":"loop,suid,exec"}"#;
+    let socket = client.socket_path().to_path_buf();
+    let response = tokio::task::spawn_blocking(move || {
+        let mut stream = UnixStream::connect(socket).unwrap();
+        writeln!(stream, "{}", request).unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        serde_json::from_str::<HelperResponse>(&line).unwrap()
+    })
+    .await
+    .unwrap();
+
+    assert!(
+        matches!(response, HelperResponse::Denied { .. }),
+        "{:?}",
+        response
+    );
+    assert!(runner.calls().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_helper_without_resource_disk_denies_setup() {
+    let dir = TempDir::new().unwrap();
+    let runner = Arc::new(FakeRunner::default());
+    let client = start_helper(
+        &dir,
+        helper(
+            Arc::new(FakeFirewall::default()),
+            runner.clone(),
+            current_uid(),
+        ),
+    );
+
+    let result = tokio::task::spawn_blocking(move || client.setup_resource_disk())
+        .await
+        .unwrap();
+
+    assert!(matches!(result, Err(HelperError::Denied(_))));
+    assert!(runner.calls().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_helper_reports_resource_disk_failure() {
+    let dir = TempDir::new().unwrap();
+    let fixture = Fixture::new();
+    let runner = Arc::new(FakeRunner::default());
+    runner.set_output("blkid", 0, "ext4\n");
+    runner.fail("mount");
+    let client = resource_disk_helper(&dir, &fixture, &runner);
+
+    let result = tokio::task::spawn_blocking(move || client.setup_resource_disk())
+        .await
+        .unwrap();
+
+    assert!(matches!(result, Err(HelperError::Failed(_))));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_helper_denies_unknown_uid() {
+    let dir = TempDir::new().unwrap();
+    let firewall = Arc::new(FakeFirewall::default());
+    let client = start_helper(
+        &dir,
+        helper(
+            firewall.clone(),
+            Arc::new(FakeRunner::default()),
+            current_uid().wrapping_add(1),
+        ),
+    );
+
+    let result = tokio::task::spawn_blocking(move || {
+        let rule = WireServerFirewallPolicy::new(DEFAULT_WIRESERVER_IP, 0).rules()[0].clone();
+        HelperFirewallManager::new(client)
+            .add_rule(&rule)
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap();
+
+    let error = result.unwrap_err();
+    assert!(error.contains("not allowed"), "{}", error);
+    assert!(firewall.rules.lock().unwrap().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_helper_denies_rules_that_would_inject_commands() {
+    let dir = TempDir::new().unwrap();
+    let firewall = Arc::new(FakeFirewall::default());
+    let client = start_helper(
+        &dir,
+        helper(
+            firewall.clone(),
+            Arc::new(FakeRunner::default()),
+            current_uid(),
+        ),
+    );
+    let rule = WireServerFirewallPolicy::new(DEFAULT_WIRESERVER_IP, 0).rules()[0].clone();
+    let mut bad_name = rule.clone();
+    bad_name.name = "x\" counter accept\nflush ruleset\n#".to_string();
+    let mut bad_owner = rule.clone();
+    bad_owner.uid_owner = Some("0 accept; flush ruleset".to_string());
+
+    let responses = tokio::task::spawn_blocking(move || {
+        [
+            HelperRequest::AddRule { rule: bad_name },
+            HelperRequest::Apply {
+                rules: vec![rule, bad_owner],
+            },
+        ]
+        .iter()
+        .map(|request| client.call(request))
+        .collect::<Vec<_>>()
+    })
+    .await
+    .unwrap();
+
+    for response in responses {
+        assert!(
+            matches!(&response, Err(HelperError::Denied(reason)) if reason.contains("invalid rule")),
+            "{:?}",
+            response
+        );
+    }
+    assert!(firewall.rules.lock().unwrap().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_helper_drops_callers_that_send_nothing() {
+    let dir = TempDir::new().unwrap();
+    let server = helper(
+        Arc::new(FakeFirewall::default()),
+        Arc::new(FakeRunner::default()),
+        current_uid(),
+    )
+    .with_request_timeout(Duration::from_millis(100));
+    let client = start_helper(&dir, server);
+    let socket = client.socket_path().to_path_buf();
+
+    let answer = tokio::task::spawn_blocking(move || {
+        let stream = UnixStream::connect(socket).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).map(|_| line)
+    })
+    .await
+    .unwrap();
+
+    // Closed without an answer, well before the read timeout
+    assert_eq!(answer.unwrap(), "");
+    assert_eq!(
+        fs::metadata(client.socket_path()).unwrap().mode() & 0o777,
+        0o660
+    );
+}
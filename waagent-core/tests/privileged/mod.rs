@@ -0,0 +1 @@
+pub mod helper_tests;
@@ -0,0 +1,269 @@
+use super::MockWireServer;
+use base64::prelude::*;
+use std::fs;
+use waagent_core::protocol::{
+    FormattedMessage, HandlerStatus, HealthDetails, HealthStatus, ProtocolError, TelemetryData,
+    WireServerClient,
+};
+
+fn goal_state_xml() -> String {
+    fs::read_to_string("tests/protocol/data/goalstate.xml").unwrap()
+}
+
+#[tokio::test]
+async fn test_fetch_goal_state() {
+    let server = MockWireServer::start(vec![("/machine?comp=goalstate", 200, goal_state_xml())]);
+    let client = WireServerClient::with_endpoint(&server.url);
+
+    let goal_state = client.fetch_goal_state().await.unwrap();
+
+    assert_eq!(goal_state.incarnation, 1);
+    assert_eq!(
+        goal_state.container_id(),
+        "c6d5526c-5ac2-4200-b6e2-56f2b70c5ab2"
+    );
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "GET");
+    assert_eq!(requests[0].path, "/machine?comp=goalstate");
+    assert_eq!(
+        requests[0].headers.get("x-ms-version").unwrap(),
+        "2012-11-30"
+    );
+}
+
+#[tokio::test]
+async fn test_fetch_goal_state_unexpected_status() {
+    let server = MockWireServer::start(vec![("/machine", 503, "busy".to_string())]);
+    let client = WireServerClient::with_endpoint(&server.url);
+
+    match client.fetch_goal_state().await {
+        Err(ProtocolError::UnexpectedStatus { status, body, .. }) => {
+            assert_eq!(status, 503);
+            assert_eq!(body, "busy");
+        }
+        other => panic!("expected UnexpectedStatus, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_fetch_goal_state_invalid_xml() {
+    let server = MockWireServer::start(vec![("/machine", 200, "<GoalState>".to_string())]);
+    let client = WireServerClient::with_endpoint(&server.url);
+
+    let result = client.fetch_goal_state().await;
+    assert!(matches!(result, Err(ProtocolError::Xml(_))));
+}
+
+#[tokio::test]
+async fn test_connection_refused_is_connectivity_error() {
+    // Bind and immediately drop a listener to get a port nothing listens on.
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let client = WireServerClient::with_endpoint(&format!("http://127.0.0.1:{}", port));
+
+    let error = client.fetch_goal_state().await.unwrap_err();
+    assert!(error.is_connectivity());
+}
+
+#[tokio::test]
+async fn test_provisioning_health_report() {
+    let server = MockWireServer::start(vec![
+        ("/machine?comp=goalstate", 200, goal_state_xml()),
+        ("/machine?comp=health", 200, String::new()),
+    ]);
+    let client = WireServerClient::with_endpoint(&server.url);
+    let goal_state = client.fetch_goal_state().await.unwrap();
+
+    client
+        .send_health_report_with_details(
+            &goal_state,
+            HealthStatus::NotReady,
+            Some(HealthDetails::provisioning_failed("useradd failed")),
+        )
+        .await
+        .unwrap();
+
+    let health = &server.requests()[1];
+    assert!(health.body.contains(
+        "<State>NotReady</State><Details><SubStatus>ProvisioningFailed</SubStatus><Description>useradd failed</Description></Details>"
+    ));
+}
+
+#[tokio::test]
+async fn test_health_telemetry_and_status() {
+    let server = MockWireServer::start(vec![
+        ("/machine?comp=goalstate", 200, goal_state_xml()),
+        ("/machine?comp=health", 200, String::new()),
+        ("/machine?comp=telemetrydata", 200, String::new()),
+        ("/status", 201, String::new()),
+    ]);
+    let client = WireServerClient::with_endpoints(&server.url, &server.url);
+    let goal_state = client.fetch_goal_state().await.unwrap();
+
+    client
+        .send_health_report(&goal_state, HealthStatus::Ready)
+        .await
+        .unwrap();
+    client
+        .send_telemetry_event(&TelemetryData::wa_start(&goal_state))
+        .await
+        .unwrap();
+    let handler = HandlerStatus {
+        handler_name: "Microsoft.Azure.Extensions.CustomScript".to_string(),
+        handler_version: "2.1.10".to_string(),
+        status: "Ready".to_string(),
+        code: 0,
+        formatted_message: Some(FormattedMessage::new("Plugin enabled")),
+        runtime_settings_status: None,
+    };
+    client
+        .send_status_report(&goal_state, &[handler])
+        .await
+        .unwrap();
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 4);
+
+    let health = &requests[1];
+    assert_eq!(health.method, "POST");
+    assert!(health
+        .body
+        .contains("<GoalStateIncarnation>1</GoalStateIncarnation>"));
+    assert!(health.body.contains("<State>Ready</State>"));
+
+    let telemetry = &requests[2];
+    assert!(telemetry.body.contains(r#"<EventData name="WAStart">"#));
+    assert_eq!(
+        telemetry.headers.get("x-ms-agent-name").unwrap(),
+        "waagent-rs"
+    );
+
+    let status = &requests[3];
+    assert_eq!(status.method, "PUT");
+    assert_eq!(status.path, "/status");
+    assert_eq!(
+        status.headers.get("x-ms-containerid").unwrap(),
+        "c6d5526c-5ac2-4200-b6e2-56f2b70c5ab2"
+    );
+    let payload: serde_json::Value = serde_json::from_str(&status.body).unwrap();
+    let content = BASE64_STANDARD
+        .decode(payload["content"].as_str().unwrap())
+        .unwrap();
+    let content: serde_json::Value = serde_json::from_slice(&content).unwrap();
+    assert_eq!(
+        content["aggregateStatus"]["vmArtifactsAggregateStatus"]["goalStateAggregateStatus"]
+            ["inSvdSeqNo"],
+        "1"
+    );
+    let handlers = &content["aggregateStatus"]["handlerAggregateStatus"];
+    assert_eq!(
+        handlers[0]["handlerName"],
+        "Microsoft.Azure.Extensions.CustomScript"
+    );
+    assert_eq!(handlers[0]["handlerVersion"], "2.1.10");
+    assert_eq!(handlers[0]["formattedMessage"]["lang"], "en-US");
+    assert!(handlers[0].get("runtimeSettingsStatus").is_none());
+}
+
+fn full_goal_state_routes() -> Vec<(&'static str, u16, String)> {
+    let data = |name: &str| fs::read_to_string(format!("tests/protocol/data/{}", name)).unwrap();
+    vec![
+        ("comp=goalstate", 200, data("goalstate_full.xml")),
+        (
+            "type=hostingEnvironmentConfig",
+            200,
+            data("hosting_environment.xml"),
+        ),
+        ("type=sharedConfig", 200, data("shared_config.xml")),
+        ("type=extensionsConfig", 200, data("extensions_config.xml")),
+        ("type=fullConfig", 200, "<FullConfig />".to_string()),
+        ("comp=certificates", 200, data("certificates.xml")),
+    ]
+}
+
+#[tokio::test]
+async fn test_fetch_full_goal_state() {
+    let server = MockWireServer::start(full_goal_state_routes());
+    let client = WireServerClient::with_endpoint(&server.url);
+
+    let full = client.fetch_full_goal_state().await.unwrap();
+
+    assert_eq!(full.incarnation(), 1);
+    assert_eq!(
+        full.hosting_environment
+            .unwrap()
+            .role
+            .unwrap()
+            .name
+            .as_deref(),
+        Some("MachineRole")
+    );
+    assert_eq!(
+        full.shared_config.unwrap().instances.instances[0]
+            .address
+            .as_deref(),
+        Some("10.0.0.4")
+    );
+    let extensions = full.extensions_config.unwrap();
+    assert_eq!(
+        extensions.plugins()[0].name,
+        "Microsoft.Azure.Extensions.CustomScript"
+    );
+    assert_eq!(full.full_config.as_deref(), Some("<FullConfig />"));
+
+    // Without a transport certificate the Certificates document is not requested.
+    assert!(full.certificates.is_none());
+    assert!(!server
+        .requests()
+        .iter()
+        .any(|r| r.path.contains("comp=certificates")));
+}
+
+#[tokio::test]
+async fn test_fetch_full_goal_state_with_transport_certificate() {
+    let server = MockWireServer::start(full_goal_state_routes());
+    let client = WireServerClient::with_endpoint(&server.url)
+        .with_transport_certificate("MIIBfake".to_string());
+
+    let full = client.fetch_full_goal_state().await.unwrap();
+
+    let certificates = full.certificates.unwrap();
+    assert_eq!(
+        certificates.format.as_deref(),
+        Some("Pkcs7BlobWithPfxContents")
+    );
+
+    let request = server
+        .requests()
+        .into_iter()
+        .find(|r| r.path.contains("comp=certificates"))
+        .unwrap();
+    assert_eq!(
+        request
+            .headers
+            .get("x-ms-guest-agent-public-x509-cert")
+            .unwrap(),
+        "MIIBfake"
+    );
+    assert!(request.headers.contains_key("x-ms-cipher-name"));
+}
+
+#[tokio::test]
+async fn test_fetch_full_goal_state_fails_on_bad_sub_document() {
+    let mut routes = full_goal_state_routes();
+    routes[3] = (
+        "type=extensionsConfig",
+        200,
+        "<Extensions><Plugins>".to_string(),
+    );
+    let server = MockWireServer::start(routes);
+    let client = WireServerClient::with_endpoint(&server.url);
+
+    let result = client.fetch_full_goal_state().await;
+    assert!(matches!(result, Err(ProtocolError::Xml(_))));
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+pub mod client_tests;
+pub mod poller_tests;
+
+/// Path fragment, status code and response body.
+type Route = (String, u16, Vec<u8>);
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+/// Minimal HTTP server standing in for the WireServer. Each route maps a path
+/// fragment to a status code and body; every request is recorded for assertions.
+/// `{endpoint}` in a body is replaced with the server URL, so goal states can
+/// point back at the mock.
+pub struct MockWireServer {
+    pub url: String,
+    pub requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    routes: Arc<Mutex<Vec<Route>>>,
+}
+
+impl MockWireServer {
+    pub fn start(routes: Vec<(&str, u16, String)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let routes: Vec<Route> = routes
+            .into_iter()
+            .map(|(p, s, b)| (p.to_string(), s, b.replace("{endpoint}", &url).into_bytes()))
+            .collect();
+        let routes = Arc::new(Mutex::new(routes));
+
+        let recorded = requests.clone();
+        let served_routes = routes.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(s) => s,
+                    Err(_) => break,
+                };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request_line = String::new();
+                if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+                    continue;
+                }
+                let mut parts = request_line.split_whitespace();
+                let method = parts.next().unwrap_or_default().to_string();
+                let path = parts.next().unwrap_or_default().to_string();
+
+                let mut headers = HashMap::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((k, v)) = line.split_once(':') {
+                        headers.insert(k.trim().to_lowercase(), v.trim().to_string());
+                    }
+                }
+
+                let length: usize = headers
+                    .get("content-length")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0);
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let (status, response_body) = served_routes
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .find(|(pattern, _, _)| path.contains(pattern.as_str()))
+                    .map(|(_, s, b)| (*s, b.clone()))
+                    .unwrap_or((404, Vec::new()));
+
+                recorded.lock().unwrap().push(RecordedRequest {
+                    method,
+                    path,
+                    headers,
+                    body: String::from_utf8_lossy(&body).to_string(),
+                });
+
+                let header = format!(
+                    "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    response_body.len()
+                );
+                let _ = stream.write_all(header.as_bytes());
+                let _ = stream.write_all(&response_body);
+            }
+        });
+
+        Self {
+            url,
+            requests,
+            routes,
+        }
+    }
+
+    /// Replaces the response of the route registered for `pattern`.
+    pub fn set_route(&self, pattern: &str, status: u16, body: String) {
+        let body = body.replace("{endpoint}", &self.url);
+        self.set_binary_route(pattern, status, body.into_bytes());
+    }
+
+    /// Like `set_route`, for bodies that are not text (e.g. zip packages).
+    pub fn set_binary_route(&self, pattern: &str, status: u16, body: Vec<u8>) {
+        let mut routes = self.routes.lock().unwrap();
+        match routes.iter_mut().find(|(p, _, _)| p == pattern) {
+            Some(route) => *route = (pattern.to_string(), status, body),
+            None => routes.push((pattern.to_string(), status, body)),
+        }
+    }
+
+    pub fn clear_requests(&self) {
+        self.requests.lock().unwrap().clear();
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,115 @@
+use super::MockWireServer;
+use std::fs;
+use std::time::Duration;
+use waagent_core::protocol::{GoalStateEvent, GoalStatePoller, WireServerClient};
+
+fn data(name: &str) -> String {
+    fs::read_to_string(format!("tests/protocol/data/{}", name)).unwrap()
+}
+
+fn start_server() -> MockWireServer {
+    MockWireServer::start(vec![
+        ("comp=goalstate", 200, data("goalstate_full.xml")),
+        (
+            "type=hostingEnvironmentConfig",
+            200,
+            data("hosting_environment.xml"),
+        ),
+        ("type=sharedConfig", 200, data("shared_config.xml")),
+        ("type=extensionsConfig", 200, data("extensions_config.xml")),
+        ("type=fullConfig", 200, "<FullConfig />".to_string()),
+    ])
+}
+
+fn poller(server: &MockWireServer) -> GoalStatePoller {
+    GoalStatePoller::new(
+        WireServerClient::with_endpoint(&server.url),
+        Duration::from_millis(50),
+        Duration::from_millis(10),
+    )
+}
+
+/// Incarnation 2 of the fixture, where only the ExtensionsConfig URI changed.
+fn goal_state_incarnation_2() -> String {
+    data("goalstate_full.xml")
+        .replace(
+            "<Incarnation>1</Incarnation>",
+            "<Incarnation>2</Incarnation>",
+        )
+        .replace(
+            "type=extensionsConfig&amp;incarnation=1",
+            "type=extensionsConfig&amp;incarnation=2",
+        )
+}
+
+#[tokio::test]
+async fn test_first_poll_publishes_goal_state() {
+    let server = start_server();
+    let mut poller = poller(&server);
+    let mut events = poller.subscribe();
+
+    let new = poller.poll_once().await.unwrap().unwrap();
+
+    assert_eq!(new.incarnation(), 1);
+    assert!(new.extensions_config.is_some());
+    let GoalStateEvent::NewGoalState(published) = events.try_recv().unwrap();
+    assert_eq!(published.incarnation(), 1);
+    assert_eq!(poller.current().unwrap().incarnation(), 1);
+}
+
+#[tokio::test]
+async fn test_same_incarnation_skips_sub_documents() {
+    let server = start_server();
+    let mut poller = poller(&server);
+    let mut events = poller.subscribe();
+    poller.poll_once().await.unwrap();
+    events.try_recv().unwrap();
+    server.clear_requests();
+
+    assert!(poller.poll_once().await.unwrap().is_none());
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].path.contains("comp=goalstate"));
+    assert!(events.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_new_incarnation_downloads_changed_documents_only() {
+    let server = start_server();
+    let mut poller = poller(&server);
+    poller.poll_once().await.unwrap();
+    server.clear_requests();
+    server.set_route("comp=goalstate", 200, goal_state_incarnation_2());
+
+    let new = poller.poll_once().await.unwrap().unwrap();
+
+    assert_eq!(new.incarnation(), 2);
+    assert!(new.hosting_environment.is_some());
+    assert!(new.shared_config.is_some());
+    let paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+    assert_eq!(paths.len(), 2);
+    assert!(paths[0].contains("comp=goalstate"));
+    assert!(paths[1].contains("type=extensionsConfig&incarnation=2"));
+}
+
+#[tokio::test]
+async fn test_run_publishes_incarnation_changes() {
+    let server = start_server();
+    let poller = poller(&server);
+    let mut events = poller.subscribe();
+    let task = tokio::spawn(poller.run());
+
+    let GoalStateEvent::NewGoalState(first) = events.recv().await.unwrap();
+    assert_eq!(first.incarnation(), 1);
+
+    server.set_route("comp=goalstate", 200, goal_state_incarnation_2());
+    let GoalStateEvent::NewGoalState(second) =
+        tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+    assert_eq!(second.incarnation(), 2);
+
+    task.abort();
+}
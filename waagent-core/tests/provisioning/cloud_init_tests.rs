@@ -0,0 +1,127 @@
+use super::FakeRunner;
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+use waagent_core::provisioning::{CloudInit, ProvisioningAgent, ProvisioningError};
+
+const POLL: Duration = Duration::from_millis(10);
+
+fn cloud_init(runner: &Arc<FakeRunner>, dir: &TempDir) -> CloudInit {
+    CloudInit::new(runner.clone()).with_result_path(&dir.path().join("result.json"))
+}
+
+#[test]
+fn test_auto_resolves_to_cloud_init_when_enabled() {
+    let dir = TempDir::new().unwrap();
+    let runner = Arc::new(FakeRunner::default());
+    runner.set_output("systemctl", 0, "enabled\n");
+    let cloud_init = cloud_init(&runner, &dir);
+
+    assert!(cloud_init.is_enabled());
+    assert_eq!(
+        ProvisioningAgent::Auto.resolve(&cloud_init, true),
+        ProvisioningAgent::CloudInit
+    );
+    assert_eq!(
+        runner.call("systemctl").unwrap().0,
+        vec!["is-enabled", "cloud-init-local.service"]
+    );
+}
+
+#[test]
+fn test_auto_resolves_to_waagent_without_cloud_init() {
+    let dir = TempDir::new().unwrap();
+    let runner = Arc::new(FakeRunner::default());
+    runner.set_output("systemctl", 1, "disabled\n");
+    let cloud_init = cloud_init(&runner, &dir);
+
+    assert_eq!(
+        ProvisioningAgent::Auto.resolve(&cloud_init, true),
+        ProvisioningAgent::Waagent
+    );
+    // Explicit choices are kept as they are.
+    assert_eq!(
+        ProvisioningAgent::CloudInit.resolve(&cloud_init, true),
+        ProvisioningAgent::CloudInit
+    );
+    assert_eq!(
+        ProvisioningAgent::Disabled.resolve(&cloud_init, true),
+        ProvisioningAgent::Disabled
+    );
+}
+
+#[test]
+fn test_auto_resolves_to_disabled_without_cloud_init_or_root() {
+    let dir = TempDir::new().unwrap();
+    let runner = Arc::new(FakeRunner::default());
+    runner.set_output("systemctl", 1, "disabled\n");
+    let cloud_init = cloud_init(&runner, &dir);
+
+    assert_eq!(
+        ProvisioningAgent::Auto.resolve(&cloud_init, false),
+        ProvisioningAgent::Disabled
+    );
+    // Asking for the agent explicitly still fails loudly when provisioning.
+    assert_eq!(
+        ProvisioningAgent::Waagent.resolve(&cloud_init, false),
+        ProvisioningAgent::Waagent
+    );
+}
+
+#[tokio::test]
+async fn test_wait_for_completion() {
+    let dir = TempDir::new().unwrap();
+    let runner = Arc::new(FakeRunner::default());
+    let cloud_init = cloud_init(&runner, &dir);
+    assert!(cloud_init.completion().is_none());
+
+    let result_path = dir.path().join("result.json");
+    let writer = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        fs::write(
+            result_path,
+            r#"{"v1": {"datasource": "DataSourceAzure [seed=/dev/sr0]", "errors": []}}"#,
+        )
+        .unwrap();
+    });
+
+    cloud_init
+        .wait_for_completion(Duration::from_secs(5), POLL)
+        .await
+        .unwrap();
+    writer.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_wait_reports_cloud_init_errors() {
+    let dir = TempDir::new().unwrap();
+    fs::write(
+        dir.path().join("result.json"),
+        r#"{"v1": {"datasource": null, "errors": ["no datasource found", "ssh keys missing"]}}"#,
+    )
+    .unwrap();
+    let cloud_init = cloud_init(&Arc::new(FakeRunner::default()), &dir);
+
+    let error = cloud_init
+        .wait_for_completion(Duration::from_secs(5), POLL)
+        .await
+        .unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "cloud-init: no datasource found; ssh keys missing"
+    );
+}
+
+#[tokio::test]
+async fn test_wait_times_out() {
+    let dir = TempDir::new().unwrap();
+    let cloud_init = cloud_init(&Arc::new(FakeRunner::default()), &dir);
+
+    let result = cloud_init
+        .wait_for_completion(Duration::from_millis(50), POLL)
+        .await;
+
+    assert!(matches!(result, Err(ProvisioningError::CloudInit(_))));
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+use waagent_core::utils::command::{CommandOutput, CommandRunner};
+
+pub mod cloud_init_tests;
+pub mod provisioner_tests;
+
+/// Program, arguments and stdin of a command.
+type Call = (String, Vec<String>, String);
+
+/// Records every command instead of running it. A program answers with the
+/// output set for it, or succeeds without output.
+#[derive(Default)]
+pub struct FakeRunner {
+    calls: Mutex<Vec<Call>>,
+    outputs: Mutex<HashMap<String, CommandOutput>>,
+    hanging: Mutex<Vec<String>>,
+}
+
+impl FakeRunner {
+    pub fn set_output(&self, program: &str, exit_code: i32, stdout: &str) {
+        self.outputs.lock().unwrap().insert(
+            program.to_string(),
+            CommandOutput {
+                exit_code,
+                stdout: stdout.to_string(),
+                stderr: String::new(),
+            },
+        );
+    }
+
+    /// Makes `program` exit with 1 and "<program> failed" on stderr.
+    pub fn fail(&self, program: &str) {
+        self.outputs.lock().unwrap().insert(
+            program.to_string(),
+            CommandOutput {
+                exit_code: 1,
+                stdout: String::new(),
+                stderr: format!("{} failed", program),
+            },
+        );
+    }
+
+    /// Makes `program` run past any timeout it is given.
+    pub fn hang(&self, program: &str) {
+        self.hanging.lock().unwrap().push(program.to_string());
+    }
+
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    pub fn programs(&self) -> Vec<String> {
+        self.calls()
+            .into_iter()
+            .map(|(program, _, _)| program)
+            .collect()
+    }
+
+    /// Arguments and stdin of the first call to `program`.
+    pub fn call(&self, program: &str) -> Option<(Vec<String>, String)> {
+        self.calls()
+            .into_iter()
+            .find(|(p, _, _)| p == program)
+            .map(|(_, args, input)| (args, input))
+    }
+}
+
+impl CommandRunner for FakeRunner {
+    fn run(&self, program: &str, args: &[&str], input: Option<&[u8]>) -> io::Result<CommandOutput> {
+        self.calls.lock().unwrap().push((
+            program.to_string(),
+            args.iter().map(|a| a.to_string()).collect(),
+            String::from_utf8_lossy(input.unwrap_or_default()).into_owned(),
+        ));
+        Ok(self
+            .outputs
+            .lock()
+            .unwrap()
+            .get(program)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn run_with_timeout(
+        &self,
+        program: &str,
+        args: &[&str],
+        input: Option<&[u8]>,
+        _timeout: Duration,
+    ) -> io::Result<Option<CommandOutput>> {
+        let output = self.run(program, args, input)?;
+        let hangs = self.hanging.lock().unwrap().iter().any(|p| p == program);
+        Ok((!hangs).then_some(output))
+    }
+}
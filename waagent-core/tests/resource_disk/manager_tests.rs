@@ -0,0 +1,322 @@
+use super::{Fixture, SWAPS_HEADER};
+use crate::provisioning::FakeRunner;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use waagent_core::resource_disk::{ResourceDiskError, ResourceDiskStatus, SwapStatus};
+
+#[test]
+fn test_formats_mounts_and_enables_swap_on_a_fresh_disk() {
+    let fixture = Fixture::new();
+    let runner = Arc::new(FakeRunner::default());
+    // A fresh temporary disk comes formatted as NTFS.
+    runner.set_output("blkid", 0, "ntfs\n");
+
+    let status = fixture.resource_disk(&runner).setup().unwrap();
+
+    assert_eq!(
+        status,
+        Some(ResourceDiskStatus {
+            mount_point: fixture.path("mnt/resource"),
+            swap: SwapStatus::Enabled {
+                path: fixture.path("mnt/resource/swapfile"),
+                encrypted: false
+            }
+        })
+    );
+    assert_eq!(
+        runner.call("parted").unwrap().0,
+        vec![
+            "-s",
+            "-a",
+            "optimal",
+            &fixture.arg("dev/sdb"),
+            "mklabel",
+            "gpt",
+            "mkpart",
+            "primary",
+            "0%",
+            "100%"
+        ]
+    );
+    assert_eq!(
+        runner.call("mkfs.ext4").unwrap().0,
+        vec!["-F", &fixture.arg("dev/sdb1")]
+    );
+    assert_eq!(
+        runner.call("mount").unwrap().0,
+        vec![
+            "-t",
+            "ext4",
+            "-o",
+            "nodev,nosuid",
+            &fixture.arg("dev/sdb1"),
+            &fixture.arg("mnt/resource")
+        ]
+    );
+    assert!(fixture.path("mnt/resource").is_dir());
+
+    let swap_file = fixture.arg("mnt/resource/swapfile");
+    assert_eq!(
+        runner.call("fallocate").unwrap().0,
+        vec!["-l", "2048M", &swap_file]
+    );
+    assert_eq!(runner.call("mkswap").unwrap().0, vec![swap_file.clone()]);
+    assert_eq!(runner.call("swapon").unwrap().0, vec![swap_file.clone()]);
+    let mode = fs::metadata(&swap_file).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode, 0o600);
+}
+
+#[test]
+fn test_keeps_a_disk_with_the_configured_filesystem() {
+    let mut fixture = Fixture::new();
+    fixture.settings.enable_swap = false;
+    fixture.settings.mount_options = None;
+    let runner = Arc::new(FakeRunner::default());
+    runner.set_output("blkid", 0, "ext4\n");
+
+    fixture.resource_disk(&runner).setup().unwrap();
+
+    assert_eq!(runner.programs(), vec!["blkid", "mount"]);
+    assert_eq!(
+        runner.call("mount").unwrap().0,
+        vec![
+            "-t",
+            "ext4",
+            "-o",
+            "nosuid,nodev",
+            &fixture.arg("dev/sdb1"),
+            &fixture.arg("mnt/resource")
+        ]
+    );
+}
+
+#[test]
+fn test_formats_a_disk_without_a_filesystem() {
+    let mut fixture = Fixture::new();
+    fixture.settings.enable_swap = false;
+    let runner = Arc::new(FakeRunner::default());
+    // blkid exits with 2 when it finds nothing on the partition.
+    runner.set_output("blkid", 2, "");
+
+    fixture.resource_disk(&runner).setup().unwrap();
+
+    assert_eq!(
+        runner.call("mkfs.ext4").unwrap().0,
+        vec!["-F", &fixture.arg("dev/sdb1")]
+    );
+}
+
+#[test]
+fn test_never_formats_a_disk_blkid_could_not_probe() {
+    for (exit_code, stdout) in [(1, ""), (4, ""), (0, "")] {
+        let fixture = Fixture::new();
+        let runner = Arc::new(FakeRunner::default());
+        runner.set_output("blkid", exit_code, stdout);
+
+        let result = fixture.resource_disk(&runner).setup();
+
+        assert!(
+            matches!(result, Err(ResourceDiskError::Command(_))),
+            "{}: {:?}",
+            exit_code,
+            result
+        );
+        assert_eq!(runner.programs(), vec!["blkid"], "{}", exit_code);
+    }
+}
+
+#[test]
+fn test_rejects_escalating_mount_options_before_formatting() {
+    let mut fixture = Fixture::new();
+    fixture.settings.mount_options = Some("loop,exec".to_string());
+    let runner = Arc::new(FakeRunner::default());
+
+    let result = fixture.resource_disk(&runner).setup();
+
+    assert!(matches!(
+        result,
+        Err(ResourceDiskError::InvalidMountOptions(_))
+    ));
+    assert!(runner.calls().is_empty());
+}
+
+#[test]
+fn test_uses_an_existing_mount() {
+    let fixture = Fixture::new();
+    fs::write(
+        fixture.path("proc/mounts"),
+        format!(
+            "/dev/sda1 / ext4 rw 0 0\n{} /mnt ext4 rw 0 0\n",
+            fixture.arg("dev/sdb1")
+        ),
+    )
+    .unwrap();
+    fs::write(
+        fixture.path("proc/swaps"),
+        format!(
+            "{}/mnt/swapfile                          file\t\t2097148\t\t0\t\t-2\n",
+            SWAPS_HEADER
+        ),
+    )
+    .unwrap();
+    let runner = Arc::new(FakeRunner::default());
+
+    let status = fixture.resource_disk(&runner).setup().unwrap().unwrap();
+
+    assert_eq!(status.mount_point, PathBuf::from("/mnt"));
+    // Mounted and swapping already, so nothing to do.
+    assert!(runner.programs().is_empty());
+}
+
+#[test]
+fn test_prefers_the_udev_symlink() {
+    let fixture = Fixture::new();
+    fs::create_dir_all(fixture.path("dev/disk/azure")).unwrap();
+    fs::write(fixture.path("dev/nvme1n1"), "").unwrap();
+    std::os::unix::fs::symlink("../../nvme1n1", fixture.path("dev/disk/azure/resource")).unwrap();
+    let runner = Arc::new(FakeRunner::default());
+    runner.set_output("blkid", 0, "ext4\n");
+
+    fixture.resource_disk(&runner).mount().unwrap();
+
+    let partition = fs::canonicalize(fixture.path("dev"))
+        .unwrap()
+        .join("nvme1n1p1");
+    assert_eq!(
+        runner.call("blkid").unwrap().0.last().unwrap(),
+        partition.to_str().unwrap()
+    );
+}
+
+#[test]
+fn test_falls_back_to_dd_without_fallocate() {
+    let fixture = Fixture::new();
+    let runner = Arc::new(FakeRunner::default());
+    runner.fail("fallocate");
+
+    fixture
+        .resource_disk(&runner)
+        .enable_swap(&fixture.path("dev"))
+        .unwrap();
+
+    assert_eq!(
+        runner.call("dd").unwrap().0,
+        vec![
+            "if=/dev/zero".to_string(),
+            format!("of={}", fixture.arg("dev/swapfile")),
+            "bs=1M".to_string(),
+            "count=2048".to_string()
+        ]
+    );
+    assert!(runner.programs().contains(&"swapon".to_string()));
+}
+
+#[test]
+fn test_encrypts_swap_with_a_random_key() {
+    let mut fixture = Fixture::new();
+    fixture.settings.enable_swap_encryption = true;
+    fs::create_dir_all(fixture.path("mnt")).unwrap();
+    let runner = Arc::new(FakeRunner::default());
+    runner.set_output("cryptsetup", 0, "cryptsetup 2.6.1\n");
+
+    let status = fixture
+        .resource_disk(&runner)
+        .enable_swap(&fixture.path("mnt"))
+        .unwrap();
+
+    let mapper = fixture.arg("dev/mapper/azure_resource_swap");
+    assert_eq!(
+        status,
+        SwapStatus::Enabled {
+            path: PathBuf::from(&mapper),
+            encrypted: true
+        }
+    );
+    let cryptsetup: Vec<_> = runner
+        .calls()
+        .into_iter()
+        .filter(|(program, _, _)| program == "cryptsetup")
+        .map(|(_, args, _)| args)
+        .collect();
+    assert_eq!(
+        cryptsetup,
+        vec![
+            vec!["--version".to_string()],
+            vec![
+                "open",
+                "--type",
+                "plain",
+                "--cipher",
+                "aes-xts-plain64",
+                "--key-size",
+                "512",
+                "--key-file",
+                "/dev/urandom",
+                &fixture.arg("mnt/swapfile"),
+                "azure_resource_swap"
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect()
+        ]
+    );
+    assert_eq!(runner.call("mkswap").unwrap().0, vec![mapper.clone()]);
+    assert_eq!(runner.call("swapon").unwrap().0, vec![mapper]);
+}
+
+#[test]
+fn test_falls_back_to_plain_swap_without_cryptsetup() {
+    let mut fixture = Fixture::new();
+    fixture.settings.enable_swap_encryption = true;
+    fs::create_dir_all(fixture.path("mnt")).unwrap();
+    let runner = Arc::new(FakeRunner::default());
+    runner.fail("cryptsetup");
+
+    let status = fixture
+        .resource_disk(&runner)
+        .enable_swap(&fixture.path("mnt"))
+        .unwrap();
+
+    let swap_file = fixture.path("mnt/swapfile");
+    assert_eq!(
+        status,
+        SwapStatus::EncryptionUnavailable {
+            path: swap_file.clone(),
+            reason: "cryptsetup is not installed".to_string()
+        }
+    );
+    assert_eq!(
+        status.to_string(),
+        format!(
+            "swap enabled on {} without encryption: cryptsetup is not installed",
+            swap_file.display()
+        )
+    );
+    assert_eq!(
+        runner.call("swapon").unwrap().0,
+        vec![swap_file.to_string_lossy().into_owned()]
+    );
+}
+
+#[test]
+fn test_missing_disk() {
+    let fixture = Fixture::new();
+    fs::remove_dir_all(fixture.path("sys/bus/vmbus/devices")).unwrap();
+    let runner = Arc::new(FakeRunner::default());
+
+    let result = fixture.resource_disk(&runner).setup();
+
+    assert!(matches!(result, Err(ResourceDiskError::DeviceNotFound)));
+}
+
+#[test]
+fn test_disabled_by_config() {
+    let mut fixture = Fixture::new();
+    fixture.settings.format = false;
+    let runner = Arc::new(FakeRunner::default());
+
+    assert_eq!(fixture.resource_disk(&runner).setup().unwrap(), None);
+    assert!(runner.programs().is_empty());
+}
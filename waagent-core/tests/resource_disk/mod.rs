@@ -0,0 +1,70 @@
+use crate::provisioning::FakeRunner;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tempfile::TempDir;
+use waagent_core::resource_disk::{ResourceDisk, ResourceDiskSettings};
+
+pub mod manager_tests;
+
+pub const SWAPS_HEADER: &str = "Filename\t\t\t\tType\t\tSize\t\tUsed\t\tPriority\n";
+
+/// A VM with a fake sysfs, /dev and /proc in a temp dir. The resource disk
+/// is `sdb`, attached to the VMBus device the way Hyper-V exposes it.
+pub struct Fixture {
+    pub dir: TempDir,
+    pub settings: ResourceDiskSettings,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        let dir = TempDir::new().unwrap();
+        let path = |name: &str| dir.path().join(name);
+
+        let os_disk = path("sys/bus/vmbus/devices/f8b3781a-1e82-4818-a1c3-63d806ec15bb");
+        fs::create_dir_all(os_disk.join("host0/target0:0:0/0:0:0:0/block/sda")).unwrap();
+        fs::write(
+            os_disk.join("device_id"),
+            "{00000000-0000-8899-0000-000000000000}\n",
+        )
+        .unwrap();
+        let resource_disk = path("sys/bus/vmbus/devices/f8b3781b-1e82-4818-a1c3-63d806ec15bb");
+        fs::create_dir_all(resource_disk.join("host1/target1:0:1/1:0:1:0/block/sdb")).unwrap();
+        fs::write(
+            resource_disk.join("device_id"),
+            "{00000000-0001-8899-0000-000000000000}\n",
+        )
+        .unwrap();
+
+        fs::create_dir_all(path("dev")).unwrap();
+        fs::create_dir_all(path("proc")).unwrap();
+        fs::write(path("proc/mounts"), "/dev/sda1 / ext4 rw,relatime 0 0\n").unwrap();
+        fs::write(path("proc/swaps"), SWAPS_HEADER).unwrap();
+
+        let settings = ResourceDiskSettings {
+            format: true,
+            mount_point: path("mnt/resource"),
+            mount_options: Some("nodev,nosuid".to_string()),
+            filesystem: "ext4".to_string(),
+            enable_swap: true,
+            swap_size_mb: 2048,
+            enable_swap_encryption: false,
+            sys_dir: path("sys"),
+            dev_dir: path("dev"),
+            proc_dir: path("proc"),
+        };
+        Self { dir, settings }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    pub fn arg(&self, name: &str) -> String {
+        self.path(name).to_string_lossy().into_owned()
+    }
+
+    pub fn resource_disk(&self, runner: &Arc<FakeRunner>) -> ResourceDisk {
+        ResourceDisk::new(self.settings.clone(), runner.clone())
+    }
+}
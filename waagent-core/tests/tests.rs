@@ -1,2 +1,10 @@
+mod certificates;
 mod config;
+mod extensions;
+mod network;
+#[cfg(unix)]
+mod privileged;
+mod protocol;
+mod provisioning;
+mod resource_disk;
 mod system;
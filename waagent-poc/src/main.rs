@@ -1,13 +1,10 @@
-use base64::prelude::*;
-use chrono::Utc;
-use quick_xml::de::from_str;
-use quick_xml::se::to_string;
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use std::future::Future;
 use std::process::Command;
 use std::time::Duration;
 use tokio::time::sleep;
-use waagent_core::system::SystemInfo;
+use waagent_core::protocol::{
+    GoalState, HealthStatus, Param, ProtocolError, TelemetryData, WireServerClient, AGENT_VERSION,
+};
 use waagent_core::system::SystemStats;
 
 // Windows service support
@@ -21,34 +18,14 @@ use windows_service::{
 const SERVICE_NAME: &str = "waagent-rs-poc";
 
 // Constants
-const WIRESERVER_ENDPOINT: &str = "http://168.63.129.16";
-const STATUS_SERVICE_PORT: u16 = 32526;
-const AGENT_VERSION: &str = "waagent-rs/0.0.1";
-const AGENT_NAME: &str = "waagent-rs";
-const WIRESERVER_API_VERSION: &str = "2012-11-30";
-const STATUS_API_VERSION: &str = "2015-09-01";
 const HEARTBEAT_INTERVAL_SECS: u64 = 30;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
-// Helper functions
-fn get_timestamp() -> String {
-    Utc::now().format("%Y-%m-%dT%H:%M:%S.%3fZ").to_string()
-}
-
-fn get_rfc3339_timestamp() -> String {
-    Utc::now().to_rfc3339()
-}
-
-fn get_user_agent() -> String {
-    format!("{}/{}", AGENT_NAME, AGENT_VERSION)
-}
-
-
 // Helper function to get the uid of a specific user
 fn get_user_uid(username: &str) -> Result<String> {
     let output = Command::new("id")
-        .args(&["-u", username])
+        .args(["-u", username])
         .output()
         .map_err(|e| format!("Failed to execute id command: {}", e))?;
 
@@ -74,7 +51,7 @@ async fn add_wireserver_iptables_rule() -> Result<()> {
 
     // First, check if the rule already exists in the security table OUTPUT chain
     let check_existing = Command::new("sudo")
-        .args(&[
+        .args([
             "iptables", 
             "-t", "security",
             "-C", "OUTPUT", 
@@ -105,7 +82,7 @@ async fn add_wireserver_iptables_rule() -> Result<()> {
     }
 
     let output = Command::new("sudo")
-        .args(&[
+        .args([
             "iptables",
             "-t", "security",
             "-I", "OUTPUT", "2",
@@ -124,7 +101,7 @@ async fn add_wireserver_iptables_rule() -> Result<()> {
                     println!("Successfully added iptables rule for wireserver to security table OUTPUT chain at position 2");
                     // Show the current security table OUTPUT rules for debugging
                     let show_rules = Command::new("sudo")
-                        .args(&["iptables", "-t", "security", "-L", "OUTPUT", "-n", "--line-numbers"])
+                        .args(["iptables", "-t", "security", "-L", "OUTPUT", "-n", "--line-numbers"])
                         .output();
                     if let Ok(rules_result) = show_rules {
                         let rules_output = String::from_utf8_lossy(&rules_result.stdout);
@@ -146,275 +123,36 @@ async fn add_wireserver_iptables_rule() -> Result<()> {
 
 
 
-fn create_base_params(goal_state: &GoalState) -> Vec<Param> {
-    vec![
-        Param {
-            name: "Version".to_string(),
-            value: AGENT_VERSION.to_string(),
-        },
-        Param {
-            name: "Timestamp".to_string(),
-            value: get_timestamp(),
-        },
-        Param {
-            name: "Container".to_string(),
-            value: goal_state.container.container_id.clone(),
-        },
-        Param {
-            name: "RoleInstance".to_string(),
-            value: goal_state.container.role_instance_list.role_instance.instance_id.clone(),
-        },
-    ]
-}
-
-#[derive(Debug, Deserialize, Clone)]
-struct GoalState {
-    #[allow(dead_code)]
-    #[serde(rename = "Version")]
-    version: String,
-    #[serde(rename = "Incarnation")]
-    incarnation: u32,
-    #[allow(dead_code)]
-    #[serde(rename = "Machine")]
-    machine: Machine,
-    #[serde(rename = "Container")]
-    container: Container,
-}
-
-#[derive(Debug, Deserialize, Clone)]
-struct Machine {
-    #[allow(dead_code)]
-    #[serde(rename = "ExpectedState")]
-    expected_state: String,
-    #[allow(dead_code)]
-    #[serde(rename = "StopRolesDeadlineHint")]
-    stop_roles_deadline_hint: u32,
-    #[allow(dead_code)]
-    #[serde(rename = "LBProbePorts")]
-    lb_probe_ports: LBProbePorts,
-    #[allow(dead_code)]
-    #[serde(rename = "ExpectHealthReport")]
-    expect_health_report: String,
-}
-
-#[derive(Debug, Deserialize, Clone)]
-struct LBProbePorts {
-    #[allow(dead_code)]
-    #[serde(rename = "Port")]
-    port: u16,
-}
-
-#[derive(Debug, Deserialize, Clone)]
-struct Container {
-    #[serde(rename = "ContainerId")]
-    container_id: String,
-    #[serde(rename = "RoleInstanceList")]
-    role_instance_list: RoleInstanceList,
-}
-
-#[derive(Debug, Deserialize, Clone)]
-struct RoleInstanceList {
-    #[serde(rename = "RoleInstance")]
-    role_instance: RoleInstance,
-}
-
-#[derive(Debug, Deserialize, Clone)]
-struct RoleInstance {
-    #[serde(rename = "InstanceId")]
-    instance_id: String,
-    #[allow(dead_code)]
-    #[serde(rename = "State")]
-    state: String,
-    #[allow(dead_code)]
-    #[serde(rename = "Configuration")]
-    configuration: Configuration,
-}
-
-#[derive(Debug, Deserialize, Clone)]
-struct Configuration {
-    #[allow(dead_code)]
-    #[serde(rename = "HostingEnvironmentConfig")]
-    hosting_environment_config: String,
-    #[allow(dead_code)]
-    #[serde(rename = "SharedConfig")]
-    shared_config: String,
-    #[allow(dead_code)]
-    #[serde(rename = "ExtensionsConfig")]
-    extensions_config: String,
-    #[allow(dead_code)]
-    #[serde(rename = "FullConfig")]
-    full_config: String,
-    #[allow(dead_code)]
-    #[serde(rename = "Certificates")]
-    certificates: String,
-    #[allow(dead_code)]
-    #[serde(rename = "ConfigName")]
-    config_name: String,
-}
-
-// Health report structures for XML generation
-#[derive(Debug, Serialize)]
-struct Health {
-    #[serde(rename = "GoalStateIncarnation")]
-    goal_state_incarnation: u32,
-    #[serde(rename = "Container")]
-    container: HealthContainer,
-}
-
-#[derive(Debug, Serialize)]
-struct HealthContainer {
-    #[serde(rename = "ContainerId")]
-    container_id: String,
-    #[serde(rename = "RoleInstanceList")]
-    role_instance_list: HealthRoleInstanceList,
-}
-
-#[derive(Debug, Serialize)]
-struct HealthRoleInstanceList {
-    #[serde(rename = "Role")]
-    role: HealthRole,
-}
-
-#[derive(Debug, Serialize)]
-struct HealthRole {
-    #[serde(rename = "InstanceId")]
-    instance_id: String,
-    #[serde(rename = "Health")]
-    health: HealthState,
-}
-
-#[derive(Debug, Serialize)]
-struct HealthState {
-    #[serde(rename = "State")]
-    state: String,
-}
-
-// Agent status structures for XML generation
-#[derive(Debug, Serialize)]
-struct TelemetryData {
-    #[serde(rename = "@version")]
-    version: String,
-    #[serde(rename = "Provider")]
-    provider: Provider,
-}
-
-#[derive(Debug, Serialize)]
-struct Provider {
-    #[serde(rename = "@id")]
-    id: String,
-    #[serde(rename = "Event")]
-    event: Event,
-}
-
-#[derive(Debug, Serialize)]
-struct Event {
-    #[serde(rename = "@id")]
-    id: String,
-    #[serde(rename = "EventData")]
-    event_data: EventData,
-}
-
-#[derive(Debug, Serialize)]
-struct EventData {
-    #[serde(rename = "@name")]
-    name: String,
-    #[serde(rename = "Param")]
-    param: Vec<Param>,
-}
-
-#[derive(Debug, Serialize)]
-struct Param {
-    #[serde(rename = "@name")]
-    name: String,
-    #[serde(rename = "@value")]
-    value: String,
-}
+async fn with_firewall_retry<T, F, Fut>(op: F) -> Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = std::result::Result<T, ProtocolError>>,
+{
+    match op().await {
+        Err(e) if e.is_connectivity() => {
+            eprintln!("Timeout or connection error reaching wireserver: {}", e);
+            eprintln!("Attempting to add iptables rule for wireserver access...");
+            add_wireserver_iptables_rule().await?;
 
-fn create_wa_start_telemetry(goal_state: &GoalState) -> TelemetryData {
-    TelemetryData {
-        version: "1.0".to_string(),
-        provider: Provider {
-            id: AGENT_NAME.to_string(),
-            event: Event {
-                id: "3".to_string(),
-                event_data: EventData {
-                    name: "WAStart".to_string(),
-                    param: vec![
-                        Param {
-                            name: "Version".to_string(),
-                            value: AGENT_VERSION.to_string(),
-                        },
-                        Param {
-                            name: "GAState".to_string(),
-                            value: "Ready".to_string(),
-                        },
-                        Param {
-                            name: "Container".to_string(),
-                            value: goal_state.container.container_id.clone(),
-                        },
-                        Param {
-                            name: "RoleInstance".to_string(),
-                            value: goal_state.container.role_instance_list.role_instance.instance_id.clone(),
-                        },
-                        Param {
-                            name: "Timestamp".to_string(),
-                            value: get_timestamp(),
-                        },
-                    ],
-                },
-            },
-        },
+            println!("Retrying wireserver connection...");
+            Ok(op().await?)
+        }
+        result => Ok(result?),
     }
 }
 
-fn create_provision_telemetry(goal_state: &GoalState) -> TelemetryData {
-    TelemetryData {
-        version: "1.0".to_string(),
-        provider: Provider {
-            id: AGENT_NAME.to_string(),
-            event: Event {
-                id: "4".to_string(),
-                event_data: EventData {
-                    name: "Provision".to_string(),
-                    param: vec![
-                        Param {
-                            name: "Version".to_string(),
-                            value: AGENT_VERSION.to_string(),
-                        },
-                        Param {
-                            name: "IsVMProvisionedForLogs".to_string(),
-                            value: "true".to_string(),
-                        },
-                        Param {
-                            name: "ProvisioningState".to_string(),
-                            value: "Ready".to_string(),
-                        },
-                        Param {
-                            name: "Container".to_string(),
-                            value: goal_state.container.container_id.clone(),
-                        },
-                        Param {
-                            name: "RoleInstance".to_string(),
-                            value: goal_state.container.role_instance_list.role_instance.instance_id.clone(),
-                        },
-                        Param {
-                            name: "Timestamp".to_string(),
-                            value: get_timestamp(),
-                        },
-                    ],
-                },
-            },
-        },
-    }
+async fn send_telemetry_event(client: &WireServerClient, telemetry_data: &TelemetryData, count: u32) -> Result<()> {
+    println!("Sending {} #{}", telemetry_data.event_name(), count);
+    with_firewall_retry(|| client.send_telemetry_event(telemetry_data)).await
 }
 
-async fn run_heartbeat_loop(client: &Client, goal_state: &GoalState) -> Result<()> {
+async fn run_heartbeat_loop(client: &WireServerClient, goal_state: &GoalState) -> Result<()> {
     let mut heartbeat_count = 1;
     loop {
         sleep(Duration::from_secs(HEARTBEAT_INTERVAL_SECS)).await;
 
         // Re-fetch the goal state before each heartbeat/telemetry event
-        let latest_goal_state = match fetch_goal_state(client).await {
+        let latest_goal_state = match with_firewall_retry(|| client.fetch_goal_state()).await {
             Ok(gs) => gs,
             Err(e) => {
                 eprintln!("Failed to refresh goal state: {e}");
@@ -424,7 +162,7 @@ async fn run_heartbeat_loop(client: &Client, goal_state: &GoalState) -> Result<(
         };
 
         // Send status report every loop
-        if let Err(e) = send_status_report(client, &latest_goal_state).await {
+        if let Err(e) = with_firewall_retry(|| client.send_status_report(&latest_goal_state, &[])).await {
             eprintln!("Failed to send status report: {e}");
         }
 
@@ -436,341 +174,46 @@ async fn run_heartbeat_loop(client: &Client, goal_state: &GoalState) -> Result<(
             _ => ("Provision", "4"),
         };
 
-        let mut params = create_base_params(&latest_goal_state);
+        let mut params = TelemetryData::base_params(&latest_goal_state);
 
         match event_name {
             "HeartBeat" => {
                 let sys_info = SystemStats::current();
                 params.extend(vec![
-                    Param {
-                        name: "IsVersionFromRSM".to_string(),
-                        value: "true".to_string(),
-                    },
-                    Param {
-                        name: "GAState".to_string(),
-                        value: "Ready".to_string(),
-                    },
-                    Param {
-                        name: "Role".to_string(),
-                        value: latest_goal_state.container.role_instance_list.role_instance.instance_id.clone(),
-                    },
-                    Param {
-                        name: "CPU".to_string(),
-                        value: sys_info.cpu_usage_str(),
-                    },
-                    Param {
-                        name: "Memory".to_string(),
-                        value: sys_info.memory_usage_str(),
-                    },
-                    Param {
-                        name: "ProcessorTime".to_string(),
-                        value: sys_info.uptime_seconds_str(),
-                    },
+                    Param::new("IsVersionFromRSM", "true"),
+                    Param::new("GAState", "Ready"),
+                    Param::new("Role", latest_goal_state.role_instance_id()),
+                    Param::new("CPU", sys_info.cpu_usage_str()),
+                    Param::new("Memory", sys_info.memory_usage_str()),
+                    Param::new("ProcessorTime", sys_info.uptime_seconds_str()),
                 ]);
             },
             "WAStart" => {
-                params.push(Param {
-                    name: "GAState".to_string(),
-                    value: "Ready".to_string(),
-                });
+                params.push(Param::new("GAState", "Ready"));
             },
             "Provision" => {
                 params.extend(vec![
-                    Param {
-                        name: "IsVMProvisionedForLogs".to_string(),
-                        value: "true".to_string(),
-                    },
-                    Param {
-                        name: "ProvisioningState".to_string(),
-                        value: "Ready".to_string(),
-                    },
+                    Param::new("IsVMProvisionedForLogs", "true"),
+                    Param::new("ProvisioningState", "Ready"),
                 ]);
             },
             "AgentStatus" => {
                 params.extend(vec![
-                    Param {
-                        name: "Status".to_string(),
-                        value: "Ready".to_string(),
-                    },
-                    Param {
-                        name: "Message".to_string(),
-                        value: "Guest Agent is running".to_string(),
-                    },
-                    Param {
-                        name: "FormattedMessage".to_string(),
-                        value: format!("Guest Agent is running (Version: {})", AGENT_VERSION),
-                    },
+                    Param::new("Status", "Ready"),
+                    Param::new("Message", "Guest Agent is running"),
+                    Param::new("FormattedMessage", format!("Guest Agent is running (Version: {})", AGENT_VERSION)),
                 ]);
             },
             _ => {}
         }
 
-        let current_telemetry = TelemetryData {
-            version: "1.0".to_string(),
-            provider: Provider {
-                id: AGENT_NAME.to_string(),
-                event: Event {
-                    id: event_id.to_string(),
-                    event_data: EventData {
-                        name: event_name.to_string(),
-                        param: params,
-                    },
-                },
-            },
-        };
+        let current_telemetry = TelemetryData::new(event_id, event_name, params);
 
-        send_telemetry_event(client, &current_telemetry, event_name, heartbeat_count).await?;
+        send_telemetry_event(client, &current_telemetry, heartbeat_count).await?;
         heartbeat_count += 1;
     }
 }
 
-async fn fetch_goal_state(client: &Client) -> Result<GoalState> {
-    let response_result = client
-        .get(&format!("{}/machine?comp=goalstate", WIRESERVER_ENDPOINT))
-        .header("x-ms-version", WIRESERVER_API_VERSION)
-        .timeout(Duration::from_secs(10))
-        .send()
-        .await;
-    
-    let response = match response_result {
-        Ok(resp) => resp,
-        Err(e) => {
-            if e.is_timeout() || e.is_connect() {
-                eprintln!("Timeout or connection error reaching wireserver: {}", e);
-                eprintln!("Attempting to add iptables rule for wireserver access...");
-                add_wireserver_iptables_rule().await?;
-                
-                // Retry the request after adding the iptables rule
-                println!("Retrying wireserver connection...");
-                client
-                    .get(&format!("{}/machine?comp=goalstate", WIRESERVER_ENDPOINT))
-                    .header("x-ms-version", WIRESERVER_API_VERSION)
-                    .timeout(Duration::from_secs(10))
-                    .send()
-                    .await?
-            } else {
-                return Err(e.into());
-            }
-        }
-    };
-    
-    let xml = response.text().await?;
-    let goal_state = from_str::<GoalState>(&xml)?;
-    
-    if cfg!(debug_assertions) {
-        println!("Received GoalState: {:#?}", goal_state);
-    }
-    
-    Ok(goal_state)
-}
-
-async fn send_health_report(client: &Client, goal_state: &GoalState) -> Result<()> {
-    let health_report = Health {
-        goal_state_incarnation: goal_state.incarnation,
-        container: HealthContainer {
-            container_id: goal_state.container.container_id.clone(),
-            role_instance_list: HealthRoleInstanceList {
-                role: HealthRole {
-                    instance_id: goal_state.container.role_instance_list.role_instance.instance_id.clone(),
-                    health: HealthState {
-                        state: "Ready".to_string(),
-                    },
-                },
-            },
-        },
-    };
-
-    let health_xml = to_string(&health_report)?;
-    if cfg!(debug_assertions) {
-        println!("Generated health report XML: {}", health_xml);
-    }
-
-    let health_response_result = client
-        .post(&format!("{}/machine?comp=health", WIRESERVER_ENDPOINT))
-        .header("x-ms-version", WIRESERVER_API_VERSION)
-        .header("x-ms-agent-name", AGENT_NAME)
-        .header("User-Agent", &get_user_agent())
-        .header("Content-Type", "text/xml;charset=utf-8")
-        .timeout(Duration::from_secs(10))
-        .body(health_xml.clone())
-        .send()
-        .await;
-        
-    let health_response = match health_response_result {
-        Ok(resp) => resp,
-        Err(e) => {
-            if e.is_timeout() || e.is_connect() {
-                eprintln!("Timeout sending health report: {}", e);
-                add_wireserver_iptables_rule().await?;
-                
-                // Retry the request
-                client
-                    .post(&format!("{}/machine?comp=health", WIRESERVER_ENDPOINT))
-                    .header("x-ms-version", WIRESERVER_API_VERSION)
-                    .header("x-ms-agent-name", AGENT_NAME)
-                    .header("User-Agent", &get_user_agent())
-                    .header("Content-Type", "text/xml;charset=utf-8")
-                    .timeout(Duration::from_secs(10))
-                    .body(health_xml)
-                    .send()
-                    .await?
-            } else {
-                return Err(e.into());
-            }
-        }
-    };
-        
-    println!("Health report status: {}", health_response.status());
-    
-    if cfg!(debug_assertions) {
-        let health_response_text = health_response.text().await?;
-        println!("Health report response: {}", health_response_text);
-    }
-    
-    Ok(())
-}
-
-async fn send_status_report(client: &Client, goal_state: &GoalState) -> Result<()> {
-    let sys_info = SystemInfo::current();
-    let status_content = serde_json::json!({
-        "version": "1.1",
-        "timestampUTC": get_rfc3339_timestamp(),
-        "aggregateStatus": {
-            "guestAgentStatus": {
-                "version": AGENT_VERSION,
-                "status": "Ready",
-                "formattedMessage": {
-                    "lang": "en-US",
-                    "message": "Guest Agent is running"
-                },
-                "updateStatus": {
-                    "expectedVersion": AGENT_VERSION,
-                    "status": "Success",
-                    "code": 0,
-                    "formattedMessage": {
-                        "lang": "en-US",
-                        "message": ""
-                    }
-                }
-            },
-            "handlerAggregateStatus": [],
-            "vmArtifactsAggregateStatus": {
-                "goalStateAggregateStatus": {
-                    "formattedMessage": {
-                        "lang": "en-US",
-                        "message": "GoalState executed successfully"
-                    },
-                    "timestampUTC": get_rfc3339_timestamp(),
-                    "inSvdSeqNo": goal_state.incarnation.to_string(),
-                    "status": "Success",
-                    "code": 0
-                }
-            }
-        },
-        "guestOSInfo": {
-            "computerName": sys_info.hostname,
-            "osName": sys_info.os_name,
-            "osVersion": sys_info.os_version,
-            "version": AGENT_VERSION
-        },
-        "supportedFeatures": [
-            {"Key": "MultipleExtensionsPerHandler", "Value": "1.0"},
-            {"Key": "VersioningGovernance", "Value": "1.0"},
-            {"Key": "FastTrack", "Value": "1.0"}
-        ]
-    });
-    
-    let status_content_str = serde_json::to_string(&status_content)?;
-    let status_content_b64 = BASE64_STANDARD.encode(status_content_str.as_bytes());
-    
-    let status_payload = serde_json::json!({
-        "content": status_content_b64,
-        "headers": [
-            {"headerName": "Content-Length", "headerValue": "1024"},
-            {"headerName": "x-ms-date", "headerValue": get_timestamp()},
-            {"headerName": "x-ms-range", "headerValue": "bytes=0-1023"},
-            {"headerName": "x-ms-page-write", "headerValue": "update"},
-            {"headerName": "x-ms-version", "headerValue": "2014-02-14"}
-        ],
-        "requestUri": format!("https://md-hdd-placeholder.z27.blob.storage.azure.net/$system/gpg.{}.status", 
-            goal_state.container.container_id)
-    });
-    
-    println!("Sending status report to status service...");
-    
-    let status_response = client
-        .put(&format!("{}:{}/status", WIRESERVER_ENDPOINT, STATUS_SERVICE_PORT))
-        .header("x-ms-version", STATUS_API_VERSION)
-        .header("x-ms-agent-name", AGENT_NAME)
-        .header("User-Agent", &get_user_agent())
-        .header("Content-Type", "application/json")
-        .header("x-ms-containerid", &goal_state.container.container_id)
-        .header("x-ms-host-config-name", format!("{}.0.{}.0._gpg.1.xml", 
-            goal_state.container.role_instance_list.role_instance.instance_id,
-            goal_state.container.role_instance_list.role_instance.instance_id))
-        .json(&status_payload)
-        .send()
-        .await?;
-        
-    println!("Status service response: {}", status_response.status());
-    if cfg!(debug_assertions) {
-        let status_response_text = status_response.text().await?;
-        println!("Status service response body: {}", status_response_text);
-    }
-    
-    Ok(())
-}
-
-async fn send_telemetry_event(client: &Client, telemetry_data: &TelemetryData, event_name: &str, count: u32) -> Result<()> {
-    let telemetry_xml = to_string(telemetry_data)?;
-    
-    println!("Sending {} #{} at {}", event_name, count, Utc::now().format("%Y-%m-%d %H:%M:%S UTC"));
-    
-    let response_result = client
-        .post(&format!("{}/machine?comp=telemetrydata", WIRESERVER_ENDPOINT))
-        .header("x-ms-version", WIRESERVER_API_VERSION)
-        .header("x-ms-agent-name", AGENT_NAME)
-        .header("User-Agent", &get_user_agent())
-        .header("Content-Type", "text/xml;charset=utf-8")
-        .timeout(Duration::from_secs(10))
-        .body(telemetry_xml.clone())
-        .send()
-        .await;
-        
-    let response = match response_result {
-        Ok(resp) => resp,
-        Err(e) => {
-            if e.is_timeout() || e.is_connect() {
-                eprintln!("Timeout sending telemetry event {}: {}", event_name, e);
-                add_wireserver_iptables_rule().await?;
-                
-                // Retry the request
-                client
-                    .post(&format!("{}/machine?comp=telemetrydata", WIRESERVER_ENDPOINT))
-                    .header("x-ms-version", WIRESERVER_API_VERSION)
-                    .header("x-ms-agent-name", AGENT_NAME)
-                    .header("User-Agent", &get_user_agent())
-                    .header("Content-Type", "text/xml;charset=utf-8")
-                    .timeout(Duration::from_secs(10))
-                    .body(telemetry_xml)
-                    .send()
-                    .await?
-            } else {
-                return Err(e.into());
-            }
-        }
-    };
-        
-    println!("{} #{} status: {}", event_name, count, response.status());
-    
-    if !response.status().is_success() {
-        let error_text = response.text().await?;
-        eprintln!("Telemetry error: {}", error_text);
-    }
-    
-    Ok(())
-}
-
 #[cfg(windows)]
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -824,21 +267,18 @@ extern "system" fn service_main(_argc: u32, _argv: *mut *mut u16) {
 }
 
 async fn main_async() -> Result<()> {
-    let client = Client::new();
-    // ...existing code...
+    let client = WireServerClient::new();
     // Fetch goal state
-    let goal_state = fetch_goal_state(&client).await?;
+    let goal_state = with_firewall_retry(|| client.fetch_goal_state()).await?;
     // Send health report
-    send_health_report(&client, &goal_state).await?;
+    with_firewall_retry(|| client.send_health_report(&goal_state, HealthStatus::Ready)).await?;
     // Send initial startup events
     println!("Sending initial agent startup events...");
-    let wa_start_telemetry = create_wa_start_telemetry(&goal_state);
-    send_telemetry_event(&client, &wa_start_telemetry, "WAStart", 0).await?;
+    send_telemetry_event(&client, &TelemetryData::wa_start(&goal_state), 0).await?;
     sleep(Duration::from_secs(2)).await;
-    let provision_telemetry = create_provision_telemetry(&goal_state);
-    send_telemetry_event(&client, &provision_telemetry, "Provision", 0).await?;
+    send_telemetry_event(&client, &TelemetryData::provision(&goal_state, HealthStatus::Ready, ""), 0).await?;
     // Send status report to status service (this is what the portal reads!)
-    send_status_report(&client, &goal_state).await?;
+    with_firewall_retry(|| client.send_status_report(&goal_state, &[])).await?;
     println!("Starting continuous heartbeat loop (send SIGINT/Ctrl+C to stop)...");
     // Continuous heartbeat loop
     run_heartbeat_loop(&client, &goal_state).await?;
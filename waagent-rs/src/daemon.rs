@@ -0,0 +1,524 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use tokio::sync::broadcast;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{info, warn};
+
+use waagent_core::certificates::CertificateStore;
+use waagent_core::config::{AgentConfig, Config};
+use waagent_core::extensions::{ExtensionsManager, HandlerOutcome};
+use waagent_core::network::firewall::{create_firewall_manager, WireServerFirewallPolicy};
+use waagent_core::protocol::{
+    FullGoalState, GoalStateEvent, GoalStatePoller, HealthDetails, HealthStatus, TelemetryData,
+    WireServerClient,
+};
+use waagent_core::provisioning::{CloudInit, Provisioner, ProvisioningAgent, ProvisioningSettings};
+use waagent_core::resource_disk::{
+    ResourceDisk, ResourceDiskSettings, ResourceDiskStatus, SwapStatus,
+};
+use waagent_core::system::SystemStats;
+use waagent_core::utils::command::SystemCommandRunner;
+
+use crate::firewall::FirewallEnforcer;
+
+const HEARTBEAT_PERIOD: Duration = Duration::from_secs(30);
+const CLOUD_INIT_POLL_PERIOD: Duration = Duration::from_secs(5);
+
+/// Runs the agent lifecycle until a shutdown signal is received.
+#[tracing::instrument]
+pub async fn run(config_path: &Path) -> Result<()> {
+    let config = Config::load(config_path).with_context(|| {
+        format!(
+            "Failed to read configuration from {}",
+            config_path.display()
+        )
+    })?;
+    let mut client = WireServerClient::new();
+    let certificates = CertificateStore::from_config(&config);
+    match certificates.ensure_transport_certificate() {
+        Ok(transport_certificate) => {
+            client = client.with_transport_certificate(transport_certificate)
+        }
+        Err(e) => warn!(
+            "Failed to set up transport certificate, certificates will not be requested: {}",
+            e
+        ),
+    }
+    let daemon = Daemon::new(config, client, certificates);
+
+    tokio::select! {
+        result = daemon.run() => result,
+        _ = shutdown_signal() => {
+            info!("Shutdown signal received, stopping agent");
+            Ok(())
+        }
+    }
+}
+
+struct Daemon {
+    config: AgentConfig,
+    client: WireServerClient,
+    certificates: CertificateStore,
+    provisioning_agent: ProvisioningAgent,
+    provisioner: Provisioner,
+    cloud_init: CloudInit,
+    resource_disk: ResourceDisk,
+    extensions: Option<ExtensionsManager>,
+}
+
+impl Daemon {
+    fn new(config: Config, client: WireServerClient, certificates: CertificateStore) -> Self {
+        let agent_config = AgentConfig::from(&config);
+        let extensions = if runs_extensions(&agent_config, is_root()) {
+            Some(ExtensionsManager::from_config(&config, client.clone()))
+        } else {
+            None
+        };
+        let runner = Arc::new(SystemCommandRunner);
+        let cloud_init = CloudInit::new(runner.clone());
+        let provisioning_agent = provisioning_agent(&config, &cloud_init, is_root());
+        info!("Provisioning agent: {}", provisioning_agent);
+        let provisioner =
+            Provisioner::new(ProvisioningSettings::from_config(&config), runner.clone());
+        let resource_disk = ResourceDisk::new(ResourceDiskSettings::from_config(&config), runner);
+        Self {
+            config: agent_config,
+            client,
+            certificates,
+            provisioning_agent,
+            provisioner,
+            cloud_init,
+            resource_disk,
+            extensions,
+        }
+    }
+
+    async fn run(&self) -> Result<()> {
+        let mut poller = GoalStatePoller::new(
+            self.client.clone(),
+            period(self.config.extensions.goal_state_period),
+            period(self.config.extensions.initial_goal_state_period),
+        );
+        let events = poller.subscribe();
+        let firewall_events = poller.subscribe();
+
+        let goal_state = self.start(&mut poller).await?;
+        tokio::spawn(poller.run());
+        self.spawn_resource_disk_setup();
+        self.spawn_firewall_enforcement(goal_state.clone(), firewall_events);
+
+        self.run_main_loop(goal_state, events).await
+    }
+
+    /// Initial handshake with the host: fetch the goal state, provision the VM,
+    /// report it as Ready and send the start-up events and first status blob.
+    async fn start(&self, poller: &mut GoalStatePoller) -> Result<Arc<FullGoalState>> {
+        info!(
+            "Starting agent, WireServer endpoint {}",
+            self.client.endpoint()
+        );
+
+        let goal_state = poller
+            .poll_once()
+            .await
+            .context("Failed to fetch goal state")?
+            .context("No goal state received")?;
+        info!(
+            "Received goal state, incarnation {}",
+            goal_state.incarnation()
+        );
+
+        // SSH keys given by fingerprint come from the goal state certificates.
+        self.install_certificates(&goal_state);
+        let (provisioning_state, provisioning_message) = self.provision(&goal_state).await;
+        self.wait_for_cloud_init_before_extensions().await;
+        if provisioning_state == HealthStatus::Ready {
+            self.client
+                .send_health_report(&goal_state.goal_state, HealthStatus::Ready)
+                .await
+                .context("Failed to send health report")?;
+        }
+
+        self.client
+            .send_telemetry_event(&TelemetryData::wa_start(&goal_state.goal_state))
+            .await
+            .context("Failed to send WAStart event")?;
+        self.client
+            .send_telemetry_event(&TelemetryData::provision(
+                &goal_state.goal_state,
+                provisioning_state,
+                &provisioning_message,
+            ))
+            .await
+            .context("Failed to send Provision event")?;
+
+        self.client
+            .send_status_report(&goal_state.goal_state, &[])
+            .await
+            .context("Failed to send status report")?;
+
+        Ok(goal_state)
+    }
+
+    async fn run_main_loop(
+        &self,
+        mut goal_state: Arc<FullGoalState>,
+        mut events: broadcast::Receiver<GoalStateEvent>,
+    ) -> Result<()> {
+        let mut heartbeat_timer = interval(HEARTBEAT_PERIOD);
+        heartbeat_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        // The interval fires immediately; the start-up sequence already covered that.
+        heartbeat_timer.tick().await;
+        let mut outcomes = Vec::new();
+
+        loop {
+            tokio::select! {
+                event = events.recv() => {
+                    match event {
+                        Ok(GoalStateEvent::NewGoalState(latest)) => {
+                            goal_state = latest;
+                            self.install_certificates(&goal_state);
+                            outcomes = self.process_extensions(&goal_state).await;
+                            self.report_status(&goal_state, &outcomes).await;
+                        }
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            warn!("Missed {} goal state events", skipped);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => {
+                            bail!("Goal state poller stopped");
+                        }
+                    }
+                }
+                _ = heartbeat_timer.tick() => {
+                    let heartbeat =
+                        TelemetryData::heartbeat(&goal_state.goal_state, &SystemStats::current());
+                    if let Err(e) = self.client.send_telemetry_event(&heartbeat).await {
+                        warn!("Failed to send heartbeat: {}", e);
+                    }
+                    self.report_status(&goal_state, &outcomes).await;
+                }
+            }
+        }
+    }
+
+    /// Provisions the VM, or waits for cloud-init to do so, and reports
+    /// progress to the host. A failure is reported as NotReady with the error.
+    /// Returns the resulting state and a message describing it.
+    async fn provision(&self, goal_state: &FullGoalState) -> (HealthStatus, String) {
+        let result = match self.provisioning_agent {
+            ProvisioningAgent::Waagent => self.provision_with_agent(goal_state).await,
+            ProvisioningAgent::CloudInit => {
+                self.report_provisioning(
+                    goal_state,
+                    HealthDetails::provisioning("Waiting for cloud-init"),
+                )
+                .await;
+                self.cloud_init
+                    .wait_for_completion(
+                        self.config.extensions.wait_for_cloud_init_timeout,
+                        CLOUD_INIT_POLL_PERIOD,
+                    )
+                    .await
+                    .map(|()| "Provisioned by cloud-init".to_string())
+                    .map_err(|e| e.to_string())
+            }
+            ProvisioningAgent::Auto | ProvisioningAgent::Disabled => {
+                Ok("Provisioning is disabled".to_string())
+            }
+        };
+
+        match result {
+            Ok(message) => (HealthStatus::Ready, message),
+            Err(error) => {
+                warn!("Provisioning failed: {}", error);
+                self.report_provisioning(
+                    goal_state,
+                    HealthDetails::provisioning_failed(error.clone()),
+                )
+                .await;
+                (HealthStatus::NotReady, error)
+            }
+        }
+    }
+
+    /// Runs the agent's own provisioning unless that already happened on an
+    /// earlier boot. A failed run is retried on the next start.
+    async fn provision_with_agent(&self, goal_state: &FullGoalState) -> Result<String, String> {
+        if self.provisioner.is_provisioned() {
+            return Ok("Provisioned on an earlier boot".to_string());
+        }
+        // The helper does not create users or edit /etc/shadow, sudoers or sshd_config
+        #[cfg(unix)]
+        if !waagent_core::privileged::is_root() {
+            return Err(
+                "Provisioning.Agent=waagent needs the agent to run as root; use cloud-init instead"
+                    .to_string(),
+            );
+        }
+
+        self.report_provisioning(goal_state, HealthDetails::provisioning("Starting"))
+            .await;
+        let provisioner = self.provisioner.clone();
+        match tokio::task::spawn_blocking(move || provisioner.provision()).await {
+            Ok(Ok(outcome)) => {
+                let ovf = &outcome.ovf;
+                info!("Provisioned VM {} for user {}", ovf.hostname, ovf.username);
+                Ok(match &outcome.custom_data {
+                    Some(custom_data) => format!("Provisioned by the agent, {}", custom_data),
+                    None => "Provisioned by the agent".to_string(),
+                })
+            }
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(e) => Err(format!("provisioning panicked: {}", e)),
+        }
+    }
+
+    /// With `Extensions.WaitForCloudInit`, extensions do not run before
+    /// cloud-init is done, even when the agent provisioned the VM itself.
+    async fn wait_for_cloud_init_before_extensions(&self) {
+        if self.provisioning_agent == ProvisioningAgent::CloudInit
+            || !self.config.extensions.wait_for_cloud_init
+            || !self.cloud_init.is_enabled()
+        {
+            return;
+        }
+        if let Err(e) = self
+            .cloud_init
+            .wait_for_completion(
+                self.config.extensions.wait_for_cloud_init_timeout,
+                CLOUD_INIT_POLL_PERIOD,
+            )
+            .await
+        {
+            warn!("Running extensions anyway: {}", e);
+        }
+    }
+
+    async fn report_provisioning(&self, goal_state: &FullGoalState, details: HealthDetails) {
+        if let Err(e) = self
+            .client
+            .send_health_report_with_details(
+                &goal_state.goal_state,
+                HealthStatus::NotReady,
+                Some(details),
+            )
+            .await
+        {
+            warn!("Failed to report provisioning status: {}", e);
+        }
+    }
+
+    /// Formatting a large temporary disk can take a while, so it does not hold
+    /// up goal state processing.
+    fn spawn_resource_disk_setup(&self) {
+        let resource_disk = self.resource_disk.clone();
+        tokio::task::spawn_blocking(move || match setup_resource_disk(&resource_disk) {
+            Ok(Some(status)) => match status.swap {
+                SwapStatus::EncryptionUnavailable { .. } => warn!("{}", status),
+                _ => info!("Resource disk is ready: {}", status),
+            },
+            Ok(None) => {}
+            Err(e) => warn!("Failed to set up the resource disk: {}", e),
+        });
+    }
+
+    /// Restricts WireServer access to the agent when `OS.EnableFirewall` is set.
+    fn spawn_firewall_enforcement(
+        &self,
+        goal_state: Arc<FullGoalState>,
+        events: broadcast::Receiver<GoalStateEvent>,
+    ) {
+        if !self.config.os.enable_firewall {
+            info!("Firewall management is disabled (OS.EnableFirewall=n)");
+            return;
+        }
+        if cfg!(not(unix)) {
+            // Windows Firewall cannot tell the agent's connections apart
+            warn!("The WireServer firewall policy is only supported on Linux");
+            return;
+        }
+
+        let policy = match WireServerFirewallPolicy::discover(&self.config.lib.dir) {
+            Ok(policy) => policy,
+            Err(e) => {
+                warn!("Failed to set up the WireServer firewall: {}", e);
+                return;
+            }
+        };
+        let enforcer = FirewallEnforcer::new(
+            policy,
+            Arc::from(create_firewall_manager()),
+            self.client.clone(),
+            period(self.config.os.enable_firewall_period),
+            period(self.config.debug.firewall_rules_log_period),
+        );
+        tokio::spawn(enforcer.run(goal_state, events));
+    }
+
+    /// Certificates have to be in place before extensions that need them run.
+    fn install_certificates(&self, goal_state: &FullGoalState) {
+        if let Some(certificates) = &goal_state.certificates {
+            if let Err(e) = self.certificates.install(certificates) {
+                warn!("Failed to install certificates: {}", e);
+            }
+        }
+    }
+
+    async fn process_extensions(&self, goal_state: &FullGoalState) -> Vec<HandlerOutcome> {
+        let (manager, extensions_config) = match (&self.extensions, &goal_state.extensions_config) {
+            (Some(manager), Some(extensions_config)) => (manager, extensions_config),
+            _ => return Vec::new(),
+        };
+
+        let outcomes = manager.process(extensions_config).await;
+        for outcome in &outcomes {
+            match &outcome.result {
+                Ok(()) => info!("{} {} is {}", outcome.name, outcome.version, outcome.state),
+                Err(e) => warn!("{} {} failed: {}", outcome.name, outcome.version, e),
+            }
+        }
+        outcomes
+    }
+
+    /// Sends the status blob. Handler status is re-read on every report, since
+    /// extensions keep updating their status files after enable returns.
+    async fn report_status(&self, goal_state: &FullGoalState, outcomes: &[HandlerOutcome]) {
+        let handlers = self
+            .extensions
+            .as_ref()
+            .map(|manager| manager.collect_status(outcomes))
+            .unwrap_or_default();
+        if let Err(e) = self
+            .client
+            .send_status_report(&goal_state.goal_state, &handlers)
+            .await
+        {
+            warn!("Failed to send status report: {}", e);
+        }
+    }
+}
+
+/// Who provisions the VM. Left on auto, an agent that is not root leaves the
+/// VM alone rather than fail to create the admin user.
+fn provisioning_agent(config: &Config, cloud_init: &CloudInit, root: bool) -> ProvisioningAgent {
+    ProvisioningAgent::from_config(config)
+        .unwrap_or_else(|e| {
+            warn!("{}, falling back to auto", e);
+            ProvisioningAgent::Auto
+        })
+        .resolve(cloud_init, root)
+}
+
+/// Handlers install packages and change the system, and the helper does not
+/// run them, so an agent that is not root leaves extensions alone.
+fn runs_extensions(config: &AgentConfig, root: bool) -> bool {
+    if !config.extensions.enabled {
+        info!("Extension handling is disabled (Extensions.Enabled=n)");
+        return false;
+    }
+    if !root {
+        warn!("Extension handling is disabled, extensions only run when the agent runs as root");
+        return false;
+    }
+    true
+}
+
+#[cfg(unix)]
+fn is_root() -> bool {
+    waagent_core::privileged::is_root()
+}
+
+/// There is no helper on Windows; the agent runs as LocalSystem.
+#[cfg(not(unix))]
+fn is_root() -> bool {
+    true
+}
+
+/// A configured period, at least a second so timers never spin.
+fn period(period: Duration) -> Duration {
+    period.max(Duration::from_secs(1))
+}
+
+/// Root sets the resource disk up itself. The unprivileged agent asks the
+/// helper, which does it as its own copy of the configuration says.
+fn setup_resource_disk(resource_disk: &ResourceDisk) -> Result<Option<ResourceDiskStatus>> {
+    #[cfg(unix)]
+    if !waagent_core::privileged::is_root() {
+        if !resource_disk.settings().format {
+            return Ok(None);
+        }
+        let client = waagent_core::privileged::HelperClient::default();
+        return Ok(client.setup_resource_disk()?);
+    }
+
+    Ok(resource_disk.setup()?)
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {},
+                    _ = sigterm.recv() => {},
+                }
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use waagent_core::utils::command::{CommandOutput, CommandRunner};
+
+    /// A system without cloud-init, or systemd to ask about it.
+    struct NoCommands;
+
+    impl CommandRunner for NoCommands {
+        fn run(&self, program: &str, _: &[&str], _: Option<&[u8]>) -> io::Result<CommandOutput> {
+            Err(io::Error::new(io::ErrorKind::NotFound, program.to_string()))
+        }
+    }
+
+    #[test]
+    fn test_unprivileged_agent_does_not_provision_on_auto() {
+        let config = Config::new();
+        let cloud_init = CloudInit::new(Arc::new(NoCommands));
+
+        assert_eq!(
+            provisioning_agent(&config, &cloud_init, false),
+            ProvisioningAgent::Disabled
+        );
+        assert_eq!(
+            provisioning_agent(&config, &cloud_init, true),
+            ProvisioningAgent::Waagent
+        );
+    }
+
+    #[test]
+    fn test_unprivileged_agent_does_not_run_extensions() {
+        let config = AgentConfig::from(&Config::new());
+        assert!(config.extensions.enabled);
+
+        assert!(!runs_extensions(&config, false));
+        assert!(runs_extensions(&config, true));
+    }
+}
@@ -0,0 +1,120 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{debug, info, warn};
+
+use waagent_core::network::firewall::{FirewallManager, WireServerFirewallPolicy};
+use waagent_core::protocol::{FullGoalState, GoalStateEvent, TelemetryData, WireServerClient};
+
+/// Keeps the WireServer firewall rules installed: checks them every
+/// `OS.EnableFirewallPeriod`, repairs drift, and logs the full rule listing
+/// every `Debug.FirewallRulesLogPeriod`.
+pub struct FirewallEnforcer {
+    policy: WireServerFirewallPolicy,
+    manager: Arc<dyn FirewallManager>,
+    client: WireServerClient,
+    enforce_period: Duration,
+    log_period: Duration,
+}
+
+impl FirewallEnforcer {
+    pub fn new(
+        policy: WireServerFirewallPolicy,
+        manager: Arc<dyn FirewallManager>,
+        client: WireServerClient,
+        enforce_period: Duration,
+        log_period: Duration,
+    ) -> Self {
+        Self {
+            policy,
+            manager,
+            client,
+            enforce_period,
+            log_period,
+        }
+    }
+
+    /// Drift events are reported against the latest goal state `events` has
+    /// announced, starting from `goal_state`.
+    pub async fn run(
+        self,
+        mut goal_state: Arc<FullGoalState>,
+        mut events: broadcast::Receiver<GoalStateEvent>,
+    ) {
+        let mut enforce_timer = interval(self.enforce_period);
+        enforce_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut log_timer = interval(self.log_period);
+        log_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        // The first pass installs the rules; only later repairs are drift.
+        let mut installed = false;
+        let mut listening = true;
+        loop {
+            tokio::select! {
+                event = events.recv(), if listening => match event {
+                    Ok(GoalStateEvent::NewGoalState(latest)) => goal_state = latest,
+                    // Only the latest goal state matters, and it is still to come
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => listening = false,
+                },
+                _ = enforce_timer.tick() => {
+                    installed |= self.enforce(&goal_state, installed).await;
+                }
+                _ = log_timer.tick() => self.log_rules().await,
+            }
+        }
+    }
+
+    /// Installs or repairs the rules. Returns whether they are in place.
+    async fn enforce(&self, goal_state: &FullGoalState, report_drift: bool) -> bool {
+        let policy = self.policy.clone();
+        let manager = self.manager.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            policy.install(manager.as_ref()).map_err(|e| e.to_string())
+        })
+        .await;
+
+        let check = match result {
+            Ok(Ok(check)) => check,
+            Ok(Err(e)) => {
+                warn!("Failed to enforce WireServer firewall rules: {}", e);
+                return false;
+            }
+            Err(e) => {
+                warn!("Firewall enforcement task failed: {}", e);
+                return false;
+            }
+        };
+
+        if check.is_compliant() {
+            debug!("WireServer firewall rules are in place");
+        } else if !report_drift {
+            info!("Installed WireServer firewall rules");
+        } else {
+            let message = format!("Repaired WireServer firewall: {}", check);
+            warn!("{}", message);
+            let event = TelemetryData::firewall(&goal_state.goal_state, &message);
+            if let Err(e) = self.client.send_telemetry_event(&event).await {
+                warn!("Failed to send firewall event: {}", e);
+            }
+        }
+        true
+    }
+
+    async fn log_rules(&self) {
+        let manager = self.manager.clone();
+        let result =
+            tokio::task::spawn_blocking(move || manager.list_rules().map_err(|e| e.to_string()))
+                .await;
+        match result {
+            Ok(Ok(rules)) => {
+                let rules: Vec<_> = rules.iter().map(|rule| rule.to_string()).collect();
+                info!("Firewall rules:\n{}", rules.join("\n"));
+            }
+            Ok(Err(e)) => warn!("Failed to list firewall rules: {}", e),
+            Err(e) => warn!("Firewall listing task failed: {}", e),
+        }
+    }
+}
@@ -1,15 +1,21 @@
+mod daemon;
+mod firewall;
+
 use std::fmt;
+use std::path::{Path, PathBuf};
 
 use anyhow::Result;
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 use tracing::{debug, error, info};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
-use waagent_core::network::firewall::{
-    create_firewall_manager, Action, Direction, FirewallRule, Protocol,
-};
+use waagent_core::network::firewall::create_firewall_manager;
+#[cfg(unix)]
+use waagent_core::network::firewall::{discover_endpoint, WireServerFirewallPolicy};
 
-use waagent_core::config::Config;
+#[cfg(unix)]
+use waagent_core::config::AgentConfig;
+use waagent_core::config::{Config, ConfigSchema};
 
 #[derive(ValueEnum, Clone, Debug, PartialEq)]
 enum LoggingLevel {
@@ -35,6 +41,9 @@ impl fmt::Display for LoggingLevel {
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Whether to configure the firewall rules
     #[arg(long, default_value_t = false)]
     configure_firewall: bool,
@@ -52,6 +61,74 @@ struct Args {
     show_configuration: bool,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the agent: report to the host and keep the VM status up to date
+    Daemon {
+        /// Path to the agent configuration file
+        #[arg(long, default_value = DEFAULT_CONFIG_PATH)]
+        config: PathBuf,
+    },
+    /// Check and inspect the agent configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Manage the agent's firewall rules
+    Firewall {
+        #[command(subcommand)]
+        command: FirewallCommand,
+    },
+    /// Run the privileged helper that performs firewall and mount operations
+    /// on behalf of the unprivileged agent
+    #[cfg(unix)]
+    Helper {
+        /// Socket to listen on when not started through socket activation
+        #[arg(long, default_value = waagent_core::privileged::DEFAULT_SOCKET_PATH)]
+        socket: PathBuf,
+        /// User the agent runs as; root is always allowed
+        #[arg(long, default_value = DEFAULT_AGENT_USER)]
+        allowed_user: String,
+        /// Configuration the resource disk is set up from; requests cannot
+        /// override it
+        #[arg(long, default_value = DEFAULT_CONFIG_PATH)]
+        config: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Report unknown keys and invalid values in the file, its fragments and
+    /// WAAGENT_* environment variables; exits non-zero on any invalid value
+    Validate {
+        /// Path to the agent configuration file
+        #[arg(long, default_value = DEFAULT_CONFIG_PATH)]
+        config: PathBuf,
+    },
+    /// Print the configuration in effect: the file, the fragments in
+    /// <CONFIG>.d and WAAGENT_<SECTION>_<KEY> environment variables
+    Show {
+        /// Path to the agent configuration file
+        #[arg(long, default_value = DEFAULT_CONFIG_PATH)]
+        config: PathBuf,
+        /// Say where each value came from
+        #[arg(long, default_value_t = false)]
+        origins: bool,
+    },
+    /// Print the configuration keys as a JSON Schema
+    Schema,
+}
+
+#[derive(Subcommand, Debug)]
+enum FirewallCommand {
+    /// Remove every firewall rule the agent created, e.g. before uninstalling it
+    Remove,
+}
+
+const DEFAULT_CONFIG_PATH: &str = "/etc/waagent.conf";
+#[cfg(unix)]
+const DEFAULT_AGENT_USER: &str = "waagent-rs";
+
 #[tokio::main]
 #[tracing::instrument]
 async fn main() -> Result<()> {
@@ -71,6 +148,29 @@ async fn main() -> Result<()> {
         config.show();
     }
 
+    match &args.command {
+        Some(Command::Daemon { config }) => daemon::run(config).await?,
+        Some(Command::Config {
+            command: ConfigCommand::Validate { config },
+        }) => validate_config(config)?,
+        Some(Command::Config {
+            command: ConfigCommand::Show { config, origins },
+        }) => show_config(config, *origins)?,
+        Some(Command::Config {
+            command: ConfigCommand::Schema,
+        }) => println!("{:#}", ConfigSchema::new().to_json_schema()),
+        Some(Command::Firewall {
+            command: FirewallCommand::Remove,
+        }) => remove_firewall_rules()?,
+        #[cfg(unix)]
+        Some(Command::Helper {
+            socket,
+            allowed_user,
+            config,
+        }) => run_helper(socket, allowed_user, config).await?,
+        None => {}
+    }
+
     Ok(())
 }
 
@@ -97,33 +197,145 @@ fn init_tracing(log_level: &LoggingLevel, log_line_numbers: &bool) -> Result<()>
     Ok(())
 }
 
+/// Installs the WireServer policy for the agent user, as configured in
+/// `/etc/waagent.conf`. Meant to be run as root, so the uid the policy lets
+/// through is the agent user's rather than this process's.
+#[cfg(unix)]
 #[tracing::instrument]
 async fn configure_firewall() -> Result<()> {
     info!("Configure firewall rules ...");
 
     let firewall_manager = create_firewall_manager();
 
-    let rule = FirewallRule {
-        name: "AllowAzureMetadata".into(),
-        direction: Direction::Outbound,
-        action: Action::Allow,
-        protocol: Protocol::Tcp,
-        destination: "168.63.129.16/32".into(),
-        port: None,
-        uid_owner: Some("999".into()), // UID for unix
-        program_path: None,
-    };
+    let config = Config::load(Path::new(DEFAULT_CONFIG_PATH)).map_err(|e| {
+        anyhow::anyhow!(
+            "Failed to read configuration from {}: {}",
+            DEFAULT_CONFIG_PATH,
+            e
+        )
+    })?;
+    let config = AgentConfig::from(&config);
+    let policy = WireServerFirewallPolicy::new(
+        discover_endpoint(&config.lib.dir),
+        agent_uid(DEFAULT_AGENT_USER)?,
+    );
+    debug!("Firewall policy: {:?}", policy);
 
-    debug!("Adding firewall rule: {:?}", rule);
-    let result = firewall_manager.add_rule(&rule);
+    let check = policy.install(firewall_manager.as_ref()).map_err(|error| {
+        error!("Failed to install firewall rules: {:?}", error);
+        anyhow::anyhow!("Failed to install firewall rules: {}", error)
+    })?;
 
-    if result.is_err() {
-        let error = result.unwrap_err();
-        error!("Failed to add firewall rule: {:?}", error);
-        return Err(anyhow::anyhow!("Failed to add firewall rule: {}", error));
+    if check.is_compliant() {
+        info!("Firewall rules are already in place");
+    } else {
+        info!("Firewall rules installed ({})", check);
     }
 
-    info!("Firewall rule added successfully");
+    Ok(())
+}
+
+/// netsh has no notion of the rule owner or connection state, so the block
+/// rule would cut off the agent and DNS as well.
+#[cfg(not(unix))]
+async fn configure_firewall() -> Result<()> {
+    anyhow::bail!("The WireServer firewall policy is only supported on Linux")
+}
+
+#[cfg(unix)]
+fn agent_uid(user: &str) -> Result<u32> {
+    use waagent_core::privileged::uid_of_user;
+
+    match uid_of_user(user, Path::new("/etc/passwd"))? {
+        Some(uid) => Ok(uid),
+        None => anyhow::bail!("User {} does not exist", user),
+    }
+}
+
+fn validate_config(path: &Path) -> Result<()> {
+    let (_, diagnostics) = Config::load_with_diagnostics(path, std::env::vars())
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+
+    for (source, diagnostic) in &diagnostics {
+        println!("{}: {}", source, diagnostic);
+    }
+
+    let errors = diagnostics.iter().filter(|(_, d)| d.is_error()).count();
+    if errors > 0 {
+        anyhow::bail!("The configuration has {} invalid value(s)", errors);
+    }
+
+    println!("{}: OK ({} warning(s))", path.display(), diagnostics.len());
+    Ok(())
+}
+
+fn show_config(path: &Path, origins: bool) -> Result<()> {
+    let config = Config::load(path).map_err(|e| {
+        anyhow::anyhow!(
+            "Failed to read configuration from {}: {}",
+            path.display(),
+            e
+        )
+    })?;
+
+    if origins {
+        print!("{}", config.show_origins());
+    } else {
+        print!("{}", config.show());
+    }
+    Ok(())
+}
+
+#[tracing::instrument]
+fn remove_firewall_rules() -> Result<()> {
+    let firewall_manager = create_firewall_manager();
+
+    let removed = firewall_manager
+        .remove_all_managed_rules()
+        .map_err(|error| {
+            error!("Failed to remove firewall rules: {:?}", error);
+            anyhow::anyhow!("Failed to remove firewall rules: {}", error)
+        })?;
+
+    info!("Removed {} firewall rules", removed);
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn run_helper(socket: &Path, allowed_user: &str, config_path: &Path) -> Result<()> {
+    use std::sync::Arc;
+    use waagent_core::privileged::{bind_socket, gid_of_group, systemd_socket, HelperServer};
+    use waagent_core::resource_disk::ResourceDiskSettings;
+    use waagent_core::utils::command::SystemCommandRunner;
+
+    let allowed_uids = vec![0, agent_uid(allowed_user)?];
+
+    let listener = match systemd_socket()? {
+        Some(listener) => {
+            info!("Using the socket passed by systemd");
+            listener
+        }
+        None => {
+            // The agent's group may connect, as with the socket unit
+            let Some(gid) = gid_of_group(allowed_user, Path::new("/etc/group"))? else {
+                anyhow::bail!("Group {} does not exist", allowed_user);
+            };
+            info!("Listening on {}", socket.display());
+            bind_socket(socket, Some(gid))?
+        }
+    };
 
+    let config = Config::load(config_path).map_err(|e| {
+        anyhow::anyhow!(
+            "Failed to read configuration from {}: {}",
+            config_path.display(),
+            e
+        )
+    })?;
+    let firewall = Arc::from(create_firewall_manager());
+    HelperServer::new(firewall, Arc::new(SystemCommandRunner), allowed_uids)
+        .with_resource_disk(ResourceDiskSettings::from_config(&config))
+        .serve(listener)
+        .await?;
     Ok(())
 }